@@ -2,6 +2,7 @@ use crate::bitboards::bitboards::constants::square;
 use crate::bitboards::bitboards::constants::{KING_ATTACKS, KNIGHT_ATTACKS};
 use crate::bitboards::bitboards::square;
 use crate::board_representation::zobrist_hashing::ZOBRIST_KEYS;
+use crate::evaluation::nnue::AccumulatorStack;
 use crate::evaluation::params::*;
 use crate::evaluation::phase::Phase;
 use crate::evaluation::EvaluationScore;
@@ -99,6 +100,14 @@ impl PieceType {
         ZOBRIST_KEYS.pieces[side][self as usize][sq]
     }
 
+    //The material key counts the pieces of every type except kings in 4 bits each.
+    //White's counts occupy the lower 20 bits, Black's counts the 20 bits above.
+    #[inline(always)]
+    pub fn to_material_key(self, side: usize) -> u64 {
+        debug_assert!(self != PieceType::King);
+        1u64 << (4 * (5 * side + self as usize))
+    }
+
     #[inline(always)]
     pub fn to_piece_score(self) -> EvaluationScore {
         match &self {
@@ -285,18 +294,20 @@ fn file_to_string(file: usize) -> &'static str {
 
 #[derive(Clone)]
 pub struct Irreversible {
-    hash: u64,
-    en_passant: u64,
-    half_moves: u16,
-    castle_permissions: u8,
-    phase: Phase,
-    psqt: EvaluationScore,
+    pub(crate) hash: u64,
+    pub(crate) material_key: u64,
+    pub(crate) en_passant: u64,
+    pub(crate) half_moves: u16,
+    pub(crate) castle_permissions: u8,
+    pub(crate) phase: Phase,
+    pub(crate) psqt: EvaluationScore,
 }
 
 impl Irreversible {
-    pub fn new(hash: u64, en_passant: u64, half_moves: u16, castle_permissions: u8, phase: Phase, psqt: EvaluationScore) -> Self {
+    pub fn new(hash: u64, material_key: u64, en_passant: u64, half_moves: u16, castle_permissions: u8, phase: Phase, psqt: EvaluationScore) -> Self {
         Irreversible {
             hash,
+            material_key,
             en_passant,
             half_moves,
             castle_permissions,
@@ -310,7 +321,7 @@ impl Irreversible {
 pub struct GameState {
     // 0 = White
     // 1 = Black
-    color_to_move: usize,
+    pub(crate) color_to_move: usize,
 
     //Array saving all the bitboards
     //Index 1:
@@ -323,12 +334,18 @@ pub struct GameState {
     //Index 2:
     // 0 -> White
     // 1 -> Black
-    color_bb: [u64; 2],
-    piece_bb: [u64; 6],
+    pub(crate) color_bb: [u64; 2],
+    pub(crate) piece_bb: [u64; 6],
 
-    irreversible: Irreversible,
+    pub(crate) irreversible: Irreversible,
 
-    full_moves: usize,
+    pub(crate) full_moves: usize,
+
+    //Irreversible state of all positions before moves made in place, see do_move and undo_move
+    pub(crate) undo_stack: Vec<Irreversible>,
+
+    //Network accumulators of the positions on the undo stack, only tracked once a network was attached, see nnue.rs
+    pub(crate) accumulators: AccumulatorStack,
 }
 
 //Getters and setters
@@ -351,6 +368,9 @@ impl GameState {
     pub fn get_hash(&self) -> u64 {
         self.irreversible.hash
     }
+    pub fn get_material_key(&self) -> u64 {
+        self.irreversible.material_key
+    }
     pub fn get_en_passant(&self) -> u64 {
         self.irreversible.en_passant
     }
@@ -421,6 +441,8 @@ impl GameState {
             color_bb,
             irreversible,
             full_moves,
+            undo_stack: Vec::new(),
+            accumulators: AccumulatorStack::default(),
         }
     }
 
@@ -462,16 +484,27 @@ impl GameState {
         }
     }
 
+    pub fn initialize_material_key(&mut self) {
+        self.irreversible.material_key = 0u64;
+        for side in 0..2 {
+            for pt in PIECE_TYPES[..5].iter() {
+                self.irreversible.material_key += pt.to_material_key(side) * self.get_piece_amt(*pt, side) as u64;
+            }
+        }
+    }
+
     pub fn initialize_psqt(&mut self) {
         let p_w = crate::evaluation::psqt_evaluation::psqt(
             self,
             WHITE,
+            &crate::evaluation::eval_params::CompiledParams,
             #[cfg(feature = "tuning")]
             &mut crate::evaluation::trace::LargeTrace::default(),
         );
         let p_b = crate::evaluation::psqt_evaluation::psqt(
             self,
             BLACK,
+            &crate::evaluation::eval_params::CompiledParams,
             #[cfg(feature = "tuning")]
             &mut crate::evaluation::trace::LargeTrace::default(),
         );
@@ -484,6 +517,7 @@ impl GameState {
 
     pub fn initialize(&mut self) {
         self.initialize_zobrist_hash();
+        self.initialize_material_key();
         self.initialize_psqt();
         self.initialize_phase();
     }
@@ -579,7 +613,7 @@ impl GameState {
             color_to_move,
             piece_bb,
             color_bb,
-            Irreversible::new(0u64, en_passant, half_moves, castle_permissions, Phase::default(), EvaluationScore(0, 0)),
+            Irreversible::new(0u64, 0u64, en_passant, half_moves, castle_permissions, Phase::default(), EvaluationScore(0, 0)),
             full_moves,
         );
         res.initialize();
@@ -677,13 +711,67 @@ impl GameState {
             color_to_move,
             piece_bb,
             color_bb,
-            Irreversible::new(0u64, 0u64, 0, CASTLE_ALL, Phase::default(), EvaluationScore(0, 0)),
+            Irreversible::new(0u64, 0u64, 0u64, 0, CASTLE_ALL, Phase::default(), EvaluationScore(0, 0)),
             1,
         );
         res.initialize();
         res
     }
 
+    //The same position seen from the other side: ranks are mirrored and the colors of all pieces, the side to move
+    //and the castle permissions are swapped. The evaluation of the mirrored position is the negated evaluation
+    pub fn mirror(&self) -> GameState {
+        let mut piece_bb = self.piece_bb;
+        for bb in piece_bb.iter_mut() {
+            *bb = bb.swap_bytes();
+        }
+        let color_bb = [self.color_bb[BLACK].swap_bytes(), self.color_bb[WHITE].swap_bytes()];
+        let castle_permissions = ((self.castle_permissions() & CASTLE_ALL_WHITE) >> 2) | ((self.castle_permissions() & CASTLE_ALL_BLACK) << 2);
+        let mut res = GameState::new(
+            swap_side(self.color_to_move),
+            piece_bb,
+            color_bb,
+            Irreversible::new(
+                0u64,
+                0u64,
+                self.get_en_passant().swap_bytes(),
+                self.irreversible.half_moves,
+                castle_permissions,
+                Phase::default(),
+                EvaluationScore(0, 0),
+            ),
+            self.full_moves,
+        );
+        res.initialize();
+        res
+    }
+
+    //Mirrors the files, the a-file becomes the h-file. Castling isn't symmetric under this, so castle permissions are dropped
+    pub fn flip_horizontal(&self) -> GameState {
+        let mut piece_bb = self.piece_bb;
+        for bb in piece_bb.iter_mut() {
+            *bb = flip_horizontal_bb(*bb);
+        }
+        let color_bb = [flip_horizontal_bb(self.color_bb[WHITE]), flip_horizontal_bb(self.color_bb[BLACK])];
+        let mut res = GameState::new(
+            self.color_to_move,
+            piece_bb,
+            color_bb,
+            Irreversible::new(
+                0u64,
+                0u64,
+                flip_horizontal_bb(self.get_en_passant()),
+                self.irreversible.half_moves,
+                0,
+                Phase::default(),
+                EvaluationScore(0, 0),
+            ),
+            self.full_moves,
+        );
+        res.initialize();
+        res
+    }
+
     #[inline(always)]
     pub fn has_non_pawns(&self, side: usize) -> bool {
         self.get_piece(PieceType::Bishop, side) != 0u64
@@ -731,7 +819,8 @@ impl GameState {
         }
     }
 
-    pub fn is_valid_tt_move(&self, mv: GameMove) -> bool {
+    //Checks whether a move, e.g. from the TT or a killer slot, could have been generated in this position, ignoring whether it leaves our king in check
+    pub fn is_pseudo_legal(&self, mv: GameMove) -> bool {
         if self.get_piece(mv.piece_type, self.color_to_move) & square(mv.from as usize) == 0u64 {
             return false;
         }
@@ -747,48 +836,24 @@ impl GameState {
         }
 
         if mv.move_type == GameMoveType::EnPassant {
-            if (self.get_en_passant() & square(mv.to as usize)) == 0u64 {
+            if mv.piece_type != PieceType::Pawn || (self.get_en_passant() & square(mv.to as usize)) == 0u64 {
                 return false;
             }
         } else if mv.move_type == GameMoveType::Castle {
-            if mv.piece_type != PieceType::King {
-                return false;
-            }
+            //Castling rights imply that king and rook are still on their original squares
             let all_piece = self.get_all_pieces();
-            if mv.to != square::G1 as u8 && mv.to != square::C1 as u8 && mv.to != square::G8 as u8 && mv.to != square::C8 as u8 || self.in_check() {
-                return false;
-            }
-            let is_invalid_wk = || {
-                mv.to == square::G1 as u8
-                    && (!self.castle_white_kingside()
-                        || all_piece & (square(square::F1) | square(square::G1)) > 0
-                        || self.square_attacked(square::F1, all_piece, 0u64)
-                        || self.square_attacked(square::G1, all_piece, 0u64))
-            };
-            let is_invalid_wq = || {
-                mv.to == square::C1 as u8
-                    && (!self.castle_white_queenside()
-                        || all_piece & (square(square::B1) | square(square::C1) | square(square::D1)) > 0
-                        || self.square_attacked(square::C1, all_piece, 0u64)
-                        || self.square_attacked(square::D1, all_piece, 0u64))
-            };
-            let is_invalid_bk = || {
-                mv.to == square::G8 as u8
-                    && (!self.castle_black_kingside()
-                        || all_piece & (square(square::F8) | square(square::G8)) > 0
-                        || self.square_attacked(square::F8, all_piece, 0u64)
-                        || self.square_attacked(square::G8, all_piece, 0u64))
-            };
-            let is_invalid_bq = || {
-                mv.to == square::C8 as u8
-                    && (!self.castle_black_queenside()
-                        || all_piece & (square(square::B8) | square(square::C8) | square(square::D8)) > 0
-                        || self.square_attacked(square::C8, all_piece, 0u64)
-                        || self.square_attacked(square::D8, all_piece, 0u64))
-            };
-            if is_invalid_wk() || is_invalid_wq() || is_invalid_bk() || is_invalid_bq() {
-                return false;
-            }
+            return mv.piece_type == PieceType::King
+                && match mv.to as usize {
+                    square::G1 => self.color_to_move == WHITE && self.castle_white_kingside() && all_piece & (square(square::F1) | square(square::G1)) == 0u64,
+                    square::C1 => {
+                        self.color_to_move == WHITE && self.castle_white_queenside() && all_piece & (square(square::B1) | square(square::C1) | square(square::D1)) == 0u64
+                    }
+                    square::G8 => self.color_to_move == BLACK && self.castle_black_kingside() && all_piece & (square(square::F8) | square(square::G8)) == 0u64,
+                    square::C8 => {
+                        self.color_to_move == BLACK && self.castle_black_queenside() && all_piece & (square(square::B8) | square(square::C8) | square(square::D8)) == 0u64
+                    }
+                    _ => false,
+                };
         } else {
             let captured_piece = mv.get_maybe_captured_piece();
             if captured_piece.is_none() {
@@ -799,82 +864,50 @@ impl GameState {
                 return false;
             }
         }
-        let mut all_pieces = self.get_all_pieces();
-        match mv.piece_type {
-            PieceType::King => {
-                if self.square_attacked(mv.to as usize, all_pieces ^ square(mv.from as usize), 0u64)
-                    || mv.move_type != GameMoveType::Castle && square(mv.to as usize) & (KING_ATTACKS[mv.from as usize]) == 0u64
-                {
-                    return false;
-                }
-            }
-            PieceType::Bishop => {
-                if square(mv.to as usize) & (bishop_attack(mv.from as usize, all_pieces)) == 0u64 {
-                    return false;
-                }
-            }
-            PieceType::Rook => {
-                if square(mv.to as usize) & (rook_attack(mv.from as usize, all_pieces)) == 0u64 {
-                    return false;
-                }
-            }
-            PieceType::Queen => {
-                if square(mv.to as usize) & (bishop_attack(mv.from as usize, all_pieces) | rook_attack(mv.from as usize, all_pieces)) == 0u64 {
-                    return false;
-                }
-            }
-            PieceType::Knight => {
-                if square(mv.to as usize) & (KNIGHT_ATTACKS[mv.from as usize]) == 0u64 {
-                    return false;
-                }
-            }
+        let all_pieces = self.get_all_pieces();
+        let targets = match mv.piece_type {
+            PieceType::King => KING_ATTACKS[mv.from as usize],
+            PieceType::Knight => KNIGHT_ATTACKS[mv.from as usize],
+            PieceType::Bishop => bishop_attack(mv.from as usize, all_pieces),
+            PieceType::Rook => rook_attack(mv.from as usize, all_pieces),
+            PieceType::Queen => bishop_attack(mv.from as usize, all_pieces) | rook_attack(mv.from as usize, all_pieces),
             PieceType::Pawn => {
                 if mv.is_capture() {
-                    if (pawn_west_targets(self.color_to_move, square(mv.from as usize)) | pawn_east_targets(self.color_to_move, square(mv.from as usize))) & square(mv.to as usize)
-                        == 0u64
-                    {
-                        return false;
-                    }
-                } else if (single_push_pawn_targets(self.color_to_move, square(mv.from as usize), !all_pieces)
-                    | double_push_pawn_targets(self.color_to_move, square(mv.from as usize), !all_pieces))
-                    & square(mv.to as usize)
-                    == 0u64
-                {
-                    return false;
+                    pawn_west_targets(self.color_to_move, square(mv.from as usize)) | pawn_east_targets(self.color_to_move, square(mv.from as usize))
+                } else {
+                    single_push_pawn_targets(self.color_to_move, square(mv.from as usize), !all_pieces)
+                        | double_push_pawn_targets(self.color_to_move, square(mv.from as usize), !all_pieces)
                 }
             }
-        }
-        if mv.move_type != GameMoveType::Castle {
-            all_pieces ^= square(mv.from as usize);
-            all_pieces |= square(mv.to as usize);
-            let cap_piece = if mv.move_type == GameMoveType::EnPassant {
-                square((mv.to ^ 8) as usize)
-            } else {
-                square(mv.to as usize)
-            };
-            let king_square = if mv.piece_type == PieceType::King {
-                mv.to as usize
-            } else {
-                self.get_king_square(self.color_to_move)
+        };
+        targets & square(mv.to as usize) != 0u64
+    }
+
+    //Checks whether a pseudo legal move leaves our king in check
+    pub fn is_legal(&self, mv: GameMove) -> bool {
+        debug_assert!(self.is_pseudo_legal(mv));
+        let all_pieces = self.get_all_pieces();
+        if mv.move_type == GameMoveType::Castle {
+            let passed_squares = match mv.to as usize {
+                square::G1 => [square::F1, square::G1],
+                square::C1 => [square::D1, square::C1],
+                square::G8 => [square::F8, square::G8],
+                _ => [square::D8, square::C8],
             };
-            if bishop_attack(king_square, all_pieces) & self.get_bishop_like_bb(swap_side(self.color_to_move)) & !cap_piece != 0u64 {
-                return false;
-            }
-            if rook_attack(king_square, all_pieces) & self.get_rook_like_bb(swap_side(self.color_to_move)) & !cap_piece != 0u64 {
-                return false;
-            }
-            if KNIGHT_ATTACKS[king_square] & self.get_piece(PieceType::Knight, swap_side(self.color_to_move)) & !cap_piece != 0u64 {
-                return false;
-            }
-            if (pawn_east_targets(self.color_to_move, square(king_square)) | pawn_west_targets(self.color_to_move, square(king_square)))
-                & self.get_piece(PieceType::Pawn, swap_side(self.color_to_move))
-                & !cap_piece
-                > 0
-            {
-                return false;
-            }
+            return !self.in_check() && !self.square_attacked(passed_squares[0], all_pieces, 0u64) && !self.square_attacked(passed_squares[1], all_pieces, 0u64);
+        }
+        if mv.piece_type == PieceType::King {
+            return !self.square_attacked(mv.to as usize, all_pieces ^ square(mv.from as usize), 0u64);
         }
-        true
+        //The captured piece doesn't attack anymore. En passant also removes the captured pawn from the board, which might uncover an attack along the rank
+        let mut occ = all_pieces ^ square(mv.from as usize) | square(mv.to as usize);
+        let captured_piece = if mv.move_type == GameMoveType::EnPassant {
+            occ ^= square((mv.to ^ 8) as usize);
+            square((mv.to ^ 8) as usize)
+        } else {
+            square(mv.to as usize)
+        };
+        !self.square_attacked(self.get_king_square(self.color_to_move), occ, captured_piece)
     }
 }
 
@@ -959,6 +992,18 @@ pub const fn mirror_square(square: usize) -> usize {
     square ^ 56
 }
 
+//Mirrors the a-file to the h-file, the b-file to the g-file and so on
+#[inline(always)]
+pub const fn flip_square(square: usize) -> usize {
+    square ^ 7
+}
+
+//Bitboard of flip_square applied to every square
+#[inline(always)]
+pub const fn flip_horizontal_bb(bb: u64) -> u64 {
+    bb.reverse_bits().swap_bytes()
+}
+
 // Mirrors square if side is BLACK
 #[inline(always)]
 pub const fn white_pov(square: usize, side: usize) -> usize {
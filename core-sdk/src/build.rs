@@ -60,6 +60,10 @@ pub fn main() {
         previous_offset += patterns.len();
     }
     write!(file, "{}", arr_to_string(&attacks, "ATTACKS")).unwrap();
+
+    let kpk_path = Path::new(&out_dir).join("kpk_bitbase.rs");
+    let mut file = File::create(kpk_path).unwrap();
+    write!(file, "{}", arr_to_string(&generate_kpk_bitbase(), "KPK_BITBASE")).unwrap();
 }
 pub fn fill_table<F: Fn(u64) -> usize>(pattern: &Vec<(u64, u64)>, f: F) -> Option<Vec<u64>> {
     let mut result = vec![std::u64::MAX; pattern.len()];
@@ -204,3 +208,138 @@ pub fn bishop_attacks_slow(square: usize, blocks: u64) -> u64 {
     }
     res
 }
+
+//KPK bitbase, the strong side is white and its pawn is on files a-d. Classification works like in Stockfish:
+//positions are first classified by immediate rules and then by their successors until nothing changes anymore.
+pub const KPK_POSITIONS: usize = 2 * 24 * 64 * 64;
+const KPK_INVALID: u8 = 0;
+const KPK_UNKNOWN: u8 = 1;
+const KPK_DRAW: u8 = 2;
+const KPK_WIN: u8 = 4;
+
+pub fn kpk_index(side_to_move: usize, strong_king: usize, weak_king: usize, pawn: usize) -> usize {
+    side_to_move + 2 * (weak_king + 64 * (strong_king + 64 * (file_of(pawn) + 4 * (rank_of(pawn) - 1))))
+}
+
+pub fn kpk_decode(index: usize) -> (usize, usize, usize, usize) {
+    let side_to_move = index % 2;
+    let weak_king = (index / 2) % 64;
+    let strong_king = (index / 128) % 64;
+    let pawn_index = index / 8192;
+    (side_to_move, strong_king, weak_king, (pawn_index % 4) + 8 * (pawn_index / 4 + 1))
+}
+
+pub fn king_attacks_slow(square: usize) -> u64 {
+    let mut res = 0u64;
+    for rank in rank_of(square).max(1) - 1..(rank_of(square) + 2).min(8) {
+        for file in file_of(square).max(1) - 1..(file_of(square) + 2).min(8) {
+            if rank * 8 + file != square {
+                res |= 1u64 << (rank * 8 + file);
+            }
+        }
+    }
+    res
+}
+
+pub fn white_pawn_attacks_slow(square: usize) -> u64 {
+    let mut res = 0u64;
+    if file_of(square) > 0 {
+        res |= 1u64 << (square + 7);
+    }
+    if file_of(square) < 7 {
+        res |= 1u64 << (square + 9);
+    }
+    res
+}
+
+pub fn square_distance(sq: usize, sq2: usize) -> usize {
+    (rank_of(sq) as isize - rank_of(sq2) as isize)
+        .abs()
+        .max((file_of(sq) as isize - file_of(sq2) as isize).abs()) as usize
+}
+
+pub fn kpk_initial_classification(side_to_move: usize, strong_king: usize, weak_king: usize, pawn: usize) -> u8 {
+    if square_distance(strong_king, weak_king) <= 1 || strong_king == pawn || weak_king == pawn || (side_to_move == 0 && white_pawn_attacks_slow(pawn) & (1u64 << weak_king) != 0) {
+        KPK_INVALID
+    } else if side_to_move == 0
+        && rank_of(pawn) == 6
+        && strong_king != pawn + 8
+        && weak_king != pawn + 8
+        && (square_distance(weak_king, pawn + 8) > 1 || square_distance(strong_king, pawn + 8) == 1)
+    {
+        //The pawn promotes safely
+        KPK_WIN
+    } else if side_to_move == 1
+        && (king_attacks_slow(weak_king) & !(king_attacks_slow(strong_king) | white_pawn_attacks_slow(pawn)) == 0
+            || square_distance(weak_king, pawn) == 1 && square_distance(strong_king, pawn) > 1)
+    {
+        //Stalemate or the pawn can be captured
+        KPK_DRAW
+    } else {
+        KPK_UNKNOWN
+    }
+}
+
+pub fn kpk_classify(db: &[u8], side_to_move: usize, strong_king: usize, weak_king: usize, pawn: usize) -> u8 {
+    //A single good successor is enough for the side to move, White is good on KPK_WIN and Black on KPK_DRAW.
+    //Without any successors the position is bad for the side to move.
+    let (good, bad) = if side_to_move == 0 { (KPK_WIN, KPK_DRAW) } else { (KPK_DRAW, KPK_WIN) };
+    let mut result = KPK_INVALID;
+    if side_to_move == 0 {
+        let mut king_moves = king_attacks_slow(strong_king) & !king_attacks_slow(weak_king) & !(1u64 << pawn);
+        while king_moves != 0 {
+            let to = king_moves.trailing_zeros() as usize;
+            king_moves ^= 1u64 << to;
+            result |= db[kpk_index(1, to, weak_king, pawn)];
+        }
+        if rank_of(pawn) < 6 && pawn + 8 != strong_king && pawn + 8 != weak_king {
+            result |= db[kpk_index(1, strong_king, weak_king, pawn + 8)];
+            if rank_of(pawn) == 1 && pawn + 16 != strong_king && pawn + 16 != weak_king {
+                result |= db[kpk_index(1, strong_king, weak_king, pawn + 16)];
+            }
+        }
+    } else {
+        let mut king_moves = king_attacks_slow(weak_king) & !king_attacks_slow(strong_king) & !white_pawn_attacks_slow(pawn) & !(1u64 << pawn);
+        while king_moves != 0 {
+            let to = king_moves.trailing_zeros() as usize;
+            king_moves ^= 1u64 << to;
+            result |= db[kpk_index(0, strong_king, to, pawn)];
+        }
+    }
+    if result & good != 0 {
+        good
+    } else if result & KPK_UNKNOWN != 0 {
+        KPK_UNKNOWN
+    } else {
+        bad
+    }
+}
+
+pub fn generate_kpk_bitbase() -> Vec<u64> {
+    let mut db = vec![KPK_UNKNOWN; KPK_POSITIONS];
+    for (index, entry) in db.iter_mut().enumerate() {
+        let (side_to_move, strong_king, weak_king, pawn) = kpk_decode(index);
+        *entry = kpk_initial_classification(side_to_move, strong_king, weak_king, pawn);
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in 0..KPK_POSITIONS {
+            if db[index] == KPK_UNKNOWN {
+                let (side_to_move, strong_king, weak_king, pawn) = kpk_decode(index);
+                let result = kpk_classify(&db, side_to_move, strong_king, weak_king, pawn);
+                if result != KPK_UNKNOWN {
+                    db[index] = result;
+                    changed = true;
+                }
+            }
+        }
+    }
+    let mut bitbase = vec![0u64; KPK_POSITIONS / 64];
+    for (index, entry) in db.iter().enumerate() {
+        if *entry == KPK_WIN {
+            bitbase[index / 64] |= 1u64 << (index % 64);
+        }
+    }
+    bitbase
+}
@@ -0,0 +1,228 @@
+use crate::bitboards::bitboards::constants::FILES;
+use crate::bitboards::bitboards::square;
+use crate::board_representation::game_state::{file_of, rank_of, swap_side, white_pov, GameState, PieceType, BLACK, WHITE};
+use crate::evaluation::get_distance;
+use crate::evaluation::params::{BISHOP_PIECE_VALUE, KNIGHT_PIECE_VALUE, PAWN_PIECE_VALUE, QUEEN_PIECE_VALUE, ROOK_PIECE_VALUE};
+
+include!(concat!(env!("OUT_DIR"), "/kpk_bitbase.rs"));
+
+pub const KNOWN_WIN: i16 = 2000;
+//Scale factors are applied to the endgame part of the evaluation, SCALE_NORMAL leaves it untouched
+pub const SCALE_NORMAL: i16 = 64;
+pub const SCALE_DRAW: i16 = 0;
+
+const SIDE_MATERIAL_MASK: u64 = 0xFFFFF;
+const NON_PAWN_MASK: u64 = 0xFFFF0;
+const BISHOP_MASK: u64 = 0xF << (4 * PieceType::Bishop as usize);
+const ONE_BISHOP: u64 = 1 << (4 * PieceType::Bishop as usize);
+
+const PUSH_CLOSE: [i16; 8] = [0, 0, 50, 40, 30, 20, 10, 5];
+
+//Builds a material key from the amounts of pawns, knights, bishops, rooks and queens of both sides
+pub const fn material_signature(white: [u64; 5], black: [u64; 5]) -> u64 {
+    let mut res = 0u64;
+    let mut i = 0;
+    while i < 5 {
+        res += white[i] << (4 * i);
+        res += black[i] << (4 * (5 + i));
+        i += 1;
+    }
+    res
+}
+
+#[inline(always)]
+pub fn swap_material_key(material_key: u64) -> u64 {
+    (material_key >> 20) | ((material_key & SIDE_MATERIAL_MASK) << 20)
+}
+
+#[inline(always)]
+pub fn side_material(material_key: u64, side: usize) -> u64 {
+    (material_key >> (20 * side)) & SIDE_MATERIAL_MASK
+}
+
+//Specialised evaluations return the score from the strong side's point of view
+pub type EndgameEvaluation = fn(&GameState, usize) -> i16;
+
+pub struct Endgame {
+    pub name: &'static str,
+    //Material signature with the strong side playing white
+    pub signature: u64,
+    pub evaluate: EndgameEvaluation,
+}
+
+pub static ENDGAMES: [Endgame; 4] = [
+    Endgame {
+        name: "KPK",
+        signature: material_signature([1, 0, 0, 0, 0], [0, 0, 0, 0, 0]),
+        evaluate: kpk,
+    },
+    Endgame {
+        name: "KBNK",
+        signature: material_signature([0, 1, 1, 0, 0], [0, 0, 0, 0, 0]),
+        evaluate: kbnk,
+    },
+    Endgame {
+        name: "KRKP",
+        signature: material_signature([0, 0, 0, 1, 0], [1, 0, 0, 0, 0]),
+        evaluate: krkp,
+    },
+    Endgame {
+        name: "KQKR",
+        signature: material_signature([0, 0, 0, 0, 1], [0, 0, 0, 1, 0]),
+        evaluate: kqkr,
+    },
+];
+
+//Returns the endgame and its strong side for the material of the game state, if we have a specialised evaluation for it
+#[inline(always)]
+pub fn probe_endgame(g: &GameState) -> Option<(&'static Endgame, usize)> {
+    //All registered endgames have at most four pieces on the board
+    if g.get_all_pieces().count_ones() > 4 {
+        return None;
+    }
+    let material_key = g.get_material_key();
+    for endgame in ENDGAMES.iter() {
+        if endgame.signature == material_key {
+            return Some((endgame, WHITE));
+        } else if swap_material_key(endgame.signature) == material_key {
+            return Some((endgame, BLACK));
+        }
+    }
+    None
+}
+
+//Specialised evaluation from white's point of view
+pub fn evaluate_endgame(g: &GameState) -> Option<i16> {
+    probe_endgame(g).map(|(endgame, strong_side)| {
+        let score = (endgame.evaluate)(g, strong_side);
+        if strong_side == WHITE {
+            score
+        } else {
+            -score
+        }
+    })
+}
+
+#[inline(always)]
+pub fn is_dark_square(sq: usize) -> bool {
+    (file_of(sq) + rank_of(sq)) & 1 == 0
+}
+
+pub fn manhattan_distance(sq: usize, sq2: usize) -> usize {
+    ((file_of(sq) as isize - file_of(sq2) as isize).abs() + (rank_of(sq) as isize - rank_of(sq2) as isize).abs()) as usize
+}
+
+//Bonus for the weak king being close to an edge and even more so to a corner
+pub fn push_to_edge(sq: usize) -> i16 {
+    let file_distance = file_of(sq).min(7 - file_of(sq));
+    let rank_distance = rank_of(sq).min(7 - rank_of(sq));
+    10 * (6 - file_distance - rank_distance) as i16
+}
+
+pub fn kpk_probe(side_to_move: usize, strong_king: usize, weak_king: usize, pawn: usize) -> bool {
+    //The bitbase only covers pawns on files a-d, the other half is mirrored
+    let flip = if file_of(pawn) > 3 { 7 } else { 0 };
+    let index = side_to_move + 2 * ((weak_king ^ flip) + 64 * ((strong_king ^ flip) + 64 * (file_of(pawn ^ flip) + 4 * (rank_of(pawn) - 1))));
+    KPK_BITBASE[index / 64] & (1u64 << (index % 64)) != 0
+}
+
+pub fn kpk(g: &GameState, strong_side: usize) -> i16 {
+    let weak_side = swap_side(strong_side);
+    let pawn = white_pov(g.get_piece(PieceType::Pawn, strong_side).trailing_zeros() as usize, strong_side);
+    let win = kpk_probe(
+        (g.get_color_to_move() != strong_side) as usize,
+        white_pov(g.get_king_square(strong_side), strong_side),
+        white_pov(g.get_king_square(weak_side), strong_side),
+        pawn,
+    );
+    if win {
+        KNOWN_WIN + PAWN_PIECE_VALUE.1 + 10 * rank_of(pawn) as i16
+    } else {
+        0
+    }
+}
+
+//Mating with bishop and knight, the weak king has to be driven into a corner of the bishop's colour
+pub fn kbnk(g: &GameState, strong_side: usize) -> i16 {
+    let weak_king = g.get_king_square(swap_side(strong_side));
+    let bishop = g.get_piece(PieceType::Bishop, strong_side).trailing_zeros() as usize;
+    let corner_distance = if is_dark_square(bishop) {
+        manhattan_distance(weak_king, square::A1).min(manhattan_distance(weak_king, square::H8))
+    } else {
+        manhattan_distance(weak_king, square::A8).min(manhattan_distance(weak_king, square::H1))
+    };
+    KNOWN_WIN + KNIGHT_PIECE_VALUE.1 + BISHOP_PIECE_VALUE.1 + 20 * (14 - corner_distance) as i16 + PUSH_CLOSE[get_distance(g.get_king_square(strong_side), weak_king)]
+}
+
+//Rook against pawn, mostly a win unless the pawn is far advanced and supported by its king
+pub fn krkp(g: &GameState, strong_side: usize) -> i16 {
+    let weak_side = swap_side(strong_side);
+    let strong_king = white_pov(g.get_king_square(strong_side), strong_side);
+    let weak_king = white_pov(g.get_king_square(weak_side), strong_side);
+    let rook = white_pov(g.get_piece(PieceType::Rook, strong_side).trailing_zeros() as usize, strong_side);
+    let pawn = white_pov(g.get_piece(PieceType::Pawn, weak_side).trailing_zeros() as usize, strong_side);
+    let queening_square = file_of(pawn);
+    let strong_to_move = (g.get_color_to_move() == strong_side) as usize;
+    let weak_to_move = 1 - strong_to_move;
+    if file_of(strong_king) == file_of(pawn) && strong_king < pawn {
+        //The strong king stands in front of the pawn
+        ROOK_PIECE_VALUE.1 - get_distance(strong_king, pawn) as i16
+    } else if get_distance(weak_king, pawn) >= 3 + weak_to_move && get_distance(weak_king, rook) >= 3 {
+        //The weak king is too far away from its pawn and the rook
+        ROOK_PIECE_VALUE.1 - get_distance(strong_king, pawn) as i16
+    } else if rank_of(weak_king) <= 2 && get_distance(weak_king, pawn) == 1 && rank_of(strong_king) >= 3 && get_distance(strong_king, pawn) > 2 + strong_to_move {
+        //The pawn is far advanced and supported by its king
+        40 - 4 * get_distance(strong_king, pawn) as i16
+    } else {
+        100 - 4 * (get_distance(strong_king, pawn - 8) as i16 - get_distance(weak_king, pawn - 8) as i16 - get_distance(pawn, queening_square) as i16)
+    }
+}
+
+pub fn kqkr(g: &GameState, strong_side: usize) -> i16 {
+    let weak_king = g.get_king_square(swap_side(strong_side));
+    QUEEN_PIECE_VALUE.1 - ROOK_PIECE_VALUE.1 + push_to_edge(weak_king) + PUSH_CLOSE[get_distance(g.get_king_square(strong_side), weak_king)]
+}
+
+//Scale factor for the endgame part of the evaluation, given the side which is ahead and its passed pawns
+pub fn scale_factor(g: &GameState, strong_side: usize, strong_passers: u64) -> i16 {
+    let weak_side = swap_side(strong_side);
+    let strong_material = side_material(g.get_material_key(), strong_side);
+    let weak_material = side_material(g.get_material_key(), weak_side);
+    if strong_material & NON_PAWN_MASK == ONE_BISHOP {
+        if weak_material & NON_PAWN_MASK == 0 && strong_material != ONE_BISHOP && is_wrong_bishop_draw(g, strong_side) {
+            return SCALE_DRAW;
+        }
+        if weak_material & NON_PAWN_MASK == ONE_BISHOP && opposite_bishops(g) {
+            //Opposite coloured bishops are very drawish, unless the strong side has passers
+            return (16 + 8 * strong_passers.count_ones() as i16).min(SCALE_NORMAL);
+        }
+    } else if strong_material & NON_PAWN_MASK == weak_material & NON_PAWN_MASK && strong_material & BISHOP_MASK == ONE_BISHOP && opposite_bishops(g) {
+        //Opposite coloured bishops with other pieces still left are only slightly drawish
+        return 46;
+    }
+    SCALE_NORMAL
+}
+
+#[inline(always)]
+pub fn opposite_bishops(g: &GameState) -> bool {
+    let (white_bishop, black_bishop) = (g.get_piece(PieceType::Bishop, WHITE), g.get_piece(PieceType::Bishop, BLACK));
+    white_bishop.count_ones() == 1
+        && black_bishop.count_ones() == 1
+        && is_dark_square(white_bishop.trailing_zeros() as usize) != is_dark_square(black_bishop.trailing_zeros() as usize)
+}
+
+//All pawns on one rook file, the bishop doesn't control the queening square and the weak king can reach the corner
+pub fn is_wrong_bishop_draw(g: &GameState, strong_side: usize) -> bool {
+    let pawns = g.get_piece(PieceType::Pawn, strong_side);
+    let file = if pawns & !FILES[0] == 0 {
+        0
+    } else if pawns & !FILES[7] == 0 {
+        7
+    } else {
+        return false;
+    };
+    let queening_square = if strong_side == WHITE { 56 + file } else { file };
+    let bishop = g.get_piece(PieceType::Bishop, strong_side).trailing_zeros() as usize;
+    let weak_king = g.get_king_square(swap_side(strong_side));
+    is_dark_square(bishop) != is_dark_square(queening_square) && get_distance(weak_king, queening_square) <= 1
+}
@@ -0,0 +1,297 @@
+// Source of the weights of the handcrafted evaluation. The evaluation is generic over EvalParams, so with
+// CompiledParams every weight is still a constant from params.rs that the compiler can fold, while RuntimeParams
+// holds weights that were loaded from a file, e.g. to play tuned parameter sets against each other without recompiling.
+// Only the evaluation itself uses them: specialised endgames, the incrementally updated psqt of the game state
+// and the search keep using params.rs.
+//
+// Supported files:
+//   - A params.rs as written by Parameters (Display). Every constant of params.rs has to be present, the order
+//     doesn't matter and the numbers after the `=` of each constant are read in the order of the array.
+//   - A plain vector of numbers in the layout of Parameters, that is all midgame normal parameters, all endgame
+//     normal parameters and then all special parameters, separated by whitespace or commas.
+//     Json such as `[1, 2, ...]` or `{"normal": [[...], [...]], "special": [...]}` is read the same way.
+use super::parameters::{normal_parameters::NORMAL_PARAMS, special_parameters::SPECIAL_PARAMS, Parameters};
+use super::params::*;
+use super::EvaluationScore;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+//Flattened view of a (nested array of) weights, an EvaluationScore consists of its midgame and endgame value
+pub trait ParamValues {
+    const LEN: usize;
+    fn set(&mut self, values: &[f64]);
+}
+impl ParamValues for f64 {
+    const LEN: usize = 1;
+    fn set(&mut self, values: &[f64]) {
+        *self = values[0];
+    }
+}
+impl ParamValues for EvaluationScore {
+    const LEN: usize = 2;
+    fn set(&mut self, values: &[f64]) {
+        *self = EvaluationScore(values[0].round() as i16, values[1].round() as i16);
+    }
+}
+impl<T: ParamValues, const N: usize> ParamValues for [T; N] {
+    const LEN: usize = N * T::LEN;
+    fn set(&mut self, values: &[f64]) {
+        for (i, value) in self.iter_mut().enumerate() {
+            value.set(&values[i * T::LEN..(i + 1) * T::LEN]);
+        }
+    }
+}
+
+macro_rules! eval_params {
+    (scalars { $($scalar:ident: $scalar_constant:ident: $scalar_ty:ty,)* } tables { $($table:ident: $table_constant:ident: $table_ty:ty,)* }) => {
+        pub trait EvalParams {
+            //Whether the psqt score of the game state, which is computed from params.rs, can be used
+            const INCREMENTAL_PSQT: bool;
+            $(fn $scalar(&self) -> $scalar_ty;)*
+            $(fn $table(&self) -> &$table_ty;)*
+        }
+
+        //The constants of params.rs
+        pub struct CompiledParams;
+
+        impl EvalParams for CompiledParams {
+            const INCREMENTAL_PSQT: bool = true;
+            $(
+                #[inline(always)]
+                fn $scalar(&self) -> $scalar_ty {
+                    $scalar_constant
+                }
+            )*
+            $(
+                #[inline(always)]
+                fn $table(&self) -> &$table_ty {
+                    &$table_constant
+                }
+            )*
+        }
+
+        //Tables are boxed, the king piece table alone is larger than 300KB
+        #[derive(Clone)]
+        pub struct RuntimeParams {
+            $(pub $scalar: $scalar_ty,)*
+            $(pub $table: Box<$table_ty>,)*
+        }
+
+        impl EvalParams for RuntimeParams {
+            const INCREMENTAL_PSQT: bool = false;
+            $(
+                #[inline(always)]
+                fn $scalar(&self) -> $scalar_ty {
+                    self.$scalar
+                }
+            )*
+            $(
+                #[inline(always)]
+                fn $table(&self) -> &$table_ty {
+                    &self.$table
+                }
+            )*
+        }
+
+        impl Default for RuntimeParams {
+            fn default() -> Self {
+                RuntimeParams {
+                    $($scalar: $scalar_constant,)*
+                    $($table: Box::new($table_constant),)*
+                }
+            }
+        }
+
+        impl RuntimeParams {
+            fn from_constants(constants: &HashMap<&str, Vec<f64>>) -> std::result::Result<RuntimeParams, String> {
+                let mut params = RuntimeParams::default();
+                $(set_constant(&mut params.$scalar, stringify!($scalar_constant), constants)?;)*
+                $(set_constant(&mut *params.$table, stringify!($table_constant), constants)?;)*
+                Ok(params)
+            }
+        }
+    };
+}
+
+eval_params! {
+    scalars {
+        slightly_winning_no_pawn: SLIGHTLY_WINNING_NO_PAWN: f64,
+        slightly_winning_enemy_can_sac: SLIGHTLY_WINNING_ENEMY_CAN_SAC: f64,
+        tempo_bonus: TEMPO_BONUS: EvaluationScore,
+        pawn_doubled_value: PAWN_DOUBLED_VALUE: EvaluationScore,
+        pawn_isolated_value: PAWN_ISOLATED_VALUE: EvaluationScore,
+        pawn_backward_value: PAWN_BACKWARD_VALUE: EvaluationScore,
+        pawn_attack_center: PAWN_ATTACK_CENTER: EvaluationScore,
+        pawn_mobility: PAWN_MOBILITY: EvaluationScore,
+        rook_behind_support_passer: ROOK_BEHIND_SUPPORT_PASSER: EvaluationScore,
+        rook_behind_enemy_passer: ROOK_BEHIND_ENEMY_PASSER: EvaluationScore,
+        pawn_passed_weak: PAWN_PASSED_WEAK: EvaluationScore,
+        knight_supported_by_pawn: KNIGHT_SUPPORTED_BY_PAWN: EvaluationScore,
+        rook_on_open_file_bonus: ROOK_ON_OPEN_FILE_BONUS: EvaluationScore,
+        rook_on_semi_open_file_bonus: ROOK_ON_SEMI_OPEN_FILE_BONUS: EvaluationScore,
+        queen_on_open_file_bonus: QUEEN_ON_OPEN_FILE_BONUS: EvaluationScore,
+        queen_on_semi_open_file_bonus: QUEEN_ON_SEMI_OPEN_FILE_BONUS: EvaluationScore,
+        rook_on_seventh: ROOK_ON_SEVENTH: EvaluationScore,
+        pawn_piece_value: PAWN_PIECE_VALUE: EvaluationScore,
+        knight_piece_value: KNIGHT_PIECE_VALUE: EvaluationScore,
+        bishop_piece_value: BISHOP_PIECE_VALUE: EvaluationScore,
+        bishop_pair_bonus: BISHOP_PAIR_BONUS: EvaluationScore,
+        rook_piece_value: ROOK_PIECE_VALUE: EvaluationScore,
+        queen_piece_value: QUEEN_PIECE_VALUE: EvaluationScore,
+        knight_attack_worth: KNIGHT_ATTACK_WORTH: EvaluationScore,
+        bishop_attack_worth: BISHOP_ATTACK_WORTH: EvaluationScore,
+        rook_attack_worth: ROOK_ATTACK_WORTH: EvaluationScore,
+        queen_attack_worth: QUEEN_ATTACK_WORTH: EvaluationScore,
+        knight_safe_check: KNIGHT_SAFE_CHECK: EvaluationScore,
+        bishop_safe_check: BISHOP_SAFE_CHECK: EvaluationScore,
+        rook_safe_check: ROOK_SAFE_CHECK: EvaluationScore,
+        queen_safe_check: QUEEN_SAFE_CHECK: EvaluationScore,
+    }
+    tables {
+        shielding_pawn_missing: SHIELDING_PAWN_MISSING: [EvaluationScore; 4],
+        shielding_pawn_missing_on_open_file: SHIELDING_PAWN_MISSING_ON_OPEN_FILE: [EvaluationScore; 4],
+        pawn_supported_value: PAWN_SUPPORTED_VALUE: [[EvaluationScore; 64]; 2],
+        pawn_passed_values: PAWN_PASSED_VALUES: [EvaluationScore; 7],
+        pawn_passed_not_blocked_values: PAWN_PASSED_NOT_BLOCKED_VALUES: [EvaluationScore; 7],
+        passed_king_distance: PASSED_KING_DISTANCE: [EvaluationScore; 7],
+        passed_enemy_king_distance: PASSED_ENEMY_KING_DISTANCE: [EvaluationScore; 7],
+        passed_subtract_distance: PASSED_SUBTRACT_DISTANCE: [EvaluationScore; 13],
+        knight_outpost_table: KNIGHT_OUTPOST_TABLE: [[EvaluationScore; 64]; 2],
+        knight_value_with_pawns: KNIGHT_VALUE_WITH_PAWNS: [EvaluationScore; 17],
+        diagonally_adjacent_squares_with_own_pawns: DIAGONALLY_ADJACENT_SQUARES_WITH_OWN_PAWNS: [EvaluationScore; 5],
+        knight_mobility_bonus: KNIGHT_MOBILITY_BONUS: [EvaluationScore; 9],
+        bishop_mobility_bonus: BISHOP_MOBILITY_BONUS: [EvaluationScore; 14],
+        rook_mobility_bonus: ROOK_MOBILITY_BONUS: [EvaluationScore; 15],
+        queen_mobility_bonus: QUEEN_MOBILITY_BONUS: [EvaluationScore; 28],
+        attack_weight: ATTACK_WEIGHT: [EvaluationScore; 8],
+        safety_table: SAFETY_TABLE: [EvaluationScore; 100],
+        king_piece_table: KING_PIECE_TABLE: [[[[[EvaluationScore; 64]; 5]; 2]; 64]; 2],
+        psqt: PSQT: [[[EvaluationScore; 64]; 2]; 6],
+    }
+}
+
+fn set_constant<T: ParamValues>(value: &mut T, name: &str, constants: &HashMap<&str, Vec<f64>>) -> std::result::Result<(), String> {
+    let values = constants.get(name).ok_or_else(|| format!("Missing constant {}", name))?;
+    if values.len() != T::LEN {
+        return Err(format!("Constant {} has {} values, expected {}", name, values.len(), T::LEN));
+    }
+    value.set(values);
+    Ok(())
+}
+
+//All numbers in text, words such as EvaluationScore or json keys are skipped
+fn numbers(text: &str) -> Vec<f64> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+' || c == 'e' || c == 'E'))
+        .filter_map(|token| token.parse::<f64>().ok())
+        .collect()
+}
+
+impl RuntimeParams {
+    pub fn load(path: &str) -> Result<RuntimeParams> {
+        RuntimeParams::parse(&fs::read_to_string(path)?).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    pub fn parse(text: &str) -> std::result::Result<RuntimeParams, String> {
+        if text.contains("pub const") {
+            RuntimeParams::parse_constants(text)
+        } else {
+            RuntimeParams::parse_vector(text)
+        }
+    }
+
+    //A params.rs, see the top of this file
+    pub fn parse_constants(text: &str) -> std::result::Result<RuntimeParams, String> {
+        let mut constants = HashMap::new();
+        for constant in text.split("pub const").skip(1) {
+            let name = constant.split(':').next().unwrap_or("").trim();
+            let (_, values) = constant.split_once('=').ok_or_else(|| format!("Constant {} has no value", name))?;
+            //The value ends at the semicolon, attributes of the following constant come after it
+            constants.insert(name, numbers(values.split(';').next().unwrap_or("")));
+        }
+        RuntimeParams::from_constants(&constants)
+    }
+
+    //A vector in the layout of Parameters, see the top of this file
+    pub fn parse_vector(text: &str) -> std::result::Result<RuntimeParams, String> {
+        let values = numbers(text);
+        if values.len() != 2 * NORMAL_PARAMS + SPECIAL_PARAMS {
+            return Err(format!(
+                "Parameter vector has {} values, expected {} (2 * {} normal and {} special parameters)",
+                values.len(),
+                2 * NORMAL_PARAMS + SPECIAL_PARAMS,
+                NORMAL_PARAMS,
+                SPECIAL_PARAMS
+            ));
+        }
+        let mut params = Box::new(Parameters::zero());
+        params.normal[0].copy_from_slice(&values[..NORMAL_PARAMS]);
+        params.normal[1].copy_from_slice(&values[NORMAL_PARAMS..2 * NORMAL_PARAMS]);
+        params.special.copy_from_slice(&values[2 * NORMAL_PARAMS..]);
+        Ok(RuntimeParams::from_parameters(&params))
+    }
+
+    //Weights are rounded the same way as in the params.rs the tuner writes
+    pub fn from_parameters(params: &Parameters) -> RuntimeParams {
+        RuntimeParams::parse_constants(&format!("{}", params)).expect("Parameters wrote an invalid params.rs")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board_representation::game_state::GameState;
+    use crate::evaluation::{eval_game_state, eval_game_state_with_params};
+
+    const POSITIONS: [&str; 6] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "3r1r1k/pb2b3/1p1q3p/1Pnp1pp1/P7/1QN1PN2/5PPP/1R1R1BK1 w - - 0 21",
+        "r1q1kr2/1bp1n1np/p7/1p3pp1/3N4/NP2R1P1/3PP1BP/R4QK1 w q - 2 21",
+        "8/p4p2/1p2p3/3kP1Nn/5P2/3K4/P7/8 w - - 0 1",
+        "7R/8/1k6/1p6/1K6/8/8/2r5 b - - 0 1",
+        "2kr1b1r/pp4p1/4qn1p/8/3p3P/6N1/PPPB2P1/2KRR3 w - - 0 21",
+    ];
+
+    fn assert_same_evaluation(params: &RuntimeParams) {
+        for fen in POSITIONS.iter() {
+            let state = GameState::from_fen(fen);
+            assert_eq!(eval_game_state(&state).final_eval, eval_game_state_with_params(&state, params).final_eval, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn runtime_params_test() {
+        //Parameters are too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(32 * 1024 * 1024)
+            .spawn(|| {
+                assert_same_evaluation(&RuntimeParams::parse(include_str!("params.rs")).unwrap());
+                let params = Parameters::default();
+                assert_same_evaluation(&RuntimeParams::from_parameters(&params));
+
+                let vector = params.normal[0]
+                    .iter()
+                    .chain(params.normal[1].iter())
+                    .chain(params.special.iter())
+                    .map(|v| v.to_string())
+                    .collect::<Vec<String>>();
+                assert_same_evaluation(&RuntimeParams::parse(&vector.join(" ")).unwrap());
+                let json = format!(
+                    "{{\"normal\": [[{}], [{}]], \"special\": [{}]}}",
+                    vector[..NORMAL_PARAMS].join(", "),
+                    vector[NORMAL_PARAMS..2 * NORMAL_PARAMS].join(", "),
+                    vector[2 * NORMAL_PARAMS..].join(", ")
+                );
+                assert_same_evaluation(&RuntimeParams::parse(&json).unwrap());
+
+                let mut tuned = params.clone();
+                tuned.normal[0][0] += 100.;
+                assert_eq!(RuntimeParams::from_parameters(&tuned).tempo_bonus, TEMPO_BONUS + EvaluationScore(100, 0));
+                assert!(RuntimeParams::parse(&vector[1..].join(" ")).is_err());
+                assert!(RuntimeParams::parse("pub const TEMPO_BONUS: EvaluationScore = EvaluationScore(1, 2);").is_err());
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
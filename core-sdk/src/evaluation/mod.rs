@@ -1,3 +1,6 @@
+pub mod endgame;
+pub mod eval_params;
+pub mod nnue;
 pub mod parameters;
 pub mod params;
 pub mod phase;
@@ -9,6 +12,7 @@ use crate::bitboards::bitboards::*;
 use crate::board_representation::game_state::{file_of, rank_of, relative_rank, swap_side, GameState, PieceType, BLACK, WHITE};
 use crate::move_generation::movegen;
 use crate::move_generation::movegen::{pawn_east_targets, pawn_targets, pawn_west_targets};
+use eval_params::{CompiledParams, EvalParams};
 
 #[cfg(feature = "tuning")]
 use crate::board_representation::game_state::white_pov;
@@ -16,7 +20,6 @@ use crate::board_representation::game_state::white_pov;
 use crate::evaluation::parameters::normal_parameters::*;
 #[cfg(feature = "tuning")]
 use crate::evaluation::trace::{trace_count, LargeTrace};
-use params::*;
 use psqt_evaluation::psqt;
 use std::fmt::{Debug, Display, Formatter, Result};
 use std::ops;
@@ -101,6 +104,10 @@ pub struct EvaluationResult {
 }
 
 pub fn eval_game_state(g: &GameState) -> EvaluationResult {
+    eval_game_state_with_params(g, &CompiledParams)
+}
+
+pub fn eval_game_state_with_params<P: EvalParams>(g: &GameState, params: &P) -> EvaluationResult {
     #[cfg(feature = "display-eval")]
     {
         println!("Evaluating GameState fen: {}", g.to_fen());
@@ -110,6 +117,18 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
         #[cfg(feature = "tuning")]
         trace: LargeTrace::default(),
     };
+    //Specialised endgame evaluations can't be expressed in the trace, so the tuner doesn't use them
+    if !cfg!(feature = "tuning") {
+        if let Some((endgame, strong_side)) = endgame::probe_endgame(g) {
+            let score = (endgame.evaluate)(g, strong_side);
+            result.final_eval = if strong_side == WHITE { score } else { -score };
+            #[cfg(feature = "display-eval")]
+            {
+                println!("\nSpecialised endgame {}: {}", endgame.name, result.final_eval);
+            }
+            return result;
+        }
+    }
     if is_guaranteed_draw(&g) {
         #[cfg(feature = "tuning")]
         {
@@ -119,7 +138,7 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
     }
     let mut res = EvaluationScore::default();
 
-    let tempo = if g.get_color_to_move() == WHITE { TEMPO_BONUS } else { TEMPO_BONUS * -1 };
+    let tempo = if g.get_color_to_move() == WHITE { params.tempo_bonus() } else { params.tempo_bonus() * -1 };
     res += tempo;
     #[cfg(feature = "display-eval")]
     {
@@ -136,17 +155,19 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
     let (black_defended_by_minors, black_defended_by_majors) = (g.get_minor_attacks_from_side(BLACK), g.get_major_attacks_from_side(BLACK));
     let black_defended = black_defended_by_minors | black_defended_by_majors | KING_ATTACKS[g.get_king_square(BLACK)];
 
-    let psqt_score: EvaluationScore = if cfg!(feature = "display-eval") || cfg!(feature = "tuning") {
+    let psqt_score: EvaluationScore = if cfg!(feature = "display-eval") || cfg!(feature = "tuning") || !P::INCREMENTAL_PSQT {
         let (psqt_w, psqt_b) = (
             psqt(
                 &g,
                 WHITE,
+                params,
                 #[cfg(feature = "tuning")]
                 &mut result.trace,
             ),
             psqt(
                 &g,
                 BLACK,
+                params,
                 #[cfg(feature = "tuning")]
                 &mut result.trace,
             ),
@@ -165,12 +186,14 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
         piece_values(
             WHITE,
             g,
+            params,
             #[cfg(feature = "tuning")]
             &mut result.trace,
         ),
         piece_values(
             BLACK,
             g,
+            params,
             #[cfg(feature = "tuning")]
             &mut result.trace,
         ),
@@ -181,20 +204,30 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
     }
     res += pieces_w - pieces_b;
 
+    let structure = pawn_structure(
+        g,
+        params,
+        #[cfg(feature = "tuning")]
+        &mut result.trace,
+    );
     let (pawns_w, pawns_b) = (
         pawns(
             WHITE,
             g,
+            params,
             white_defended,
             black_defended,
+            &structure,
             #[cfg(feature = "tuning")]
             &mut result.trace,
         ),
         pawns(
             BLACK,
             g,
+            params,
             black_defended,
             white_defended,
+            &structure,
             #[cfg(feature = "tuning")]
             &mut result.trace,
         ),
@@ -209,12 +242,14 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
         knights(
             WHITE,
             g,
+            params,
             #[cfg(feature = "tuning")]
             &mut result.trace,
         ),
         knights(
             BLACK,
             g,
+            params,
             #[cfg(feature = "tuning")]
             &mut result.trace,
         ),
@@ -229,6 +264,7 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
         piecewise(
             WHITE,
             g,
+            params,
             black_defended_by_minors,
             black_defended,
             #[cfg(feature = "tuning")]
@@ -237,6 +273,7 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
         piecewise(
             BLACK,
             g,
+            params,
             white_defended_by_minors,
             white_defended,
             #[cfg(feature = "tuning")]
@@ -253,12 +290,14 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
         king(
             WHITE,
             g,
+            params,
             #[cfg(feature = "tuning")]
             &mut result.trace,
         ),
         king(
             BLACK,
             g,
+            params,
             #[cfg(feature = "tuning")]
             &mut result.trace,
         ),
@@ -276,12 +315,24 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
     }
     endgame_rescaling(
         g,
+        params,
         &mut res,
         phase,
         (pawns_w, pawns_b),
         #[cfg(feature = "tuning")]
         &mut result.trace,
     );
+    if !cfg!(feature = "tuning") && res.1 != 0 {
+        let strong_side = if res.1 > 0 { WHITE } else { BLACK };
+        let scale = endgame::scale_factor(g, strong_side, structure.passed_pawns[strong_side]);
+        if scale != endgame::SCALE_NORMAL {
+            res.1 = (i32::from(res.1) * i32::from(scale) / i32::from(endgame::SCALE_NORMAL)) as i16;
+            #[cfg(feature = "display-eval")]
+            {
+                println!("\nEndgame scale factor: {}/{}", scale, endgame::SCALE_NORMAL);
+            }
+        }
+    }
 
     //Phasing is done the same way stockfish does it
     result.final_eval = res.interpolate(phase);
@@ -295,7 +346,7 @@ pub fn eval_game_state(g: &GameState) -> EvaluationResult {
             king_w - king_b,
             pawns_w - pawns_b,
             pieces_w - pieces_b,
-            if g.get_color_to_move() == 0 { TEMPO_BONUS } else { TEMPO_BONUS * -1 },
+            if g.get_color_to_move() == 0 { params.tempo_bonus() } else { params.tempo_bonus() * -1 },
             res
         );
         println!("Phase: {}", phase);
@@ -323,7 +374,14 @@ pub fn is_guaranteed_draw(g: &GameState) -> bool {
     false
 }
 
-pub fn endgame_rescaling(g: &GameState, res: &mut EvaluationScore, phase: f32, pawn_eval: (EvaluationScore, EvaluationScore), #[cfg(feature = "tuning")] trace: &mut LargeTrace) {
+pub fn endgame_rescaling<P: EvalParams>(
+    g: &GameState,
+    params: &P,
+    res: &mut EvaluationScore,
+    phase: f32,
+    pawn_eval: (EvaluationScore, EvaluationScore),
+    #[cfg(feature = "tuning")] trace: &mut LargeTrace,
+) {
     let score = res.interpolate(phase);
     if score == 0{
         return;
@@ -334,21 +392,21 @@ pub fn endgame_rescaling(g: &GameState, res: &mut EvaluationScore, phase: f32, p
     if winning_pawns <= 1 {
         let losing_minors = (g.get_piece(PieceType::Bishop, side_losing) | g.get_piece(PieceType::Knight, side_losing)).count_ones() as usize;
         let score = score.abs();
-        let knight_value = KNIGHT_PIECE_VALUE.1;
-        let pawn_value = PAWN_PIECE_VALUE.1;
+        let knight_value = params.knight_piece_value().1;
+        let pawn_value = params.pawn_piece_value().1;
         let pawn_evaluation = if side_ahead == WHITE { pawn_eval.0 } else { pawn_eval.1 }.1.max(0);
         let threshold = knight_value + (1.5f64 * pawn_value as f64) as i16;
         let winnable_ahead = score.abs() >= threshold;
 
         if !winnable_ahead && (winning_pawns == 0) {
-            let factor = SLIGHTLY_WINNING_NO_PAWN;
+            let factor = params.slightly_winning_no_pawn();
             *res = EvaluationScore(res.0, (res.1 as f64 * factor) as i16);
             #[cfg(feature = "tuning")]
             {
                 trace.slightly_winning_no_pawn = true;
             }
         } else if !winnable_ahead && losing_minors >= 1 && score.abs() + knight_value - pawn_value - pawn_evaluation <= threshold {
-            let factor = SLIGHTLY_WINNING_ENEMY_CAN_SAC;
+            let factor = params.slightly_winning_enemy_can_sac();
             *res = EvaluationScore(res.0, (res.1 as f64 * factor) as i16);
             #[cfg(feature = "tuning")]
             {
@@ -358,14 +416,14 @@ pub fn endgame_rescaling(g: &GameState, res: &mut EvaluationScore, phase: f32, p
     }
 }
 
-pub fn knights(side: usize, g: &GameState, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
+pub fn knights<P: EvalParams>(side: usize, g: &GameState, params: &P, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
     let mut res = EvaluationScore::default();
 
     let my_pawn_attacks = pawn_targets(side, g.get_piece(PieceType::Pawn, side));
 
     let supported_knights = g.get_piece(PieceType::Knight, side) & my_pawn_attacks;
     let supported_knights_amount = supported_knights.count_ones() as i16;
-    res += KNIGHT_SUPPORTED_BY_PAWN * supported_knights_amount;
+    res += params.knight_supported_by_pawn() * supported_knights_amount;
     #[cfg(feature = "tuning")]
     {
         trace.add(IDX_KNIGHT_SUPPORTED, supported_knights_amount as i8 * trace_count(side));
@@ -380,7 +438,7 @@ pub fn knights(side: usize, g: &GameState, #[cfg(feature = "tuning")] trace: &mu
         front_span = west_one(front_span) | east_one(front_span);
         if g.get_piece(PieceType::Pawn, swap_side(side)) & front_span == 0u64 {
             _outposts += 1;
-            outpost += KNIGHT_OUTPOST_TABLE[side][idx];
+            outpost += params.knight_outpost_table()[side][idx];
             #[cfg(feature = "tuning")]
             {
                 trace.add(IDX_KNIGHT_OUTPOST_TABLE + white_pov(idx, side), trace_count(side));
@@ -394,7 +452,7 @@ pub fn knights(side: usize, g: &GameState, #[cfg(feature = "tuning")] trace: &mu
         println!(
             "\tSupported by pawns: {} -> {}",
             supported_knights_amount,
-            KNIGHT_SUPPORTED_BY_PAWN * supported_knights_amount,
+            params.knight_supported_by_pawn() * supported_knights_amount,
         );
         println!("\tOutposts: {} -> {}", _outposts, outpost);
         println!("Sum: {}", res);
@@ -403,7 +461,14 @@ pub fn knights(side: usize, g: &GameState, #[cfg(feature = "tuning")] trace: &mu
     res
 }
 
-pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_defended: u64, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
+pub fn piecewise<P: EvalParams>(
+    side: usize,
+    g: &GameState,
+    params: &P,
+    enemy_defend_by_minors: u64,
+    enemy_defended: u64,
+    #[cfg(feature = "tuning")] trace: &mut LargeTrace,
+) -> EvaluationScore {
     let defended_by_minors = enemy_defend_by_minors;
     let defended_squares = enemy_defended;
     let my_pieces = g.get_pieces_from_side(side);
@@ -425,16 +490,16 @@ pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_
         let targets = PieceType::Knight.attacks(idx, all_pieces) & !my_pieces;
 
         let mobility = targets.count_ones() as usize;
-        mk += KNIGHT_MOBILITY_BONUS[mobility];
+        mk += params.knight_mobility_bonus()[mobility];
 
         let has_safe_check = (targets & knight_checks & !defended_squares) != 0u64;
         let enemy_king_attacks = targets & enemy_king_attackable;
         if has_safe_check || enemy_king_attacks != 0u64 {
             knight_attackers += 1;
         }
-        knight_attacker_values += KNIGHT_ATTACK_WORTH * enemy_king_attacks.count_ones() as i16;
+        knight_attacker_values += params.knight_attack_worth() * enemy_king_attacks.count_ones() as i16;
         if has_safe_check {
-            knight_attacker_values += KNIGHT_SAFE_CHECK;
+            knight_attacker_values += params.knight_safe_check();
         }
         #[cfg(feature = "tuning")]
         {
@@ -455,20 +520,20 @@ pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_
         let idx = bishops.trailing_zeros() as usize;
         let bishop_attack = PieceType::Bishop.attacks(idx, all_pieces ^ square(enemy_king_idx));
         let diagonally_adjacent_pawns = (DIAGONALLY_ADJACENT[idx] & g.get_piece(PieceType::Pawn, side)).count_ones() as usize;
-        mb_diag += DIAGONALLY_ADJACENT_SQUARES_WITH_OWN_PAWNS[diagonally_adjacent_pawns];
+        mb_diag += params.diagonally_adjacent_squares_with_own_pawns()[diagonally_adjacent_pawns];
 
         let targets = bishop_attack & !my_pieces;
         let mobility = targets.count_ones() as usize;
-        mb += BISHOP_MOBILITY_BONUS[mobility];
+        mb += params.bishop_mobility_bonus()[mobility];
 
         let has_safe_check = (targets & bishop_checks & !defended_squares) != 0u64;
         let enemy_king_attacks = targets & enemy_king_attackable;
         if has_safe_check || enemy_king_attacks != 0u64 {
             bishop_attackers += 1;
         }
-        bishop_attacker_values += BISHOP_ATTACK_WORTH * enemy_king_attacks.count_ones() as i16;
+        bishop_attacker_values += params.bishop_attack_worth() * enemy_king_attacks.count_ones() as i16;
         if has_safe_check {
-            bishop_attacker_values += BISHOP_SAFE_CHECK;
+            bishop_attacker_values += params.bishop_safe_check();
         }
         #[cfg(feature = "tuning")]
         {
@@ -501,16 +566,16 @@ pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_
         let targets = rook_attack & !my_pieces;
 
         let mobility = targets.count_ones() as usize;
-        mr += ROOK_MOBILITY_BONUS[mobility];
+        mr += params.rook_mobility_bonus()[mobility];
 
         let has_safe_check = (targets & rook_checks & !defended_squares) != 0u64;
         let enemy_king_attacks = targets & enemy_king_attackable;
         if has_safe_check || enemy_king_attacks != 0u64 {
             rook_attackers += 1;
         }
-        rook_attacker_values += ROOK_ATTACK_WORTH * enemy_king_attacks.count_ones() as i16;
+        rook_attacker_values += params.rook_attack_worth() * enemy_king_attacks.count_ones() as i16;
         if has_safe_check {
-            rook_attacker_values += ROOK_SAFE_CHECK;
+            rook_attacker_values += params.rook_safe_check();
         }
         #[cfg(feature = "tuning")]
         {
@@ -544,16 +609,16 @@ pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_
         let targets = queen_attack & !my_pieces;
 
         let mobility = targets.count_ones() as usize;
-        mq += QUEEN_MOBILITY_BONUS[mobility];
+        mq += params.queen_mobility_bonus()[mobility];
 
         let has_safe_check = (targets & (bishop_checks | rook_checks) & !defended_squares) != 0u64;
         let enemy_king_attacks = targets & enemy_king_attackable;
         if has_safe_check || enemy_king_attacks != 0u64 {
             queen_attackers += 1;
         }
-        queen_attacker_values += QUEEN_ATTACK_WORTH * enemy_king_attacks.count_ones() as i16;
+        queen_attacker_values += params.queen_attack_worth() * enemy_king_attacks.count_ones() as i16;
         if has_safe_check {
-            queen_attacker_values += QUEEN_SAFE_CHECK;
+            queen_attacker_values += params.queen_safe_check();
         }
 
         #[cfg(feature = "tuning")]
@@ -575,11 +640,11 @@ pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_
         trace.add(IDX_QUEEN_ON_SEMI_OPEN, queens_on_semi_open as i8 * trace_count(side));
     }
 
-    let attack_mg = ((SAFETY_TABLE[(knight_attacker_values.0 + bishop_attacker_values.0 + rook_attacker_values.0 + queen_attacker_values.0).min(99) as usize].0 as isize
-        * ATTACK_WEIGHT[(knight_attackers + bishop_attackers + rook_attackers + queen_attackers).min(7) as usize].0 as isize) as f64
+    let attack_mg = ((params.safety_table()[(knight_attacker_values.0 + bishop_attacker_values.0 + rook_attacker_values.0 + queen_attacker_values.0).min(99) as usize].0 as isize
+        * params.attack_weight()[(knight_attackers + bishop_attackers + rook_attackers + queen_attackers).min(7) as usize].0 as isize) as f64
         / 100.0) as i16;
-    let attack_eg = ((SAFETY_TABLE[(knight_attacker_values.1 + bishop_attacker_values.1 + rook_attacker_values.1 + queen_attacker_values.1).min(99) as usize].1 as isize
-        * ATTACK_WEIGHT[(knight_attackers + bishop_attackers + rook_attackers + queen_attackers).min(7) as usize].1 as isize) as f64
+    let attack_eg = ((params.safety_table()[(knight_attacker_values.1 + bishop_attacker_values.1 + rook_attacker_values.1 + queen_attacker_values.1).min(99) as usize].1 as isize
+        * params.attack_weight()[(knight_attackers + bishop_attackers + rook_attackers + queen_attackers).min(7) as usize].1 as isize) as f64
         / 100.0) as i16;
     let attack = EvaluationScore(attack_mg, attack_eg);
     #[cfg(feature = "tuning")]
@@ -592,11 +657,11 @@ pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_
         + mr
         + mq
         + mb_diag
-        + ROOK_ON_OPEN_FILE_BONUS * rooks_onopen
-        + ROOK_ON_SEMI_OPEN_FILE_BONUS * rooks_on_semi_open
-        + ROOK_ON_SEVENTH * rooks_onseventh
-        + QUEEN_ON_OPEN_FILE_BONUS * queens_onopen
-        + QUEEN_ON_SEMI_OPEN_FILE_BONUS * queens_on_semi_open
+        + params.rook_on_open_file_bonus() * rooks_onopen
+        + params.rook_on_semi_open_file_bonus() * rooks_on_semi_open
+        + params.rook_on_seventh() * rooks_onseventh
+        + params.queen_on_open_file_bonus() * queens_onopen
+        + params.queen_on_semi_open_file_bonus() * queens_on_semi_open
         + attack;
 
     #[cfg(feature = "display-eval")]
@@ -607,15 +672,15 @@ pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_
         println!("\tBishop Diagonally Adj: {}", mb_diag);
         println!("\tMobility Rook  : {}", mr);
         println!("\tMobility Queen : {}", mq);
-        println!("\tRooks on open  : {} -> {}", rooks_onopen, ROOK_ON_OPEN_FILE_BONUS * rooks_onopen,);
-        println!("\tRooks on semi-open  : {} -> {}", rooks_on_semi_open, ROOK_ON_SEMI_OPEN_FILE_BONUS * rooks_on_semi_open,);
-        println!("\tQueens on open  : {} -> {}", queens_onopen, QUEEN_ON_OPEN_FILE_BONUS * queens_onopen,);
+        println!("\tRooks on open  : {} -> {}", rooks_onopen, params.rook_on_open_file_bonus() * rooks_onopen,);
+        println!("\tRooks on semi-open  : {} -> {}", rooks_on_semi_open, params.rook_on_semi_open_file_bonus() * rooks_on_semi_open,);
+        println!("\tQueens on open  : {} -> {}", queens_onopen, params.queen_on_open_file_bonus() * queens_onopen,);
         println!(
             "\tQueens on semi-open  : {} -> {}",
             queens_on_semi_open,
-            QUEEN_ON_SEMI_OPEN_FILE_BONUS * queens_on_semi_open,
+            params.queen_on_semi_open_file_bonus() * queens_on_semi_open,
         );
-        println!("\tRooks on seventh: {} -> {}", rooks_onseventh, ROOK_ON_SEVENTH * rooks_onseventh);
+        println!("\tRooks on seventh: {} -> {}", rooks_onseventh, params.rook_on_seventh() * rooks_onseventh);
         println!("\tKnight Attackers: Num: {} , Val: {}", knight_attackers, knight_attacker_values);
         println!("\tBishop Attackers: Num: {} , Val: {}", bishop_attackers, bishop_attacker_values);
         println!("\tRook Attackers: Num: {} , Val: {}", rook_attackers, rook_attacker_values);
@@ -627,14 +692,14 @@ pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_
         );
         println!(
             "\tAttack MG value: {} * {} / 100.0 -> {}",
-            SAFETY_TABLE[(knight_attacker_values.0 + bishop_attacker_values.0 + rook_attacker_values.0 + queen_attacker_values.0).min(99) as usize].0,
-            ATTACK_WEIGHT[(knight_attackers + bishop_attackers + rook_attackers + queen_attackers).min(7) as usize].0,
+            params.safety_table()[(knight_attacker_values.0 + bishop_attacker_values.0 + rook_attacker_values.0 + queen_attacker_values.0).min(99) as usize].0,
+            params.attack_weight()[(knight_attackers + bishop_attackers + rook_attackers + queen_attackers).min(7) as usize].0,
             attack_mg
         );
         println!(
             "\tAttack EG value: {} * {} / 100.0 -> {}",
-            SAFETY_TABLE[(knight_attacker_values.1 + bishop_attacker_values.1 + rook_attacker_values.1 + queen_attacker_values.1).min(99) as usize].1,
-            ATTACK_WEIGHT[(knight_attackers + bishop_attackers + rook_attackers + queen_attackers).min(7) as usize].1,
+            params.safety_table()[(knight_attacker_values.1 + bishop_attacker_values.1 + rook_attacker_values.1 + queen_attacker_values.1).min(99) as usize].1,
+            params.attack_weight()[(knight_attackers + bishop_attackers + rook_attackers + queen_attackers).min(7) as usize].1,
             attack_eg
         );
         println!("Sum: {}", res);
@@ -642,7 +707,7 @@ pub fn piecewise(side: usize, g: &GameState, enemy_defend_by_minors: u64, enemy_
     res
 }
 
-pub fn king(side: usize, g: &GameState, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
+pub fn king<P: EvalParams>(side: usize, g: &GameState, params: &P, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
     let mut pawn_shield = SHIELDING_PAWNS[side][g.get_king_square(side)];
     let mut king_front_span = pawn_front_span(g.get_piece(PieceType::King, side), side);
     king_front_span |= west_one(king_front_span) | east_one(king_front_span);
@@ -672,15 +737,15 @@ pub fn king(side: usize, g: &GameState, #[cfg(feature = "tuning")] trace: &mut L
         trace.add(IDX_SHIELDING_PAWN_ONOPEN_MISSING + shields_on_open_missing, trace_count(side));
     }
     #[allow(clippy::let_and_return)]
-    let res = SHIELDING_PAWN_MISSING[shields_missing] + SHIELDING_PAWN_MISSING_ON_OPEN_FILE[shields_on_open_missing];
+    let res = params.shielding_pawn_missing()[shields_missing] + params.shielding_pawn_missing_on_open_file()[shields_on_open_missing];
 
     #[cfg(feature = "display-eval")]
     {
         println!("\nKing for {}:", if side == WHITE { "White" } else { "Black" });
-        println!("\tShield pawn missing: {} -> {}", shields_missing, SHIELDING_PAWN_MISSING[shields_missing],);
+        println!("\tShield pawn missing: {} -> {}", shields_missing, params.shielding_pawn_missing()[shields_missing],);
         println!(
             "\tShield pawn on open file missing: {} -> {}",
-            shields_on_open_missing, SHIELDING_PAWN_MISSING_ON_OPEN_FILE[shields_on_open_missing],
+            shields_on_open_missing, params.shielding_pawn_missing_on_open_file()[shields_on_open_missing],
         );
         println!("Sum: {}", res);
     }
@@ -693,10 +758,37 @@ pub fn get_distance(sq: usize, sq2: usize) -> usize {
         .max((file_of(sq) as isize - file_of(sq2) as isize).abs()) as usize
 }
 
-pub fn pawns(side: usize, g: &GameState, defended: u64, enemy_defended: u64, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
+//The terms which only depend on the pawns of both sides and the passed pawns of each side
+pub struct PawnStructure {
+    pub score: [EvaluationScore; 2],
+    pub passed_pawns: [u64; 2],
+}
+
+pub fn pawn_structure<P: EvalParams>(g: &GameState, params: &P, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> PawnStructure {
+    let (white_score, white_passers) = pawn_structure_side(
+        WHITE,
+        g,
+        params,
+        #[cfg(feature = "tuning")]
+        trace,
+    );
+    let (black_score, black_passers) = pawn_structure_side(
+        BLACK,
+        g,
+        params,
+        #[cfg(feature = "tuning")]
+        trace,
+    );
+    PawnStructure {
+        score: [white_score, black_score],
+        passed_pawns: [white_passers, black_passers],
+    }
+}
+
+//Evaluates all terms which only depend on the pawns of both sides. Also returns the passed pawns of side
+pub fn pawn_structure_side<P: EvalParams>(side: usize, g: &GameState, params: &P, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> (EvaluationScore, u64) {
     let mut res = EvaluationScore::default();
 
-    let empty = !g.get_all_pieces();
     let pawns = g.get_piece(PieceType::Pawn, side);
     let enemy_pawns = g.get_piece(PieceType::Pawn, swap_side(side));
     //Bitboards
@@ -704,12 +796,9 @@ pub fn pawns(side: usize, g: &GameState, defended: u64, enemy_defended: u64, #[c
     let front_span = pawn_front_span(pawns, side);
     let mut enemy_front_spans = pawn_front_span(enemy_pawns, swap_side(side));
     enemy_front_spans |= west_one(enemy_front_spans) | east_one(enemy_front_spans);
-    let (my_west_attacks, my_east_attacks, enemy_pawn_attacks) = (pawn_west_targets(side, pawns), pawn_east_targets(side, pawns), pawn_targets(swap_side(side), enemy_pawns));
-    let my_pawn_attacks = my_west_attacks | my_east_attacks;
-    let (my_pawn_pushes, my_pawn_double_pushes) = (movegen::single_push_pawn_targets(side, pawns, empty), movegen::double_push_pawn_targets(side, pawns, empty));
+    let (my_pawn_attacks, enemy_pawn_attacks) = (pawn_targets(side, pawns), pawn_targets(swap_side(side), enemy_pawns));
 
     let is_attackable = west_one(front_span) | east_one(front_span);
-    let enemy_pieces = g.get_pieces_from_side(swap_side(side));
 
     let doubled_pawns = (pawns & front_span).count_ones() as i16;
     let isolated_pawns = (pawns & !west_one(pawn_file_fill) & !east_one(pawn_file_fill)).count_ones() as i16;
@@ -720,7 +809,7 @@ pub fn pawns(side: usize, g: &GameState, defended: u64, enemy_defended: u64, #[c
     while supported_pawns != 0u64 {
         let index = supported_pawns.trailing_zeros() as usize;
         supported_pawns ^= square(index);
-        supp += PAWN_SUPPORTED_VALUE[side][index];
+        supp += params.pawn_supported_value()[side][index];
         #[cfg(feature = "tuning")]
         {
             trace.add(IDX_PAWN_SUPPORTED + white_pov(index, side), trace_count(side));
@@ -729,12 +818,10 @@ pub fn pawns(side: usize, g: &GameState, defended: u64, enemy_defended: u64, #[c
     res += supp;
 
     let center_attack_pawns = (pawns & pawn_targets(swap_side(side), INNER_CENTER)).count_ones() as i16;
-    let pawn_mobility = (my_west_attacks.count_ones() + my_east_attacks.count_ones() + my_pawn_pushes.count_ones() + my_pawn_double_pushes.count_ones()) as i16;
-    res += PAWN_DOUBLED_VALUE * doubled_pawns
-        + PAWN_ISOLATED_VALUE * isolated_pawns
-        + PAWN_BACKWARD_VALUE * backward_pawns
-        + PAWN_ATTACK_CENTER * center_attack_pawns
-        + PAWN_MOBILITY * pawn_mobility;
+    res += params.pawn_doubled_value() * doubled_pawns
+        + params.pawn_isolated_value() * isolated_pawns
+        + params.pawn_backward_value() * backward_pawns
+        + params.pawn_attack_center() * center_attack_pawns;
 
     #[cfg(feature = "tuning")]
     {
@@ -742,17 +829,68 @@ pub fn pawns(side: usize, g: &GameState, defended: u64, enemy_defended: u64, #[c
         trace.add(IDX_PAWN_ISOLATED, isolated_pawns as i8 * trace_count(side));
         trace.add(IDX_PAWN_BACKWARD, backward_pawns as i8 * trace_count(side));
         trace.add(IDX_PAWN_ATTACK_CENTER, center_attack_pawns as i8 * trace_count(side));
+    }
+    //Passers
+    let passed_pawns: u64 = pawns & !enemy_front_spans;
+    let mut passers = passed_pawns;
+    let mut passer_score = EvaluationScore::default();
+    while passers != 0u64 {
+        let idx = passers.trailing_zeros() as usize;
+        passer_score += params.pawn_passed_values()[relative_rank(side, idx)];
+        #[cfg(feature = "tuning")]
+        {
+            trace.add(IDX_PAWN_PASSED + relative_rank(side, idx), trace_count(side));
+        }
+        passers ^= square(idx);
+    }
+    res += passer_score;
+    #[cfg(feature = "display-eval")]
+    {
+        println!("\nPawn structure for {}:", if side == WHITE { "White" } else { "Black" });
+        println!("\tDoubled: {} -> {}", doubled_pawns, params.pawn_doubled_value() * doubled_pawns);
+        println!("\tIsolated: {} -> {}", isolated_pawns, params.pawn_isolated_value() * isolated_pawns,);
+        println!("\tBackward: {} -> {}", backward_pawns, params.pawn_backward_value() * backward_pawns,);
+        println!("\tSupported: {} -> {}", _supported_amt, supp);
+        println!("\tAttack Center: {} -> {}", center_attack_pawns, params.pawn_attack_center() * center_attack_pawns,);
+        println!("\tPassers: {} -> {}", passed_pawns.count_ones(), passer_score);
+        println!("Sum: {}", res);
+    }
+    (res, passed_pawns)
+}
+
+pub fn pawns<P: EvalParams>(
+    side: usize,
+    g: &GameState,
+    params: &P,
+    defended: u64,
+    enemy_defended: u64,
+    structure: &PawnStructure,
+    #[cfg(feature = "tuning")] trace: &mut LargeTrace,
+) -> EvaluationScore {
+    let mut res = structure.score[side];
+
+    let empty = !g.get_all_pieces();
+    let pawns = g.get_piece(PieceType::Pawn, side);
+    let (my_west_attacks, my_east_attacks) = (pawn_west_targets(side, pawns), pawn_east_targets(side, pawns));
+    let (my_pawn_pushes, my_pawn_double_pushes) = (movegen::single_push_pawn_targets(side, pawns, empty), movegen::double_push_pawn_targets(side, pawns, empty));
+    let enemy_pieces = g.get_pieces_from_side(swap_side(side));
+
+    let pawn_mobility = (my_west_attacks.count_ones() + my_east_attacks.count_ones() + my_pawn_pushes.count_ones() + my_pawn_double_pushes.count_ones()) as i16;
+    res += params.pawn_mobility() * pawn_mobility;
+    #[cfg(feature = "tuning")]
+    {
         trace.add(IDX_PAWN_MOBILITY, pawn_mobility as i8 * trace_count(side));
     }
+
     //Passers
-    let mut passed_pawns: u64 = pawns & !enemy_front_spans;
-    let (mut passer_score, mut _passer_normal, mut _passer_notblocked) = (EvaluationScore::default(), 0, 0);
+    let mut passed_pawns: u64 = structure.passed_pawns[side];
+    let (mut passer_score, mut _passer_notblocked) = (EvaluationScore::default(), 0);
     let mut passer_dist = EvaluationScore::default();
     let mut weak_passers = 0;
     let behind_passers = pawn_front_span(passed_pawns, swap_side(side));
     let rooks_support_passer = (behind_passers & g.get_rook_like_bb(side)).count_ones() as i16;
     let enemy_rooks_attack_passer = (behind_passers & g.get_rook_like_bb(swap_side(side))).count_ones() as i16;
-    res += ROOK_BEHIND_SUPPORT_PASSER * rooks_support_passer + ROOK_BEHIND_ENEMY_PASSER * enemy_rooks_attack_passer;
+    res += params.rook_behind_support_passer() * rooks_support_passer + params.rook_behind_enemy_passer() * enemy_rooks_attack_passer;
     #[cfg(feature = "tuning")]
     {
         trace.add(IDX_ROOK_BEHIND_SUPPORT_PASSER, rooks_support_passer as i8 * trace_count(side));
@@ -760,13 +898,6 @@ pub fn pawns(side: usize, g: &GameState, defended: u64, enemy_defended: u64, #[c
     }
     while passed_pawns != 0u64 {
         let idx = passed_pawns.trailing_zeros() as usize;
-        //Passed and blocked
-        _passer_normal += 1;
-        passer_score += PAWN_PASSED_VALUES[relative_rank(side, idx)];
-        #[cfg(feature = "tuning")]
-        {
-            trace.add(IDX_PAWN_PASSED + relative_rank(side, idx), trace_count(side));
-        }
         //A weak passer is an attacked and not defended passer
         let weak_passer = square(idx) & enemy_defended != 0u64 && square(idx) & defended == 0u64;
         if weak_passer {
@@ -777,7 +908,7 @@ pub fn pawns(side: usize, g: &GameState, defended: u64, enemy_defended: u64, #[c
         if !weak_passer && pawn_front_span(square(idx), side) & (enemy_defended | enemy_pieces) & !defended == 0u64 {
             //Passed and not blocked
             _passer_notblocked += 1;
-            passer_score += PAWN_PASSED_NOT_BLOCKED_VALUES[relative_rank(side, idx)];
+            passer_score += params.pawn_passed_not_blocked_values()[relative_rank(side, idx)];
             #[cfg(feature = "tuning")]
             {
                 trace.add(IDX_PAWN_PASSED_NOTBLOCKED + relative_rank(side, idx), trace_count(side));
@@ -788,7 +919,7 @@ pub fn pawns(side: usize, g: &GameState, defended: u64, enemy_defended: u64, #[c
         let d_myking = get_distance(idx, g.get_king_square(side));
         let d_enemyking = get_distance(idx, g.get_king_square(swap_side(side)));
         let sub_dist = ((d_myking as isize - d_enemyking as isize) + 6) as usize;
-        passer_dist += PASSED_KING_DISTANCE[d_myking - 1] + PASSED_ENEMY_KING_DISTANCE[d_enemyking - 1] + PASSED_SUBTRACT_DISTANCE[sub_dist];
+        passer_dist += params.passed_king_distance()[d_myking - 1] + params.passed_enemy_king_distance()[d_enemyking - 1] + params.passed_subtract_distance()[sub_dist];
         #[cfg(feature = "tuning")]
         {
             trace.add(IDX_PAWN_PASSED_KINGDISTANCE + d_myking - 1, trace_count(side));
@@ -801,31 +932,27 @@ pub fn pawns(side: usize, g: &GameState, defended: u64, enemy_defended: u64, #[c
     {
         trace.add(IDX_PAWN_PASSED_WEAK, weak_passers as i8 * trace_count(side));
     }
-    res += passer_score + PAWN_PASSED_WEAK * weak_passers + passer_dist;
+    res += passer_score + params.pawn_passed_weak() * weak_passers + passer_dist;
     #[cfg(feature = "display-eval")]
     {
         println!("\nPawns for {}:", if side == WHITE { "White" } else { "Black" });
-        println!("\tDoubled: {} -> {}", doubled_pawns, PAWN_DOUBLED_VALUE * doubled_pawns);
-        println!("\tIsolated: {} -> {}", isolated_pawns, PAWN_ISOLATED_VALUE * isolated_pawns,);
-        println!("\tBackward: {} -> {}", backward_pawns, PAWN_BACKWARD_VALUE * backward_pawns,);
-        println!("\tSupported: {} -> {}", _supported_amt, supp);
-        println!("\tAttack Center: {} -> {}", center_attack_pawns, PAWN_ATTACK_CENTER * center_attack_pawns,);
-        println!("\tMobility: {} -> {}", pawn_mobility, PAWN_MOBILITY * pawn_mobility,);
-        println!("\tPasser Blocked/Not Blocked: {} , {} -> {}", _passer_normal, _passer_notblocked, passer_score);
-        println!("\tRook behind passer: {} -> {}", rooks_support_passer, ROOK_BEHIND_SUPPORT_PASSER * rooks_support_passer,);
+        println!("\tStructure: {}", structure.score[side]);
+        println!("\tMobility: {} -> {}", pawn_mobility, params.pawn_mobility() * pawn_mobility,);
+        println!("\tPasser Not Blocked: {} -> {}", _passer_notblocked, passer_score);
+        println!("\tRook behind passer: {} -> {}", rooks_support_passer, params.rook_behind_support_passer() * rooks_support_passer,);
         println!(
             "\tEnemy Rook behind passer: {} -> {}",
             enemy_rooks_attack_passer,
-            ROOK_BEHIND_ENEMY_PASSER * enemy_rooks_attack_passer,
+            params.rook_behind_enemy_passer() * enemy_rooks_attack_passer,
         );
-        println!("\tWeak passer: {} -> {}", weak_passers, PAWN_PASSED_WEAK * weak_passers,);
+        println!("\tWeak passer: {} -> {}", weak_passers, params.pawn_passed_weak() * weak_passers,);
         println!("\tPassers distance to kings -> {}", passer_dist);
         println!("Sum: {}", res);
     }
     res
 }
 
-pub fn piece_values(side: usize, g: &GameState, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
+pub fn piece_values<P: EvalParams>(side: usize, g: &GameState, params: &P, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
     let mut res = EvaluationScore::default();
 
     // Piece counts
@@ -836,12 +963,12 @@ pub fn piece_values(side: usize, g: &GameState, #[cfg(feature = "tuning")] trace
     let my_queens = g.get_piece(PieceType::Queen, side).count_ones() as i16;
     let all_pawns = g.get_piece_bb(PieceType::Pawn).count_ones() as usize;
 
-    res += PAWN_PIECE_VALUE * my_pawns
-        + (KNIGHT_PIECE_VALUE + KNIGHT_VALUE_WITH_PAWNS[all_pawns]) * my_knights
-        + BISHOP_PIECE_VALUE * my_bishops
-        + BISHOP_PAIR_BONUS * (my_bishops > 1) as i16
-        + ROOK_PIECE_VALUE * my_rooks
-        + QUEEN_PIECE_VALUE * my_queens;
+    res += params.pawn_piece_value() * my_pawns
+        + (params.knight_piece_value() + params.knight_value_with_pawns()[all_pawns]) * my_knights
+        + params.bishop_piece_value() * my_bishops
+        + params.bishop_pair_bonus() * (my_bishops > 1) as i16
+        + params.rook_piece_value() * my_rooks
+        + params.queen_piece_value() * my_queens;
 
     #[cfg(feature = "tuning")]
     {
@@ -859,18 +986,18 @@ pub fn piece_values(side: usize, g: &GameState, #[cfg(feature = "tuning")] trace
     #[cfg(feature = "display-eval")]
     {
         println!("\nPiece values for {}", if side == WHITE { "White" } else { "Black" });
-        println!("\tPawns: {} -> {}", my_pawns, PAWN_PIECE_VALUE * my_pawns,);
+        println!("\tPawns: {} -> {}", my_pawns, params.pawn_piece_value() * my_pawns,);
         println!(
             "\tKnights: {} -> {}",
             my_knights,
-            (KNIGHT_PIECE_VALUE + KNIGHT_VALUE_WITH_PAWNS[all_pawns]) * my_knights,
+            (params.knight_piece_value() + params.knight_value_with_pawns()[all_pawns]) * my_knights,
         );
-        println!("\tBishops: {} -> {}", my_bishops, BISHOP_PIECE_VALUE * my_bishops,);
+        println!("\tBishops: {} -> {}", my_bishops, params.bishop_piece_value() * my_bishops,);
         if my_bishops > 1 {
-            println!("\tBishop-Pair: {} -> {}", 1, BISHOP_PAIR_BONUS);
+            println!("\tBishop-Pair: {} -> {}", 1, params.bishop_pair_bonus());
         }
-        println!("\tRooks: {} -> {}", my_rooks, ROOK_PIECE_VALUE * my_rooks,);
-        println!("\tQueens: {} -> {}", my_queens, QUEEN_PIECE_VALUE * my_queens,);
+        println!("\tRooks: {} -> {}", my_rooks, params.rook_piece_value() * my_rooks,);
+        println!("\tQueens: {} -> {}", my_queens, params.queen_piece_value() * my_queens,);
         println!("Sum: {}", res);
     }
     res
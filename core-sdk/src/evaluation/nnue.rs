@@ -0,0 +1,566 @@
+// NNUE style evaluation: a network with a single hidden layer, whose first layer (the feature transformer) is updated
+// incrementally. do_move only records which pieces changed, the accumulators are brought up to date lazily when a
+// position is evaluated. Without a loaded network the handcrafted evaluation is used.
+//
+// Network file format, version 1. All values are little endian:
+//   magic            4 bytes "FCNN"
+//   version          u32, 1
+//   feature set      u32, 0 = HalfKP, 1 = HalfKA
+//   hidden size      u32, neurons of the hidden layer per perspective, a multiple of 16
+//   qa               u32, quantization of the hidden layer, activations are clipped to [0, qa]
+//   qb               u32, quantization of the output weights
+//   scale            u32, the output layer is multiplied by scale / (qa * qb) to get centipawns
+//   feature weights  inputs * hidden i16, all weights of the first feature, then of the second feature and so on
+//   feature biases   hidden i16
+//   output weights   2 * hidden i16, first for the accumulator of the side to move, then for the other one
+//   output bias      i32
+// Both sides see the board from their own perspective, for black the board is flipped vertically:
+//   HalfKP: king square * 640 + (piece type * 2 + (piece color != perspective)) * 64 + square, kings are not part of the input
+//   HalfKA: king square * 768 + (piece type * 2 + (piece color != perspective)) * 64 + square
+// Piece types are ordered pawn, knight, bishop, rook, queen, king and squares are a1 = 0, b1 = 1, ..., h8 = 63.
+use crate::board_representation::game_state::{GameMove, GameMoveType, GameState, PieceType, BLACK, PIECE_TYPES, WHITE};
+use crate::move_generation::makemove::rook_castling;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+
+pub const NNUE_MAGIC: &[u8; 4] = b"FCNN";
+pub const NNUE_VERSION: u32 = 1;
+pub const MAX_NNUE_EVAL: i32 = 10000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FeatureSet {
+    HalfKP,
+    HalfKA,
+}
+
+impl FeatureSet {
+    pub fn inputs(self) -> usize {
+        match self {
+            FeatureSet::HalfKP => 64 * 640,
+            FeatureSet::HalfKA => 64 * 768,
+        }
+    }
+
+    #[inline(always)]
+    pub fn feature_index(self, perspective: usize, king_square: usize, piece: PieceType, color: usize, square: usize) -> Option<usize> {
+        let orient = if perspective == WHITE { 0 } else { 56 };
+        let piece_index = piece as usize * 2 + (color != perspective) as usize;
+        match self {
+            FeatureSet::HalfKP if piece == PieceType::King => None,
+            FeatureSet::HalfKP => Some((king_square ^ orient) * 640 + piece_index * 64 + (square ^ orient)),
+            FeatureSet::HalfKA => Some((king_square ^ orient) * 768 + piece_index * 64 + (square ^ orient)),
+        }
+    }
+
+    //All active features of a position from one side's perspective
+    pub fn features(self, game_state: &GameState, perspective: usize) -> Vec<usize> {
+        let king_square = game_state.get_king_square(perspective);
+        let mut res = Vec::with_capacity(32);
+        for color in [WHITE, BLACK].iter() {
+            for piece in PIECE_TYPES.iter() {
+                let mut pieces = game_state.get_piece(*piece, *color);
+                while pieces != 0u64 {
+                    let square = pieces.trailing_zeros() as usize;
+                    res.extend(self.feature_index(perspective, king_square, *piece, *color, square));
+                    pieces &= pieces - 1;
+                }
+            }
+        }
+        res
+    }
+}
+
+pub struct Network {
+    pub feature_set: FeatureSet,
+    pub hidden: usize,
+    pub qa: i32,
+    pub qb: i32,
+    pub scale: i32,
+    pub feature_weights: Vec<i16>,
+    pub feature_biases: Vec<i16>,
+    pub output_weights: Vec<i16>,
+    pub output_bias: i32,
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u32(reader: &mut dyn Read) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i16s(reader: &mut dyn Read, len: usize) -> Result<Vec<i16>> {
+    let mut bytes = vec![0u8; 2 * len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect())
+}
+
+fn write_i16s(writer: &mut dyn Write, values: &[i16]) -> Result<()> {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    writer.write_all(&bytes)
+}
+
+impl Network {
+    //A network with all weights set to zero
+    pub fn zeroed(feature_set: FeatureSet, hidden: usize, qa: i32, qb: i32, scale: i32) -> Self {
+        Network {
+            feature_set,
+            hidden,
+            qa,
+            qb,
+            scale,
+            feature_weights: vec![0; feature_set.inputs() * hidden],
+            feature_biases: vec![0; hidden],
+            output_weights: vec![0; 2 * hidden],
+            output_bias: 0,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Network> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        Network::read(&mut BufReader::new(file), size)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    //Size in bytes of a network file with the given architecture, None if it does not fit into a u64
+    pub fn file_size(feature_set: FeatureSet, hidden: usize) -> Option<u64> {
+        let weights = (feature_set.inputs() as u64).checked_mul(hidden as u64)?.checked_add(3 * hidden as u64)?;
+        weights.checked_mul(2)?.checked_add(7 * 4 + 4)
+    }
+
+    //size is the number of bytes the reader holds, the sizes in the header are checked against it before anything is allocated
+    pub fn read(reader: &mut dyn Read, size: u64) -> Result<Network> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != NNUE_MAGIC {
+            return Err(invalid_data("Not a FabChess network file".to_owned()));
+        }
+        let version = read_u32(reader)?;
+        if version != NNUE_VERSION {
+            return Err(invalid_data(format!("Unsupported network version {}, expected {}", version, NNUE_VERSION)));
+        }
+        let feature_set = match read_u32(reader)? {
+            0 => FeatureSet::HalfKP,
+            1 => FeatureSet::HalfKA,
+            other => return Err(invalid_data(format!("Unknown feature set {}", other))),
+        };
+        let hidden = read_u32(reader)? as usize;
+        if hidden == 0 || !hidden.is_multiple_of(16) {
+            return Err(invalid_data(format!("Hidden size {} is not a multiple of 16", hidden)));
+        }
+        match Network::file_size(feature_set, hidden) {
+            Some(expected) if expected == size => {}
+            _ => return Err(invalid_data(format!("Network file has {} bytes, which does not match a hidden size of {}", size, hidden))),
+        }
+        let qa = read_u32(reader)? as i32;
+        let qb = read_u32(reader)? as i32;
+        let scale = read_u32(reader)? as i32;
+        if qa <= 0 || qb <= 0 {
+            return Err(invalid_data("Quantization factors have to be positive".to_owned()));
+        }
+        let feature_weights = read_i16s(reader, feature_set.inputs() * hidden)?;
+        let feature_biases = read_i16s(reader, hidden)?;
+        let output_weights = read_i16s(reader, 2 * hidden)?;
+        let output_bias = read_u32(reader)? as i32;
+        Ok(Network {
+            feature_set,
+            hidden,
+            qa,
+            qb,
+            scale,
+            feature_weights,
+            feature_biases,
+            output_weights,
+            output_bias,
+        })
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(NNUE_MAGIC)?;
+        let feature_set = match self.feature_set {
+            FeatureSet::HalfKP => 0u32,
+            FeatureSet::HalfKA => 1u32,
+        };
+        for value in [NNUE_VERSION, feature_set, self.hidden as u32, self.qa as u32, self.qb as u32, self.scale as u32].iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        write_i16s(writer, &self.feature_weights)?;
+        write_i16s(writer, &self.feature_biases)?;
+        write_i16s(writer, &self.output_weights)?;
+        writer.write_all(&self.output_bias.to_le_bytes())
+    }
+
+    #[inline(always)]
+    fn feature_weights(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+
+    //Starts tracking the accumulators of a position, so that they are updated incrementally from now on
+    pub fn init_accumulators(&self, game_state: &mut GameState) {
+        game_state.accumulators.activate(self.hidden);
+    }
+
+    fn refresh(&self, game_state: &GameState, perspective: usize, values: &mut [i16]) {
+        values.copy_from_slice(&self.feature_biases);
+        for feature in self.feature_set.features(game_state, perspective) {
+            simd::add_assign(values, self.feature_weights(feature));
+        }
+    }
+
+    fn apply(&self, dirty: &DirtyPieces, perspective: usize, king_square: usize, values: &mut [i16]) {
+        for (piece, color, square) in dirty.removed[..dirty.removed_len].iter() {
+            if let Some(feature) = self.feature_set.feature_index(perspective, king_square, *piece, *color, *square) {
+                simd::sub_assign(values, self.feature_weights(feature));
+            }
+        }
+        for (piece, color, square) in dirty.added[..dirty.added_len].iter() {
+            if let Some(feature) = self.feature_set.feature_index(perspective, king_square, *piece, *color, *square) {
+                simd::add_assign(values, self.feature_weights(feature));
+            }
+        }
+    }
+
+    //Brings the accumulator of the current position up to date, starting from the last computed one.
+    //If our king moved in between, all features change and the accumulator is computed from scratch.
+    fn update(&self, game_state: &mut GameState, perspective: usize) {
+        let king_square = game_state.get_king_square(perspective);
+        let stack = &mut game_state.accumulators;
+        let top = stack.top;
+        let mut start = top;
+        while !stack.entries[start].computed[perspective] {
+            if start == 0 || stack.entries[start].dirty.king_moved[perspective] {
+                let mut values = std::mem::take(&mut stack.entries[top].values[perspective]);
+                self.refresh(game_state, perspective, &mut values);
+                let entry = &mut game_state.accumulators.entries[top];
+                entry.values[perspective] = values;
+                entry.computed[perspective] = true;
+                return;
+            }
+            start -= 1;
+        }
+        for index in start + 1..=top {
+            let (before, after) = stack.entries.split_at_mut(index);
+            let (previous, entry) = (&before[index - 1], &mut after[0]);
+            entry.values[perspective].copy_from_slice(&previous.values[perspective]);
+            self.apply(&entry.dirty, perspective, king_square, &mut entry.values[perspective]);
+            entry.computed[perspective] = true;
+        }
+    }
+
+    fn output(&self, us: &[i16], them: &[i16]) -> i16 {
+        let sum = self.output_bias as i64
+            + simd::dot_crelu(us, &self.output_weights[..self.hidden], self.qa as i16) as i64
+            + simd::dot_crelu(them, &self.output_weights[self.hidden..], self.qa as i16) as i64;
+        let eval = sum * self.scale as i64 / (self.qa as i64 * self.qb as i64);
+        eval.max(-MAX_NNUE_EVAL as i64).min(MAX_NNUE_EVAL as i64) as i16
+    }
+
+    //Evaluation from white's point of view. Uses the incrementally updated accumulators if they are tracked
+    pub fn evaluate(&self, game_state: &mut GameState) -> i16 {
+        if !game_state.accumulators.active || game_state.accumulators.hidden() != self.hidden {
+            return self.evaluate_full(game_state);
+        }
+        let stm = game_state.get_color_to_move();
+        self.update(game_state, stm);
+        self.update(game_state, 1 - stm);
+        let entry = &game_state.accumulators.entries[game_state.accumulators.top];
+        let eval = self.output(&entry.values[stm], &entry.values[1 - stm]);
+        if stm == WHITE {
+            eval
+        } else {
+            -eval
+        }
+    }
+
+    //Evaluation from white's point of view, computing the accumulators from scratch
+    pub fn evaluate_full(&self, game_state: &GameState) -> i16 {
+        let stm = game_state.get_color_to_move();
+        let mut us = vec![0i16; self.hidden];
+        let mut them = vec![0i16; self.hidden];
+        self.refresh(game_state, stm, &mut us);
+        self.refresh(game_state, 1 - stm, &mut them);
+        let eval = self.output(&us, &them);
+        if stm == WHITE {
+            eval
+        } else {
+            -eval
+        }
+    }
+}
+
+//Pieces that were removed from and added to the board by a move
+#[derive(Copy, Clone)]
+pub struct DirtyPieces {
+    removed: [(PieceType, usize, usize); 2],
+    removed_len: usize,
+    added: [(PieceType, usize, usize); 2],
+    added_len: usize,
+    king_moved: [bool; 2],
+}
+
+impl Default for DirtyPieces {
+    fn default() -> Self {
+        DirtyPieces {
+            removed: [(PieceType::Pawn, WHITE, 0); 2],
+            removed_len: 0,
+            added: [(PieceType::Pawn, WHITE, 0); 2],
+            added_len: 0,
+            king_moved: [false; 2],
+        }
+    }
+}
+
+impl DirtyPieces {
+    fn remove(&mut self, piece: PieceType, color: usize, square: usize) {
+        self.removed[self.removed_len] = (piece, color, square);
+        self.removed_len += 1;
+    }
+
+    fn add(&mut self, piece: PieceType, color: usize, square: usize) {
+        self.added[self.added_len] = (piece, color, square);
+        self.added_len += 1;
+    }
+
+    //The move has to be made by the given side
+    pub fn from_move(mv: GameMove, color: usize) -> Self {
+        let mut res = DirtyPieces::default();
+        let (from, to) = (mv.from as usize, mv.to as usize);
+        res.king_moved[color] = mv.piece_type == PieceType::King;
+        res.remove(mv.piece_type, color, from);
+        if let Some(captured) = mv.get_maybe_captured_piece() {
+            let square = if mv.move_type == GameMoveType::EnPassant { to ^ 8 } else { to };
+            res.remove(captured, 1 - color, square);
+        }
+        match mv.move_type {
+            GameMoveType::Promotion(promo, _) => res.add(promo, color, to),
+            GameMoveType::Castle => {
+                res.add(PieceType::King, color, to);
+                let (rook_from, rook_to) = rook_castling(to);
+                res.remove(PieceType::Rook, color, rook_from);
+                res.add(PieceType::Rook, color, rook_to);
+            }
+            _ => res.add(mv.piece_type, color, to),
+        }
+        res
+    }
+}
+
+#[derive(Clone)]
+struct AccumulatorEntry {
+    values: [Vec<i16>; 2],
+    computed: [bool; 2],
+    //Changes from the previous entry to this one
+    dirty: DirtyPieces,
+}
+
+impl AccumulatorEntry {
+    fn new(hidden: usize) -> Self {
+        AccumulatorEntry {
+            values: [vec![0; hidden], vec![0; hidden]],
+            computed: [false; 2],
+            dirty: DirtyPieces::default(),
+        }
+    }
+}
+
+//One entry for every position on the undo stack of a game state. Entries are kept when popped, so that
+//their memory is reused by the next move
+#[derive(Clone, Default)]
+pub struct AccumulatorStack {
+    entries: Vec<AccumulatorEntry>,
+    top: usize,
+    active: bool,
+}
+
+impl AccumulatorStack {
+    pub fn activate(&mut self, hidden: usize) {
+        self.entries.clear();
+        self.entries.push(AccumulatorEntry::new(hidden));
+        self.top = 0;
+        self.active = true;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    fn hidden(&self) -> usize {
+        self.entries[0].values[0].len()
+    }
+
+    #[inline(always)]
+    pub(crate) fn push(&mut self, dirty: DirtyPieces) {
+        if !self.active {
+            return;
+        }
+        self.top += 1;
+        if self.top == self.entries.len() {
+            let hidden = self.hidden();
+            self.entries.push(AccumulatorEntry::new(hidden));
+        }
+        let entry = &mut self.entries[self.top];
+        entry.dirty = dirty;
+        entry.computed = [false; 2];
+    }
+
+    #[inline(always)]
+    pub(crate) fn pop(&mut self) {
+        if self.active {
+            self.top -= 1;
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+mod simd {
+    use std::arch::x86_64::*;
+
+    //The hidden size is a multiple of 16, so all slices consist of whole 256 bit registers
+    #[inline(always)]
+    pub fn add_assign(values: &mut [i16], weights: &[i16]) {
+        for (v, w) in values.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+            unsafe {
+                let sum = _mm256_add_epi16(_mm256_loadu_si256(v.as_ptr() as *const __m256i), _mm256_loadu_si256(w.as_ptr() as *const __m256i));
+                _mm256_storeu_si256(v.as_mut_ptr() as *mut __m256i, sum);
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn sub_assign(values: &mut [i16], weights: &[i16]) {
+        for (v, w) in values.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+            unsafe {
+                let diff = _mm256_sub_epi16(_mm256_loadu_si256(v.as_ptr() as *const __m256i), _mm256_loadu_si256(w.as_ptr() as *const __m256i));
+                _mm256_storeu_si256(v.as_mut_ptr() as *mut __m256i, diff);
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn dot_crelu(values: &[i16], weights: &[i16], qa: i16) -> i32 {
+        unsafe {
+            let zero = _mm256_setzero_si256();
+            let max = _mm256_set1_epi16(qa);
+            let mut sum = _mm256_setzero_si256();
+            for (v, w) in values.chunks_exact(16).zip(weights.chunks_exact(16)) {
+                let clipped = _mm256_min_epi16(_mm256_max_epi16(_mm256_loadu_si256(v.as_ptr() as *const __m256i), zero), max);
+                sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, _mm256_loadu_si256(w.as_ptr() as *const __m256i)));
+            }
+            let sum128 = _mm_add_epi32(_mm256_castsi256_si128(sum), _mm256_extracti128_si256(sum, 1));
+            let sum64 = _mm_add_epi32(sum128, _mm_unpackhi_epi64(sum128, sum128));
+            let sum32 = _mm_add_epi32(sum64, _mm_shuffle_epi32(sum64, 1));
+            _mm_cvtsi128_si32(sum32)
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_feature = "avx2")))]
+mod simd {
+    #[inline(always)]
+    pub fn add_assign(values: &mut [i16], weights: &[i16]) {
+        for (v, w) in values.iter_mut().zip(weights.iter()) {
+            *v = v.wrapping_add(*w);
+        }
+    }
+
+    #[inline(always)]
+    pub fn sub_assign(values: &mut [i16], weights: &[i16]) {
+        for (v, w) in values.iter_mut().zip(weights.iter()) {
+            *v = v.wrapping_sub(*w);
+        }
+    }
+
+    #[inline(always)]
+    pub fn dot_crelu(values: &[i16], weights: &[i16], qa: i16) -> i32 {
+        values.iter().zip(weights.iter()).map(|(v, w)| i32::from((*v).max(0).min(qa)) * i32::from(*w)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeatureSet, Network};
+    use crate::board_representation::game_state::GameState;
+    use crate::move_generation::movegen::{generate_moves, MoveList};
+
+    fn random_network(feature_set: FeatureSet) -> Network {
+        let mut network = Network::zeroed(feature_set, 32, 255, 64, 40);
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = |range: i64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            ((seed % (2 * range as u64 + 1)) as i64 - range) as i16
+        };
+        network.feature_weights.iter_mut().for_each(|w| *w = next(20));
+        network.feature_biases.iter_mut().for_each(|w| *w = next(100));
+        network.output_weights.iter_mut().for_each(|w| *w = next(64));
+        network.output_bias = 1234;
+        network
+    }
+
+    #[test]
+    fn network_file_test() {
+        let network = random_network(FeatureSet::HalfKA);
+        let mut bytes = Vec::new();
+        network.write(&mut bytes).unwrap();
+        assert_eq!(Network::file_size(FeatureSet::HalfKA, network.hidden), Some(bytes.len() as u64));
+        let loaded = Network::read(&mut bytes.as_slice(), bytes.len() as u64).unwrap();
+        assert_eq!(loaded.feature_set, FeatureSet::HalfKA);
+        assert_eq!(loaded.feature_weights, network.feature_weights);
+        assert_eq!(loaded.output_bias, 1234);
+        //A corrupted hidden size must be rejected before the weights are allocated
+        let mut corrupted = bytes.clone();
+        corrupted[12..16].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        assert!(Network::read(&mut corrupted.as_slice(), corrupted.len() as u64).is_err());
+        assert!(Network::read(&mut bytes[..bytes.len() - 2].as_ref(), bytes.len() as u64 - 2).is_err());
+        bytes[4] = 2;
+        assert!(Network::read(&mut bytes.as_slice(), bytes.len() as u64).is_err());
+    }
+
+    #[test]
+    fn incremental_accumulator_test() {
+        //Castling, en passant, promotions with capture and king moves
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        ];
+        for feature_set in [FeatureSet::HalfKP, FeatureSet::HalfKA].iter() {
+            let network = random_network(*feature_set);
+            for fen in fens.iter() {
+                let mut game_state = GameState::from_fen(fen);
+                network.init_accumulators(&mut game_state);
+                let mut movelist = MoveList::default();
+                generate_moves(&game_state, false, &mut movelist);
+                let moves: Vec<_> = movelist.move_list.iter().map(|gmv| gmv.0).collect();
+                for mv in moves {
+                    game_state.do_move(mv);
+                    //Evaluate only every other ply, so that several moves are applied at once
+                    let mut replies = MoveList::default();
+                    generate_moves(&game_state, false, &mut replies);
+                    if let Some(reply) = replies.move_list.first().map(|gmv| gmv.0) {
+                        game_state.do_move(reply);
+                        assert_eq!(network.evaluate(&mut game_state), network.evaluate_full(&game_state));
+                        game_state.do_nullmove();
+                        assert_eq!(network.evaluate(&mut game_state), network.evaluate_full(&game_state));
+                        game_state.undo_nullmove();
+                        game_state.undo_move(reply);
+                    }
+                    assert_eq!(network.evaluate(&mut game_state), network.evaluate_full(&game_state));
+                    game_state.undo_move(mv);
+                }
+                assert_eq!(network.evaluate(&mut game_state), network.evaluate_full(&game_state));
+            }
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use super::EvaluationScore;
 use crate::bitboards::bitboards::constants::square;
 use crate::board_representation::game_state::{GameState, PieceType, PIECE_TYPES, WHITE};
-use crate::evaluation::params::{KING_PIECE_TABLE, PSQT};
+use crate::evaluation::eval_params::EvalParams;
+use crate::evaluation::params::KING_PIECE_TABLE;
 
 #[cfg(feature = "tuning")]
 use crate::board_representation::game_state::white_pov;
@@ -12,7 +13,7 @@ use crate::evaluation::parameters::normal_parameters::IDX_PSQT;
 #[cfg(feature = "tuning")]
 use crate::evaluation::trace::LargeTrace;
 
-pub fn psqt(game_state: &GameState, side: usize, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
+pub fn psqt<P: EvalParams>(game_state: &GameState, side: usize, params: &P, #[cfg(feature = "tuning")] trace: &mut LargeTrace) -> EvaluationScore {
     #[cfg(feature = "display-eval")]
     {
         println!("\nPSQT for {}:", if side == WHITE { "White" } else { "Black" });
@@ -27,7 +28,7 @@ pub fn psqt(game_state: &GameState, side: usize, #[cfg(feature = "tuning")] trac
         while piece > 0 {
             let idx = piece.trailing_zeros() as usize;
             piece ^= square(idx);
-            piece_sum += PSQT[pt as usize][side][idx] * if side == WHITE { 1 } else { -1 };
+            piece_sum += params.psqt()[pt as usize][side][idx] * if side == WHITE { 1 } else { -1 };
 
             #[cfg(feature = "tuning")]
             {
@@ -50,7 +51,7 @@ pub fn psqt(game_state: &GameState, side: usize, #[cfg(feature = "tuning")] trac
             while piece > 0 {
                 let idx = piece.trailing_zeros() as usize;
                 piece ^= square(idx);
-                king_piece_sum += KING_PIECE_TABLE[side][game_state.get_king_square(side)][piece_side][piece_type as usize][idx] * if side == WHITE { 1 } else { -1 };
+                king_piece_sum += params.king_piece_table()[side][game_state.get_king_square(side)][piece_side][piece_type as usize][idx] * if side == WHITE { 1 } else { -1 };
                 #[cfg(feature = "tuning")]
                 {
                     trace.add(
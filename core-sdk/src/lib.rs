@@ -3,12 +3,13 @@ pub mod board_representation;
 pub mod evaluation;
 pub mod move_generation;
 pub mod search;
+pub mod solver;
 
 use crate::board_representation::game_state::GameState;
-use crate::move_generation::makemove::make_move;
 use crate::move_generation::movegen;
 use crate::search::alphabeta::DEFAULT_FUTILITY_MARGIN;
 use crate::search::cache::DEFAULT_HASH_SIZE;
+use crate::search::eval_cache::DEFAULT_EVAL_CACHE_SIZE;
 use crate::search::reserved_memory::ReservedMoveList;
 use crate::search::searcher::{InterThreadCommunicationSystem, DEFAULT_SKIP_RATIO, DEFAULT_THREADS};
 use crate::search::timecontrol::DEFAULT_MOVE_OVERHEAD;
@@ -18,22 +19,29 @@ use std::time::Instant;
 #[derive(Copy, Clone)]
 pub struct UCIOptions {
     pub hash_size: usize,
+    pub eval_cache_size: usize,
     pub threads: usize,
     pub move_overhead: u64,
     pub debug_print: bool,
+    pub search_statistics: bool,
     pub skip_ratio: usize,
 
     pub futility_margin: i16,
+    //Suppresses the search's UCI output, for searches run from within another program
+    pub quiet: bool,
 }
 impl Default for UCIOptions {
     fn default() -> Self {
         UCIOptions {
             hash_size: DEFAULT_HASH_SIZE,
+            eval_cache_size: DEFAULT_EVAL_CACHE_SIZE,
             threads: DEFAULT_THREADS,
             move_overhead: DEFAULT_MOVE_OVERHEAD,
             debug_print: false,
+            search_statistics: false,
             skip_ratio: DEFAULT_SKIP_RATIO,
             futility_margin: DEFAULT_FUTILITY_MARGIN,
+            quiet: false,
         }
     }
 }
@@ -42,13 +50,15 @@ pub fn perft_div(g: &GameState, depth: usize) -> u64 {
     let mut count = 0u64;
     let mut movelist = ReservedMoveList::default();
     let now = Instant::now();
+    let mut g = g.clone();
 
     let _ = movegen::generate_moves(&g, false, &mut movelist.move_lists[depth]);
     let len = movelist.move_lists[depth].move_list.len();
     for i in 0..len {
         let gmv = movelist.move_lists[depth].move_list[i];
-        let next_g = make_move(&g, gmv.0);
-        let res = perft(&next_g, depth - 1, &mut movelist);
+        g.do_move(gmv.0);
+        let res = perft(&mut g, depth - 1, &mut movelist);
+        g.undo_move(gmv.0);
         println!("{:?}: {}", gmv.0, res);
         count += res;
     }
@@ -60,25 +70,27 @@ pub fn perft_div(g: &GameState, depth: usize) -> u64 {
     count
 }
 
-pub fn perft(g: &GameState, depth: usize, movelist: &mut ReservedMoveList) -> u64 {
+pub fn perft(g: &mut GameState, depth: usize, movelist: &mut ReservedMoveList) -> u64 {
     if depth == 1 {
-        let _ = movegen::generate_moves(&g, false, &mut movelist.move_lists[depth]);
+        let _ = movegen::generate_moves(g, false, &mut movelist.move_lists[depth]);
         movelist.move_lists[depth].move_list.len() as u64
     } else {
         if depth == 0 {
             return 1;
         }
         let mut res = 0;
-        let _ = movegen::generate_moves(&g, false, &mut movelist.move_lists[depth]);
+        let _ = movegen::generate_moves(g, false, &mut movelist.move_lists[depth]);
         let len = movelist.move_lists[depth].move_list.len();
         for i in 0..len {
             let mv = movelist.move_lists[depth].move_list[i].0;
-            res += perft(&make_move(&g, mv), depth - 1, movelist);
+            g.do_move(mv);
+            res += perft(g, depth - 1, movelist);
+            g.undo_move(mv);
         }
         res
     }
 }
-const BENCHMARKING_POSITIONS: [&str; 50] = [
+pub const BENCHMARKING_POSITIONS: [&str; 50] = [
     "r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14",
     "4rrk1/2p1b1p1/p1p3q1/4p3/2P2n1p/1P1NR2P/PB3PP1/3R1QK1 b - - 2 24",
     "r3qbrk/6p1/2b2pPp/p3pP1Q/PpPpP2P/3P1B2/2PB3K/R5R1 w - - 16 42",
@@ -133,7 +145,7 @@ const BENCHMARKING_POSITIONS: [&str; 50] = [
 pub fn bench(depth: usize) {
     let itcs = Arc::new(InterThreadCommunicationSystem::default());
     InterThreadCommunicationSystem::update_thread_count(&itcs, 1);
-    *itcs.cache() = search::cache::Cache::with_size_threaded(8, 1);
+    itcs.resize_cache(8, 1);
     let before_time = Instant::now();
     let mut nodes = 0;
     for position in BENCHMARKING_POSITIONS.iter() {
@@ -2,6 +2,7 @@ use crate::bitboards::bitboards::constants::{square, CASTLE_PERMISSION};
 use crate::bitboards::bitboards::square;
 use crate::board_representation::game_state::{ep_pawn_square, file_of, swap_side, GameMove, GameMoveType, GameState, Irreversible, PieceType};
 use crate::board_representation::zobrist_hashing::ZOBRIST_KEYS;
+use crate::evaluation::nnue::DirtyPieces;
 use crate::evaluation::psqt_evaluation::{kp_add_piece, kp_move_king, kp_remove_piece, psqt_add_piece, psqt_remove_piece};
 use crate::evaluation::EvaluationScore;
 
@@ -41,21 +42,9 @@ pub fn castle_hash(old: &GameState, new: u8, hash: &mut u64) {
 }
 
 pub fn make_nullmove(g: &GameState) -> GameState {
-    let color_to_move = swap_side(g.get_color_to_move());
-    let piece_bb = g.get_piece_bb_array();
-    let color_bb = g.get_color_bb_array();
-    let en_passant = 0u64;
-    let half_moves = g.get_half_moves() + 1;
-    let full_moves = g.get_full_moves() + g.get_color_to_move();
-    let mut hash = g.get_hash() ^ ZOBRIST_KEYS.side_to_move;
-    enpassant_hash(g.get_en_passant(), en_passant, &mut hash);
-    GameState::new(
-        color_to_move,
-        piece_bb,
-        color_bb,
-        Irreversible::new(hash, en_passant, half_moves as u16, g.castle_permissions(), g.get_phase().clone(), g.get_psqt()),
-        full_moves,
-    )
+    let mut res = GameState::new(g.color_to_move, g.piece_bb, g.color_bb, g.irreversible.clone(), g.full_moves);
+    res.apply_nullmove();
+    res
 }
 
 #[inline(always)]
@@ -69,87 +58,179 @@ pub fn rook_castling(to: usize) -> (usize, usize) {
     }
 }
 
+//Copy-make convenience wrapper around the in place move making. The new state starts with an empty undo stack.
 pub fn make_move(g: &GameState, mv: GameMove) -> GameState {
-    //Step 1. Update immediate fields
-    let color = g.get_color_to_move();
-    let full_moves = g.get_full_moves() + color;
-    //Step 2. Update pieces, hash and other incremental fields
-    let mut piece_bb = g.get_piece_bb_array();
-    let mut color_bb = g.get_color_bb_array();
-    let mut hash = g.get_hash() ^ ZOBRIST_KEYS.side_to_move;
-    let mut psqt = g.get_psqt();
-    let mut phase = g.get_phase().clone();
-    let to = mv.to as usize;
-    let from = mv.from as usize;
-    let mut our_king_square = g.get_king_square(color);
-    //Remove piece from original square
-    if mv.piece_type == PieceType::King {
-        //Update our KP tables
-        kp_move_king(
-            from,
-            to,
-            g.get_piece(PieceType::Pawn, color),
-            g.get_piece(PieceType::Pawn, swap_side(color)),
-            color,
-            &mut psqt,
-        );
-        our_king_square = to;
-    } else if mv.piece_type == PieceType::Pawn {
-        //Move pawn for enemy king
-        kp_remove_piece(swap_side(color), g.get_king_square(swap_side(color)), false, PieceType::Pawn, from, &mut psqt);
-        kp_add_piece(swap_side(color), g.get_king_square(swap_side(color)), false, PieceType::Pawn, to, &mut psqt);
-        //Move pawn for our king
-        kp_remove_piece(color, our_king_square, true, PieceType::Pawn, from, &mut psqt);
-        kp_add_piece(color, our_king_square, true, PieceType::Pawn, to, &mut psqt);
+    let mut res = GameState::new(g.color_to_move, g.piece_bb, g.color_bb, g.irreversible.clone(), g.full_moves);
+    res.apply_move(mv);
+    res
+}
+
+impl GameState {
+    //Makes the move in place. The irreversible state before the move is pushed onto the undo stack, so the move can be taken back with undo_move
+    #[inline(always)]
+    pub fn do_move(&mut self, mv: GameMove) {
+        if self.accumulators.is_active() {
+            self.accumulators.push(DirtyPieces::from_move(mv, self.color_to_move));
+        }
+        let irreversible = self.apply_move(mv);
+        self.undo_stack.push(irreversible);
     }
-    remove_piece(&mut piece_bb, &mut color_bb, mv.piece_type, from, color, &mut hash, &mut psqt);
-    //Delete piece if capture
-    if let Some(piece) = mv.get_maybe_captured_piece() {
-        let square = to ^ (8 * (mv.move_type == GameMoveType::EnPassant) as usize);
-        remove_piece(&mut piece_bb, &mut color_bb, piece, square, swap_side(color), &mut hash, &mut psqt);
-        phase.delete_piece(piece);
-        if piece == PieceType::Pawn {
-            //Remove piece for our king
-            kp_remove_piece(color, our_king_square, false, PieceType::Pawn, square as usize, &mut psqt);
-            //Remove piece for enemy king
-            kp_remove_piece(swap_side(color), g.get_king_square(swap_side(color)), true, PieceType::Pawn, square as usize, &mut psqt);
+
+    #[inline(always)]
+    pub fn undo_move(&mut self, mv: GameMove) {
+        let irreversible = self.undo_stack.pop().expect("Can't undo move, undo stack is empty!");
+        self.accumulators.pop();
+        self.color_to_move = swap_side(self.color_to_move);
+        let color = self.color_to_move;
+        self.full_moves -= color;
+        let to = mv.to as usize;
+        let from = mv.from as usize;
+        if let GameMoveType::Castle = mv.move_type {
+            let (rook_from, rook_to) = rook_castling(to);
+            self.toggle_square(PieceType::Rook, rook_to, color);
+            self.toggle_square(PieceType::Rook, rook_from, color);
+            self.toggle_square(PieceType::King, to, color);
+        } else if let GameMoveType::Promotion(promo, _) = mv.move_type {
+            self.toggle_square(promo, to, color);
+        } else {
+            self.toggle_square(mv.piece_type, to, color);
         }
+        self.toggle_square(mv.piece_type, from, color);
+        if let Some(piece) = mv.get_maybe_captured_piece() {
+            self.toggle_square(piece, to ^ (8 * (mv.move_type == GameMoveType::EnPassant) as usize), swap_side(color));
+        }
+        //Hash, psqt and all other incremental fields are simply restored
+        self.irreversible = irreversible;
+    }
+
+    pub fn do_nullmove(&mut self) {
+        let irreversible = self.apply_nullmove();
+        self.undo_stack.push(irreversible);
+        self.accumulators.push(DirtyPieces::default());
+    }
+
+    pub fn undo_nullmove(&mut self) {
+        self.irreversible = self.undo_stack.pop().expect("Can't undo null move, undo stack is empty!");
+        self.accumulators.pop();
+        self.color_to_move = swap_side(self.color_to_move);
+        self.full_moves -= self.color_to_move;
     }
-    //Move rook for castling
-    if let GameMoveType::Castle = mv.move_type {
-        add_piece(&mut piece_bb, &mut color_bb, mv.piece_type, to, color, &mut hash, &mut psqt);
-        let (rook_from, rook_to) = rook_castling(to);
-        remove_piece(&mut piece_bb, &mut color_bb, PieceType::Rook, rook_from, color, &mut hash, &mut psqt);
-        add_piece(&mut piece_bb, &mut color_bb, PieceType::Rook, rook_to, color, &mut hash, &mut psqt);
-    } else if let GameMoveType::Promotion(promo, _) = mv.move_type {
-        //If promotion, add promotion piece
-        add_piece(&mut piece_bb, &mut color_bb, promo, to, color, &mut hash, &mut psqt);
-        phase.add_piece(promo);
-    } else {
-        //Add piece again at to
-        add_piece(&mut piece_bb, &mut color_bb, mv.piece_type, to, color, &mut hash, &mut psqt);
+
+    #[inline(always)]
+    fn toggle_square(&mut self, piece: PieceType, sq: usize, color: usize) {
+        self.piece_bb[piece as usize] ^= square(sq);
+        self.color_bb[color] ^= square(sq);
+    }
+
+    //Returns the irreversible state before the null move
+    fn apply_nullmove(&mut self) -> Irreversible {
+        let en_passant = 0u64;
+        let half_moves = self.get_half_moves() + 1;
+        let mut hash = self.get_hash() ^ ZOBRIST_KEYS.side_to_move;
+        enpassant_hash(self.get_en_passant(), en_passant, &mut hash);
+        let irreversible = Irreversible::new(
+            hash,
+            self.get_material_key(),
+            en_passant,
+            half_moves as u16,
+            self.castle_permissions(),
+            self.get_phase().clone(),
+            self.get_psqt(),
+        );
+        self.full_moves += self.color_to_move;
+        self.color_to_move = swap_side(self.color_to_move);
+        std::mem::replace(&mut self.irreversible, irreversible)
+    }
+
+    //Returns the irreversible state before the move
+    #[inline(always)]
+    fn apply_move(&mut self, mv: GameMove) -> Irreversible {
+        let g = &*self;
+        //Step 1. Update immediate fields
+        let color = g.get_color_to_move();
+        let full_moves = g.get_full_moves() + color;
+        //Step 2. Update pieces, hash and other incremental fields
+        let mut piece_bb = g.get_piece_bb_array();
+        let mut color_bb = g.get_color_bb_array();
+        let mut hash = g.get_hash() ^ ZOBRIST_KEYS.side_to_move;
+        let mut material_key = g.get_material_key();
+        let mut psqt = g.get_psqt();
+        let mut phase = g.get_phase().clone();
+        let to = mv.to as usize;
+        let from = mv.from as usize;
+        let mut our_king_square = g.get_king_square(color);
+        //Remove piece from original square
+        if mv.piece_type == PieceType::King {
+            //Update our KP tables
+            kp_move_king(
+                from,
+                to,
+                g.get_piece(PieceType::Pawn, color),
+                g.get_piece(PieceType::Pawn, swap_side(color)),
+                color,
+                &mut psqt,
+            );
+            our_king_square = to;
+        } else if mv.piece_type == PieceType::Pawn {
+            //Move pawn for enemy king
+            kp_remove_piece(swap_side(color), g.get_king_square(swap_side(color)), false, PieceType::Pawn, from, &mut psqt);
+            kp_add_piece(swap_side(color), g.get_king_square(swap_side(color)), false, PieceType::Pawn, to, &mut psqt);
+            //Move pawn for our king
+            kp_remove_piece(color, our_king_square, true, PieceType::Pawn, from, &mut psqt);
+            kp_add_piece(color, our_king_square, true, PieceType::Pawn, to, &mut psqt);
+        }
+        remove_piece(&mut piece_bb, &mut color_bb, mv.piece_type, from, color, &mut hash, &mut psqt);
+        //Delete piece if capture
+        if let Some(piece) = mv.get_maybe_captured_piece() {
+            let square = to ^ (8 * (mv.move_type == GameMoveType::EnPassant) as usize);
+            remove_piece(&mut piece_bb, &mut color_bb, piece, square, swap_side(color), &mut hash, &mut psqt);
+            phase.delete_piece(piece);
+            material_key -= piece.to_material_key(swap_side(color));
+            if piece == PieceType::Pawn {
+                //Remove piece for our king
+                kp_remove_piece(color, our_king_square, false, PieceType::Pawn, square as usize, &mut psqt);
+                //Remove piece for enemy king
+                kp_remove_piece(swap_side(color), g.get_king_square(swap_side(color)), true, PieceType::Pawn, square as usize, &mut psqt);
+            }
+        }
+        //Move rook for castling
+        if let GameMoveType::Castle = mv.move_type {
+            add_piece(&mut piece_bb, &mut color_bb, mv.piece_type, to, color, &mut hash, &mut psqt);
+            let (rook_from, rook_to) = rook_castling(to);
+            remove_piece(&mut piece_bb, &mut color_bb, PieceType::Rook, rook_from, color, &mut hash, &mut psqt);
+            add_piece(&mut piece_bb, &mut color_bb, PieceType::Rook, rook_to, color, &mut hash, &mut psqt);
+        } else if let GameMoveType::Promotion(promo, _) = mv.move_type {
+            //If promotion, add promotion piece
+            add_piece(&mut piece_bb, &mut color_bb, promo, to, color, &mut hash, &mut psqt);
+            phase.add_piece(promo);
+            material_key = material_key - PieceType::Pawn.to_material_key(color) + promo.to_material_key(color);
+        } else {
+            //Add piece again at to
+            add_piece(&mut piece_bb, &mut color_bb, mv.piece_type, to, color, &mut hash, &mut psqt);
+        }
+        //Step 3. Update Castling Rights
+        let castle_permissions = g.castle_permissions() & CASTLE_PERMISSION[from] & CASTLE_PERMISSION[to];
+        castle_hash(g, castle_permissions, &mut hash);
+        //Step 4. Update en passant field
+        let en_passant = if mv.move_type == GameMoveType::Quiet && mv.piece_type == PieceType::Pawn && (to as isize - from as isize).abs() == 16 {
+            square(ep_pawn_square(to))
+        } else {
+            0u64
+        };
+        enpassant_hash(g.get_en_passant(), en_passant, &mut hash);
+        //Step 5. Half moves
+        let half_moves = if mv.move_type == GameMoveType::Quiet && mv.piece_type != PieceType::Pawn {
+            g.get_half_moves() + 1
+        } else {
+            0
+        };
+        self.color_to_move = swap_side(color);
+        self.piece_bb = piece_bb;
+        self.color_bb = color_bb;
+        self.full_moves = full_moves;
+        std::mem::replace(
+            &mut self.irreversible,
+            Irreversible::new(hash, material_key, en_passant, half_moves as u16, castle_permissions, phase, psqt),
+        )
     }
-    //Step 3. Update Castling Rights
-    let castle_permissions = g.castle_permissions() & CASTLE_PERMISSION[from] & CASTLE_PERMISSION[to];
-    castle_hash(g, castle_permissions, &mut hash);
-    //Step 4. Update en passant field
-    let en_passant = if mv.move_type == GameMoveType::Quiet && mv.piece_type == PieceType::Pawn && (to as isize - from as isize).abs() == 16 {
-        square(ep_pawn_square(to))
-    } else {
-        0u64
-    };
-    enpassant_hash(g.get_en_passant(), en_passant, &mut hash);
-    //Step 5. Half moves
-    let half_moves = if mv.move_type == GameMoveType::Quiet && mv.piece_type != PieceType::Pawn {
-        g.get_half_moves() + 1
-    } else {
-        0
-    };
-    GameState::new(
-        swap_side(color),
-        piece_bb,
-        color_bb,
-        Irreversible::new(hash, en_passant, half_moves as u16, castle_permissions, phase, psqt),
-        full_moves,
-    )
 }
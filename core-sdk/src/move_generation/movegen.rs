@@ -75,6 +75,27 @@ impl GameState {
             | pawn_targets(self.get_color_to_move(), square) & self.get_piece(PieceType::Pawn, swap_side(self.get_color_to_move()))
     }
 
+    //Pieces of side which are pinned to their king by an enemy slider
+    pub fn get_pinned_pieces(&self, side: usize) -> u64 {
+        let king_square = self.get_king_square(side);
+        let all_pieces = self.get_all_pieces();
+        let side_pieces = self.get_pieces_from_side(side);
+        let mut pinned_pieces = 0u64;
+        let mut rook_pinners = xray_rook_attacks(rook_attack(king_square, all_pieces), all_pieces, side_pieces, king_square) & self.get_rook_like_bb(swap_side(side));
+        while rook_pinners != 0u64 {
+            let pinner_square = rook_pinners.trailing_zeros() as usize;
+            pinned_pieces |= ROOK_RAYS[king_square][pinner_square] & side_pieces;
+            rook_pinners ^= square(pinner_square);
+        }
+        let mut bishop_pinners = xray_bishop_attacks(bishop_attack(king_square, all_pieces), all_pieces, side_pieces, king_square) & self.get_bishop_like_bb(swap_side(side));
+        while bishop_pinners != 0u64 {
+            let pinner_square = bishop_pinners.trailing_zeros() as usize;
+            pinned_pieces |= BISHOP_RAYS[king_square][pinner_square] & side_pieces;
+            bishop_pinners ^= square(pinner_square);
+        }
+        pinned_pieces
+    }
+
     pub fn get_checkers(&self) -> u64 {
         self.square_attackers(self.get_king_square(self.get_color_to_move()), self.get_all_pieces())
     }
@@ -185,10 +206,9 @@ pub fn xray_bishop_attacks(bishop_attacks: u64, occupied_squares: u64, my_pieces
 #[inline(always)]
 pub fn add_pin_moves_to_movelist(
     legal_moves: &mut MoveList,
-    only_captures: bool,
+    captures: bool,
+    quiets: bool,
     ray_to_king: u64,
-    push_mask: u64,
-    capture_mask: u64,
     enemy_pinner: u64,
     pinned_piece_position: usize,
     moving_piece_type: PieceType,
@@ -196,12 +216,11 @@ pub fn add_pin_moves_to_movelist(
     enemy_queens: u64,
     other_pinner_piece_type: PieceType,
 ) {
-    let pin_quiet_targets = ray_to_king & push_mask & !square(pinned_piece_position);
-    let pin_capture_possible = (capture_mask & enemy_pinner) != 0u64;
-    if !only_captures {
+    let pin_quiet_targets = ray_to_king & !square(pinned_piece_position);
+    if quiets {
         add_moves_to_movelist(legal_moves, pinned_piece_position, pin_quiet_targets, moving_piece_type, GameMoveType::Quiet);
     }
-    if pin_capture_possible {
+    if captures {
         add_move_to_movelist(
             legal_moves,
             pinned_piece_position,
@@ -217,22 +236,23 @@ pub fn add_pin_moves_to_movelist(
 }
 
 #[inline(always)]
-pub fn add_king_moves_to_movelist(g: &GameState, legal_moves: &mut MoveList, only_captures: bool, stm_legal_kingmoves: u64, stm_king_index: usize, enemy_pieces: u64) {
-    let mut captures = stm_legal_kingmoves & enemy_pieces;
-    let quiets = stm_legal_kingmoves & !captures;
-    while captures != 0u64 {
-        let capture_index = captures.trailing_zeros() as usize;
-        add_move_to_movelist(
-            legal_moves,
-            stm_king_index,
-            capture_index,
-            PieceType::King,
-            GameMoveType::Capture(find_captured_piece_type(g, capture_index)),
-        );
-        captures ^= square(capture_index);
+pub fn add_king_moves_to_movelist(g: &GameState, legal_moves: &mut MoveList, captures: bool, quiets: bool, stm_legal_kingmoves: u64, stm_king_index: usize, enemy_pieces: u64) {
+    if captures {
+        let mut captures = stm_legal_kingmoves & enemy_pieces;
+        while captures != 0u64 {
+            let capture_index = captures.trailing_zeros() as usize;
+            add_move_to_movelist(
+                legal_moves,
+                stm_king_index,
+                capture_index,
+                PieceType::King,
+                GameMoveType::Capture(find_captured_piece_type(g, capture_index)),
+            );
+            captures ^= square(capture_index);
+        }
     }
-    if !only_captures {
-        add_moves_to_movelist(legal_moves, stm_king_index, quiets, PieceType::King, GameMoveType::Quiet);
+    if quiets {
+        add_moves_to_movelist(legal_moves, stm_king_index, stm_legal_kingmoves & !enemy_pieces, PieceType::King, GameMoveType::Quiet);
     }
 }
 
@@ -270,27 +290,29 @@ pub fn add_normal_moves_to_movelist(
     empty_squares: u64,
     push_mask: u64,
     capture_mask: u64,
-    only_captures: bool,
+    captures: bool,
+    quiets: bool,
 ) {
     while piece_board != 0u64 {
         let piece_index = piece_board.trailing_zeros() as usize;
         let piece = square(piece_index);
         if pinned_pieces & piece == 0 {
             let piece_target = piece_type.attacks(piece_index, g.get_all_pieces());
-            let mut captures = piece_target & capture_mask & enemy_pieces;
-            while captures != 0u64 {
-                let capture_index = captures.trailing_zeros() as usize;
-                add_move_to_movelist(
-                    legal_moves,
-                    piece_index,
-                    capture_index,
-                    piece_type,
-                    GameMoveType::Capture(find_captured_piece_type(g, capture_index)),
-                );
-                captures ^= square(capture_index);
+            if captures {
+                let mut captures = piece_target & capture_mask & enemy_pieces;
+                while captures != 0u64 {
+                    let capture_index = captures.trailing_zeros() as usize;
+                    add_move_to_movelist(
+                        legal_moves,
+                        piece_index,
+                        capture_index,
+                        piece_type,
+                        GameMoveType::Capture(find_captured_piece_type(g, capture_index)),
+                    );
+                    captures ^= square(capture_index);
+                }
             }
-
-            if !only_captures {
+            if quiets {
                 let quiets = piece_target & push_mask & empty_squares;
                 add_moves_to_movelist(legal_moves, piece_index, quiets, piece_type, GameMoveType::Quiet);
             }
@@ -299,6 +321,28 @@ pub fn add_normal_moves_to_movelist(
     }
 }
 
+//Adds the en passant capture onto the target, unless the capturing pawn is pinned or removing both pawns uncovers a rook-like attack along the rank
+#[inline(always)]
+pub fn add_enpassant_to_movelist(g: &GameState, legal_moves: &mut MoveList, enpassant_target: u64, shift: usize, pinned_pieces: u64, all_pieces: u64) {
+    if enpassant_target == 0u64 {
+        return;
+    }
+    let side = g.get_color_to_move();
+    let pawn_index = enpassant_target.trailing_zeros() as usize;
+    let (pawn_from, removed_piece_index) = if side == WHITE {
+        (pawn_index - shift, pawn_index - 8)
+    } else {
+        (pawn_index + shift, pawn_index + 8)
+    };
+    if square(pawn_from) & pinned_pieces != 0u64 {
+        return;
+    }
+    let all_pieces_without_en_passants = all_pieces & !square(pawn_from) & !square(removed_piece_index);
+    if rook_attack(g.get_king_square(side), all_pieces_without_en_passants) & RANKS[rank_of(g.get_king_square(side))] & g.get_rook_like_bb(swap_side(side)) == 0u64 {
+        add_move_to_movelist(legal_moves, pawn_from, pawn_index, PieceType::Pawn, GameMoveType::EnPassant);
+    }
+}
+
 #[inline(always)]
 pub fn add_promotion_move_to_movelist(legal_moves: &mut MoveList, from_square: usize, to_square: usize, move_type: GameMoveType) {
     let new_types = if let GameMoveType::Capture(x) = move_type {
@@ -396,12 +440,121 @@ impl MoveList {
     }
 }
 
+//Generates all legal moves. If only_captures is set, quiet moves and quiet promotions are left out.
 pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveList) -> AdditionalGameStateInformation {
+    movelist.move_list.clear();
+    let check_board = g.get_checkers();
+    if check_board != 0u64 {
+        add_evasions_to_movelist(g, check_board, movelist);
+        if only_captures {
+            movelist.move_list.retain(|gmv| gmv.0.is_capture());
+        }
+    } else {
+        add_non_evasions_to_movelist(g, true, !only_captures, !only_captures, movelist);
+    }
+    AdditionalGameStateInformation { stm_incheck: check_board != 0u64 }
+}
+
+//Staged generation for a side which is not in check. Captures and all promotions are generated first, quiet moves (castling included)
+//are only appended on demand. Both stages together generate exactly the moves of generate_moves.
+pub fn generate_captures(g: &GameState, movelist: &mut MoveList) {
+    debug_assert!(!g.in_check());
+    movelist.move_list.clear();
+    add_non_evasions_to_movelist(g, true, true, false, movelist);
+}
+
+pub fn generate_quiets(g: &GameState, movelist: &mut MoveList) {
+    debug_assert!(!g.in_check());
+    add_non_evasions_to_movelist(g, false, false, true, movelist);
+}
+
+//Generates all legal moves for a side in check. Only king moves, captures of the checker and blocks of a sliding check need to be considered.
+pub fn generate_evasions(g: &GameState, movelist: &mut MoveList) {
+    movelist.move_list.clear();
+    add_evasions_to_movelist(g, g.get_checkers(), movelist);
+}
+
+fn add_evasions_to_movelist(g: &GameState, check_board: u64, movelist: &mut MoveList) {
+    debug_assert!(check_board != 0u64);
+    let side = g.get_color_to_move();
+    let enemy = swap_side(side);
+    let stm_color_iswhite: bool = side == WHITE;
+    let king_square = g.get_king_square(side);
+    let side_pieces = g.get_pieces_from_side(side);
+    let enemy_pieces = g.get_pieces_from_side(enemy);
+    let all_pieces = enemy_pieces | side_pieces;
+    let empty_squares = !all_pieces;
+
+    //1. Safe King moves. Enemy sliders xray our king, so we can't step back along the checking ray
+    let stm_legal_kingmoves = KING_ATTACKS[king_square] & !g.get_attacks_from_side(enemy) & !side_pieces;
+    add_king_moves_to_movelist(g, movelist, true, true, stm_legal_kingmoves, king_square, enemy_pieces);
+    if check_board.count_ones() > 1 {
+        //Double check, only safe king moves are legal
+        return;
+    }
+
+    //2. Capture the checker or block a sliding check. Pinned pieces can never do either
+    let capture_mask = check_board;
+    let push_mask = if check_board & (g.get_bishop_like_bb(enemy) | g.get_piece(PieceType::Rook, enemy)) != 0u64 {
+        let checker_square = check_board.trailing_zeros() as usize;
+        if check_board & FREEFIELD_ROOK_ATTACKS[king_square] != 0u64 {
+            ROOK_RAYS[king_square][checker_square]
+        } else {
+            BISHOP_RAYS[king_square][checker_square]
+        }
+    } else {
+        0u64
+    };
+    let pinned_pieces = g.get_pinned_pieces(side);
+    let side_pawns = g.get_piece(PieceType::Pawn, side) & !pinned_pieces;
+    let promotion_rank = RANKS[if stm_color_iswhite { 7 } else { 0 }];
+
+    //3. Pawn blocks and captures
+    if push_mask != 0u64 {
+        let stm_pawns_single_push = forward_one(side_pawns, side) & empty_squares;
+        let single_push_blocks = stm_pawns_single_push & push_mask;
+        add_pawn_moves_to_movelist(g, movelist, single_push_blocks & promotion_rank, 8, false, true, pinned_pieces);
+        add_pawn_moves_to_movelist(g, movelist, single_push_blocks & !promotion_rank, 8, false, false, pinned_pieces);
+        let stm_pawns_double_push = double_push_pawn_targets(side, side_pawns, empty_squares) & push_mask;
+        add_pawn_moves_to_movelist(g, movelist, stm_pawns_double_push, 16, false, false, pinned_pieces);
+    }
+    let west_targets = pawn_west_targets(side, side_pawns);
+    let stm_pawn_west_captures = west_targets & capture_mask & enemy_pieces;
+    add_pawn_moves_to_movelist(g, movelist, stm_pawn_west_captures & promotion_rank, 7, true, true, pinned_pieces);
+    add_pawn_moves_to_movelist(g, movelist, stm_pawn_west_captures & !promotion_rank, 7, true, false, pinned_pieces);
+    let east_targets = pawn_east_targets(side, side_pawns);
+    let stm_pawn_east_captures = east_targets & capture_mask & enemy_pieces;
+    add_pawn_moves_to_movelist(g, movelist, stm_pawn_east_captures & promotion_rank, 9, true, true, pinned_pieces);
+    add_pawn_moves_to_movelist(g, movelist, stm_pawn_east_captures & !promotion_rank, 9, true, false, pinned_pieces);
+    //En passant is only an evasion if the double pushed pawn gives check
+    let enpassant_mask = g.get_en_passant() & if stm_color_iswhite { capture_mask << 8 } else { capture_mask >> 8 };
+    add_enpassant_to_movelist(g, movelist, west_targets & enpassant_mask, 7, pinned_pieces, all_pieces);
+    add_enpassant_to_movelist(g, movelist, east_targets & enpassant_mask, 9, pinned_pieces, all_pieces);
+
+    //4. Piece blocks and captures
+    for pt in [PieceType::Knight, PieceType::Queen, PieceType::Bishop, PieceType::Rook].iter() {
+        add_normal_moves_to_movelist(
+            g,
+            movelist,
+            *pt,
+            g.get_piece(*pt, side),
+            pinned_pieces,
+            enemy_pieces,
+            empty_squares,
+            push_mask,
+            capture_mask,
+            true,
+            true,
+        )
+    }
+}
+
+//Generates legal moves for a side which is not in check. Quiet promotions are considered separately from the other quiet moves,
+//since the staged generation searches them together with the captures
+fn add_non_evasions_to_movelist(g: &GameState, captures: bool, quiet_promotions: bool, quiets: bool, movelist: &mut MoveList) {
     //----------------------------------------------------------------------
     //**********************************************************************
     //1. General bitboards and variable initialization
-    movelist.move_list.clear();
-
     let side = g.get_color_to_move();
     let enemy = swap_side(side);
     let stm_color_iswhite: bool = side == WHITE;
@@ -411,50 +564,23 @@ pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveLis
     let enemy_pieces = g.get_pieces_from_side(enemy);
     let all_pieces = enemy_pieces | side_pieces;
     let empty_squares = !all_pieces;
+    let promotion_rank = RANKS[if stm_color_iswhite { 7 } else { 0 }];
 
     let enemy_attacks = g.get_attacks_from_side(enemy); //TODO: Check if square_attacked is faster
 
     //----------------------------------------------------------------------
     //**********************************************************************
     //2. Safe King moves
-    let stm_legal_kingmoves = PieceType::King.attacks(g.get_king_square(side), all_pieces) & !enemy_attacks & !side_pieces;
-    add_king_moves_to_movelist(g, movelist, only_captures, stm_legal_kingmoves, g.get_king_square(side), enemy_pieces);
-    //----------------------------------------------------------------------
-    //**********************************************************************
-    //3. Check & Check Evasions
-    let check_board = g.get_checkers();
-    let checkers = check_board.count_ones() as usize;
-    let stm_incheck = checkers > 0;
-
-    let mut capture_mask = 0xFFFF_FFFF_FFFF_FFFFu64;
-    let mut push_mask = 0xFFFF_FFFF_FFFF_FFFFu64;
-    if checkers > 1 {
-        //Double check, only safe king moves are legal
-        return AdditionalGameStateInformation { stm_incheck };
-    } else if checkers == 1 {
-        //Only a single checker
-        capture_mask = check_board;
-        //If it's a slider, we can also push in its way
-        if check_board & (g.get_bishop_like_bb(enemy) | g.get_piece(PieceType::Rook, enemy)) != 0u64 {
-            let checker_square = check_board.trailing_zeros() as usize;
-            if check_board & (FREEFIELD_ROOK_ATTACKS[g.get_king_square(side)]) != 0u64 {
-                //Checker is rook-like
-                push_mask = ROOK_RAYS[g.get_king_square(side)][checker_square];
-            } else {
-                //Checker is bishop-like
-                push_mask = BISHOP_RAYS[g.get_king_square(side)][checker_square];
-            }
-        } else {
-            //else, we can't do push (quiet) moves
-            push_mask = 0u64;
-        }
+    if captures || quiets {
+        let stm_legal_kingmoves = KING_ATTACKS[g.get_king_square(side)] & !enemy_attacks & !side_pieces;
+        add_king_moves_to_movelist(g, movelist, captures, quiets, stm_legal_kingmoves, g.get_king_square(side), enemy_pieces);
     }
 
     //----------------------------------------------------------------------
     //**********************************************************************
-    //4. Pins and pinned pieces
+    //3. Pins and pinned pieces. A pinned piece can only move along the ray between our king and the pinner, or capture the pinner
     let mut pinned_pieces = 0u64;
-    //4.1 Rook-Like pins
+    //3.1 Rook-Like pins
     if FREEFIELD_ROOK_ATTACKS[g.get_king_square(side)] & g.get_rook_like_bb(enemy) != 0u64 {
         let stm_rook_attacks_from_king = rook_attack(g.get_king_square(side), all_pieces);
         let stm_xray_rook_attacks_from_king = xray_rook_attacks(stm_rook_attacks_from_king, all_pieces, side_pieces, g.get_king_square(side));
@@ -470,10 +596,9 @@ pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveLis
                 //Add possible queen pushes
                 add_pin_moves_to_movelist(
                     movelist,
-                    only_captures,
+                    captures,
+                    quiets,
                     ray_to_king,
-                    push_mask,
-                    capture_mask,
                     enemy_rook,
                     pinned_piece_position,
                     PieceType::Queen,
@@ -485,10 +610,9 @@ pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveLis
                 //Add possible rook pushes
                 add_pin_moves_to_movelist(
                     movelist,
-                    only_captures,
+                    captures,
+                    quiets,
                     ray_to_king,
-                    push_mask,
-                    capture_mask,
                     enemy_rook,
                     pinned_piece_position,
                     PieceType::Rook,
@@ -499,9 +623,9 @@ pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveLis
             } else if pinned_piece & side_pawns != 0u64 {
                 //Add possible pawn pushes
                 side_pawns ^= pinned_piece;
-                let stm_pawn_pin_single_push = single_push_pawn_targets(side, pinned_piece, empty_squares) & ray_to_king & push_mask;
-                let stm_pawn_pin_double_push = double_push_pawn_targets(side, pinned_piece, empty_squares) & ray_to_king & push_mask;
-                if !only_captures {
+                let stm_pawn_pin_single_push = single_push_pawn_targets(side, pinned_piece, empty_squares) & ray_to_king;
+                let stm_pawn_pin_double_push = double_push_pawn_targets(side, pinned_piece, empty_squares) & ray_to_king;
+                if quiets {
                     add_moves_to_movelist(
                         movelist,
                         pinned_piece_position,
@@ -514,7 +638,7 @@ pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveLis
             enemy_rooks_on_xray ^= enemy_rook;
         }
     }
-    //4.2 Bishop-Like pins
+    //3.2 Bishop-Like pins
     if FREEFIELD_BISHOP_ATTACKS[g.get_king_square(side)] & g.get_bishop_like_bb(enemy) != 0u64 {
         let stm_bishop_attacks_from_king = bishop_attack(g.get_king_square(side), all_pieces);
         let stm_xray_bishop_attacks_from_king = xray_bishop_attacks(stm_bishop_attacks_from_king, all_pieces, side_pieces, g.get_king_square(side));
@@ -530,10 +654,9 @@ pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveLis
                 //Add possible queen pushes
                 add_pin_moves_to_movelist(
                     movelist,
-                    only_captures,
+                    captures,
+                    quiets,
                     ray_to_king,
-                    push_mask,
-                    capture_mask,
                     enemy_bishop,
                     pinned_piece_position,
                     PieceType::Queen,
@@ -545,10 +668,9 @@ pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveLis
                 //Add possible bishop pushes
                 add_pin_moves_to_movelist(
                     movelist,
-                    only_captures,
+                    captures,
+                    quiets,
                     ray_to_king,
-                    push_mask,
-                    capture_mask,
                     enemy_bishop,
                     pinned_piece_position,
                     PieceType::Bishop,
@@ -560,45 +682,36 @@ pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveLis
                 //Add possible pawn captures
                 side_pawns ^= pinned_piece;
                 let stm_pawn_pin_target = pawn_targets(side, pinned_piece);
-                //Normal captures
-                let stm_pawn_pin_captures = stm_pawn_pin_target & capture_mask & enemy_bishop;
-                let stm_pawn_pin_promotion_capture = stm_pawn_pin_captures & RANKS[if stm_color_iswhite { 7 } else { 0 }];
-                if stm_pawn_pin_promotion_capture != 0u64 {
-                    add_promotion_move_to_movelist(
-                        movelist,
-                        pinned_piece_position,
-                        enemy_bishop_position,
-                        GameMoveType::Capture(if enemy_bishop & g.get_piece(PieceType::Queen, enemy) != 0u64 {
-                            PieceType::Queen
-                        } else {
-                            PieceType::Bishop
-                        }),
-                    );
-                }
-                let stm_pawn_pin_nonpromotion_capture = stm_pawn_pin_captures & !stm_pawn_pin_promotion_capture;
-                if stm_pawn_pin_nonpromotion_capture != 0u64 {
-                    add_move_to_movelist(
-                        movelist,
-                        pinned_piece_position,
-                        enemy_bishop_position,
-                        PieceType::Pawn,
-                        GameMoveType::Capture(if enemy_bishop & g.get_piece(PieceType::Queen, enemy) != 0u64 {
-                            PieceType::Queen
-                        } else {
-                            PieceType::Bishop
-                        }),
-                    );
-                }
-                //En passants
-                let stm_pawn_pin_enpassant = stm_pawn_pin_target & g.get_en_passant() & capture_mask & ray_to_king;
-                if stm_pawn_pin_enpassant != 0u64 {
-                    add_move_to_movelist(
-                        movelist,
-                        pinned_piece_position,
-                        stm_pawn_pin_enpassant.trailing_zeros() as usize,
-                        PieceType::Pawn,
-                        GameMoveType::EnPassant,
-                    );
+                if captures {
+                    //Normal captures
+                    let stm_pawn_pin_captures = stm_pawn_pin_target & enemy_bishop;
+                    let captured_piece = if enemy_bishop & g.get_piece(PieceType::Queen, enemy) != 0u64 {
+                        PieceType::Queen
+                    } else {
+                        PieceType::Bishop
+                    };
+                    if stm_pawn_pin_captures & promotion_rank != 0u64 {
+                        add_promotion_move_to_movelist(movelist, pinned_piece_position, enemy_bishop_position, GameMoveType::Capture(captured_piece));
+                    } else if stm_pawn_pin_captures != 0u64 {
+                        add_move_to_movelist(
+                            movelist,
+                            pinned_piece_position,
+                            enemy_bishop_position,
+                            PieceType::Pawn,
+                            GameMoveType::Capture(captured_piece),
+                        );
+                    }
+                    //En passants
+                    let stm_pawn_pin_enpassant = stm_pawn_pin_target & g.get_en_passant() & ray_to_king;
+                    if stm_pawn_pin_enpassant != 0u64 {
+                        add_move_to_movelist(
+                            movelist,
+                            pinned_piece_position,
+                            stm_pawn_pin_enpassant.trailing_zeros() as usize,
+                            PieceType::Pawn,
+                            GameMoveType::EnPassant,
+                        );
+                    }
                 }
             }
             enemy_bishop_on_xray ^= enemy_bishop;
@@ -607,138 +720,122 @@ pub fn generate_moves(g: &GameState, only_captures: bool, movelist: &mut MoveLis
 
     //----------------------------------------------------------------------
     //**********************************************************************
-    //5. Pawn pushes, captures, and promotions (captures, capture-enpassant, capture-promotion, normal-promotion)
-    //5.1 Single push (promotions and pushes)
-    if !only_captures {
-        let stm_pawns_single_push = (forward_one(side_pawns, side) & empty_squares) & push_mask;
-        let stm_pawn_promotions = stm_pawns_single_push & RANKS[if stm_color_iswhite { 7 } else { 0 }];
-        add_pawn_moves_to_movelist(g, movelist, stm_pawn_promotions, 8, false, true, pinned_pieces);
-        let stm_pawns_quiet_single_push = stm_pawns_single_push & !stm_pawn_promotions;
-        add_pawn_moves_to_movelist(g, movelist, stm_pawns_quiet_single_push, 8, false, false, pinned_pieces);
-    }
-
-    //5.2 Double push
-    if !only_captures {
-        let stm_pawns_double_push = double_push_pawn_targets(side, side_pawns, empty_squares) & push_mask;
+    //4. Pawn pushes, captures, and promotions (captures, capture-enpassant, capture-promotion, normal-promotion)
+    //4.1 Single push (promotions and pushes)
+    let stm_pawns_single_push = forward_one(side_pawns, side) & empty_squares;
+    if quiet_promotions {
+        add_pawn_moves_to_movelist(g, movelist, stm_pawns_single_push & promotion_rank, 8, false, true, pinned_pieces);
+    }
+    if quiets {
+        add_pawn_moves_to_movelist(g, movelist, stm_pawns_single_push & !promotion_rank, 8, false, false, pinned_pieces);
+        //4.2 Double push
+        let stm_pawns_double_push = double_push_pawn_targets(side, side_pawns, empty_squares);
         add_pawn_moves_to_movelist(g, movelist, stm_pawns_double_push, 16, false, false, pinned_pieces);
     }
 
-    //5.3 West captures (normal capture, promotion capture, en passant)
-    let west_targets = pawn_west_targets(side, side_pawns);
-    let stm_pawn_west_captures = west_targets & capture_mask & enemy_pieces;
-    //Split up in promotion and non-promotion captures
-    let stm_pawn_west_promotion_capture = stm_pawn_west_captures & RANKS[if stm_color_iswhite { 7 } else { 0 }];
-    add_pawn_moves_to_movelist(g, movelist, stm_pawn_west_promotion_capture, 7, true, true, pinned_pieces);
-    let stm_pawn_west_nonpromotion_capture = stm_pawn_west_captures & !stm_pawn_west_promotion_capture;
-    add_pawn_moves_to_movelist(g, movelist, stm_pawn_west_nonpromotion_capture, 7, true, false, pinned_pieces);
-    //En passants
-    let stm_pawn_west_enpassants = west_targets & g.get_en_passant() & if stm_color_iswhite { capture_mask << 8 } else { capture_mask >> 8 };
-    if stm_pawn_west_enpassants != 0u64
-        && if stm_color_iswhite {
-            stm_pawn_west_enpassants >> 7
-        } else {
-            stm_pawn_west_enpassants << 7
-        } & pinned_pieces
-            == 0u64
-    {
-        let pawn_index = stm_pawn_west_enpassants.trailing_zeros() as usize;
-        let (pawn_from, removed_piece_index) = if stm_color_iswhite {
-            (pawn_index - 7, pawn_index - 8)
-        } else {
-            (pawn_index + 7, pawn_index + 8)
-        };
-        let all_pieces_without_en_passants = all_pieces & !square(pawn_from) & !square(removed_piece_index);
-        if rook_attack(g.get_king_square(side), all_pieces_without_en_passants) & RANKS[rank_of(g.get_king_square(side))] & g.get_rook_like_bb(enemy) == 0u64 {
-            add_move_to_movelist(movelist, pawn_from, pawn_index, PieceType::Pawn, GameMoveType::EnPassant);
-        }
-    }
-    //5.4 East captures (normal capture, promotion capture, en passant)
-    let east_targets = pawn_east_targets(side, side_pawns);
-    let stm_pawn_east_captures = east_targets & capture_mask & enemy_pieces;
-    //Split up in promotion and non-promotion captures
-    let stm_pawn_east_promotion_capture = stm_pawn_east_captures & RANKS[if stm_color_iswhite { 7 } else { 0 }];
-    add_pawn_moves_to_movelist(g, movelist, stm_pawn_east_promotion_capture, 9, true, true, pinned_pieces);
-    let stm_pawn_east_nonpromotion_capture = stm_pawn_east_captures & !stm_pawn_east_promotion_capture;
-    add_pawn_moves_to_movelist(g, movelist, stm_pawn_east_nonpromotion_capture, 9, true, false, pinned_pieces);
-    //En passants
-    let stm_pawn_east_enpassants = east_targets & g.get_en_passant() & if stm_color_iswhite { capture_mask << 8 } else { capture_mask >> 8 };
-    if stm_pawn_east_enpassants != 0u64
-        && if stm_color_iswhite {
-            stm_pawn_east_enpassants >> 9
-        } else {
-            stm_pawn_east_enpassants << 9
-        } & pinned_pieces
-            == 0u64
-    {
-        let pawn_index = stm_pawn_east_enpassants.trailing_zeros() as usize;
-        let (pawn_from, removed_piece_index) = if stm_color_iswhite {
-            (pawn_index - 9, pawn_index - 8)
-        } else {
-            (pawn_index + 9, pawn_index + 8)
-        };
-        let all_pieces_without_en_passants = all_pieces & !square(pawn_from) & !square(removed_piece_index);
-        if rook_attack(g.get_king_square(side), all_pieces_without_en_passants) & RANKS[rank_of(g.get_king_square(side))] & g.get_rook_like_bb(enemy) == 0u64 {
-            add_move_to_movelist(movelist, pawn_from, pawn_index, PieceType::Pawn, GameMoveType::EnPassant);
-        }
+    if captures {
+        //4.3 West captures (normal capture, promotion capture, en passant)
+        let west_targets = pawn_west_targets(side, side_pawns);
+        let stm_pawn_west_captures = west_targets & enemy_pieces;
+        //Split up in promotion and non-promotion captures
+        add_pawn_moves_to_movelist(g, movelist, stm_pawn_west_captures & promotion_rank, 7, true, true, pinned_pieces);
+        add_pawn_moves_to_movelist(g, movelist, stm_pawn_west_captures & !promotion_rank, 7, true, false, pinned_pieces);
+        add_enpassant_to_movelist(g, movelist, west_targets & g.get_en_passant(), 7, pinned_pieces, all_pieces);
+        //4.4 East captures (normal capture, promotion capture, en passant)
+        let east_targets = pawn_east_targets(side, side_pawns);
+        let stm_pawn_east_captures = east_targets & enemy_pieces;
+        add_pawn_moves_to_movelist(g, movelist, stm_pawn_east_captures & promotion_rank, 9, true, true, pinned_pieces);
+        add_pawn_moves_to_movelist(g, movelist, stm_pawn_east_captures & !promotion_rank, 9, true, false, pinned_pieces);
+        add_enpassant_to_movelist(g, movelist, east_targets & g.get_en_passant(), 9, pinned_pieces, all_pieces);
     }
 
     //----------------------------------------------------------------------
     //**********************************************************************
-    //6. All other legal moves (knights, bishops, rooks, queens)
-    for pt in [PieceType::Knight, PieceType::Queen, PieceType::Bishop, PieceType::Rook].iter() {
-        add_normal_moves_to_movelist(
-            g,
-            movelist,
-            *pt,
-            g.get_piece(*pt, side),
-            pinned_pieces,
-            enemy_pieces,
-            empty_squares,
-            push_mask,
-            capture_mask,
-            only_captures,
-        )
+    //5. All other legal moves (knights, bishops, rooks, queens)
+    if captures || quiets {
+        for pt in [PieceType::Knight, PieceType::Queen, PieceType::Bishop, PieceType::Rook].iter() {
+            add_normal_moves_to_movelist(
+                g,
+                movelist,
+                *pt,
+                g.get_piece(*pt, side),
+                pinned_pieces,
+                enemy_pieces,
+                empty_squares,
+                !0u64,
+                !0u64,
+                captures,
+                quiets,
+            )
+        }
     }
     //----------------------------------------------------------------------
     //**********************************************************************
-    //7. Castling
-    if !only_captures && checkers == 0 {
+    //6. Castling
+    if quiets {
         if stm_color_iswhite {
             if g.castle_white_kingside() && (all_pieces | enemy_attacks) & (square(square::F1) | square(square::G1)) == 0u64 {
-                movelist.add_move(GameMove {
-                    from: g.get_king_square(side) as u8,
-                    to: square::G1 as u8,
-                    move_type: GameMoveType::Castle,
-                    piece_type: PieceType::King,
-                });
+                add_move_to_movelist(movelist, g.get_king_square(side), square::G1, PieceType::King, GameMoveType::Castle);
             }
             if g.castle_white_queenside() && ((all_pieces | enemy_attacks) & (square(square::C1) | square(square::D1)) | all_pieces & square(square::B1)) == 0u64 {
-                movelist.add_move(GameMove {
-                    from: g.get_king_square(side) as u8,
-                    to: square::C1 as u8,
-                    move_type: GameMoveType::Castle,
-                    piece_type: PieceType::King,
-                });
+                add_move_to_movelist(movelist, g.get_king_square(side), square::C1, PieceType::King, GameMoveType::Castle);
             }
         } else {
             if g.castle_black_kingside() && (all_pieces | enemy_attacks) & (square(square::F8) | square(square::G8)) == 0u64 {
-                movelist.add_move(GameMove {
-                    from: g.get_king_square(side) as u8,
-                    to: square::G8 as u8,
-                    move_type: GameMoveType::Castle,
-                    piece_type: PieceType::King,
-                });
+                add_move_to_movelist(movelist, g.get_king_square(side), square::G8, PieceType::King, GameMoveType::Castle);
             }
             if g.castle_black_queenside() && ((all_pieces | enemy_attacks) & (square(square::C8) | square(square::D8)) | all_pieces & square(square::B8)) == 0u64 {
-                movelist.add_move(GameMove {
-                    from: g.get_king_square(side) as u8,
-                    to: square::C8 as u8,
-                    move_type: GameMoveType::Castle,
-                    piece_type: PieceType::King,
-                });
+                add_move_to_movelist(movelist, g.get_king_square(side), square::C8, PieceType::King, GameMoveType::Castle);
             }
         }
     }
-    //----------------------------------------------------------------------
-    AdditionalGameStateInformation { stm_incheck }
+}
+
+//Generates all legal quiet moves which give check, either directly or by discovery (castling included).
+//Quiet promotions are not generated, just like captures are not.
+pub fn generate_quiet_checks(g: &GameState, movelist: &mut MoveList) {
+    if g.in_check() {
+        generate_evasions(g, movelist);
+    } else {
+        movelist.move_list.clear();
+        generate_quiets(g, movelist);
+    }
+    let side = g.get_color_to_move();
+    let enemy_king = g.get_king_square(swap_side(side));
+    let all_pieces = g.get_all_pieces();
+
+    //Squares from which a piece gives direct check
+    let bishop_checks = bishop_attack(enemy_king, all_pieces);
+    let rook_checks = rook_attack(enemy_king, all_pieces);
+    let knight_checks = KNIGHT_ATTACKS[enemy_king];
+    let pawn_checks = pawn_targets(swap_side(side), square(enemy_king));
+    //Our pieces which might uncover an attack of one of our sliders when moving away
+    let discoverers = (bishop_checks | rook_checks) & g.get_pieces_from_side(side);
+
+    movelist.move_list.retain(|gmv| {
+        let mv = gmv.0;
+        match mv.move_type {
+            GameMoveType::Quiet => {
+                let direct_checks = match mv.piece_type {
+                    PieceType::Pawn => pawn_checks,
+                    PieceType::Knight => knight_checks,
+                    PieceType::Bishop => bishop_checks,
+                    PieceType::Rook => rook_checks,
+                    PieceType::Queen => bishop_checks | rook_checks,
+                    PieceType::King => 0u64,
+                };
+                direct_checks & square(mv.to as usize) != 0u64 || discoverers & square(mv.from as usize) != 0u64 && g.gives_check(mv)
+            }
+            GameMoveType::Castle => {
+                let (rook_from, rook_to) = if mv.to as usize == square::G1 || mv.to as usize == square::G8 {
+                    (mv.to as usize + 1, mv.to as usize - 1)
+                } else {
+                    (mv.to as usize - 2, mv.to as usize + 1)
+                };
+                let occ_after_castle = all_pieces ^ square(mv.from as usize) ^ square(mv.to as usize) ^ square(rook_from) ^ square(rook_to);
+                rook_attack(enemy_king, occ_after_castle) & square(rook_to) != 0u64
+            }
+            _ => false,
+        }
+    });
 }
@@ -3,11 +3,12 @@ use super::quiescence::q_search;
 use super::*;
 use super::{MATE_SCORE, MAX_SEARCH_DEPTH, STANDARD_SCORE};
 use crate::evaluation::eval_game_state;
-use crate::move_generation::makemove::{make_move, make_nullmove};
 use crate::search::cache::{CacheEntry, INVALID_STATIC_EVALUATION};
 use crate::search::moveordering::{MoveOrderer, NORMAL_STAGES};
 use crate::search::quiescence::{piece_value, see};
 use crate::search::searcher::Thread;
+#[cfg(feature = "search-trace")]
+use crate::search::trace::PruneReason;
 
 pub const LMP_DEPTH: usize = 4;
 
@@ -25,14 +26,28 @@ pub const SEE_PRUNING_DEPTH: i16 = 6;
 pub const SEE_PRUNING_CAPTURE_MULT: f64 = -23.;
 pub const SEE_PRUNING_QUIET_MULT: f64 = -23.;
 
-pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
+#[inline(always)]
+pub fn principal_variation_search(p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
+    #[cfg(feature = "search-trace")]
+    {
+        thread.trace.enter(&p, false);
+    }
+    let score = search_node(p, thread);
+    #[cfg(feature = "search-trace")]
+    {
+        thread.trace.exit(score);
+    }
+    score
+}
+
+fn search_node(mut p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
     //Step 0. Prepare variables
     thread.search_statistics.add_normal_node(p.current_depth);
     clear_pv(p.current_depth, thread);
     let root = p.current_depth == 0;
     let is_pv_node = p.beta - p.alpha > 1;
     //Step 1. Check timeout and if stop flag is set, if we are main thread
-    if thread.search_statistics.nodes_searched % 4096 == 0 {
+    if thread.search_statistics.nodes_searched % 4096 == 0 || thread.search_statistics.nodes_searched >= thread.node_limit {
         checkup(thread)
     }
     if thread.search_statistics.nodes_searched % 8192 == 0 {
@@ -50,10 +65,25 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
     //Step 3. Check for draw or mate distance pruning if not root (need best move at root)
     if !root {
         if let SearchInstruction::StopSearching(r) = check_for_draw(p.game_state, &thread.history) {
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_node(PruneReason::Draw);
+            }
             return r;
         }
+        if let SearchInstruction::StopSearching(res) = upcoming_repetition(&mut p, &thread.history) {
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_node(PruneReason::UpcomingRepetition);
+            }
+            return res;
+        }
         //Mate distance pruning
         if let SearchInstruction::StopSearching(res) = mate_distance_pruning(&mut p) {
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_node(PruneReason::MateDistance);
+            }
             return res;
         }
     }
@@ -84,11 +114,19 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
 
     //Step 8. TT Lookup
     let mut tt_entry: Option<CacheEntry> = None;
-    if let SearchInstruction::StopSearching(res) = thread.itcs.cache().lookup(&p, &mut tt_entry) {
+    #[cfg(feature = "search-statistics")]
+    {
+        thread.search_statistics.add_cache_probe();
+    }
+    if let SearchInstruction::StopSearching(res) = thread.cache.lookup(&p, &mut tt_entry) {
         #[cfg(feature = "search-statistics")]
         {
             thread.search_statistics.add_cache_hit_aj_replace_ns();
         }
+        #[cfg(feature = "search-trace")]
+        {
+            thread.trace.prune_node(PruneReason::TTCutoff);
+        }
         return res;
     }
     #[cfg(feature = "search-statistics")]
@@ -105,9 +143,13 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
     let static_evaluation = if tt_entry.is_some() && tt_entry.unwrap().static_evaluation != INVALID_STATIC_EVALUATION {
         tt_entry.unwrap().static_evaluation
     } else {
-        eval_game_state(p.game_state).final_eval
+        static_evaluation(p.game_state, thread)
     };
     thread.eval_hist[p.current_depth] = Some(static_evaluation);
+    #[cfg(feature = "search-trace")]
+    {
+        thread.trace.set_static_evaluation(static_evaluation);
+    }
     thread.history.push(p.game_state.get_hash(), p.game_state.get_half_moves() == 0);
 
     //Step 9. Static Eval if needed
@@ -144,10 +186,18 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
     if prunable {
         //Step 10.1 Static Null Move Pruning
         if let SearchInstruction::StopSearching(res) = static_null_move_pruning(&p, thread, static_evaluation) {
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_node(PruneReason::StaticNullMove);
+            }
             return res;
         }
         //Step 10.2 Null Move Forward Pruning
-        if let SearchInstruction::StopSearching(res) = null_move_pruning(&p, thread, static_evaluation, &tt_entry) {
+        if let SearchInstruction::StopSearching(res) = null_move_pruning(&mut p, thread, static_evaluation, &tt_entry) {
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_node(PruneReason::NullMove);
+            }
             return res;
         }
     }
@@ -159,11 +209,7 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
     let mut index: usize = 0;
     let mut quiets_tried: usize = 0;
     let mut search_quiets = true;
-    let mut move_orderer = MoveOrderer {
-        stage: 0,
-        stages: &NORMAL_STAGES,
-        gen_only_captures: false,
-    };
+    let mut move_orderer = MoveOrderer::new(&NORMAL_STAGES, incheck);
     loop {
         let mv = move_orderer.next(thread, &p, pv_table_move, tt_move, search_quiets);
         if mv.is_none() {
@@ -186,6 +232,10 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
                 {
                     thread.search_statistics.add_futil_pruning();
                 }
+                #[cfg(feature = "search-trace")]
+                {
+                    thread.trace.prune_move(mv, PruneReason::Futility);
+                }
                 index += 1;
                 search_quiets = false;
                 continue;
@@ -196,11 +246,23 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
                 {
                     thread.search_statistics.add_history_pruned();
                 }
+                #[cfg(feature = "search-trace")]
+                {
+                    thread.trace.prune_move(mv, PruneReason::History);
+                }
                 index += 1;
                 continue;
             }
 
             if !incheck && p.depth_left <= LMP_DEPTH as i16 && quiets_tried > (3 * 2u32.pow((p.depth_left - 1) as u32)) as usize {
+                #[cfg(feature = "search-statistics")]
+                {
+                    thread.search_statistics.add_lmp_pruned();
+                }
+                #[cfg(feature = "search-trace")]
+                {
+                    thread.trace.prune_move(mv, PruneReason::LateMove);
+                }
                 index += 1;
                 search_quiets = false;
                 continue;
@@ -210,6 +272,14 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
             if p.depth_left <= SEE_PRUNING_DEPTH && -piece_value(mv.piece_type) < margin {
                 let see_value = see(p.game_state, mv, true, &mut thread.see_buffer);
                 if see_value < margin {
+                    #[cfg(feature = "search-statistics")]
+                    {
+                        thread.search_statistics.add_see_pruned();
+                    }
+                    #[cfg(feature = "search-trace")]
+                    {
+                        thread.trace.prune_move(mv, PruneReason::SEE);
+                    }
                     index += 1;
                     continue;
                 }
@@ -222,6 +292,14 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
             && p.game_state.has_non_pawns(p.game_state.get_color_to_move())
             && !gives_check
         {
+            #[cfg(feature = "search-statistics")]
+            {
+                thread.search_statistics.add_see_pruned();
+            }
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_move(mv, PruneReason::SEE);
+            }
             index += 1;
             continue;
         }
@@ -233,7 +311,11 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
             0
         };
 
-        let next_state = make_move(p.game_state, mv);
+        p.game_state.do_move(mv);
+        #[cfg(feature = "search-trace")]
+        {
+            thread.trace.set_move(p.current_depth + 1, Some(mv));
+        }
         //Step 14.8. Search the moves
         let mut following_score: i16;
         if p.depth_left <= 2 || !is_pv_node || index == 0 {
@@ -242,12 +324,12 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
             // zero window again (with reduction). If the reduced zero window search raises alpha, research without reduction
             debug_assert!(!is_pv_node || reduction == 0);
             following_score = -principal_variation_search(
-                CombinedSearchParameters::from(-p.beta, -p.alpha, p.depth_left - 1 - reduction, &next_state, -p.color, p.current_depth + 1),
+                CombinedSearchParameters::from(-p.beta, -p.alpha, p.depth_left - 1 - reduction, p.game_state, -p.color, p.current_depth + 1),
                 thread,
             );
             if reduction > 0 && following_score > p.alpha {
                 following_score = -principal_variation_search(
-                    CombinedSearchParameters::from(-p.beta, -p.alpha, p.depth_left - 1, &next_state, -p.color, p.current_depth + 1),
+                    CombinedSearchParameters::from(-p.beta, -p.alpha, p.depth_left - 1, p.game_state, -p.color, p.current_depth + 1),
                     thread,
                 );
             }
@@ -255,16 +337,17 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
             //We are in a pv node and search with zero window all moves except the first (and with reduction). If
             // the reduced zero window search raises alpha, research
             following_score = -principal_variation_search(
-                CombinedSearchParameters::from(-p.alpha - 1, -p.alpha, p.depth_left - 1 - reduction, &next_state, -p.color, p.current_depth + 1),
+                CombinedSearchParameters::from(-p.alpha - 1, -p.alpha, p.depth_left - 1 - reduction, p.game_state, -p.color, p.current_depth + 1),
                 thread,
             );
             if following_score > p.alpha {
                 following_score = -principal_variation_search(
-                    CombinedSearchParameters::from(-p.beta, -p.alpha, p.depth_left - 1, &next_state, -p.color, p.current_depth + 1),
+                    CombinedSearchParameters::from(-p.beta, -p.alpha, p.depth_left - 1, p.game_state, -p.color, p.current_depth + 1),
                     thread,
                 );
             }
         }
+        p.game_state.undo_move(mv);
 
         //Step 14.9. Update principal variation if move raised current best moves score (does not have to raise alpha)
         // Also update UCI pv
@@ -317,9 +400,9 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
     {
         if p.alpha < p.beta {
             thread.search_statistics.add_normal_node_non_beta_cutoff();
-            if !improving2 {
+            /*if !improving2 {
                 thread.search_statistics.improving2[1] += 1;
-            }
+            }*/
         }
         if current_max_score < p.alpha {
             thread.search_statistics.normal_nodes_fail_lows += 1;
@@ -329,7 +412,7 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
 
     //Step 16. Make TT Entry
     if !thread.self_stop {
-        thread.itcs.cache().insert(
+        thread.cache.insert(
             &p,
             thread.pv_table[p.current_depth].pv[0].expect("Can't unwrap move for TT"),
             current_max_score,
@@ -344,7 +427,7 @@ pub fn principal_variation_search(mut p: CombinedSearchParameters, thread: &mut
 
 #[inline(always)]
 pub fn uci_report_move(p: &CombinedSearchParameters, thread: &mut Thread, mv: GameMove, index: usize) {
-    if p.current_depth == 0 && thread.itcs.get_time_elapsed() > 1000 {
+    if p.current_depth == 0 && !thread.uci_options.quiet && thread.itcs.get_time_elapsed() > 1000 {
         println!("info depth {} currmove {:?} currmovenumber {}", p.depth_left, mv, (index + 1));
     }
 }
@@ -399,16 +482,30 @@ pub fn static_null_move_pruning(p: &CombinedSearchParameters, thread: &mut Threa
 }
 
 #[inline(always)]
-pub fn null_move_pruning(p: &CombinedSearchParameters, thread: &mut Thread, static_evaluation: i16, tt_entry: &Option<CacheEntry>) -> SearchInstruction {
+pub fn null_move_pruning(p: &mut CombinedSearchParameters, thread: &mut Thread, static_evaluation: i16, tt_entry: &Option<CacheEntry>) -> SearchInstruction {
     let tt_do_nmp = tt_entry.is_some() && !tt_entry.unwrap().is_upper_bound() && tt_entry.unwrap().score >= p.beta;
     let tt_dont_nmp = tt_entry.is_some() && !tt_entry.unwrap().is_lower_bound() && tt_entry.unwrap().score < p.beta;
     let static_do_nmp = static_evaluation * p.color >= p.beta;
     if p.depth_left >= NULL_MOVE_PRUNING_DEPTH && p.game_state.has_non_pawns(p.game_state.get_color_to_move()) && (tt_do_nmp || static_do_nmp) && !tt_dont_nmp {
-        let nextgs = make_nullmove(p.game_state);
+        p.game_state.do_nullmove();
+        thread.history.set_nullmove(true);
+        #[cfg(feature = "search-trace")]
+        {
+            thread.trace.set_move(p.current_depth + 1, None);
+        }
         let rat = -principal_variation_search(
-            CombinedSearchParameters::from(-p.beta, -p.beta + 1, (p.depth_left - 4 - p.depth_left / 6).max(0), &nextgs, -p.color, p.current_depth + 1),
+            CombinedSearchParameters::from(
+                -p.beta,
+                -p.beta + 1,
+                (p.depth_left - 4 - p.depth_left / 6).max(0),
+                p.game_state,
+                -p.color,
+                p.current_depth + 1,
+            ),
             thread,
         );
+        p.game_state.undo_nullmove();
+        thread.history.set_nullmove(false);
         if rat >= p.beta {
             #[cfg(feature = "search-statistics")]
             {
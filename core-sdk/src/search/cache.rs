@@ -1,6 +1,6 @@
 use crate::board_representation::game_state::{swap_side, GameMove, GameMoveType, GameState, PieceType, PIECE_TYPES};
 use crate::search::{CombinedSearchParameters, SearchInstruction, MATED_IN_MAX};
-use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 
 pub const INVALID_STATIC_EVALUATION: i16 = -32768;
 pub const DEFAULT_HASH_SIZE: usize = 256; //IN MB
@@ -11,16 +11,31 @@ pub const MAXIMUM_AGE: u8 = 32;
 pub struct Cache {
     pub entries: usize,
     pub buckets: usize,
-    pub cache: UnsafeCell<Vec<CacheBucket>>,
-    pub current_age: u8,
+    pub cache: Vec<CacheBucket>,
+    pub current_age: AtomicU8,
 }
 
-unsafe impl std::marker::Sync for Cache {}
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            entries: 0,
+            buckets: 0,
+            cache: Vec::new(),
+            current_age: AtomicU8::new(0),
+        }
+    }
+}
 
 impl Cache {
-    pub fn increase_age(&mut self) {
-        self.current_age = self.current_age.wrapping_add(1);
-        self.current_age %= MAXIMUM_AGE;
+    // Only called between searches, so a plain load and store suffices.
+    pub fn increase_age(&self) {
+        let age = self.current_age.load(Ordering::Relaxed);
+        self.current_age.store(age.wrapping_add(1) % MAXIMUM_AGE, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn get_current_age(&self) -> u8 {
+        self.current_age.load(Ordering::Relaxed)
     }
 
     pub fn score_to_tt_score(score: i16, current_depth: i16) -> i16 {
@@ -49,12 +64,12 @@ impl Cache {
     pub fn with_size_threaded(mb_size: usize, num_threads: usize) -> Self {
         let buckets = 1024 * 1024 * mb_size / 64;
         let entries = buckets * 3;
-        let cache = UnsafeCell::new(Cache::get_init_cache(buckets, num_threads));
+        let cache = Cache::get_init_cache(buckets, num_threads);
         Cache {
             entries,
             buckets,
             cache,
-            current_age: 0u8,
+            current_age: AtomicU8::new(0),
         }
     }
 
@@ -64,7 +79,6 @@ impl Cache {
         // and a startup time of >1min is unacceptable.
         let mut cache_vec: Vec<CacheBucket> = Vec::with_capacity(buckets);
         unsafe {
-            cache_vec.set_len(buckets);
             let chunksize = (buckets + num_threads - 1) / num_threads;
 
             let mut ptr = cache_vec.as_mut_ptr();
@@ -79,7 +93,7 @@ impl Cache {
                 handles.push(std::thread::spawn(move || {
                     let mut inner_ptr = w.p;
                     for _ in 0..this_chunk {
-                        *inner_ptr = CacheBucket::default();
+                        inner_ptr.write(CacheBucket::default());
                         inner_ptr = inner_ptr.offset(1);
                     }
                 }));
@@ -89,6 +103,7 @@ impl Cache {
             for handle in handles {
                 handle.join().expect("Could not unwrap handle while initializing the cache!");
             }
+            cache_vec.set_len(buckets);
         }
         cache_vec
     }
@@ -102,18 +117,16 @@ impl Cache {
         let mut full = 0;
 
         let mut index = 0;
-        while index < unsafe { (&*self.cache.get()).len() } && counted_entries < 500 {
-            let bucket = unsafe { (&*self.cache.get()).get(index).unwrap() };
+        while index < self.cache.len() && counted_entries < 500 {
+            full += self.cache[index].fill_status();
             index += 1;
-            full += bucket.fill_status();
             counted_entries += 3;
         }
         //Count upper 500 entries
-        let mut index = unsafe { (&*self.cache.get()).len() - 1 };
+        let mut index = self.cache.len() - 1;
         while counted_entries < 1000 {
-            let bucket = unsafe { (&*self.cache.get()).get(index).unwrap() };
             debug_assert!(index > 0);
-            full += bucket.fill_status();
+            full += self.cache[index].fill_status();
             counted_entries += 3;
             if index == 0 {
                 break;
@@ -124,31 +137,36 @@ impl Cache {
     }
 
     pub fn clear_threaded(&self, num_threads: usize) {
-        unsafe {
-            *self.cache.get() = Cache::get_init_cache(self.buckets, num_threads);
-        }
+        let chunksize = self.buckets.div_ceil(num_threads).max(1);
+        std::thread::scope(|s| {
+            for chunk in self.cache.chunks(chunksize) {
+                s.spawn(move || chunk.iter().for_each(CacheBucket::clear));
+            }
+        });
     }
 
     pub fn age_entry(&self, hash: u64, new_age: u8) {
-        unsafe {
-            (&mut *self.cache.get()).get_unchecked_mut(hash as usize % self.buckets).age_entry(hash, new_age);
-        }
+        self.get(hash).age_entry(hash, new_age);
     }
 
-    pub fn get(&self, hash: u64) -> CacheBucket {
-        unsafe { *(&*self.cache.get()).get_unchecked(hash as usize % self.buckets) }
+    #[inline(always)]
+    pub fn get(&self, hash: u64) -> &CacheBucket {
+        unsafe { self.cache.get_unchecked(hash as usize % self.buckets) }
     }
 
     pub fn insert(&self, p: &CombinedSearchParameters, mv: GameMove, score: i16, original_alpha: i16, static_evaluation: i16) {
         if self.entries == 0 {
             return;
         }
-        let index = p.game_state.get_hash() as usize % self.buckets;
-        unsafe {
-            (&mut *self.cache.get())
-                .get_unchecked_mut(index)
-                .replace_entry(p, mv, score, original_alpha, static_evaluation, self.current_age);
-        };
+        self.get(p.game_state.get_hash())
+            .replace_entry(p, mv, score, original_alpha, static_evaluation, self.get_current_age());
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<CacheEntry> {
+        if self.entries == 0 {
+            return None;
+        }
+        self.get(hash).probe(hash)
     }
 
     pub fn lookup(&self, p: &CombinedSearchParameters, tt_entry: &mut Option<CacheEntry>) -> SearchInstruction {
@@ -165,22 +183,83 @@ impl Cache {
             {
                 return SearchInstruction::StopSearching(ce.score);
             }
-            if ce.get_age() != self.current_age {
-                self.age_entry(p.game_state.get_hash(), self.current_age);
+            let current_age = self.get_current_age();
+            if ce.get_age() != current_age {
+                self.age_entry(p.game_state.get_hash(), current_age);
             }
         }
         SearchInstruction::ContinueSearching
     }
 }
 
+// A slot of the table is stored as two relaxed atomic words: the packed entry data and the
+// hash xor-ed with that data. A torn read (key of one write, data of another) decodes to a
+// garbage hash and therefore fails validation, so no locking is required.
+#[derive(Default)]
+pub struct AtomicCacheEntry {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl AtomicCacheEntry {
+    #[inline(always)]
+    pub fn load(&self) -> CacheEntry {
+        let key = self.key.load(Ordering::Relaxed);
+        let data = self.data.load(Ordering::Relaxed);
+        CacheEntry::unpack(key ^ data, data)
+    }
+
+    #[inline(always)]
+    pub fn store(&self, entry: &CacheEntry) {
+        let data = entry.pack();
+        self.key.store(entry.hash ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        self.key.store(0, Ordering::Relaxed);
+        self.data.store(0, Ordering::Relaxed);
+    }
+}
+
 #[repr(align(64))]
-#[derive(Copy, Clone)]
-pub struct CacheBucket([CacheEntry; 3]);
+#[derive(Default)]
+pub struct CacheBucket([AtomicCacheEntry; 3]);
 
 pub const MAXIMUM_AGE_DIFF_REPLACE: usize = 3;
 
 impl CacheBucket {
-    pub fn replace_entry(&mut self, p: &CombinedSearchParameters, mv: GameMove, score: i16, original_alpha: i16, static_evaluation: i16, current_age: u8) -> bool {
+    #[inline(always)]
+    pub fn load(&self) -> [CacheEntry; 3] {
+        [self.0[0].load(), self.0[1].load(), self.0[2].load()]
+    }
+
+    pub fn clear(&self) {
+        self.0.iter().for_each(AtomicCacheEntry::clear);
+    }
+
+    pub fn replace_entry(&self, p: &CombinedSearchParameters, mv: GameMove, score: i16, original_alpha: i16, static_evaluation: i16, current_age: u8) -> bool {
+        // Work on a snapshot of the bucket and only write back the slots that changed.
+        let old = self.load();
+        let mut entries = old;
+        let res = CacheBucket::replace_in_snapshot(&mut entries, p, mv, score, original_alpha, static_evaluation, current_age);
+        for i in 0..3 {
+            if entries[i] != old[i] {
+                self.0[i].store(&entries[i]);
+            }
+        }
+        res
+    }
+
+    fn replace_in_snapshot(
+        entries: &mut [CacheEntry; 3],
+        p: &CombinedSearchParameters,
+        mv: GameMove,
+        score: i16,
+        original_alpha: i16,
+        static_evaluation: i16,
+        current_age: u8,
+    ) -> bool {
         let lower_bound = score >= p.beta;
         let upper_bound = score <= original_alpha;
         let score = Cache::score_to_tt_score(score, p.current_depth as i16);
@@ -207,37 +286,37 @@ impl CacheBucket {
             }
         };
 
-        if self.0[0].is_invalid() || self.0[0].age_diff(current_age) >= MAXIMUM_AGE_DIFF_REPLACE || self.0[0].validate_hash(p.game_state.get_hash()) {
-            let res = self.0[0].is_invalid();
-            renew_entry(&mut self.0[0]);
+        if entries[0].is_invalid() || entries[0].age_diff(current_age) >= MAXIMUM_AGE_DIFF_REPLACE || entries[0].validate_hash(p.game_state.get_hash()) {
+            let res = entries[0].is_invalid();
+            renew_entry(&mut entries[0]);
             return res;
-        } else if self.0[1].is_invalid() || self.0[1].age_diff(current_age) >= MAXIMUM_AGE_DIFF_REPLACE || self.0[1].validate_hash(p.game_state.get_hash()) {
-            let res = self.0[1].is_invalid();
-            renew_entry(&mut self.0[1]);
-            self.0.swap(0, 1);
+        } else if entries[1].is_invalid() || entries[1].age_diff(current_age) >= MAXIMUM_AGE_DIFF_REPLACE || entries[1].validate_hash(p.game_state.get_hash()) {
+            let res = entries[1].is_invalid();
+            renew_entry(&mut entries[1]);
+            entries.swap(0, 1);
             return res;
-        } else if self.0[2].is_invalid() || self.0[2].age_diff(current_age) >= MAXIMUM_AGE_DIFF_REPLACE || self.0[2].validate_hash(p.game_state.get_hash()) {
-            let res = self.0[2].is_invalid();
-            renew_entry(&mut self.0[2]);
-            self.0.swap(0, 2);
-            self.0.swap(1, 2);
+        } else if entries[2].is_invalid() || entries[2].age_diff(current_age) >= MAXIMUM_AGE_DIFF_REPLACE || entries[2].validate_hash(p.game_state.get_hash()) {
+            let res = entries[2].is_invalid();
+            renew_entry(&mut entries[2]);
+            entries.swap(0, 2);
+            entries.swap(1, 2);
             return res;
         }
-        let mut min_score = self.0[2].get_score();
+        let mut min_score = entries[2].get_score();
         let mut min_entry = 2;
 
-        if self.0[1].get_score() < min_score {
-            min_score = self.0[1].get_score();
+        if entries[1].get_score() < min_score {
+            min_score = entries[1].get_score();
             min_entry = 1;
         }
-        if self.0[0].get_score() < min_score {
-            min_score = self.0[0].get_score();
+        if entries[0].get_score() < min_score {
+            min_score = entries[0].get_score();
             min_entry = 0;
         }
 
         let new_score = p.depth_left as f64 * if pv_node { 1. } else { 0.7 };
         if new_score >= min_score {
-            write_entry(&mut self.0[min_entry]);
+            write_entry(&mut entries[min_entry]);
         }
         false
     }
@@ -246,34 +325,28 @@ impl CacheBucket {
         if hash == 0u64 {
             return None;
         }
-        if self.0[0].validate_hash(hash) {
-            return Some(self.0[0]);
-        } else if self.0[1].validate_hash(hash) {
-            return Some(self.0[1]);
-        } else if self.0[2].validate_hash(hash) {
-            return Some(self.0[2]);
+        for slot in self.0.iter() {
+            let entry = slot.load();
+            if entry.validate_hash(hash) && !entry.is_invalid() {
+                return Some(entry);
+            }
         }
         None
     }
 
-    pub fn age_entry(&mut self, hash: u64, new_age: u8) {
-        if self.0[0].validate_hash(hash) {
-            self.0[0].set_age(new_age);
-        } else if self.0[1].validate_hash(hash) {
-            self.0[1].set_age(new_age);
-        } else if self.0[2].validate_hash(hash) {
-            self.0[2].set_age(new_age)
+    pub fn age_entry(&self, hash: u64, new_age: u8) {
+        for slot in self.0.iter() {
+            let mut entry = slot.load();
+            if entry.validate_hash(hash) {
+                entry.set_age(new_age);
+                slot.store(&entry);
+                return;
+            }
         }
     }
 
     pub fn fill_status(&self) -> usize {
-        (if self.0[0].is_invalid() { 0 } else { 1 }) + (if self.0[1].is_invalid() { 0 } else { 1 }) + (if self.0[2].is_invalid() { 0 } else { 1 })
-    }
-}
-
-impl Default for CacheBucket {
-    fn default() -> Self {
-        CacheBucket([CacheEntry::invalid(); 3])
+        self.load().iter().filter(|entry| !entry.is_invalid()).count()
     }
 }
 
@@ -281,14 +354,12 @@ pub const LOWER_BOUND: u8 = 0x1;
 pub const UPPER_BOUND: u8 = 0x2;
 pub const PV_NODE: u8 = 0x4;
 
-#[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct CacheEntry {
+    pub hash: u64,
     pub flags: u8,
     pub depth: i8,
     pub score: i16,
-    pub upper_hash: u32,
-    pub lower_hash: u32,
     pub mv: u16,
     pub static_evaluation: i16,
 }
@@ -329,7 +400,7 @@ impl CacheEntry {
     }
 
     pub fn validate_hash(&self, hash: u64) -> bool {
-        (self.upper_hash as u64) == (hash >> 32) && ((self.lower_hash ^ self.mv as u32) as u64) == (hash & 0xFFFF_FFFF)
+        self.hash == hash
     }
     //I know this is not idiomatic, but it saves memory...
     pub fn is_invalid(&self) -> bool {
@@ -337,8 +408,7 @@ impl CacheEntry {
     }
     pub fn invalid() -> CacheEntry {
         CacheEntry {
-            upper_hash: 0,
-            lower_hash: 0,
+            hash: 0,
             depth: 0,
             score: 0,
             flags: 0,
@@ -346,10 +416,26 @@ impl CacheEntry {
             static_evaluation: INVALID_STATIC_EVALUATION,
         }
     }
+
+    // Layout of the data word: flags | depth << 8 | score << 16 | mv << 32 | static_evaluation << 48
+    #[inline(always)]
+    pub fn pack(&self) -> u64 {
+        self.flags as u64 | (self.depth as u8 as u64) << 8 | (self.score as u16 as u64) << 16 | (self.mv as u64) << 32 | (self.static_evaluation as u16 as u64) << 48
+    }
+    #[inline(always)]
+    pub fn unpack(hash: u64, data: u64) -> CacheEntry {
+        CacheEntry {
+            hash,
+            flags: data as u8,
+            depth: (data >> 8) as u8 as i8,
+            score: (data >> 16) as u16 as i16,
+            mv: (data >> 32) as u16,
+            static_evaluation: (data >> 48) as u16 as i16,
+        }
+    }
+
     pub fn write(&mut self, hash: u64, depth: i16, score: i16, static_evaluation: i16, pv_node: bool, alpha: bool, beta: bool, mv: GameMove, current_age: u8) {
-        let mv = CacheEntry::mv_to_u16(mv);
-        self.upper_hash = (hash >> 32) as u32;
-        self.lower_hash = (hash & 0xFFFF_FFFF) as u32 ^ mv as u32;
+        self.hash = hash;
         self.depth = depth as i8;
         self.score = score;
         self.flags = 0u8;
@@ -357,7 +443,7 @@ impl CacheEntry {
         self.flags |= (alpha as u8) << 1;
         self.flags |= (pv_node as u8) << 2;
         self.flags |= current_age << 3;
-        self.mv = mv;
+        self.mv = CacheEntry::mv_to_u16(mv);
         self.static_evaluation = static_evaluation;
     }
 
@@ -474,9 +560,128 @@ unsafe impl<T> Send for PtrWrapper<T> {}
 
 #[cfg(test)]
 mod tests {
-    use super::CacheEntry;
+    use super::{Cache, CacheBucket, CacheEntry};
     use crate::board_representation::game_state::{GameMove, GameMoveType, GameState, PieceType};
     use crate::move_generation::makemove::make_move;
+    use crate::move_generation::movegen::{generate_moves, MoveList};
+    use crate::search::CombinedSearchParameters;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+    // Every field of the entry is derived from its hash, so a probe that pairs the key of
+    // one write with the data of another is detected as inconsistent.
+    fn entry_for_hash(hash: u64) -> CacheEntry {
+        CacheEntry {
+            hash,
+            flags: (hash >> 8) as u8 & 0x7,
+            depth: (hash >> 16) as i8,
+            score: (hash >> 24) as i16,
+            mv: (hash >> 40) as u16 | 1,
+            static_evaluation: (hash >> 48) as i16,
+        }
+    }
+
+    #[test]
+    fn pack_unpack_test() {
+        let mut rng = 0x9E37_79B9_7F4A_7C15u64;
+        for _ in 0..10_000 {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            let entry = entry_for_hash(rng);
+            assert_eq!(CacheEntry::unpack(rng, entry.pack()), entry);
+        }
+    }
+
+    // Positions of the first two plies, each paired with a legal move to store for it.
+    fn stress_positions(count: usize) -> Vec<(GameState, GameMove)> {
+        let mut movelist = MoveList::default();
+        let mut states = vec![GameState::standard()];
+        let mut index = 0;
+        while states.len() < count {
+            generate_moves(&states[index], false, &mut movelist);
+            let children: Vec<GameState> = movelist.move_list.iter().map(|mv| make_move(&states[index], mv.0)).collect();
+            states.extend(children);
+            index += 1;
+        }
+        states.truncate(count);
+        states
+            .into_iter()
+            .map(|state| {
+                generate_moves(&state, false, &mut movelist);
+                let mv = movelist.move_list[0].0;
+                (state, mv)
+            })
+            .collect()
+    }
+
+    // Every parameter of the write is derived from the hash of the position, so any probe for that
+    // position has to return exactly the entry a single threaded write produces.
+    fn write(cache: &Cache, state: &GameState, mv: GameMove) {
+        let hash = state.get_hash();
+        let mut state = state.clone();
+        let alpha = (hash >> 16) as i16 % 1000;
+        let beta = if hash & 1 > 0 { alpha + 1 } else { alpha + 50 };
+        let p = CombinedSearchParameters::from(alpha, beta, ((hash >> 8) % 20) as i16 + 1, &mut state, 1, 0);
+        cache.insert(&p, mv, (hash >> 32) as i16 % 1200, alpha, (hash >> 48) as i16 % 1000);
+    }
+
+    #[test]
+    fn concurrent_stress_test() {
+        const THREADS: usize = 16;
+        const ITERATIONS: usize = 500_000;
+        const POSITIONS: usize = 64;
+        let positions = stress_positions(POSITIONS);
+        let reference: HashMap<u64, CacheEntry> = {
+            let cache = Cache::with_size_threaded(1, 1);
+            positions
+                .iter()
+                .map(|(state, mv)| {
+                    write(&cache, state, *mv);
+                    (state.get_hash(), cache.probe(state.get_hash()).expect("Entry was not stored"))
+                })
+                .collect()
+        };
+        assert_eq!(reference.len(), POSITIONS);
+        // A tiny table, so that all threads hammer the same few buckets.
+        let cache = Cache {
+            entries: 4 * 3,
+            buckets: 4,
+            cache: (0..4).map(|_| CacheBucket::default()).collect(),
+            current_age: AtomicU8::new(0),
+        };
+        let hits = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for t in 0..THREADS {
+                let (cache, positions, reference, hits) = (&cache, &positions, &reference, &hits);
+                s.spawn(move || {
+                    let mut rng = 0x2545_F491_4F6C_DD1Du64 ^ (t as u64 + 1);
+                    for _ in 0..ITERATIONS {
+                        rng ^= rng << 13;
+                        rng ^= rng >> 7;
+                        rng ^= rng << 17;
+                        let (state, mv) = &positions[rng as usize % POSITIONS];
+                        if rng & (1 << 40) > 0 {
+                            write(cache, state, *mv);
+                        } else if let Some(entry) = cache.probe(state.get_hash()) {
+                            assert_eq!(entry, reference[&state.get_hash()]);
+                            hits.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert!(hits.into_inner() > 0);
+        // A write may move a torn slot within its bucket, which leaves an entry under a garbage hash
+        // that no probe can reach. Everything stored under a real hash has to be intact though.
+        for bucket in cache.cache.iter() {
+            for entry in bucket.load().iter().filter(|entry| !entry.is_invalid()) {
+                if let Some(expected) = reference.get(&entry.hash) {
+                    assert_eq!(entry, expected);
+                }
+            }
+        }
+    }
 
     #[test]
     fn mv_to_u16_test() {
@@ -0,0 +1,114 @@
+// Cuckoo tables of all reversible moves (no pawns) for detecting upcoming repetitions, see
+// "Marcel van Kervinck: Detecting upcoming repetitions with cuckoo hashing".
+// A key is the xor of the zobrist keys of a piece on its origin and destination square and the side to move key,
+// which is exactly the hash difference between a position and the position after that move.
+use crate::bitboards::bitboards::constants::{square, FREEFIELD_BISHOP_ATTACKS, FREEFIELD_ROOK_ATTACKS, KING_ATTACKS, KNIGHT_ATTACKS};
+use crate::board_representation::zobrist_hashing::ZOBRIST_KEYS;
+
+pub const CUCKOO_SIZE: usize = 8192;
+
+pub struct CuckooTable {
+    pub keys: [u64; CUCKOO_SIZE],
+    //From square in the lower 6 bits, to square in the upper bits. 0 is an empty slot
+    pub moves: [u16; CUCKOO_SIZE],
+}
+
+pub static CUCKOO: CuckooTable = CuckooTable::new();
+
+#[inline(always)]
+pub const fn h1(key: u64) -> usize {
+    (key & 0x1fff) as usize
+}
+
+#[inline(always)]
+pub const fn h2(key: u64) -> usize {
+    ((key >> 16) & 0x1fff) as usize
+}
+
+const fn freefield_attacks(piece_type: usize, sq: usize) -> u64 {
+    match piece_type {
+        1 => KNIGHT_ATTACKS[sq],
+        2 => FREEFIELD_BISHOP_ATTACKS[sq],
+        3 => FREEFIELD_ROOK_ATTACKS[sq],
+        4 => FREEFIELD_BISHOP_ATTACKS[sq] | FREEFIELD_ROOK_ATTACKS[sq],
+        _ => KING_ATTACKS[sq],
+    }
+}
+
+impl CuckooTable {
+    const fn new() -> Self {
+        let mut keys = [0u64; CUCKOO_SIZE];
+        let mut moves = [0u16; CUCKOO_SIZE];
+        let mut side = 0;
+        while side < 2 {
+            //Pawn moves are never reversible
+            let mut piece_type = 1;
+            while piece_type < 6 {
+                let mut from = 0;
+                while from < 64 {
+                    let mut to = from + 1;
+                    while to < 64 {
+                        if freefield_attacks(piece_type, from) & square(to) != 0u64 {
+                            let mut key = ZOBRIST_KEYS.pieces[side][piece_type][from] ^ ZOBRIST_KEYS.pieces[side][piece_type][to] ^ ZOBRIST_KEYS.side_to_move;
+                            let mut mv = (from | to << 6) as u16;
+                            let mut index = h1(key);
+                            //Kick out entries until one lands in an empty slot
+                            loop {
+                                let temp_key = keys[index];
+                                keys[index] = key;
+                                key = temp_key;
+                                let temp_mv = moves[index];
+                                moves[index] = mv;
+                                mv = temp_mv;
+                                if mv == 0 {
+                                    break;
+                                }
+                                index = if index == h1(key) { h2(key) } else { h1(key) };
+                            }
+                        }
+                        to += 1;
+                    }
+                    from += 1;
+                }
+                piece_type += 1;
+            }
+            side += 1;
+        }
+        CuckooTable { keys, moves }
+    }
+
+    //Returns the squares of the reversible move leading from one position to the other, if there is one
+    #[inline(always)]
+    pub fn probe(&self, move_key: u64) -> Option<(usize, usize)> {
+        let mut index = h1(move_key);
+        if self.keys[index] != move_key {
+            index = h2(move_key);
+            if self.keys[index] != move_key {
+                return None;
+            }
+        }
+        let mv = self.moves[index] as usize;
+        Some((mv & 63, mv >> 6))
+    }
+
+    pub fn entries(&self) -> usize {
+        self.moves.iter().filter(|mv| **mv != 0).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CUCKOO, CUCKOO_SIZE};
+
+    #[test]
+    fn cuckoo_table_test() {
+        //Knight, bishop, rook, queen and king moves between all square pairs on an empty board, for both sides
+        assert_eq!(CUCKOO.entries(), 3668);
+        for index in 0..CUCKOO_SIZE {
+            if CUCKOO.moves[index] != 0 {
+                let (from, to) = CUCKOO.probe(CUCKOO.keys[index]).unwrap();
+                assert_eq!(from | to << 6, CUCKOO.moves[index] as usize);
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+pub const DEFAULT_EVAL_CACHE_SIZE: usize = 4; //IN MB
+pub const MIN_EVAL_CACHE_SIZE: usize = 1; //IN MB
+pub const MAX_EVAL_CACHE_SIZE: usize = 1024; //IN MB
+
+const EVAL_MASK: u64 = 0xFFFF;
+
+//Every search thread owns its own eval cache, so no synchronization is needed.
+//An entry packs the upper 48 bits of the hash together with the static evaluation from white's point of view.
+pub struct EvalCache {
+    entries: Vec<u64>,
+    size: usize,
+}
+
+impl Default for EvalCache {
+    fn default() -> Self {
+        EvalCache::with_size(DEFAULT_EVAL_CACHE_SIZE)
+    }
+}
+
+impl EvalCache {
+    pub fn with_size(mb_size: usize) -> Self {
+        //Round down to a power of two, so the index is just a mask of the hash
+        let entries = mb_size.max(MIN_EVAL_CACHE_SIZE) * 1024 * 1024 / 8;
+        let entries = if entries.is_power_of_two() { entries } else { entries.next_power_of_two() / 2 };
+        EvalCache {
+            entries: vec![0u64; entries],
+            size: mb_size,
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = 0u64;
+        }
+    }
+
+    #[inline(always)]
+    pub fn probe(&self, hash: u64) -> Option<i16> {
+        let entry = unsafe { *self.entries.get_unchecked(hash as usize & (self.entries.len() - 1)) };
+        if entry & !EVAL_MASK == hash & !EVAL_MASK {
+            Some((entry & EVAL_MASK) as u16 as i16)
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub fn insert(&mut self, hash: u64, static_evaluation: i16) {
+        let index = hash as usize & (self.entries.len() - 1);
+        unsafe {
+            *self.entries.get_unchecked_mut(index) = hash & !EVAL_MASK | u64::from(static_evaluation as u16);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EvalCache;
+
+    #[test]
+    fn eval_cache_test() {
+        let mut cache = EvalCache::with_size(1);
+        let hash = 0xDEAD_BEEF_1234_5678u64;
+        assert_eq!(cache.probe(hash), None);
+        for eval in [-32767i16, -1, 0, 1, 32767].iter() {
+            cache.insert(hash, *eval);
+            assert_eq!(cache.probe(hash), Some(*eval));
+            assert_eq!(cache.probe(hash ^ (1u64 << 63)), None);
+        }
+        cache.clear();
+        assert_eq!(cache.probe(hash), None);
+    }
+}
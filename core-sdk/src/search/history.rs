@@ -1,10 +1,25 @@
+use super::cuckoo::CUCKOO;
 use super::MAX_SEARCH_DEPTH;
+use crate::bitboards::bitboards::constants::{square, BISHOP_RAYS, FREEFIELD_BISHOP_ATTACKS, FREEFIELD_ROOK_ATTACKS, ROOK_RAYS};
 use crate::board_representation::game_state::GameState;
 
+//Squares strictly between two squares on a line, empty for knight moves
+#[inline(always)]
+fn between(from: usize, to: usize) -> u64 {
+    if FREEFIELD_ROOK_ATTACKS[from] & square(to) != 0u64 {
+        ROOK_RAYS[from][to]
+    } else if FREEFIELD_BISHOP_ATTACKS[from] & square(to) != 0u64 {
+        BISHOP_RAYS[from][to]
+    } else {
+        0u64
+    }
+}
+
 #[derive(Clone)]
 pub struct History {
     pub hist: Vec<u64>,
     pub is_unique: Vec<bool>,
+    pub before_nullmove: Vec<bool>,
     pub pointer: usize,
 }
 
@@ -13,6 +28,7 @@ impl Default for History {
         History {
             hist: vec![0u64; MAX_SEARCH_DEPTH + 100],
             is_unique: vec![false; MAX_SEARCH_DEPTH + 100],
+            before_nullmove: vec![false; MAX_SEARCH_DEPTH + 100],
             pointer: 0,
         }
     }
@@ -22,18 +38,28 @@ impl History {
     pub fn push(&mut self, hash: u64, is_unique: bool) {
         self.hist[self.pointer] = hash;
         self.is_unique[self.pointer] = is_unique;
+        self.before_nullmove[self.pointer] = false;
         self.pointer += 1;
     }
 
+    //Marks the last pushed position as the one a null move is made from
+    pub fn set_nullmove(&mut self, is_nullmove: bool) {
+        self.before_nullmove[self.pointer - 1] = is_nullmove;
+    }
+
     pub fn pop(&mut self) {
         self.pointer -= 1;
     }
 
     pub fn get_occurences(&self, game_state: &GameState) -> usize {
+        self.occurences_before(self.pointer, game_state.get_hash())
+    }
+
+    fn occurences_before(&self, end: usize, hash: u64) -> usize {
         let mut occurences = 0;
-        let mut index = self.pointer as isize - 1;
+        let mut index = end as isize - 1;
         while index >= 0 {
-            if self.hist[index as usize] == game_state.get_hash() {
+            if self.hist[index as usize] == hash {
                 occurences += 1;
             }
             if self.is_unique[index as usize] {
@@ -43,4 +69,141 @@ impl History {
         }
         occurences
     }
+
+    //Checks if the side to move can repeat a position of the history with its next move, i.e. if there is a
+    //reversible move with an empty path whose key is the hash difference to a position an odd number of plies ago.
+    //Needs to be called before the hash of the current position is pushed
+    pub fn has_upcoming_repetition(&self, game_state: &GameState, ply: usize) -> bool {
+        let max_distance = game_state.get_half_moves().min(self.pointer);
+        if max_distance < 3 {
+            return false;
+        }
+        let occupied = game_state.get_all_pieces();
+        let mut distance = 1;
+        while distance <= max_distance {
+            let index = self.pointer - distance;
+            //The hash difference to a position before a null move doesn't correspond to a sequence of real moves
+            if self.before_nullmove[index] {
+                break;
+            }
+            if distance >= 3 && distance % 2 == 1 {
+                if let Some((from, to)) = CUCKOO.probe(game_state.get_hash() ^ self.hist[index]) {
+                    if between(from, to) & occupied == 0u64 {
+                        //A single repetition inside of the search tree is already scored as draw
+                        if ply > distance {
+                            return true;
+                        }
+                        //Positions before the root need to have occured twice already and the move has to be ours,
+                        //not one leading from that position to the current one
+                        let piece_square = if occupied & square(from) != 0u64 { from } else { to };
+                        if game_state.get_pieces_from_side(game_state.get_color_to_move()) & square(piece_square) != 0u64 && self.occurences_before(index, self.hist[index]) >= 1 {
+                            return true;
+                        }
+                    }
+                }
+            }
+            //Positions before an irreversible move can't be reached anymore
+            if self.is_unique[index] {
+                break;
+            }
+            distance += 1;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use crate::board_representation::game_state::{GameMove, GameMoveType, GameState, PieceType};
+
+    fn play(game_state: &mut GameState, history: &mut History, moves: &[(u8, u8, PieceType)]) {
+        for (from, to, piece_type) in moves {
+            history.push(game_state.get_hash(), game_state.get_half_moves() == 0);
+            game_state.do_move(GameMove {
+                from: *from,
+                to: *to,
+                move_type: GameMoveType::Quiet,
+                piece_type: *piece_type,
+            });
+        }
+    }
+
+    #[test]
+    fn upcoming_repetition_test() {
+        //After 1. Nf3 Nf6 2. Ng1 black can repeat the starting position with Ng8
+        let mut game_state = GameState::standard();
+        let mut history = History::default();
+        play(
+            &mut game_state,
+            &mut history,
+            &[(6, 21, PieceType::Knight), (62, 45, PieceType::Knight), (21, 6, PieceType::Knight)],
+        );
+        assert!(history.has_upcoming_repetition(&game_state, 4));
+        //Before the root the starting position would have to be repeated already
+        assert!(!history.has_upcoming_repetition(&game_state, 0));
+
+        let mut game_state = GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        let mut history = History::default();
+        play(
+            &mut game_state,
+            &mut history,
+            &[(0, 16, PieceType::Rook), (60, 59, PieceType::King), (16, 19, PieceType::Rook)],
+        );
+        //Black can't undo both 1...Kd8 and 2. Rd3
+        assert!(!history.has_upcoming_repetition(&game_state, 4));
+        play(&mut game_state, &mut history, &[(59, 60, PieceType::King)]);
+        //White can go back to the position after 1. Ra3 with Rd3-a3
+        assert!(history.has_upcoming_repetition(&game_state, 5));
+
+        //Rh3-a3 repeats the position after 1. Ra3, but only if the path of the rook is empty
+        for (fen, free_path) in [("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", true), ("4k3/8/8/8/8/3P4/8/R3K3 w - - 0 1", false)].iter() {
+            let mut game_state = GameState::from_fen(fen);
+            let mut history = History::default();
+            play(
+                &mut game_state,
+                &mut history,
+                &[
+                    (0, 16, PieceType::Rook),
+                    (60, 59, PieceType::King),
+                    (16, 32, PieceType::Rook),
+                    (59, 58, PieceType::King),
+                    (32, 39, PieceType::Rook),
+                    (58, 59, PieceType::King),
+                    (39, 23, PieceType::Rook),
+                    (59, 60, PieceType::King),
+                ],
+            );
+            assert_eq!(history.has_upcoming_repetition(&game_state, 10), *free_path);
+        }
+
+        //Positions before an irreversible move can't be repeated
+        let mut game_state = GameState::standard();
+        let mut history = History::default();
+        play(
+            &mut game_state,
+            &mut history,
+            &[
+                (6, 21, PieceType::Knight),
+                (62, 45, PieceType::Knight),
+                (12, 20, PieceType::Pawn),
+                (45, 62, PieceType::Knight),
+                (21, 6, PieceType::Knight),
+            ],
+        );
+        assert!(!history.has_upcoming_repetition(&game_state, 10));
+
+        //After 1. Ra3 (null) 2. Ra5 black can't play Ra5-a1, the scan has to stop at the null move
+        let mut game_state = GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        let mut history = History::default();
+        play(&mut game_state, &mut history, &[(0, 16, PieceType::Rook)]);
+        history.push(game_state.get_hash(), game_state.get_half_moves() == 0);
+        history.set_nullmove(true);
+        game_state.do_nullmove();
+        play(&mut game_state, &mut history, &[(16, 32, PieceType::Rook)]);
+        assert!(!history.has_upcoming_repetition(&game_state, 10));
+        //Without the marker the hash difference to the starting position looks like Ra1-a5
+        history.before_nullmove[1] = false;
+        assert!(history.has_upcoming_repetition(&game_state, 10));
+    }
 }
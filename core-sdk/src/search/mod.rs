@@ -1,5 +1,7 @@
 pub mod alphabeta;
 pub mod cache;
+pub mod cuckoo;
+pub mod eval_cache;
 pub mod history;
 pub mod moveordering;
 pub mod quiescence;
@@ -7,8 +9,10 @@ pub mod reserved_memory;
 pub mod searcher;
 pub mod statistics;
 pub mod timecontrol;
+pub mod trace;
 
 use crate::board_representation::game_state::*;
+use crate::evaluation::{eval_game_state, eval_game_state_with_params};
 use crate::search::searcher::Thread;
 use crate::search::timecontrol::TimeControlInformation;
 use history::History;
@@ -23,13 +27,13 @@ pub struct CombinedSearchParameters<'a> {
     pub alpha: i16,
     pub beta: i16,
     pub depth_left: i16,
-    pub game_state: &'a GameState,
+    pub game_state: &'a mut GameState,
     pub color: i16,
     pub current_depth: usize,
 }
 
 impl<'a> CombinedSearchParameters<'a> {
-    pub fn from(alpha: i16, beta: i16, depth_left: i16, game_state: &'a GameState, color: i16, current_depth: usize) -> Self {
+    pub fn from(alpha: i16, beta: i16, depth_left: i16, game_state: &'a mut GameState, color: i16, current_depth: usize) -> Self {
         CombinedSearchParameters {
             alpha,
             beta,
@@ -122,6 +126,18 @@ pub fn check_for_draw(game_state: &GameState, history: &History) -> SearchInstru
     SearchInstruction::ContinueSearching
 }
 
+#[inline(always)]
+pub fn upcoming_repetition(p: &mut CombinedSearchParameters, history: &History) -> SearchInstruction {
+    //If we can repeat a position with our next move, we can atleast hold the draw
+    if p.alpha < 0 && history.has_upcoming_repetition(p.game_state, p.current_depth) {
+        p.alpha = 0;
+        if p.alpha >= p.beta {
+            return SearchInstruction::StopSearching(p.alpha);
+        }
+    }
+    SearchInstruction::ContinueSearching
+}
+
 #[inline(always)]
 pub fn check_end_condition(game_state: &GameState, has_legal_moves: bool, in_check: bool) -> GameResult {
     if in_check && !has_legal_moves {
@@ -159,17 +175,45 @@ pub fn concatenate_pv(at_depth: usize, thread: &mut Thread) {
     }
 }
 
+//Static evaluation from white's point of view. The eval cache is bypassed when we need the displayed evaluation
+#[inline(always)]
+pub fn static_evaluation(game_state: &mut GameState, thread: &mut Thread) -> i16 {
+    let use_cache = !cfg!(feature = "display-eval") && !cfg!(feature = "tuning");
+    if use_cache {
+        if let Some(eval) = thread.eval_cache.probe(game_state.get_hash()) {
+            #[cfg(feature = "search-statistics")]
+            {
+                thread.search_statistics.add_eval_cache_probe(true);
+            }
+            return eval;
+        }
+        #[cfg(feature = "search-statistics")]
+        {
+            thread.search_statistics.add_eval_cache_probe(false);
+        }
+    }
+    let eval = match (&thread.network, &thread.eval_params) {
+        (Some(network), _) => network.evaluate(game_state),
+        (None, Some(params)) => eval_game_state_with_params(game_state, &**params).final_eval,
+        (None, None) => eval_game_state(game_state).final_eval,
+    };
+    if use_cache {
+        thread.eval_cache.insert(game_state.get_hash(), eval);
+    }
+    eval
+}
+
 #[inline(always)]
 pub fn checkup(thread: &mut Thread) {
     if (thread.id == 0
-        && thread.tc.time_over(
+        && (thread.tc.time_over(
             thread.itcs.get_time_elapsed(),
             &TimeControlInformation {
                 time_saved: thread.time_saved,
                 stable_pv: thread.itcs.stable_pv.load(std::sync::atomic::Ordering::Relaxed),
             },
             thread.uci_options.move_overhead,
-        ))
+        ) || thread.tc.nodes_over(thread.itcs.get_nodes_sum() - thread.itcs.nodes_searched()[0].load(std::sync::atomic::Ordering::Relaxed) + thread.search_statistics.nodes_searched)))
         || *thread.itcs.timeout_flag.read().expect("Reading posioned timeoutflag")
     {
         if thread.id == 0 {
@@ -1,6 +1,8 @@
 use crate::board_representation::game_state::{GameMove, PieceType};
 use crate::move_generation::movegen;
-use crate::search::moveordering::MoveOrderingStage::{BadCapture, GoodCapture, GoodCaptureInitialization, Killer, PVMove, Quiet, QuietInitialization, TTMove};
+use crate::search::moveordering::MoveOrderingStage::{
+    BadCapture, GoodCapture, GoodCaptureInitialization, Killer, PVMove, Quiet, QuietCheckInitialization, QuietGeneration, QuietInitialization, TTMove,
+};
 use crate::search::quiescence::{see, PIECE_VALUES};
 use crate::search::searcher::Thread;
 use crate::search::{CombinedSearchParameters, GradedMove};
@@ -14,8 +16,20 @@ pub fn mvvlva(mv: GameMove) -> i16 {
     TARGET_VALUE[mv.get_captured_piece() as usize] - ATTACKER_VALUE[mv.piece_type as usize]
 }
 
-pub const NORMAL_STAGES: [MoveOrderingStage; 8] = [PVMove, TTMove, GoodCaptureInitialization, GoodCapture, Killer, QuietInitialization, Quiet, BadCapture];
+pub const NORMAL_STAGES: [MoveOrderingStage; 9] = [
+    PVMove,
+    TTMove,
+    GoodCaptureInitialization,
+    GoodCapture,
+    Killer,
+    QuietGeneration,
+    QuietInitialization,
+    Quiet,
+    BadCapture,
+];
 pub const QUIESCENCE_STAGES: [MoveOrderingStage; 3] = [TTMove, GoodCaptureInitialization, GoodCapture];
+pub const QUIESCENCE_CHECK_STAGES: [MoveOrderingStage; 6] = [TTMove, GoodCaptureInitialization, GoodCapture, QuietCheckInitialization, QuietInitialization, Quiet];
+pub const QUIESCENCE_EVASION_STAGES: [MoveOrderingStage; 6] = [TTMove, GoodCaptureInitialization, GoodCapture, QuietInitialization, Quiet, BadCapture];
 
 pub enum MoveOrderingStage {
     PVMove,
@@ -23,18 +37,32 @@ pub enum MoveOrderingStage {
     GoodCaptureInitialization,
     GoodCapture,
     Killer,
+    QuietGeneration,
+    QuietCheckInitialization,
     QuietInitialization,
     Quiet,
     BadCapture,
 }
 
+//Moves are generated in stages: PV, TT and killer moves are only checked for legality and tried before any generation.
+//Captures (or all evasions when in check) are generated next, the remaining quiet moves only if they are needed.
 pub struct MoveOrderer {
     pub stage: usize,
     pub stages: &'static [MoveOrderingStage],
-    pub gen_only_captures: bool,
+    pub in_check: bool,
+    pub killer: usize,
 }
 
 impl MoveOrderer {
+    pub fn new(stages: &'static [MoveOrderingStage], in_check: bool) -> Self {
+        MoveOrderer {
+            stage: 0,
+            stages,
+            in_check,
+            killer: 0,
+        }
+    }
+
     pub fn next(
         &mut self,
         thread: &mut Thread,
@@ -49,7 +77,7 @@ impl MoveOrderer {
         match self.stages[self.stage] {
             MoveOrderingStage::PVMove => {
                 self.stage += 1;
-                if pv_table_move.is_some() && p.game_state.is_valid_tt_move(pv_table_move.unwrap()) {
+                if pv_table_move.is_some() && is_valid_move(p, pv_table_move.unwrap()) {
                     Some((pv_table_move.unwrap(), 0.))
                 } else {
                     self.next(thread, p, pv_table_move, tt_move, search_quiets)
@@ -57,16 +85,20 @@ impl MoveOrderer {
             }
             MoveOrderingStage::TTMove => {
                 self.stage += 1;
-                if tt_move.is_some() && tt_move != pv_table_move && p.game_state.is_valid_tt_move(tt_move.unwrap()) {
+                if tt_move.is_some() && tt_move != pv_table_move && is_valid_move(p, tt_move.unwrap()) {
                     Some((tt_move.unwrap(), 0.))
                 } else {
                     self.next(thread, p, pv_table_move, tt_move, search_quiets)
                 }
             }
             MoveOrderingStage::GoodCaptureInitialization => {
-                //Generate moves first!
-                movegen::generate_moves(&p.game_state, self.gen_only_captures, &mut thread.movelist.move_lists[p.current_depth]);
+                //Generate captures and promotions first. When in check, all evasions are generated at once
                 let our_mvlist = &mut thread.movelist.move_lists[p.current_depth];
+                if self.in_check {
+                    movegen::generate_evasions(p.game_state, our_mvlist);
+                } else {
+                    movegen::generate_captures(p.game_state, our_mvlist);
+                }
 
                 if let Some(pv_move) = pv_table_move {
                     let mv_index = our_mvlist.find_move(pv_move, false);
@@ -89,7 +121,7 @@ impl MoveOrderer {
                 }
 
                 self.stage += 1;
-                self.next(thread, p, None, None, search_quiets)
+                self.next(thread, p, pv_table_move, tt_move, search_quiets)
             }
             MoveOrderingStage::GoodCapture => {
                 //We now have all of the captures sorted by mvv lva
@@ -111,30 +143,49 @@ impl MoveOrderer {
                             Some((graded_move.0, 0.))
                         } else {
                             our_list.move_list.push(GradedMove(graded_move.0, Some(f64::from(see_value))));
-                            self.next(thread, p, None, None, search_quiets)
+                            self.next(thread, p, pv_table_move, tt_move, search_quiets)
                         }
                     }
                 }
             }
             MoveOrderingStage::Killer => {
-                debug_assert!(thread.killer_moves[p.current_depth][0].is_none() || !thread.killer_moves[p.current_depth][0].unwrap().is_capture());
-                debug_assert!(thread.killer_moves[p.current_depth][1].is_none() || !thread.killer_moves[p.current_depth][1].unwrap().is_capture());
-                let our_list = &mut thread.movelist.move_lists[p.current_depth];
-                let mut found_index = our_list.move_list.len();
-                for (index, gmv) in our_list.move_list.iter().enumerate() {
-                    if gmv.1.is_none() && (Some(gmv.0) == thread.killer_moves[p.current_depth][0] || Some(gmv.0) == thread.killer_moves[p.current_depth][1]) {
-                        found_index = index;
-                        break;
+                while self.killer < 2 {
+                    let killer = thread.killer_moves[p.current_depth][self.killer];
+                    self.killer += 1;
+                    if let Some(killer) = killer {
+                        debug_assert!(!killer.is_capture());
+                        if Some(killer) != pv_table_move && Some(killer) != tt_move && is_valid_move(p, killer) {
+                            //Quiet promotions and evasions have already been generated
+                            let our_list = &mut thread.movelist.move_lists[p.current_depth];
+                            let mv_index = our_list.find_move(killer, false);
+                            if mv_index < our_list.move_list.len() {
+                                our_list.move_list.remove(mv_index);
+                            }
+                            return Some((killer, 0.));
+                        }
                     }
                 }
-                if found_index < our_list.move_list.len() {
-                    let res = our_list.move_list[found_index].0;
-                    our_list.move_list.remove(found_index);
-                    Some((res, 0.))
-                } else {
-                    self.stage += 1;
-                    self.next(thread, p, None, None, search_quiets)
+                self.stage += 1;
+                self.next(thread, p, pv_table_move, tt_move, search_quiets)
+            }
+            MoveOrderingStage::QuietGeneration => {
+                //Evasions are already complete, and we don't need the quiets if they are pruned anyway
+                if !self.in_check && search_quiets {
+                    let killers = thread.killer_moves[p.current_depth];
+                    let our_list = &mut thread.movelist.move_lists[p.current_depth];
+                    movegen::generate_quiets(p.game_state, our_list);
+                    our_list
+                        .move_list
+                        .retain(|gmv| Some(gmv.0) != pv_table_move && Some(gmv.0) != tt_move && Some(gmv.0) != killers[0] && Some(gmv.0) != killers[1]);
                 }
+                self.stage += 1;
+                self.next(thread, p, pv_table_move, tt_move, search_quiets)
+            }
+            MoveOrderingStage::QuietCheckInitialization => {
+                //Replaces the remaining (bad) captures with the quiet checks
+                movegen::generate_quiet_checks(p.game_state, &mut thread.movelist.move_lists[p.current_depth]);
+                self.stage += 1;
+                self.next(thread, p, pv_table_move, tt_move, search_quiets)
             }
             MoveOrderingStage::QuietInitialization => {
                 if search_quiets {
@@ -150,20 +201,20 @@ impl MoveOrderer {
                     }
                 }
                 self.stage += 1;
-                self.next(thread, p, None, None, search_quiets)
+                self.next(thread, p, pv_table_move, tt_move, search_quiets)
             }
             MoveOrderingStage::Quiet => {
                 if !search_quiets {
                     thread.movelist.move_lists[p.current_depth].move_list.retain(|x| x.0.is_capture());
                     self.stage += 1;
-                    return self.next(thread, p, None, None, search_quiets);
+                    return self.next(thread, p, pv_table_move, tt_move, search_quiets);
                 }
                 let our_list = &mut thread.movelist.move_lists[p.current_depth];
                 let highest = our_list.highest_score();
                 if let Some((index, gmv)) = highest {
                     if gmv.1.unwrap() < 0. {
                         self.stage += 1;
-                        return self.next(thread, p, None, None, search_quiets);
+                        return self.next(thread, p, pv_table_move, tt_move, search_quiets);
                     }
                     debug_assert!(!gmv.0.is_capture());
                     our_list.move_list.remove(index);
@@ -189,3 +240,8 @@ impl MoveOrderer {
         }
     }
 }
+
+#[inline(always)]
+fn is_valid_move(p: &CombinedSearchParameters, mv: GameMove) -> bool {
+    p.game_state.is_pseudo_legal(mv) && p.game_state.is_legal(mv)
+}
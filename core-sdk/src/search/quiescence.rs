@@ -1,17 +1,31 @@
 use super::super::board_representation::game_state::{GameMove, GameMoveType, GameState, PieceType, BLACK, WHITE};
-use super::super::evaluation::eval_game_state;
 use super::super::move_generation::movegen;
 use super::alphabeta::*;
 use super::*;
 use crate::bitboards::bitboards::constants::{KING_ATTACKS, KNIGHT_ATTACKS, RANKS};
-use crate::move_generation::makemove::make_move;
 use crate::search::cache::CacheEntry;
-use crate::search::moveordering::{MoveOrderer, QUIESCENCE_STAGES};
+use crate::search::moveordering::{MoveOrderer, QUIESCENCE_CHECK_STAGES, QUIESCENCE_EVASION_STAGES, QUIESCENCE_STAGES};
+#[cfg(feature = "search-trace")]
+use crate::search::trace::PruneReason;
 
 pub const DELTA_PRUNING: i16 = 100;
 pub const PIECE_VALUES: [i16; 6] = [100, 400, 400, 650, 1100, 30000];
 
-pub fn q_search(mut p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
+#[inline(always)]
+pub fn q_search(p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
+    #[cfg(feature = "search-trace")]
+    {
+        thread.trace.enter(&p, true);
+    }
+    let score = q_search_node(p, thread);
+    #[cfg(feature = "search-trace")]
+    {
+        thread.trace.exit(score);
+    }
+    score
+}
+
+fn q_search_node(mut p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
     //Step 0. Prepare variables
     thread.search_statistics.add_q_node(p.current_depth);
     clear_pv(p.current_depth, thread);
@@ -28,27 +42,74 @@ pub fn q_search(mut p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
 
     //Step 3. Check for draw
     if let SearchInstruction::StopSearching(res) = check_for_draw(p.game_state, &thread.history) {
+        #[cfg(feature = "search-trace")]
+        {
+            thread.trace.prune_node(PruneReason::Draw);
+        }
+        return res;
+    }
+    if let SearchInstruction::StopSearching(res) = upcoming_repetition(&mut p, &thread.history) {
+        #[cfg(feature = "search-trace")]
+        {
+            thread.trace.prune_node(PruneReason::UpcomingRepetition);
+        }
         return res;
     }
 
+    //Step 4. In check flag
+    //Principal variation search extends checks, so the first ply of quiescence search is never in check
+    let incheck = p.depth_left < 0 && p.game_state.in_check();
+    #[cfg(feature = "search-statistics")]
+    {
+        if incheck {
+            thread.search_statistics.add_q_evasion_node();
+        }
+    }
+
     //Step 5. Get standing pat when not in check
-    let stand_pat = eval_game_state(&p.game_state).final_eval * p.color;
+    let stand_pat = if incheck {
+        -MATE_SCORE + p.current_depth as i16
+    } else {
+        static_evaluation(p.game_state, thread) * p.color
+    };
 
     //Step 6. Preliminary pruning
-    if let SearchInstruction::StopSearching(res) = adjust_standpat(&mut p, stand_pat) {
-        return res;
-    } else if let SearchInstruction::StopSearching(res) = delta_pruning(&p, stand_pat) {
-        return res;
+    if !incheck {
+        #[cfg(feature = "search-trace")]
+        {
+            thread.trace.set_static_evaluation(stand_pat * p.color);
+        }
+        if let SearchInstruction::StopSearching(res) = adjust_standpat(&mut p, stand_pat) {
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_node(PruneReason::StandPat);
+            }
+            return res;
+        } else if let SearchInstruction::StopSearching(res) = delta_pruning(&p, stand_pat) {
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_node(PruneReason::Delta);
+            }
+            return res;
+        }
     }
 
     //Step 7. TT Lookup
     let mut tt_entry = None;
     if p.depth_left == 0 {
-        if let SearchInstruction::StopSearching(res) = thread.itcs.cache().lookup(&p, &mut tt_entry) {
+        #[cfg(feature = "search-statistics")]
+        {
+            thread.search_statistics.add_cache_probe();
+        }
+        if let SearchInstruction::StopSearching(res) = thread.cache.lookup(&p, &mut tt_entry) {
             #[cfg(feature = "search-statistics")]
             {
                 thread.search_statistics.add_cache_hit_aj_replace_ns();
             }
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_node(PruneReason::TTCutoff);
+            }
             return res;
         }
     }
@@ -64,46 +125,86 @@ pub fn q_search(mut p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
         None
     };
     //Only captures are valid tt moves (if not in check)
-    if tt_move.is_some() && !tt_move.as_ref().unwrap().is_capture() {
+    if !incheck && tt_move.is_some() && !tt_move.as_ref().unwrap().is_capture() {
         tt_move = None;
     }
 
     thread.history.push(p.game_state.get_hash(), p.game_state.get_half_moves() == 0);
 
     //Step 8. Iterate through moves
-
+    //When in check, all evasions are searched. In the first ply, quiet checks are searched after the captures.
+    let search_quiets = incheck || p.depth_left == 0;
     let mut current_max_score = stand_pat;
 
     let mut has_pv = false;
-    let mut move_orderer = MoveOrderer {
-        stage: 0,
-        stages: &QUIESCENCE_STAGES,
-        gen_only_captures: true,
-    };
+    let mut move_orderer = MoveOrderer::new(
+        if incheck {
+            &QUIESCENCE_EVASION_STAGES
+        } else if search_quiets {
+            &QUIESCENCE_CHECK_STAGES
+        } else {
+            &QUIESCENCE_STAGES
+        },
+        incheck,
+    );
 
-    //let mut index = 0;
+    #[cfg(feature = "search-statistics")]
+    let mut index = 0;
     loop {
-        let mv = move_orderer.next(thread, &p, None, tt_move, false);
+        let mv = move_orderer.next(thread, &p, None, tt_move, search_quiets);
         if mv.is_none() {
             break;
         }
-        //index += 1;
-        let (capture_move, _) = mv.unwrap();
-        if !passes_delta_pruning(capture_move, p.game_state.get_phase().phase, stand_pat, p.alpha) {
+        #[cfg(feature = "search-statistics")]
+        {
+            index += 1;
+        }
+        let (mv, _) = mv.unwrap();
+        //Step 8.1. Prune captures which can't raise alpha and quiet checks which lose material
+        if !incheck && mv.is_capture() && !passes_delta_pruning(mv, p.game_state.get_phase().phase, stand_pat, p.alpha) {
+            #[cfg(feature = "search-statistics")]
+            {
+                thread.search_statistics.add_q_delta_cutoff();
+            }
+            #[cfg(feature = "search-trace")]
+            {
+                thread.trace.prune_move(mv, PruneReason::QDelta);
+            }
             continue;
         }
-        debug_assert!(capture_move.is_capture());
-        let next_g = make_move(p.game_state, capture_move);
+        if !incheck && !mv.is_capture() {
+            if see(p.game_state, mv, false, &mut thread.see_buffer) < 0 {
+                #[cfg(feature = "search-statistics")]
+                {
+                    thread.search_statistics.add_q_see_cutoff();
+                }
+                #[cfg(feature = "search-trace")]
+                {
+                    thread.trace.prune_move(mv, PruneReason::QSEE);
+                }
+                continue;
+            }
+            #[cfg(feature = "search-statistics")]
+            {
+                thread.search_statistics.add_q_quiet_check();
+            }
+        }
+        p.game_state.do_move(mv);
+        #[cfg(feature = "search-trace")]
+        {
+            thread.trace.set_move(p.current_depth + 1, Some(mv));
+        }
         //Step 8.4. Search move
         let score = -q_search(
-            CombinedSearchParameters::from(-p.beta, -p.alpha, p.depth_left - 1, &next_g, -p.color, p.current_depth + 1),
+            CombinedSearchParameters::from(-p.beta, -p.alpha, p.depth_left - 1, p.game_state, -p.color, p.current_depth + 1),
             thread,
         );
+        p.game_state.undo_move(mv);
 
         //Step 8.5 Move raises best moves score, so update pv and score
         if score > current_max_score {
             current_max_score = score;
-            thread.pv_table[p.current_depth].pv[0] = Some(capture_move);
+            thread.pv_table[p.current_depth].pv[0] = Some(mv);
             has_pv = true;
             //Hang on following pv in theory
         }
@@ -111,7 +212,7 @@ pub fn q_search(mut p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
         if score >= p.beta {
             #[cfg(feature = "search-statistics")]
             {
-                thread.search_statistics.add_q_beta_cutoff(index);
+                thread.search_statistics.add_q_beta_cutoff(index - 1);
             }
             break;
         }
@@ -131,7 +232,7 @@ pub fn q_search(mut p: CombinedSearchParameters, thread: &mut Thread) -> i16 {
     }
     //Step 10. Make TT entry
     if has_pv && p.depth_left == 0 && !thread.self_stop {
-        thread.itcs.cache().insert(
+        thread.cache.insert(
             &p,
             thread.pv_table[p.current_depth].pv[0].expect("Can't unwrap move for TT in qsearch!"),
             current_max_score,
@@ -1,26 +1,29 @@
 use super::alphabeta::principal_variation_search;
 use super::cache::Cache;
+use super::eval_cache::EvalCache;
 use super::history::History;
 use super::statistics::SearchStatistics;
 use super::timecontrol::TimeControl;
+#[cfg(feature = "search-trace")]
+use super::trace::{TraceConfig, TraceRecorder};
 use super::GameMove;
 use super::PrincipalVariation;
 use super::MATED_IN_MAX;
 use super::MAX_SEARCH_DEPTH;
 use crate::board_representation::game_state::{GameState, WHITE};
+use crate::evaluation::eval_params::RuntimeParams;
+use crate::evaluation::nnue::Network;
 //use crate::logging::log;
-use crate::move_generation::makemove::make_move;
 use crate::move_generation::movegen::{generate_moves, MoveList};
 use crate::search::reserved_memory::ReservedMoveList;
 use crate::search::{CombinedSearchParameters, ScoredPrincipalVariation, MATE_SCORE};
 use crate::UCIOptions;
-use std::cell::UnsafeCell;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
 use std::thread;
 use std::time::Instant;
 
@@ -44,10 +47,15 @@ pub struct InterThreadCommunicationSystem {
     pub best_pv: Mutex<ScoredPrincipalVariation>,
     pub stable_pv: AtomicBool,
     pub depth_info: Mutex<[DepthInformation; MAX_SEARCH_DEPTH]>,
-    pub start_time: RwLock<Instant>,                //Only used for reporting
-    pub nodes_searched: UnsafeCell<Vec<AtomicU64>>, // Only used for reporting
-    pub seldepth: AtomicUsize,                      // Only used for reporting
-    pub cache: UnsafeCell<Cache>,                   //Only used for reporting
+    pub start_time: RwLock<Instant>,                     //Only used for reporting
+    pub nodes_searched: RwLock<Vec<AtomicU64>>,          // Only used for reporting
+    pub seldepth: AtomicUsize,                           // Only used for reporting
+    pub search_statistics: Mutex<Vec<SearchStatistics>>, // Only used for reporting
+    #[cfg(feature = "search-trace")]
+    pub trace_config: RwLock<TraceConfig>,
+    pub cache: RwLock<Arc<Cache>>, //Only replaced while no search is running
+    pub network: RwLock<Option<Arc<Network>>>,
+    pub eval_params: RwLock<Option<Arc<RuntimeParams>>>,
     pub cache_status: AtomicUsize,
     pub last_cache_status: Mutex<Option<Instant>>,
     pub timeout_flag: RwLock<bool>,
@@ -65,12 +73,17 @@ impl Default for InterThreadCommunicationSystem {
             best_pv: Mutex::new(ScoredPrincipalVariation::default()),
             stable_pv: AtomicBool::new(false),
             depth_info: Mutex::new([DepthInformation::UnSearched; MAX_SEARCH_DEPTH]),
-            nodes_searched: UnsafeCell::new(Vec::new()),
+            nodes_searched: RwLock::new(Vec::new()),
             seldepth: AtomicUsize::new(0),
+            search_statistics: Mutex::new(Vec::new()),
+            #[cfg(feature = "search-trace")]
+            trace_config: RwLock::new(TraceConfig::default()),
             start_time: RwLock::new(Instant::now()),
             last_cache_status: Mutex::new(None),
+            network: RwLock::new(None),
+            eval_params: RwLock::new(None),
             cache_status: AtomicUsize::new(0),
-            cache: UnsafeCell::new(Cache::with_size_threaded(0, 1)),
+            cache: RwLock::new(Arc::new(Cache::default())),
             timeout_flag: RwLock::new(false),
             saved_time: AtomicU64::new(0u64),
             tx: RwLock::new(Vec::new()),
@@ -81,14 +94,21 @@ impl Default for InterThreadCommunicationSystem {
 }
 
 impl InterThreadCommunicationSystem {
-    pub fn cache(&self) -> &mut Cache {
-        unsafe { self.cache.get().as_mut().unwrap() }
+    pub fn cache(&self) -> Arc<Cache> {
+        Arc::clone(&self.cache.read().unwrap())
+    }
+    //Only call this while no search is running. Threads release the table after every search,
+    //so the old table is freed before the new one is allocated
+    pub fn resize_cache(&self, mb_size: usize, num_threads: usize) {
+        let mut cache = self.cache.write().unwrap();
+        *cache = Arc::default();
+        *cache = Arc::new(Cache::with_size_threaded(mb_size, num_threads));
     }
     pub fn get_current_uci_options(&self) -> UCIOptions {
         *self.uci_options.read().unwrap()
     }
-    pub fn nodes_searched(&self) -> &mut Vec<AtomicU64> {
-        unsafe { self.nodes_searched.get().as_mut().unwrap() }
+    pub fn nodes_searched(&self) -> RwLockReadGuard<'_, Vec<AtomicU64>> {
+        self.nodes_searched.read().unwrap()
     }
 
     pub fn update_thread_count(itcs: &Arc<InterThreadCommunicationSystem>, new_thread_count: usize) {
@@ -100,9 +120,10 @@ impl InterThreadCommunicationSystem {
         }
         itcs.uci_options.write().unwrap().threads = new_thread_count;
         let itcs_tx = &mut *itcs.tx.write().unwrap();
-        let itcs_nodes_searched = itcs.nodes_searched();
+        let itcs_nodes_searched = &mut *itcs.nodes_searched.write().unwrap();
         *itcs_tx = Vec::with_capacity(new_thread_count);
         *itcs_nodes_searched = Vec::with_capacity(new_thread_count);
+        *itcs.search_statistics.lock().unwrap() = vec![SearchStatistics::default(); new_thread_count];
         for id in 0..new_thread_count {
             itcs_nodes_searched.push(AtomicU64::new(0));
             let (tx, rx) = channel();
@@ -119,6 +140,13 @@ impl InterThreadCommunicationSystem {
         }
     }
 
+    //Every thread clears its eval cache before it starts its next search
+    pub fn clear_eval_caches(&self) {
+        for tx in self.tx.read().unwrap().iter() {
+            tx.send(ThreadInstruction::ClearEvalCache).expect("couldn't send clear eval cache flag");
+        }
+    }
+
     pub fn get_time_elapsed(&self) -> u64 {
         let now = Instant::now();
         let dur = now.duration_since(*self.start_time.read().unwrap());
@@ -131,6 +159,20 @@ impl InterThreadCommunicationSystem {
         self.nodes_searched()[thread_id].store(nodes_searched, Ordering::Relaxed);
     }
 
+    // Stores the statistics of a thread after it completed an iteration. The main thread also reports
+    // the statistics aggregated over all threads as one line of JSON.
+    pub fn report_statistics(&self, thread_id: usize, statistics: &SearchStatistics, iteration: usize) {
+        let mut all_statistics = self.search_statistics.lock().unwrap();
+        all_statistics[thread_id] = *statistics;
+        if thread_id == 0 {
+            let mut sum = SearchStatistics::default();
+            for other in all_statistics.iter() {
+                sum.merge(other);
+            }
+            println!("info string statistics {}", sum.to_json(iteration, all_statistics.len(), self.get_time_elapsed()));
+        }
+    }
+
     pub fn get_nodes_sum(&self) -> u64 {
         self.nodes_searched().iter().map(|x| x.load(Ordering::Relaxed)).sum()
     }
@@ -149,6 +191,9 @@ impl InterThreadCommunicationSystem {
                 *curr_best = scored_pv.clone();
             }
             //Report to UCI
+            if self.get_current_uci_options().quiet {
+                return;
+            }
             let searched_nodes: u64 = self.get_nodes_sum();
             let elapsed_time = self.get_time_elapsed();
             let mut cache_status = self.last_cache_status.lock().unwrap();
@@ -188,6 +233,9 @@ impl InterThreadCommunicationSystem {
     }
 
     pub fn report_bestmove(&self) {
+        if self.get_current_uci_options().quiet {
+            return;
+        }
         println!("bestmove {:?}", self.best_pv.lock().unwrap().pv.pv[0].as_ref().expect("Could not unwrap pv for bestmove!"));
     }
 
@@ -229,7 +277,8 @@ unsafe impl std::marker::Sync for InterThreadCommunicationSystem {}
 
 pub enum ThreadInstruction {
     Exit,
-    StartSearch(i16, GameState, TimeControl, History, u64),
+    ClearEvalCache,
+    StartSearch(i16, Box<GameState>, TimeControl, History, u64),
 }
 
 pub struct Thread {
@@ -246,8 +295,15 @@ pub struct Thread {
     pub bf_score: [[[usize; 64]; 64]; 2],
     pub history_score: [[[isize; 64]; 64]; 2],
     pub see_buffer: Vec<i16>,
+    pub eval_cache: EvalCache,
+    pub cache: Arc<Cache>,                       //Transposition table of the current search, released once the search is over
+    pub network: Option<Arc<Network>>,           //Network that was loaded last time we started searching, None for the handcrafted evaluation
+    pub eval_params: Option<Arc<RuntimeParams>>, //Params of the handcrafted evaluation loaded last time we started searching, None for the compiled ones
     pub search_statistics: SearchStatistics,
+    #[cfg(feature = "search-trace")]
+    pub trace: TraceRecorder, //Only the main thread records its search tree
     pub tc: TimeControl, //Only thread 0 takes care of Timecontrol though
+    pub node_limit: u64, //Nodes after which the main thread checks the node limit on every node
     pub time_saved: u64,
     pub self_stop: bool, //This is set when timeout_stop is set(timeout_stop isn't always polled)
     pub current_pv: ScoredPrincipalVariation,
@@ -264,15 +320,11 @@ impl Thread {
         self.current_pv = scored_pv;
         self.pv_applicable.clear();
         self.pv_applicable.push(root.get_hash());
-        let mut next_state = None;
+        let mut next_state = root.clone();
         for mv in self.current_pv.pv.pv.iter() {
             if let Some(mv) = mv {
-                if next_state.is_none() {
-                    next_state = Some(make_move(root, *mv));
-                } else {
-                    next_state = Some(make_move(next_state.as_ref().unwrap(), *mv));
-                }
-                self.pv_applicable.push(next_state.as_ref().unwrap().get_hash());
+                next_state.do_move(*mv);
+                self.pv_applicable.push(next_state.get_hash());
             } else {
                 break;
             }
@@ -283,6 +335,7 @@ impl Thread {
         for i in 0..MAX_SEARCH_DEPTH {
             pv_table.push(PrincipalVariation::new(MAX_SEARCH_DEPTH - i));
         }
+        let eval_cache = EvalCache::with_size(itcs.get_current_uci_options().eval_cache_size);
         Thread {
             id,
             itcs,
@@ -297,8 +350,15 @@ impl Thread {
             bf_score: [[[1; 64]; 64]; 2],
             history_score: [[[0; 64]; 64]; 2],
             see_buffer: vec![0i16; MAX_SEARCH_DEPTH],
+            eval_cache,
+            cache: Arc::default(),
+            network: None,
+            eval_params: None,
             search_statistics: SearchStatistics::default(),
+            #[cfg(feature = "search-trace")]
+            trace: TraceRecorder::default(),
             tc: TimeControl::MoveTime(0u64),
+            node_limit: u64::MAX,
             time_saved: 0u64,
             self_stop: false,
             current_pv: ScoredPrincipalVariation::default(),
@@ -318,6 +378,9 @@ impl Thread {
                     self.tx.send(()).expect("Error sending exit flag!");
                     break;
                 }
+                ThreadInstruction::ClearEvalCache => {
+                    self.eval_cache.clear();
+                }
                 ThreadInstruction::StartSearch(max_depth, state, tc, history, time_saved) => {
                     self.root_plies_played = (state.get_full_moves() - 1) * 2 + state.get_color_to_move();
                     self.history = history;
@@ -330,20 +393,53 @@ impl Thread {
                     self.bf_score = [[[1; 64]; 64]; 2];
                     self.history_score = [[[0; 64]; 64]; 2];
                     self.search_statistics = SearchStatistics::default();
+                    self.node_limit = match tc {
+                        TimeControl::Nodes(nodes) if self.id == 0 => nodes,
+                        _ => u64::MAX,
+                    };
                     self.tc = tc;
                     self.self_stop = false;
                     self.uci_options = self.itcs.get_current_uci_options();
-                    self.search(max_depth, state);
+                    self.cache = self.itcs.cache();
+                    let network = self.itcs.network.read().unwrap().clone();
+                    let network_changed = match (&network, &self.network) {
+                        (Some(new), Some(old)) => !Arc::ptr_eq(new, old),
+                        (new, old) => new.is_some() != old.is_some(),
+                    };
+                    self.network = network;
+                    let eval_params = self.itcs.eval_params.read().unwrap().clone();
+                    let eval_params_changed = match (&eval_params, &self.eval_params) {
+                        (Some(new), Some(old)) => !Arc::ptr_eq(new, old),
+                        (new, old) => new.is_some() != old.is_some(),
+                    };
+                    self.eval_params = eval_params;
+                    if network_changed || eval_params_changed || self.eval_cache.get_size() != self.uci_options.eval_cache_size {
+                        self.eval_cache = EvalCache::with_size(self.uci_options.eval_cache_size);
+                    }
+                    self.search(max_depth, *state);
+                    self.cache = Arc::default();
                     self.tx.send(()).expect("Error sending finish flag!");
                 }
             }
         }
     }
 
-    fn search(&mut self, max_depth: i16, state: GameState) {
+    fn search(&mut self, max_depth: i16, mut state: GameState) {
         if self.uci_options.debug_print {
             println!("info String Thread {} starting the search of state!", self.id);
         }
+        #[cfg(feature = "search-trace")]
+        {
+            if self.id == 0 {
+                self.trace = TraceRecorder::create(&self.itcs.trace_config.read().unwrap(), &state).unwrap_or_else(|e| {
+                    println!("info String Could not create search trace: {}", e);
+                    TraceRecorder::default()
+                });
+            }
+        }
+        if let Some(network) = &self.network {
+            network.init_accumulators(&mut state);
+        }
         let mut curr_depth = 0;
         let mut previous_score: Option<i16> = None;
         loop {
@@ -360,11 +456,9 @@ impl Thread {
             let mut delta = if let Some(ps) = previous_score { ps.abs() / 50 } else { 0 } + 14;
             let mut alpha = if curr_depth == 1 { -16000 } else { self.current_pv.score - delta };
             let mut beta = if curr_depth == 1 { 16000 } else { self.current_pv.score + delta };
+            let color = if state.get_color_to_move() == WHITE { 1 } else { -1 };
             loop {
-                principal_variation_search(
-                    CombinedSearchParameters::from(alpha, beta, curr_depth as i16, &state, if state.get_color_to_move() == WHITE { 1 } else { -1 }, 0),
-                    self,
-                );
+                principal_variation_search(CombinedSearchParameters::from(alpha, beta, curr_depth as i16, &mut state, color, 0), self);
                 if self.self_stop {
                     break;
                 }
@@ -401,17 +495,22 @@ impl Thread {
                 delta = (f64::from(delta) * 1.5) as i16;
             }
             previous_score = Some(self.current_pv.score);
-            #[cfg(feature = "search-statistics")]
-            {
-                println!("{}", self.search_statistics);
-            }
             if self.self_stop {
                 break;
             }
+            if self.uci_options.search_statistics {
+                self.itcs.report_statistics(self.id, &self.search_statistics, curr_depth);
+            }
         }
         if self.uci_options.debug_print {
             println!("info String Thread {} stopping the search of state!", self.id);
         }
+        #[cfg(feature = "search-trace")]
+        {
+            if let Err(e) = self.trace.finish() {
+                println!("info String Could not write search trace: {}", e);
+            }
+        }
 
         //Report nodes in the end
         self.itcs.update(self.id, self.search_statistics.nodes_searched, self.search_statistics.seldepth);
@@ -430,6 +529,7 @@ pub fn search_move(itcs: Arc<InterThreadCommunicationSystem>, max_depth: i16, ga
     *itcs.depth_info.lock().unwrap() = [DepthInformation::UnSearched; MAX_SEARCH_DEPTH];
     itcs.nodes_searched().iter().for_each(|x| x.store(0u64, Ordering::Relaxed));
     itcs.seldepth.store(0, Ordering::Relaxed);
+    itcs.search_statistics.lock().unwrap().iter_mut().for_each(|x| *x = SearchStatistics::default());
     *itcs.start_time.write().unwrap() = Instant::now();
     *itcs.last_cache_status.lock().unwrap() = None;
     itcs.cache_status.store(0, Ordering::Relaxed);
@@ -446,11 +546,13 @@ pub fn search_move(itcs: Arc<InterThreadCommunicationSystem>, max_depth: i16, ga
         panic!("The root position given does not have any legal move!");
     } else if movelist.move_list.len() == 1
         && match tc {
-            TimeControl::Infinite | TimeControl::MoveTime(_) => false,
+            TimeControl::Infinite | TimeControl::MoveTime(_) | TimeControl::Nodes(_) => false,
             _ => true,
         }
     {
-        println!("bestmove {:?}", movelist.move_list[0].0);
+        if !uci_options.quiet {
+            println!("bestmove {:?}", movelist.move_list[0].0);
+        }
 
         let new_timesaved: u64 = (time_saved_before as i64 + tc.time_saved(0, time_saved_before, uci_options.move_overhead)).max(0) as u64;
         itcs.saved_time.store(new_timesaved, Ordering::Relaxed);
@@ -472,7 +574,7 @@ pub fn search_move(itcs: Arc<InterThreadCommunicationSystem>, max_depth: i16, ga
 
     //Step 4. Send search command
     for tx in itcs.tx.read().unwrap().iter() {
-        tx.send(ThreadInstruction::StartSearch(max_depth, game_state.clone(), tc, hist.clone(), time_saved_before))
+        tx.send(ThreadInstruction::StartSearch(max_depth, Box::new(game_state.clone()), tc, hist.clone(), time_saved_before))
             .expect("Couldn't send search command!");
     }
 
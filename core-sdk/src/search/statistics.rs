@@ -1,5 +1,6 @@
 use std::fmt::{Display, Formatter, Result};
 
+#[derive(Copy, Clone, Default)]
 pub struct SearchStatistics {
     pub improving2: [u64; 2],
     pub depth: usize,
@@ -13,14 +14,19 @@ pub struct SearchStatistics {
     pub q_beta_cutoffs: u64,
     pub q_beta_cutoffs_index: [usize; 32],
     pub q_non_beta_cutoffs: u64,
+    pub q_quiet_checks: u64,
+    pub q_evasion_nodes: u64,
     pub normal_nodes_beta_cutoffs: u64,
     pub normal_nodes_beta_cutoffs_index: [usize; 32],
     pub normal_nodes_non_beta_cutoffs: u64,
     pub normal_nodes_fail_lows: u64,
     pub normal_nodes_improv_cutoffs: [u64; 2],
     pub normal_nodes_improv_faillows: [u64; 2],
+    pub cache_probes: u64,
     pub cache_hit: u64,
     pub cache_hit_aj_replaces: u64,
+    pub eval_cache_probes: u64,
+    pub eval_cache_hits: u64,
     pub nm_pruned: u64,
     pub snm_pruned: u64,
     pub static_eval_nodes: u64,
@@ -28,40 +34,8 @@ pub struct SearchStatistics {
     pub iid_nodes: u64,
     pub futil_nodes: u64,
     pub history_pruned: u64,
-}
-
-impl Default for SearchStatistics {
-    fn default() -> Self {
-        SearchStatistics {
-            improving2: [0; 2],
-            depth: 0,
-            seldepth: 0,
-            nodes_searched: 0,
-            q_nodes_searched: 0,
-            normal_nodes_searched: 0,
-            nodes_improving: [0; 2],
-            q_delta_cutoffs: 0,
-            q_see_cutoffs: 0,
-            q_beta_cutoffs: 0,
-            q_beta_cutoffs_index: [0; 32],
-            q_non_beta_cutoffs: 0,
-            normal_nodes_beta_cutoffs: 0,
-            normal_nodes_non_beta_cutoffs: 0,
-            normal_nodes_beta_cutoffs_index: [0; 32],
-            normal_nodes_fail_lows: 0,
-            normal_nodes_improv_cutoffs: [0; 2],
-            normal_nodes_improv_faillows: [0; 2],
-            cache_hit: 0,
-            cache_hit_aj_replaces: 0,
-            nm_pruned: 0,
-            snm_pruned: 0,
-            static_eval_nodes: 0,
-            cache_replace_eval: 0,
-            iid_nodes: 0,
-            futil_nodes: 0,
-            history_pruned: 0,
-        }
-    }
+    pub lmp_pruned: u64,
+    pub see_pruned: u64,
 }
 
 impl SearchStatistics {
@@ -78,6 +52,14 @@ impl SearchStatistics {
         self.history_pruned += 1;
     }
     #[inline(always)]
+    pub fn add_lmp_pruned(&mut self) {
+        self.lmp_pruned += 1;
+    }
+    #[inline(always)]
+    pub fn add_see_pruned(&mut self) {
+        self.see_pruned += 1;
+    }
+    #[inline(always)]
     pub fn add_q_node(&mut self, seldepth: usize) {
         self.nodes_searched += 1;
         self.q_nodes_searched += 1;
@@ -121,13 +103,25 @@ impl SearchStatistics {
     #[inline(always)]
     pub fn add_q_beta_cutoff(&mut self, index: usize) {
         self.q_beta_cutoffs += 1;
-        self.q_beta_cutoffs_index[index] += 1;
+        if index > 31 {
+            self.q_beta_cutoffs_index[31] += 1;
+        } else {
+            self.q_beta_cutoffs_index[index] += 1;
+        }
     }
     #[inline(always)]
     pub fn add_q_beta_noncutoff(&mut self) {
         self.q_non_beta_cutoffs += 1;
     }
     #[inline(always)]
+    pub fn add_q_quiet_check(&mut self) {
+        self.q_quiet_checks += 1;
+    }
+    #[inline(always)]
+    pub fn add_q_evasion_node(&mut self) {
+        self.q_evasion_nodes += 1;
+    }
+    #[inline(always)]
     pub fn add_normal_node_beta_cutoff(&mut self, index: usize) {
         self.normal_nodes_beta_cutoffs += 1;
         if index > 31 {
@@ -141,6 +135,10 @@ impl SearchStatistics {
         self.normal_nodes_non_beta_cutoffs += 1;
     }
     #[inline(always)]
+    pub fn add_cache_probe(&mut self) {
+        self.cache_probes += 1;
+    }
+    #[inline(always)]
     pub fn add_cache_hit_ns(&mut self) {
         self.cache_hit += 1;
     }
@@ -150,9 +148,120 @@ impl SearchStatistics {
         self.cache_hit_aj_replaces += 1;
     }
     #[inline(always)]
+    pub fn add_eval_cache_probe(&mut self, hit: bool) {
+        self.eval_cache_probes += 1;
+        self.eval_cache_hits += hit as u64;
+    }
+    #[inline(always)]
     pub fn add_nm_pruning(&mut self) {
         self.nm_pruned += 1;
     }
+
+    // Adds the counters of another thread, used to aggregate the statistics of all threads
+    pub fn merge(&mut self, other: &SearchStatistics) {
+        self.depth = self.depth.max(other.depth);
+        self.seldepth = self.seldepth.max(other.seldepth);
+        self.nodes_searched += other.nodes_searched;
+        self.q_nodes_searched += other.q_nodes_searched;
+        self.normal_nodes_searched += other.normal_nodes_searched;
+        self.q_delta_cutoffs += other.q_delta_cutoffs;
+        self.q_see_cutoffs += other.q_see_cutoffs;
+        self.q_beta_cutoffs += other.q_beta_cutoffs;
+        self.q_non_beta_cutoffs += other.q_non_beta_cutoffs;
+        self.q_quiet_checks += other.q_quiet_checks;
+        self.q_evasion_nodes += other.q_evasion_nodes;
+        self.normal_nodes_beta_cutoffs += other.normal_nodes_beta_cutoffs;
+        self.normal_nodes_non_beta_cutoffs += other.normal_nodes_non_beta_cutoffs;
+        self.normal_nodes_fail_lows += other.normal_nodes_fail_lows;
+        for i in 0..32 {
+            self.q_beta_cutoffs_index[i] += other.q_beta_cutoffs_index[i];
+            self.normal_nodes_beta_cutoffs_index[i] += other.normal_nodes_beta_cutoffs_index[i];
+        }
+        for i in 0..2 {
+            self.improving2[i] += other.improving2[i];
+            self.nodes_improving[i] += other.nodes_improving[i];
+            self.normal_nodes_improv_cutoffs[i] += other.normal_nodes_improv_cutoffs[i];
+            self.normal_nodes_improv_faillows[i] += other.normal_nodes_improv_faillows[i];
+        }
+        self.cache_probes += other.cache_probes;
+        self.cache_hit += other.cache_hit;
+        self.cache_hit_aj_replaces += other.cache_hit_aj_replaces;
+        self.eval_cache_probes += other.eval_cache_probes;
+        self.eval_cache_hits += other.eval_cache_hits;
+        self.nm_pruned += other.nm_pruned;
+        self.snm_pruned += other.snm_pruned;
+        self.static_eval_nodes += other.static_eval_nodes;
+        self.cache_replace_eval += other.cache_replace_eval;
+        self.iid_nodes += other.iid_nodes;
+        self.futil_nodes += other.futil_nodes;
+        self.history_pruned += other.history_pruned;
+        self.lmp_pruned += other.lmp_pruned;
+        self.see_pruned += other.see_pruned;
+    }
+
+    // One line of JSON, so that the output of several runs can be compared by script.
+    // Counters are cumulative since the start of the search. Without the search-statistics feature only nodes
+    // and depths are counted, "detailed" tells whether the other counters were collected.
+    pub fn to_json(&self, iteration: usize, threads: usize, time_ms: u64) -> String {
+        fn ratio(a: u64, b: u64) -> f64 {
+            if b == 0 {
+                0.
+            } else {
+                a as f64 / b as f64
+            }
+        }
+        format!(
+            concat!(
+                "{{\"iteration\":{},\"threads\":{},\"time_ms\":{},\"seldepth\":{},\"nodes\":{},\"normal_nodes\":{},\"q_nodes\":{},\"q_ratio\":{:.4},\"detailed\":{},",
+                "\"tt\":{{\"probes\":{},\"hits\":{},\"cutoffs\":{},\"hit_rate\":{:.4},\"cutoff_rate\":{:.4}}},",
+                "\"eval_cache\":{{\"probes\":{},\"hits\":{},\"hit_rate\":{:.4}}},",
+                "\"pruning\":{{\"static_null_move\":{},\"null_move\":{},\"futility\":{},\"history\":{},\"late_move\":{},\"see\":{}}},",
+                "\"normal\":{{\"beta_cutoffs\":{},\"non_beta_cutoffs\":{},\"fail_lows\":{},\"first_move_cutoff_rate\":{:.4},\"cutoff_index\":{:?},",
+                "\"improving\":{:?},\"improving_cutoffs\":{:?},\"improving_fail_lows\":{:?}}},",
+                "\"qsearch\":{{\"beta_cutoffs\":{},\"non_beta_cutoffs\":{},\"delta_cutoffs\":{},\"see_cutoffs\":{},\"quiet_checks\":{},\"evasion_nodes\":{},",
+                "\"cutoff_rate\":{:.4},\"cutoff_index\":{:?}}}}}"
+            ),
+            iteration,
+            threads,
+            time_ms,
+            self.seldepth,
+            self.nodes_searched,
+            self.normal_nodes_searched,
+            self.q_nodes_searched,
+            ratio(self.q_nodes_searched, self.nodes_searched),
+            cfg!(feature = "search-statistics"),
+            self.cache_probes,
+            self.cache_hit,
+            self.cache_hit_aj_replaces,
+            ratio(self.cache_hit, self.cache_probes),
+            ratio(self.cache_hit_aj_replaces, self.cache_probes),
+            self.eval_cache_probes,
+            self.eval_cache_hits,
+            ratio(self.eval_cache_hits, self.eval_cache_probes),
+            self.snm_pruned,
+            self.nm_pruned,
+            self.futil_nodes,
+            self.history_pruned,
+            self.lmp_pruned,
+            self.see_pruned,
+            self.normal_nodes_beta_cutoffs,
+            self.normal_nodes_non_beta_cutoffs,
+            self.normal_nodes_fail_lows,
+            ratio(self.normal_nodes_beta_cutoffs_index[0] as u64, self.normal_nodes_beta_cutoffs),
+            self.normal_nodes_beta_cutoffs_index,
+            self.nodes_improving,
+            self.normal_nodes_improv_cutoffs,
+            self.normal_nodes_improv_faillows,
+            self.q_beta_cutoffs,
+            self.q_non_beta_cutoffs,
+            self.q_delta_cutoffs,
+            self.q_see_cutoffs,
+            self.q_quiet_checks,
+            self.q_evasion_nodes,
+            ratio(self.q_beta_cutoffs, self.q_nodes_searched),
+            self.q_beta_cutoffs_index,
+        )
+    }
 }
 
 impl Display for SearchStatistics {
@@ -197,6 +306,11 @@ impl Display for SearchStatistics {
             self.cache_replace_eval,
             (self.cache_replace_eval as f64 / self.cache_hit as f64 * 100.0)
         ));
+        res_str.push_str(&format!(
+            "Eval-Cache-Hits: {} ({}%)\n",
+            self.eval_cache_hits,
+            (self.eval_cache_hits as f64 / self.eval_cache_probes as f64 * 100.0)
+        ));
         res_str.push_str(&format!(
             "Normal-Search IID Nodes : {} ({}%)\n",
             self.iid_nodes,
@@ -255,6 +369,50 @@ impl Display for SearchStatistics {
             self.q_non_beta_cutoffs,
             (self.q_non_beta_cutoffs as f64 / self.q_nodes_searched as f64 * 100.0)
         ));
+        res_str.push_str(&format!(
+            "Q-Search Quiet checks: {} ({}%)\n",
+            self.q_quiet_checks,
+            (self.q_quiet_checks as f64 / self.q_nodes_searched as f64 * 100.0)
+        ));
+        res_str.push_str(&format!(
+            "Q-Search Evasion nodes: {} ({}%)\n",
+            self.q_evasion_nodes,
+            (self.q_evasion_nodes as f64 / self.q_nodes_searched as f64 * 100.0)
+        ));
         write!(formatter, "{}", res_str)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SearchStatistics;
+
+    #[test]
+    fn merge_and_json_test() {
+        let mut a = SearchStatistics::default();
+        a.add_normal_node(3);
+        a.add_cache_probe();
+        a.add_cache_hit_ns();
+        a.add_normal_node_beta_cutoff(0);
+        let mut b = SearchStatistics::default();
+        b.add_normal_node(5);
+        b.add_q_node(9);
+        b.add_cache_probe();
+        b.add_normal_node_beta_cutoff(40);
+        a.merge(&b);
+        assert_eq!(a.nodes_searched, 3);
+        assert_eq!(a.depth, 5);
+        assert_eq!(a.seldepth, 9);
+        assert_eq!(a.normal_nodes_beta_cutoffs_index[0], 1);
+        assert_eq!(a.normal_nodes_beta_cutoffs_index[31], 1);
+
+        let json = a.to_json(7, 2, 100);
+        assert!(json.starts_with("{\"iteration\":7,\"threads\":2,\"time_ms\":100,"));
+        assert!(json.contains("\"tt\":{\"probes\":2,\"hits\":1,\"cutoffs\":0,\"hit_rate\":0.5000,"));
+        assert!(!json.contains('\n'));
+        assert!(!json.contains("NaN"));
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        // Ratios of empty statistics are reported as 0 instead of NaN
+        assert!(!SearchStatistics::default().to_json(1, 1, 0).contains("NaN"));
+    }
+}
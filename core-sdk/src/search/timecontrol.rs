@@ -19,6 +19,8 @@ pub enum TimeControl {
     MoveTime(u64),
     Infinite,
     Tournament(u64, u64, usize),
+    //Searches until the threads together searched that many nodes
+    Nodes(u64),
 }
 
 impl TimeControl {
@@ -33,6 +35,7 @@ impl TimeControl {
             }
             TimeControl::MoveTime(time) => format!("movetime {}", time),
             TimeControl::Infinite => "infinite".to_owned(),
+            TimeControl::Nodes(nodes) => format!("nodes {}", nodes),
             TimeControl::Tournament(time_left, inc, movestogo) => {
                 if white {
                     format!("wtime {} winc {} movestogo {}", time_left, inc, movestogo)
@@ -51,6 +54,7 @@ impl TimeControl {
             TimeControl::MoveTime(time) => {
                 *self = TimeControl::MoveTime(*time);
             }
+            TimeControl::Nodes(_) => {}
             TimeControl::Infinite => panic!("Should not call updat eon Infinite"),
             TimeControl::Tournament(left, inc, movestogo) => {
                 assert!(*left > time_spent);
@@ -68,6 +72,7 @@ impl TimeControl {
             TimeControl::Incremental(left, _) => *left,
             TimeControl::MoveTime(left) => *left,
             TimeControl::Infinite => panic!("Should not call time_left on Infinite"),
+            TimeControl::Nodes(_) => panic!("Should not call time_left on Nodes"),
             TimeControl::Tournament(left, _, _) => *left,
         }
     }
@@ -78,8 +83,15 @@ impl TimeControl {
             _ => panic!("Only call this function on normal timecontrols"),
         }
     }
+    pub fn nodes_over(&self, nodes: u64) -> bool {
+        match self {
+            TimeControl::Nodes(limit) => nodes >= *limit,
+            _ => false,
+        }
+    }
+
     pub fn time_over(&self, time_spent: u64, tc_information: &TimeControlInformation, move_overhead: u64) -> bool {
-        if let TimeControl::Infinite = self {
+        if let TimeControl::Infinite | TimeControl::Nodes(_) = self {
             return false;
         } else if let TimeControl::MoveTime(move_time) = self {
             return time_spent > move_time - move_overhead || *move_time < move_overhead;
@@ -122,6 +134,8 @@ impl TimeControl {
             res_str.push_str(&format!("Limited movetime: {}\n", time));
         } else if let TimeControl::Infinite = self {
             res_str.push_str("Infinite Time!\n");
+        } else if let TimeControl::Nodes(nodes) = self {
+            res_str.push_str(&format!("Limited nodes: {}\n", nodes));
         } else {
             let (mytime, myinc, movestogo) = self.get_normal_tc_info();
             res_str.push_str(&format!("My Time: {}\n", mytime));
@@ -0,0 +1,461 @@
+// Search tree recorder. The search only calls into the recorder when core-sdk is built with the
+// `search-trace` feature, the file format itself is always available so that tools can read traces.
+//
+// File layout (little endian):
+//   magic "FCTR", version: u16, fen length: u16, fen bytes of the root position
+//   followed by TraceRecord::SIZE byte records, written when a node is left (post-order).
+use crate::board_representation::game_state::{GameMove, GameState};
+use crate::search::cache::{CacheEntry, INVALID_STATIC_EVALUATION};
+use crate::search::CombinedSearchParameters;
+use std::fs::File;
+use std::io::{BufWriter, Read, Result, Write};
+
+pub const TRACE_MAGIC: &[u8; 4] = b"FCTR";
+pub const TRACE_VERSION: u16 = 1;
+pub const NO_PARENT: u32 = u32::MAX;
+pub const NO_SCORE: i16 = -32768;
+
+pub const FLAG_QSEARCH: u8 = 0x1;
+pub const FLAG_PRUNED_MOVE: u8 = 0x2;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PruneReason {
+    None,
+    TTCutoff,
+    Draw,
+    MateDistance,
+    StaticNullMove,
+    NullMove,
+    Futility,
+    History,
+    LateMove,
+    SEE,
+    StandPat,
+    Delta,
+    QDelta,
+    QSEE,
+    UpcomingRepetition,
+}
+
+pub const PRUNE_REASONS: [PruneReason; 15] = [
+    PruneReason::None,
+    PruneReason::TTCutoff,
+    PruneReason::Draw,
+    PruneReason::MateDistance,
+    PruneReason::StaticNullMove,
+    PruneReason::NullMove,
+    PruneReason::Futility,
+    PruneReason::History,
+    PruneReason::LateMove,
+    PruneReason::SEE,
+    PruneReason::StandPat,
+    PruneReason::Delta,
+    PruneReason::QDelta,
+    PruneReason::QSEE,
+    PruneReason::UpcomingRepetition,
+];
+
+impl PruneReason {
+    pub fn from_u8(reason: u8) -> PruneReason {
+        PRUNE_REASONS.get(reason as usize).copied().unwrap_or(PruneReason::None)
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            PruneReason::None => "none",
+            PruneReason::TTCutoff => "tt",
+            PruneReason::Draw => "draw",
+            PruneReason::MateDistance => "mdp",
+            PruneReason::StaticNullMove => "snmp",
+            PruneReason::NullMove => "nmp",
+            PruneReason::Futility => "futility",
+            PruneReason::History => "history",
+            PruneReason::LateMove => "lmp",
+            PruneReason::SEE => "see",
+            PruneReason::StandPat => "standpat",
+            PruneReason::Delta => "delta",
+            PruneReason::QDelta => "qdelta",
+            PruneReason::QSEE => "qsee",
+            PruneReason::UpcomingRepetition => "cycle",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<PruneReason> {
+        PRUNE_REASONS.iter().copied().find(|reason| reason.name() == name)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TraceRecord {
+    pub id: u32,
+    pub parent: u32,
+    pub ply: u8,
+    pub depth: i8,
+    pub reason: PruneReason,
+    pub flags: u8,
+    pub mv: u16, // CacheEntry::mv_to_u16 encoding, 0 for the root and null moves
+    pub alpha: i16,
+    pub beta: i16,
+    pub static_evaluation: i16,
+    pub score: i16,
+}
+
+impl TraceRecord {
+    pub const SIZE: usize = 24;
+
+    pub fn is_qsearch(&self) -> bool {
+        self.flags & FLAG_QSEARCH > 0
+    }
+    pub fn is_pruned_move(&self) -> bool {
+        self.flags & FLAG_PRUNED_MOVE > 0
+    }
+
+    pub fn to_bytes(&self) -> [u8; TraceRecord::SIZE] {
+        let mut res = [0u8; TraceRecord::SIZE];
+        res[0..4].copy_from_slice(&self.id.to_le_bytes());
+        res[4..8].copy_from_slice(&self.parent.to_le_bytes());
+        res[8] = self.ply;
+        res[9] = self.depth as u8;
+        res[10] = self.reason as u8;
+        res[11] = self.flags;
+        res[12..14].copy_from_slice(&self.mv.to_le_bytes());
+        res[14..16].copy_from_slice(&self.alpha.to_le_bytes());
+        res[16..18].copy_from_slice(&self.beta.to_le_bytes());
+        res[18..20].copy_from_slice(&self.static_evaluation.to_le_bytes());
+        res[20..22].copy_from_slice(&self.score.to_le_bytes());
+        res
+    }
+
+    pub fn from_bytes(bytes: &[u8; TraceRecord::SIZE]) -> TraceRecord {
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        TraceRecord {
+            id: u32_at(0),
+            parent: u32_at(4),
+            ply: bytes[8],
+            depth: bytes[9] as i8,
+            reason: PruneReason::from_u8(bytes[10]),
+            flags: bytes[11],
+            mv: u16_at(12),
+            alpha: u16_at(14) as i16,
+            beta: u16_at(16) as i16,
+            static_evaluation: u16_at(18) as i16,
+            score: u16_at(20) as i16,
+        }
+    }
+}
+
+// Long algebraic notation of a move in CacheEntry::mv_to_u16 encoding, which does not need a board.
+pub fn move_to_uci(mv: u16) -> String {
+    if mv == 0 {
+        return "0000".to_owned();
+    }
+    let square = |sq: u16| format!("{}{}", (b'a' + (sq % 8) as u8) as char, sq / 8 + 1);
+    let promotion = match mv & 15 {
+        3 => "q",
+        4 => "r",
+        5 => "b",
+        6 => "n",
+        _ => "",
+    };
+    format!("{}{}{}", square((mv >> 10) & 63), square((mv >> 4) & 63), promotion)
+}
+
+pub fn write_header<W: Write>(writer: &mut W, fen: &str) -> Result<()> {
+    writer.write_all(TRACE_MAGIC)?;
+    writer.write_all(&TRACE_VERSION.to_le_bytes())?;
+    writer.write_all(&(fen.len() as u16).to_le_bytes())?;
+    writer.write_all(fen.as_bytes())
+}
+
+// Reads a whole trace, returning the fen of the root position and all records
+pub fn read_trace<R: Read>(reader: &mut R) -> Result<(String, Vec<TraceRecord>)> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_owned());
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != TRACE_MAGIC {
+        return Err(invalid("Not a search trace"));
+    }
+    if u16::from_le_bytes([header[4], header[5]]) != TRACE_VERSION {
+        return Err(invalid("Unsupported search trace version"));
+    }
+    let mut fen = vec![0u8; u16::from_le_bytes([header[6], header[7]]) as usize];
+    reader.read_exact(&mut fen)?;
+    let fen = String::from_utf8(fen).map_err(|_| invalid("Invalid fen in search trace"))?;
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let records = data
+        .chunks_exact(TraceRecord::SIZE)
+        .map(|chunk| {
+            let mut bytes = [0u8; TraceRecord::SIZE];
+            bytes.copy_from_slice(chunk);
+            TraceRecord::from_bytes(&bytes)
+        })
+        .collect();
+    Ok((fen, records))
+}
+
+#[derive(Clone)]
+pub struct TraceConfig {
+    pub file: Option<String>,
+    pub node_from: u32,
+    pub node_to: u32,
+    pub pv_prefix: Vec<String>, //Moves in long algebraic notation, only the subtree below them is recorded
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        TraceConfig {
+            file: None,
+            node_from: 0,
+            node_to: u32::MAX,
+            pv_prefix: Vec::new(),
+        }
+    }
+}
+
+impl TraceConfig {
+    // Parses a node range in the format "from-to", where both ends are optional
+    pub fn set_node_range(&mut self, range: &str) -> bool {
+        let mut split = range.splitn(2, '-');
+        let from = split.next().unwrap_or("").trim();
+        let to = split.next().unwrap_or("").trim();
+        let from = if from.is_empty() { Ok(0) } else { from.parse::<u32>() };
+        let to = if to.is_empty() { Ok(u32::MAX) } else { to.parse::<u32>() };
+        if let (Ok(from), Ok(to)) = (from, to) {
+            self.node_from = from;
+            self.node_to = to;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct OpenNode {
+    record: TraceRecord,
+    recorded: bool,
+}
+
+// Records the nodes visited by one thread. Nodes are numbered in the order they are entered,
+// records are written when the node is left.
+#[derive(Default)]
+pub struct TraceRecorder {
+    writer: Option<BufWriter<File>>,
+    config: TraceConfig,
+    next_id: u32,
+    stack: Vec<OpenNode>,
+    moves: Vec<u16>,
+    on_prefix: Vec<bool>,
+}
+
+impl TraceRecorder {
+    pub fn create(config: &TraceConfig, root: &GameState) -> Result<TraceRecorder> {
+        let mut recorder = TraceRecorder::default();
+        if let Some(file) = config.file.as_ref() {
+            let mut writer = BufWriter::new(File::create(file)?);
+            write_header(&mut writer, &root.to_fen())?;
+            recorder.writer = Some(writer);
+            recorder.config = config.clone();
+        }
+        Ok(recorder)
+    }
+
+    #[inline(always)]
+    pub fn is_active(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    // Sets the move which leads to the nodes of the given ply, None for null moves
+    #[inline(always)]
+    pub fn set_move(&mut self, ply: usize, mv: Option<GameMove>) {
+        if !self.is_active() {
+            return;
+        }
+        if self.moves.len() <= ply {
+            self.moves.resize(ply + 1, 0);
+        }
+        self.moves[ply] = mv.map(CacheEntry::mv_to_u16).unwrap_or(0);
+    }
+
+    fn matches_prefix(&mut self, ply: usize) -> bool {
+        if self.on_prefix.len() <= ply {
+            self.on_prefix.resize(ply + 1, false);
+        }
+        let on_prefix = ply == 0 || self.on_prefix[ply - 1] && (ply > self.config.pv_prefix.len() || move_to_uci(self.moves[ply]) == self.config.pv_prefix[ply - 1]);
+        self.on_prefix[ply] = on_prefix;
+        on_prefix
+    }
+
+    fn next_record(&mut self, ply: usize, flags: u8) -> (TraceRecord, bool) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let parent = self.stack.last().map(|node| node.record.id).unwrap_or(NO_PARENT);
+        let mv = if ply == 0 || flags & FLAG_PRUNED_MOVE > 0 { 0 } else { self.moves[ply] };
+        let recorded = id >= self.config.node_from && id <= self.config.node_to;
+        (
+            TraceRecord {
+                id,
+                parent,
+                ply: ply as u8,
+                depth: 0,
+                reason: PruneReason::None,
+                flags,
+                mv,
+                alpha: 0,
+                beta: 0,
+                static_evaluation: INVALID_STATIC_EVALUATION,
+                score: NO_SCORE,
+            },
+            recorded,
+        )
+    }
+
+    #[inline(always)]
+    pub fn enter(&mut self, p: &CombinedSearchParameters, qsearch: bool) {
+        if !self.is_active() {
+            return;
+        }
+        if self.moves.len() <= p.current_depth {
+            self.moves.resize(p.current_depth + 1, 0);
+        }
+        let (mut record, recorded) = self.next_record(p.current_depth, if qsearch { FLAG_QSEARCH } else { 0 });
+        let recorded = self.matches_prefix(p.current_depth) && recorded;
+        record.depth = p.depth_left.max(i8::MIN as i16).min(i8::MAX as i16) as i8;
+        record.alpha = p.alpha;
+        record.beta = p.beta;
+        self.stack.push(OpenNode { record, recorded });
+    }
+
+    #[inline(always)]
+    pub fn exit(&mut self, score: i16) {
+        if !self.is_active() {
+            return;
+        }
+        let mut node = self.stack.pop().expect("Left a node which was never entered");
+        node.record.score = score;
+        if node.recorded {
+            self.write(&node.record);
+        }
+    }
+
+    #[inline(always)]
+    pub fn set_static_evaluation(&mut self, static_evaluation: i16) {
+        if let Some(node) = self.stack.last_mut() {
+            node.record.static_evaluation = static_evaluation;
+        }
+    }
+
+    // The current node is cut off without searching its moves
+    #[inline(always)]
+    pub fn prune_node(&mut self, reason: PruneReason) {
+        if let Some(node) = self.stack.last_mut() {
+            node.record.reason = reason;
+        }
+    }
+
+    // A move of the current node is skipped. It is recorded as a child without a score
+    #[inline(always)]
+    pub fn prune_move(&mut self, mv: GameMove, reason: PruneReason) {
+        if !self.is_active() || self.stack.is_empty() {
+            return;
+        }
+        let (parent_ply, parent_depth, parent_flags) = {
+            let parent = self.stack.last().unwrap();
+            (parent.record.ply as usize, parent.record.depth, parent.record.flags)
+        };
+        let (mut record, recorded) = self.next_record(parent_ply + 1, FLAG_PRUNED_MOVE | (parent_flags & FLAG_QSEARCH));
+        record.mv = CacheEntry::mv_to_u16(mv);
+        record.depth = parent_depth.saturating_sub(1);
+        record.reason = reason;
+        let prefix_ok = self.on_prefix[parent_ply] && (parent_ply >= self.config.pv_prefix.len() || move_to_uci(record.mv) == self.config.pv_prefix[parent_ply]);
+        if recorded && prefix_ok {
+            self.write(&record);
+        }
+    }
+
+    fn write(&mut self, record: &TraceRecord) {
+        if let Some(writer) = self.writer.as_mut() {
+            if writer.write_all(&record.to_bytes()).is_err() {
+                println!("info String Could not write search trace, stopping the trace");
+                self.writer = None;
+            }
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.stack.clear();
+        match self.writer.take() {
+            Some(mut writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{move_to_uci, read_trace, write_header, PruneReason, TraceConfig, TraceRecord, FLAG_PRUNED_MOVE, NO_PARENT, PRUNE_REASONS};
+    use crate::board_representation::game_state::{GameMove, GameMoveType, PieceType};
+    use crate::search::cache::CacheEntry;
+
+    #[test]
+    fn trace_record_roundtrip_test() {
+        let record = TraceRecord {
+            id: 123_456,
+            parent: NO_PARENT,
+            ply: 7,
+            depth: -3,
+            reason: PruneReason::QSEE,
+            flags: FLAG_PRUNED_MOVE,
+            mv: 0xABCD,
+            alpha: -16000,
+            beta: 271,
+            static_evaluation: -32768,
+            score: 31,
+        };
+        assert_eq!(TraceRecord::from_bytes(&record.to_bytes()), record);
+        for reason in PRUNE_REASONS.iter() {
+            assert_eq!(PruneReason::from_u8(*reason as u8), *reason);
+            assert_eq!(PruneReason::from_name(reason.name()), Some(*reason));
+        }
+
+        let mut file = Vec::new();
+        write_header(&mut file, "8/8/8/8/8/8/8/K6k w - - 0 1").unwrap();
+        file.extend_from_slice(&record.to_bytes());
+        file.extend_from_slice(&record.to_bytes());
+        let (fen, records) = read_trace(&mut file.as_slice()).unwrap();
+        assert_eq!(fen, "8/8/8/8/8/8/8/K6k w - - 0 1");
+        assert_eq!(records, vec![record, record]);
+        assert!(read_trace(&mut &b"FCTX\x01\x00\x00\x00"[..]).is_err());
+    }
+
+    #[test]
+    fn move_to_uci_test() {
+        let e7e8n = GameMove {
+            from: 52,
+            to: 60,
+            piece_type: PieceType::Pawn,
+            move_type: GameMoveType::Promotion(PieceType::Knight, None),
+        };
+        assert_eq!(move_to_uci(CacheEntry::mv_to_u16(e7e8n)), "e7e8n");
+        let g1f3 = GameMove {
+            from: 6,
+            to: 21,
+            piece_type: PieceType::Knight,
+            move_type: GameMoveType::Quiet,
+        };
+        assert_eq!(move_to_uci(CacheEntry::mv_to_u16(g1f3)), "g1f3");
+        assert_eq!(move_to_uci(0), "0000");
+    }
+
+    #[test]
+    fn node_range_test() {
+        let mut config = TraceConfig::default();
+        assert!(config.set_node_range("100-200"));
+        assert_eq!((config.node_from, config.node_to), (100, 200));
+        assert!(config.set_node_range("-50"));
+        assert_eq!((config.node_from, config.node_to), (0, 50));
+        assert!(config.set_node_range("7-"));
+        assert_eq!((config.node_from, config.node_to), (7, u32::MAX));
+        assert!(!config.set_node_range("a-b"));
+    }
+}
@@ -0,0 +1,479 @@
+// Depth-first proof-number search (df-pn) for studies and mate puzzles. Unlike alpha-beta, which only gives a heuristic
+// score, a result is only reported once it is proven, together with the proof tree.
+// The game theoretical value is found with two searches: can the side to move force a win, and if not, can the opponent.
+// If neither side can force a win, the position is a draw.
+use crate::board_representation::game_state::{GameMove, GameState, WHITE};
+use crate::move_generation::makemove::make_move;
+use crate::move_generation::movegen::{generate_moves, MoveList};
+use crate::search::history::History;
+use crate::search::{check_for_draw, SearchInstruction};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result};
+use std::time::Instant;
+
+pub const PN_INFINITY: u32 = 100_000_000;
+pub const DEFAULT_SOLVER_NODES: u64 = 2_000_000;
+//Proof trees are verified by walking them again, this bounds the work spent on that
+pub const MAX_PROOF_TREE_NODES: usize = 1_000_000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Verdict {
+    Win,
+    Loss,
+    Draw,
+    Unknown,
+}
+
+impl Display for Verdict {
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        let name = match self {
+            Verdict::Win => "win",
+            Verdict::Loss => "loss",
+            Verdict::Draw => "draw",
+            Verdict::Unknown => "unknown",
+        };
+        write!(formatter, "{}", name)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct SolverLimits {
+    pub nodes: u64,
+    pub max_depth: usize,
+    pub time: Option<u64>,
+}
+
+impl Default for SolverLimits {
+    fn default() -> Self {
+        SolverLimits {
+            nodes: DEFAULT_SOLVER_NODES,
+            max_depth: 64,
+            time: None,
+        }
+    }
+}
+
+pub struct ProofNode {
+    pub mv: GameMove,
+    pub children: Vec<ProofNode>,
+}
+
+impl ProofNode {
+    pub fn size(&self) -> usize {
+        1 + self.children.iter().map(|child| child.size()).sum::<usize>()
+    }
+}
+
+pub struct Solution {
+    //Seen from the side to move in the root position
+    pub verdict: Verdict,
+    pub root: GameState,
+    //For a win, the winning move with all defences. For a loss, all moves with their refutation.
+    //For a draw, how the side to move holds the draw against all tries of the opponent.
+    pub tree: Vec<ProofNode>,
+    pub nodes: u64,
+    pub time: u64,
+}
+
+impl Solution {
+    //Follows the most resistant defence, which is the first child of every node
+    pub fn main_line(&self) -> Vec<GameMove> {
+        let mut res = Vec::new();
+        let mut nodes = &self.tree;
+        while !nodes.is_empty() {
+            res.push(nodes[0].mv);
+            nodes = &nodes[0].children;
+        }
+        res
+    }
+
+    pub fn result_string(&self) -> &str {
+        let root_white = self.root.get_color_to_move() == WHITE;
+        match self.verdict {
+            Verdict::Win if root_white => "1-0",
+            Verdict::Loss if !root_white => "1-0",
+            Verdict::Win | Verdict::Loss => "0-1",
+            Verdict::Draw => "1/2-1/2",
+            Verdict::Unknown => "*",
+        }
+    }
+
+    //The proof tree as PGN, alternative defences are written as variations
+    pub fn to_pgn(&self) -> String {
+        let mut res = String::new();
+        res.push_str("[Event \"FabChess solver\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n[White \"?\"]\n[Black \"?\"]\n");
+        res.push_str(&format!("[Result \"{}\"]\n", self.result_string()));
+        res.push_str(&format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", self.root.to_fen()));
+        res.push_str(&format!("[Annotator \"Proof-number search, {} nodes\"]\n\n", self.nodes));
+
+        let mut tokens = Vec::new();
+        write_variation(&mut tokens, &self.root, &self.tree, true);
+        tokens.push(self.result_string().to_owned());
+        //Make sure that every line is only 80 long at maximum
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + token.len() + 1 > 80 {
+                res.push_str(&format!("{}\n", line));
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        res.push_str(&format!("{}\n", line));
+        res
+    }
+}
+
+fn move_number(game_state: &GameState, number_black: bool) -> Option<String> {
+    if game_state.get_color_to_move() == WHITE {
+        Some(format!("{}.", game_state.get_full_moves()))
+    } else if number_black {
+        Some(format!("{}...", game_state.get_full_moves()))
+    } else {
+        None
+    }
+}
+
+fn write_variation(tokens: &mut Vec<String>, game_state: &GameState, nodes: &[ProofNode], number_black: bool) {
+    if nodes.is_empty() {
+        return;
+    }
+    tokens.extend(move_number(game_state, number_black));
+    tokens.push(nodes[0].mv.to_san(game_state));
+    for alternative in nodes[1..].iter() {
+        let mut variation = Vec::new();
+        variation.extend(move_number(game_state, true));
+        variation.push(alternative.mv.to_san(game_state));
+        write_variation(&mut variation, &make_move(game_state, alternative.mv), &alternative.children, false);
+        variation[0] = format!("({}", variation[0]);
+        let last = variation.len() - 1;
+        variation[last] = format!("{})", variation[last]);
+        tokens.append(&mut variation);
+    }
+    write_variation(tokens, &make_move(game_state, nodes[0].mv), &nodes[0].children, nodes.len() > 1);
+}
+
+struct DfPn {
+    //The side trying to force a win
+    attacker: usize,
+    //Phi and delta of a position seen from the side to move. For the attacker these are the proof and disproof number,
+    //for the defender the disproof and proof number.
+    table: HashMap<u64, (u32, u32)>,
+    history: History,
+    //Used at the root, where a repetition of an earlier position is not a draw yet
+    root_history: History,
+    root_pointer: usize,
+    max_pointer: usize,
+    limits: SolverLimits,
+    start: Instant,
+    nodes: u64,
+    stop: bool,
+    tree_nodes: usize,
+}
+
+//A draw disproves the win of the attacker, as (phi, delta) seen from the side to move
+#[inline(always)]
+fn draw_value(attacker_to_move: bool) -> (u32, u32) {
+    if attacker_to_move {
+        (PN_INFINITY, 0)
+    } else {
+        (0, PN_INFINITY)
+    }
+}
+
+impl DfPn {
+    fn new(attacker: usize, history: &History, limits: SolverLimits, start: Instant) -> Self {
+        DfPn {
+            attacker,
+            table: HashMap::new(),
+            history: history.clone(),
+            root_history: History::default(),
+            root_pointer: history.pointer,
+            max_pointer: (history.pointer + limits.max_depth).min(history.hist.len() - 1),
+            limits,
+            start,
+            nodes: 0,
+            stop: false,
+            tree_nodes: 0,
+        }
+    }
+
+    fn is_draw(&self, game_state: &GameState) -> bool {
+        let history = if self.history.pointer == self.root_pointer { &self.root_history } else { &self.history };
+        matches!(check_for_draw(game_state, history), SearchInstruction::StopSearching(_))
+    }
+
+    fn check_limits(&mut self) {
+        if self.nodes >= self.limits.nodes || self.limits.time.map(|time| self.start.elapsed().as_millis() as u64 >= time).unwrap_or(false) {
+            self.stop = true;
+        }
+    }
+
+    fn search(&mut self, game_state: &mut GameState) -> (u32, u32) {
+        self.mid(game_state, PN_INFINITY, PN_INFINITY)
+    }
+
+    //Expands the most proving node below this one until one of the thresholds is reached
+    fn mid(&mut self, game_state: &mut GameState, phi_threshold: u32, delta_threshold: u32) -> (u32, u32) {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(4096) {
+            self.check_limits();
+        }
+        let attacker_to_move = game_state.get_color_to_move() == self.attacker;
+        //Draws by rule or repetition depend on the path and are not stored
+        if self.history.pointer >= self.max_pointer || self.is_draw(game_state) {
+            return draw_value(attacker_to_move);
+        }
+        let mut movelist = MoveList::default();
+        let agsi = generate_moves(game_state, false, &mut movelist);
+        if movelist.move_list.is_empty() {
+            let value = if agsi.stm_incheck { (PN_INFINITY, 0) } else { draw_value(attacker_to_move) };
+            self.table.insert(game_state.get_hash(), value);
+            return value;
+        }
+
+        self.history.push(game_state.get_hash(), game_state.get_half_moves() == 0);
+        let mut children: Vec<(GameMove, u64, bool)> = Vec::with_capacity(movelist.move_list.len());
+        for gmv in movelist.move_list.iter() {
+            game_state.do_move(gmv.0);
+            let draw = self.history.pointer >= self.max_pointer || self.is_draw(game_state);
+            children.push((gmv.0, game_state.get_hash(), draw));
+            game_state.undo_move(gmv.0);
+        }
+        let value = loop {
+            let (phi, delta, best, best_phi, second_delta) = self.select(&children, !attacker_to_move);
+            if phi >= phi_threshold || delta >= delta_threshold || self.stop {
+                break (phi, delta);
+            }
+            let child_phi_threshold = (delta_threshold as u64 + best_phi as u64 - delta as u64).min(PN_INFINITY as u64) as u32;
+            let child_delta_threshold = phi_threshold.min(second_delta.saturating_add(1));
+            let mv = children[best].0;
+            game_state.do_move(mv);
+            self.mid(game_state, child_phi_threshold, child_delta_threshold);
+            game_state.undo_move(mv);
+        };
+        self.history.pop();
+        self.table.insert(game_state.get_hash(), value);
+        value
+    }
+
+    //Returns phi and delta of the node, the most proving child, its phi and the second smallest delta of the children
+    fn select(&self, children: &[(GameMove, u64, bool)], attacker_to_move: bool) -> (u32, u32, usize, u32, u32) {
+        let mut phi = PN_INFINITY;
+        let mut delta = 0u32;
+        let mut best = 0;
+        let mut best_phi = PN_INFINITY;
+        let mut second_delta = PN_INFINITY;
+        for (index, (_, hash, draw)) in children.iter().enumerate() {
+            let (child_phi, child_delta) = if *draw {
+                draw_value(attacker_to_move)
+            } else {
+                self.table.get(hash).copied().unwrap_or((1, 1))
+            };
+            delta = delta.saturating_add(child_phi).min(PN_INFINITY);
+            if child_delta < phi {
+                second_delta = phi;
+                phi = child_delta;
+                best = index;
+                best_phi = child_phi;
+            } else if child_delta < second_delta {
+                second_delta = child_delta;
+            }
+        }
+        (phi, delta, best, best_phi, second_delta)
+    }
+
+    //Whether the table proves (or disproves) the position for the attacker
+    fn is_resolved(&self, game_state: &GameState, prove: bool) -> bool {
+        if !prove && self.is_draw(game_state) {
+            return true;
+        }
+        match self.table.get(&game_state.get_hash()) {
+            Some((phi, delta)) => {
+                let (proof, disproof) = if game_state.get_color_to_move() == self.attacker {
+                    (*phi, *delta)
+                } else {
+                    (*delta, *phi)
+                };
+                if prove {
+                    proof == 0
+                } else {
+                    disproof == 0
+                }
+            }
+            None => false,
+        }
+    }
+
+    //Walks the (dis)proof again and returns the tree if every leaf really is a mate, or for disproofs a draw or mate of the attacker.
+    //Because the table does not know about the path a position was reached on, a (dis)proof found by the search is not trusted on its own.
+    fn extract(&mut self, game_state: &mut GameState, prove: bool) -> Option<Vec<ProofNode>> {
+        self.tree_nodes += 1;
+        if self.tree_nodes > MAX_PROOF_TREE_NODES {
+            return None;
+        }
+        if self.is_draw(game_state) {
+            return if prove { None } else { Some(Vec::new()) };
+        }
+        //Our side is the attacker for proofs and the defender for disproofs
+        let our_move = (game_state.get_color_to_move() == self.attacker) == prove;
+        let mut movelist = MoveList::default();
+        let agsi = generate_moves(game_state, false, &mut movelist);
+        if movelist.move_list.is_empty() {
+            return if (agsi.stm_incheck && !our_move) || (!agsi.stm_incheck && !prove) {
+                Some(Vec::new())
+            } else {
+                None
+            };
+        }
+        if self.history.pointer >= self.max_pointer {
+            return None;
+        }
+        //The search finds any mate, not the shortest one. Atleast don't miss a mate in one
+        if our_move && prove {
+            let mut child_movelist = MoveList::default();
+            for gmv in movelist.move_list.iter() {
+                let child = make_move(game_state, gmv.0);
+                if generate_moves(&child, false, &mut child_movelist).stm_incheck && child_movelist.move_list.is_empty() {
+                    return Some(vec![ProofNode { mv: gmv.0, children: Vec::new() }]);
+                }
+            }
+        }
+
+        self.history.push(game_state.get_hash(), game_state.get_half_moves() == 0);
+        let mut tree = Vec::new();
+        let mut proven = !our_move;
+        for gmv in movelist.move_list.iter() {
+            let mv = gmv.0;
+            game_state.do_move(mv);
+            let subtree = if !our_move || self.is_resolved(game_state, prove) {
+                self.extract(game_state, prove)
+            } else {
+                None
+            };
+            game_state.undo_move(mv);
+            match subtree {
+                Some(children) => {
+                    tree.push(ProofNode { mv, children });
+                    if our_move {
+                        proven = true;
+                        break;
+                    }
+                }
+                None if !our_move => {
+                    proven = false;
+                    break;
+                }
+                None => {}
+            }
+        }
+        self.history.pop();
+        if !proven {
+            return None;
+        }
+        //The most resistant defence becomes the main line
+        tree.sort_by_key(|node| std::cmp::Reverse(node.size()));
+        Some(tree)
+    }
+}
+
+//Solves the position for the side to move. The history contains the positions before it in the game, for repetitions.
+pub fn solve(game_state: &GameState, history: &[GameState], limits: SolverLimits) -> Solution {
+    let start = Instant::now();
+    let mut hist = History::default();
+    let mut relevant_hashes: Vec<u64> = Vec::with_capacity(100);
+    for gs in history.iter().rev() {
+        relevant_hashes.push(gs.get_hash());
+        if gs.get_half_moves() == 0 {
+            break;
+        }
+    }
+    for hash in relevant_hashes.iter().rev() {
+        hist.push(*hash, false);
+    }
+
+    let mut root = game_state.clone();
+    let us = game_state.get_color_to_move();
+    let mut solution = Solution {
+        verdict: Verdict::Unknown,
+        root: game_state.clone(),
+        tree: Vec::new(),
+        nodes: 0,
+        time: 0,
+    };
+
+    //1. Can we force a win?
+    let mut win_search = DfPn::new(us, &hist, limits, start);
+    let (phi, delta) = win_search.search(&mut root);
+    solution.nodes += win_search.nodes;
+    if phi == 0 {
+        if let Some(tree) = win_search.extract(&mut root, true) {
+            solution.verdict = Verdict::Win;
+            solution.tree = tree;
+        }
+    } else if delta == 0 {
+        //2. Can the opponent force a win?
+        let mut loss_search = DfPn::new(1 - us, &hist, limits, start);
+        loss_search.nodes = win_search.nodes;
+        let (phi, delta) = loss_search.search(&mut root);
+        solution.nodes = loss_search.nodes;
+        //The opponent is the attacker, so for them the root is a defending node
+        if delta == 0 {
+            if let Some(tree) = loss_search.extract(&mut root, true) {
+                solution.verdict = Verdict::Loss;
+                solution.tree = tree;
+            }
+        } else if phi == 0 {
+            if let (Some(tree), Some(_)) = (loss_search.extract(&mut root, false), win_search.extract(&mut root, false)) {
+                solution.verdict = Verdict::Draw;
+                solution.tree = tree;
+            }
+        }
+    }
+    solution.time = start.elapsed().as_millis() as u64;
+    solution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{solve, SolverLimits, Verdict};
+    use crate::board_representation::game_state::GameState;
+
+    #[test]
+    fn solver_test() {
+        //Two rooks against the lone king
+        let solution = solve(&GameState::from_fen("k7/8/8/8/8/8/1R6/2R3K1 w - - 0 1"), &[], SolverLimits::default());
+        assert_eq!(solution.verdict, Verdict::Win);
+        let pgn = solution.to_pgn();
+        assert!(pgn.contains("[Result \"1-0\"]"));
+        assert!(pgn.contains("#"));
+        assert!(pgn.trim_end().ends_with("1-0"));
+
+        //Kb8 is forced and runs into Rh8#
+        let solution = solve(&GameState::from_fen("k7/8/1K6/8/8/8/8/7R b - - 0 1"), &[], SolverLimits::default());
+        assert_eq!(solution.verdict, Verdict::Loss);
+        assert_eq!(solution.main_line().len(), 2);
+        assert_eq!(format!("{:?}", solution.main_line()[1]), "h1h8");
+        assert_eq!(solution.result_string(), "1-0");
+
+        //Stalemate and insufficient material
+        let solution = solve(&GameState::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), &[], SolverLimits::default());
+        assert_eq!(solution.verdict, Verdict::Draw);
+        let solution = solve(&GameState::from_fen("8/8/8/3k4/8/3K4/2N5/8 w - - 0 1"), &[], SolverLimits::default());
+        assert_eq!(solution.verdict, Verdict::Draw);
+
+        //The opening is out of reach
+        let solution = solve(
+            &GameState::standard(),
+            &[],
+            SolverLimits {
+                nodes: 10_000,
+                ..SolverLimits::default()
+            },
+        );
+        assert_eq!(solution.verdict, Verdict::Unknown);
+        assert!(solution.tree.is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+use extended_sdk::datagen::{run, DatagenConfig};
+
+const USAGE: &str = "Usage: datagen [options]
+Plays self-play games and writes quiet positions with search score and result, readable by the tuning crate.
+Rerunning with the same output file continues an interrupted run.
+Options:
+  --games <n>                Number of games, default 1000
+  --nodes <n>                Nodes per move, default 5000
+  --threads <n>              Games played in parallel, default all cores
+  --hash <mb>                Hash size of each engine instance, default 16
+  --random-plies <n>         Random moves before the game starts, default 8
+  --book <pgn>               Start from positions of these games instead of the start position
+  --book-plies <n>           Plies of the book games to play, default 8
+  --eval-file <file>         Network to evaluate with instead of the handcrafted evaluation
+  --max-plies <n>            Games are drawn after that many plies, default 400
+  --adjudication-score <cp>  Games are won once the score stays above this, default 2000
+  --adjudication-plies <n>   for that many plies, default 8
+  --seed <n>                 Default 0
+  --out <file>               Default datagen.txt";
+
+fn parse<T: std::str::FromStr>(option: &str, value: Option<String>) -> T {
+    value.and_then(|v| v.parse::<T>().ok()).unwrap_or_else(|| panic!("Invalid value for {}\n{}", option, USAGE))
+}
+
+fn main() {
+    let mut config = DatagenConfig::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--games" => config.games = parse(&arg, args.next()),
+            "--nodes" => config.nodes = parse(&arg, args.next()),
+            "--threads" => config.threads = parse(&arg, args.next()),
+            "--hash" => config.hash_size = parse(&arg, args.next()),
+            "--random-plies" => config.random_plies = parse(&arg, args.next()),
+            "--book" => config.book = Some(parse(&arg, args.next())),
+            "--book-plies" => config.book_plies = parse(&arg, args.next()),
+            "--eval-file" => config.eval_file = Some(parse(&arg, args.next())),
+            "--max-plies" => config.max_plies = parse(&arg, args.next()),
+            "--adjudication-score" => config.adjudication_score = parse(&arg, args.next()),
+            "--adjudication-plies" => config.adjudication_plies = parse(&arg, args.next()),
+            "--seed" => config.seed = parse(&arg, args.next()),
+            "--out" => config.output = parse(&arg, args.next()),
+            _ => {
+                println!("{}", USAGE);
+                return;
+            }
+        }
+    }
+    if let Err(e) = run(config) {
+        println!("Datagen failed: {}", e);
+        std::process::exit(1);
+    }
+}
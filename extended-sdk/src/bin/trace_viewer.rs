@@ -0,0 +1,63 @@
+use core_sdk::search::trace::PruneReason;
+use extended_sdk::search_trace::{record_to_string, TraceTree};
+
+const USAGE: &str = "Usage: trace_viewer <trace file> <command>
+Commands:
+  summary                          Overview over the recorded tree
+  roots                            List the root nodes (one per search iteration or aspiration window)
+  tree <id> [plies]                Print the subtree below a node, 1 ply deep by default
+  path <id>                        Print the moves leading to a node
+  find <id> <moves...>             Follow moves (e.g. e2e4 e7e5) from a node and print the node reached
+  filter <reason> [limit]          List nodes and moves cut off by a reason";
+
+fn parse_id(arg: Option<&String>) -> u32 {
+    arg.and_then(|id| id.trim_start_matches('#').parse::<u32>().ok())
+        .unwrap_or_else(|| panic!("Expected a node id\n{}", USAGE))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        println!("{}", USAGE);
+        return;
+    }
+    let tree = TraceTree::load(&args[1]).expect("Could not read search trace");
+    match args[2].as_str() {
+        "summary" => print!("{}", tree.summary()),
+        "roots" => {
+            for root in tree.roots.iter() {
+                let record = &tree.records[*root];
+                println!("{} ({} children)", record_to_string(record), tree.children(record.id).len());
+            }
+        }
+        "tree" => {
+            let plies = args.get(4).and_then(|plies| plies.parse::<usize>().ok()).unwrap_or(1);
+            print!("{}", tree.subtree_to_string(parse_id(args.get(3)), plies));
+        }
+        "path" => {
+            for record in tree.path(parse_id(args.get(3))) {
+                println!("{}", record_to_string(record));
+            }
+        }
+        "find" => {
+            let moves: Vec<&str> = args.get(4..).unwrap_or(&[]).iter().map(|mv| mv.as_str()).collect();
+            match tree.find(parse_id(args.get(3)), &moves) {
+                Some(record) => print!("{}", tree.subtree_to_string(record.id, 1)),
+                None => println!("No recorded node reached by {}", moves.join(" ")),
+            }
+        }
+        "filter" => {
+            let reason = args.get(3).and_then(|reason| PruneReason::from_name(reason)).unwrap_or_else(|| {
+                panic!(
+                    "Unknown reason, expected one of: {}",
+                    core_sdk::search::trace::PRUNE_REASONS.iter().map(|reason| reason.name()).collect::<Vec<&str>>().join(", ")
+                )
+            });
+            let limit = args.get(4).and_then(|limit| limit.parse::<usize>().ok()).unwrap_or(usize::MAX);
+            for record in tree.filter(reason).into_iter().take(limit) {
+                println!("{} after {}", record_to_string(record), tree.path_string(record.id));
+            }
+        }
+        _ => println!("{}", USAGE),
+    }
+}
@@ -0,0 +1,359 @@
+// Self-play data generation. Every worker thread plays games between two in-process FabChess instances, which search
+// each move to a fixed number of nodes. Quiet positions are written together with the search score and the final
+// result in the position format of the tuning crate:
+//   <fen> |White|<score>   (result White, Black or Draw, score in centipawns from white's point of view)
+// Games are written in the order of their index, and every game's openings are derived from the seed and its index. Next
+// to the output file a progress file keeps the number of finished games and the length of the output, so an interrupted
+// run continues where it stopped and produces the same file as an uninterrupted one.
+use crate::openings::load_db_until;
+use core_sdk::board_representation::game_state::{GameMove, GameMoveType, GameResult, GameState, PieceType, BLACK, WHITE};
+use core_sdk::evaluation::nnue::Network;
+use core_sdk::move_generation::makemove::make_move;
+use core_sdk::move_generation::movegen::{generate_moves, MoveList};
+use core_sdk::search::check_end_condition;
+use core_sdk::search::searcher::{search_move, InterThreadCommunicationSystem};
+use core_sdk::search::timecontrol::TimeControl;
+use core_sdk::search::{MATE_SCORE, MAX_SEARCH_DEPTH};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+pub struct DatagenConfig {
+    pub games: usize,
+    pub nodes: u64,
+    pub threads: usize,
+    //Hash size of each instance in MB
+    pub hash_size: usize,
+    //Random moves played from the start position or the book position before the game starts
+    pub random_plies: usize,
+    //PGN file whose games are cut after book_plies to get openings
+    pub book: Option<String>,
+    pub book_plies: usize,
+    pub eval_file: Option<String>,
+    pub max_plies: usize,
+    //A game is won once both instances agree on a score of at least this for adjudication_plies plies
+    pub adjudication_score: i16,
+    pub adjudication_plies: usize,
+    pub seed: u64,
+    pub output: String,
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        DatagenConfig {
+            games: 1000,
+            nodes: 5000,
+            threads: thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+            hash_size: 16,
+            random_plies: 8,
+            book: None,
+            book_plies: 8,
+            eval_file: None,
+            max_plies: 400,
+            adjudication_score: 2000,
+            adjudication_plies: 8,
+            seed: 0,
+            output: "datagen.txt".to_owned(),
+        }
+    }
+}
+
+pub struct GameRecord {
+    pub result: GameResult,
+    //Fen and score from white's point of view of every quiet position
+    pub positions: Vec<(String, i16)>,
+}
+
+impl GameRecord {
+    pub fn to_lines(&self) -> String {
+        let result = match self.result {
+            GameResult::WhiteWin => "White",
+            GameResult::BlackWin => "Black",
+            _ => "Draw",
+        };
+        self.positions.iter().map(|(fen, score)| format!("{} |{}|{}\n", fen, result, score)).collect()
+    }
+}
+
+//An in-process engine instance with its own transposition table
+pub struct Player {
+    itcs: Arc<InterThreadCommunicationSystem>,
+}
+
+impl Player {
+    pub fn new(hash_size: usize, network: Option<Arc<Network>>) -> Self {
+        let itcs = Arc::new(InterThreadCommunicationSystem::default());
+        {
+            let mut uci_options = itcs.uci_options.write().unwrap();
+            uci_options.hash_size = hash_size;
+            uci_options.quiet = true;
+        }
+        InterThreadCommunicationSystem::update_thread_count(&itcs, 1);
+        itcs.resize_cache(hash_size, 1);
+        *itcs.network.write().unwrap() = network;
+        Player { itcs }
+    }
+
+    pub fn new_game(&self) {
+        self.itcs.cache().clear_threaded(1);
+        self.itcs.clear_eval_caches();
+    }
+
+    //Best move and its score from the side to move's point of view
+    pub fn search(&self, game_state: &GameState, history: &[GameState], nodes: u64) -> (GameMove, i16) {
+        let score = search_move(
+            Arc::clone(&self.itcs),
+            MAX_SEARCH_DEPTH as i16,
+            game_state.clone(),
+            history.to_vec(),
+            TimeControl::Nodes(nodes),
+        )
+        .expect("Search did not return a score");
+        let mv = self.itcs.best_pv.lock().unwrap().pv.pv[0].expect("Search did not return a move");
+        (mv, score)
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        InterThreadCommunicationSystem::update_thread_count(&self.itcs, 0);
+    }
+}
+
+fn insufficient_material(game_state: &GameState) -> bool {
+    game_state.get_piece_bb(PieceType::Pawn) | game_state.get_piece_bb(PieceType::Rook) | game_state.get_piece_bb(PieceType::Queen) == 0u64
+        && (game_state.get_piece(PieceType::Knight, WHITE) | game_state.get_piece(PieceType::Bishop, WHITE)).count_ones() <= 1
+        && (game_state.get_piece(PieceType::Knight, BLACK) | game_state.get_piece(PieceType::Bishop, BLACK)).count_ones() <= 1
+}
+
+//Plays random moves from the start position, until a position with legal moves is reached
+fn random_opening(start: &GameState, plies: usize, rng: &mut StdRng) -> GameState {
+    let mut movelist = MoveList::default();
+    'retry: loop {
+        let mut game_state = start.clone();
+        for _ in 0..plies {
+            generate_moves(&game_state, false, &mut movelist);
+            match movelist.move_list.choose(rng) {
+                Some(gmv) => game_state = make_move(&game_state, gmv.0),
+                None => continue 'retry,
+            }
+        }
+        generate_moves(&game_state, false, &mut movelist);
+        if !movelist.move_list.is_empty() {
+            return game_state;
+        }
+    }
+}
+
+pub fn play_game(players: &[Player; 2], config: &DatagenConfig, book: &[GameState], index: usize) -> GameRecord {
+    let mut rng = StdRng::seed_from_u64(config.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15).wrapping_add(index as u64));
+    let start = if book.is_empty() {
+        GameState::standard()
+    } else {
+        book[rng.gen_range(0, book.len())].clone()
+    };
+    let mut game_state = random_opening(&start, config.random_plies, &mut rng);
+    players.iter().for_each(|player| player.new_game());
+
+    let mut history: Vec<GameState> = Vec::with_capacity(config.max_plies);
+    let mut positions = Vec::new();
+    let mut movelist = MoveList::default();
+    let mut adjudication = 0i32;
+    let result = loop {
+        let agsi = generate_moves(&game_state, false, &mut movelist);
+        let result = check_end_condition(&game_state, !movelist.move_list.is_empty(), agsi.stm_incheck);
+        if result != GameResult::Ingame {
+            break result;
+        }
+        if insufficient_material(&game_state)
+            || game_state.get_half_moves() >= 100
+            || history.iter().filter(|other| other.get_hash() == game_state.get_hash()).count() >= 2
+            || history.len() >= config.max_plies
+        {
+            break GameResult::Draw;
+        }
+        //Both instances play both colors over consecutive games
+        let player = &players[(history.len() + index) % 2];
+        let (mv, score) = player.search(&game_state, &history, config.nodes);
+        let white_score = if game_state.get_color_to_move() == WHITE { score } else { -score };
+
+        if white_score.abs() >= config.adjudication_score {
+            adjudication = if (adjudication > 0) == (white_score > 0) {
+                adjudication + white_score.signum() as i32
+            } else {
+                white_score.signum() as i32
+            };
+            if adjudication.unsigned_abs() as usize >= config.adjudication_plies {
+                break if adjudication > 0 { GameResult::WhiteWin } else { GameResult::BlackWin };
+            }
+        } else {
+            adjudication = 0;
+        }
+        //Skip noisy positions, whose score the static evaluation can't be expected to know
+        let tactical = mv.is_capture() || matches!(mv.move_type, GameMoveType::Promotion(_, _));
+        if !agsi.stm_incheck && !tactical && score.abs() < MATE_SCORE - 200 {
+            positions.push((game_state.to_fen(), white_score));
+        }
+        let next = make_move(&game_state, mv);
+        history.push(game_state);
+        game_state = next;
+    };
+    GameRecord { result, positions }
+}
+
+fn progress_file(output: &str) -> String {
+    format!("{}.progress", output)
+}
+
+fn read_progress(output: &str) -> Result<(usize, u64)> {
+    let progress = match fs::read_to_string(progress_file(output)) {
+        Ok(progress) => progress,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            if fs::metadata(output).map(|m| m.len() > 0).unwrap_or(false) {
+                return Err(Error::new(ErrorKind::AlreadyExists, format!("{} exists, but has no progress file", output)));
+            }
+            return Ok((0, 0));
+        }
+        Err(e) => return Err(e),
+    };
+    let mut split = progress.split_whitespace().map(|value| value.parse::<u64>());
+    match (split.next(), split.next()) {
+        (Some(Ok(games)), Some(Ok(bytes))) => Ok((games as usize, bytes)),
+        _ => Err(Error::new(ErrorKind::InvalidData, "Invalid progress file")),
+    }
+}
+
+//Output file with the games written so far. Games finishing out of order wait until all games before them are written
+struct Writer {
+    file: File,
+    output: String,
+    games: usize,
+    bytes: u64,
+    positions: usize,
+    pending: BTreeMap<usize, GameRecord>,
+}
+
+impl Writer {
+    fn add(&mut self, index: usize, record: GameRecord) -> Result<()> {
+        self.pending.insert(index, record);
+        while let Some(record) = self.pending.remove(&self.games) {
+            let lines = record.to_lines();
+            self.file.write_all(lines.as_bytes())?;
+            self.file.flush()?;
+            self.games += 1;
+            self.bytes += lines.len() as u64;
+            self.positions += record.positions.len();
+            //Replace the progress file at once, so that it always describes a complete prefix of the output
+            let temp = format!("{}.tmp", progress_file(&self.output));
+            fs::write(&temp, format!("{} {}\n", self.games, self.bytes))?;
+            fs::rename(&temp, progress_file(&self.output))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run(config: DatagenConfig) -> Result<()> {
+    let (games_done, bytes) = read_progress(&config.output)?;
+    //Drop anything written after the last recorded game
+    OpenOptions::new().create(true).truncate(false).write(true).open(&config.output)?.set_len(bytes)?;
+    let file = OpenOptions::new().append(true).open(&config.output)?;
+    if games_done > 0 {
+        println!("Resuming after {} games", games_done);
+    }
+    let network = match &config.eval_file {
+        Some(eval_file) => Some(Arc::new(Network::load(eval_file)?)),
+        None => None,
+    };
+    let book = match &config.book {
+        Some(book) => load_db_until(book, config.book_plies).0,
+        None => Vec::new(),
+    };
+    let writer = Mutex::new(Writer {
+        file,
+        output: config.output.clone(),
+        games: games_done,
+        bytes,
+        positions: 0,
+        pending: BTreeMap::new(),
+    });
+    let next_game = AtomicUsize::new(games_done);
+    let start = Instant::now();
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..config.threads.max(1))
+            .map(|_| {
+                let (config, book, writer, next_game, network) = (&config, &book, &writer, &next_game, network.clone());
+                thread::Builder::new()
+                    .stack_size(12 * 1024 * 1024)
+                    .spawn_scoped(s, move || -> Result<()> {
+                        let players = [Player::new(config.hash_size, network.clone()), Player::new(config.hash_size, network)];
+                        loop {
+                            let index = next_game.fetch_add(1, Ordering::Relaxed);
+                            if index >= config.games {
+                                return Ok(());
+                            }
+                            let record = play_game(&players, config, book, index);
+                            let mut writer = writer.lock().unwrap();
+                            writer.add(index, record)?;
+                            if writer.games % 10 == 0 || writer.games == config.games {
+                                let elapsed = start.elapsed().as_millis().max(1);
+                                println!(
+                                    "Games: {}/{}, positions: {}, positions/s: {:.0}",
+                                    writer.games,
+                                    config.games,
+                                    writer.positions,
+                                    writer.positions as f64 * 1000. / elapsed as f64
+                                );
+                            }
+                        }
+                    })
+                    .expect("Could not spawn datagen thread")
+            })
+            .collect();
+        handles.into_iter().try_for_each(|handle| handle.join().unwrap())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, DatagenConfig};
+    use std::fs;
+
+    #[test]
+    fn datagen_resume_test() {
+        let dir = std::env::temp_dir();
+        let config = |games: usize, output: &str| DatagenConfig {
+            games,
+            nodes: 300,
+            threads: 2,
+            hash_size: 1,
+            max_plies: 40,
+            seed: 7,
+            output: dir.join(output).to_str().unwrap().to_owned(),
+            ..DatagenConfig::default()
+        };
+        for output in ["datagen_full.txt", "datagen_resumed.txt"].iter() {
+            let _ = fs::remove_file(dir.join(output));
+            let _ = fs::remove_file(dir.join(format!("{}.progress", output)));
+        }
+        run(config(4, "datagen_full.txt")).unwrap();
+        run(config(2, "datagen_resumed.txt")).unwrap();
+        run(config(4, "datagen_resumed.txt")).unwrap();
+        let full = fs::read_to_string(dir.join("datagen_full.txt")).unwrap();
+        assert!(!full.is_empty());
+        assert_eq!(full, fs::read_to_string(dir.join("datagen_resumed.txt")).unwrap());
+        for line in full.lines() {
+            let split: Vec<&str> = line.split('|').collect();
+            assert_eq!(split.len(), 3);
+            assert!(["White", "Black", "Draw"].contains(&split[1]));
+            split[2].parse::<i16>().unwrap();
+        }
+        assert_eq!(fs::read_to_string(dir.join("datagen_resumed.txt.progress")).unwrap().split_whitespace().next(), Some("4"));
+    }
+}
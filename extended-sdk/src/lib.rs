@@ -1,3 +1,5 @@
+pub mod datagen;
 pub mod misc;
 pub mod openings;
 pub mod pgn;
+pub mod search_trace;
@@ -1,6 +1,7 @@
 use core_sdk::board_representation::game_state::{char_to_file, char_to_rank, file_of, rank_of, GameMove, GameMoveType, GameState, PieceType, WHITE};
 use core_sdk::move_generation::makemove::make_move;
 use core_sdk::move_generation::movegen;
+use core_sdk::search::MATE_SCORE;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
@@ -14,49 +15,121 @@ pub struct GameParser {
 impl Iterator for GameParser {
     type Item = (Vec<GameMove>, Vec<GameState>, isize);
     fn next(&mut self) -> Option<(Vec<GameMove>, Vec<GameState>, isize)> {
-        let next = self.pgn_parser.next();
-        let mut vec_res: Vec<GameMove> = Vec::new();
-        let mut vec_gs: Vec<GameState> = Vec::new();
-        vec_gs.push(GameState::standard());
-        match next {
-            None => None,
-            Some(res) => {
-                let game = res.replace("\r", "").replace("\n", " ");
-                if game.contains("--") || game.contains('*') || game.contains("..") {
-                    //Invalid state
-                    return Some((vec_res, vec_gs, -2));
-                }
-                //log(&format!("{}\n", game));
-                let moves = game.split(' ').collect::<Vec<&str>>();
-                for move_str in moves.iter().take(moves.len() - 2) {
-                    let mut move_str = (*move_str).to_string();
-                    if move_str.contains('.') {
-                        move_str = move_str.rsplit('.').collect::<Vec<&str>>()[0].to_string();
-                    }
-                    if move_str.is_empty() {
-                        continue;
-                    }
-                    //println!("{} || len: {}", move_str, move_str.len());
-                    let last_state = &vec_gs[vec_gs.len() - 1];
-                    let parsed_move = parse_move(last_state, &move_str, &mut self.move_list);
-                    vec_gs.push(parsed_move.1);
-                    vec_res.push(parsed_move.0);
-                    if self.is_opening && vec_res.len() == self.opening_load_untilply {
-                        break;
-                    }
-                }
-                let last_elem = moves[moves.len() - 2];
-                let mut score = 0;
-                if last_elem == "1-0" {
-                    score = 1;
-                } else if last_elem == "0-1" {
-                    score = -1;
-                } else {
-                    assert!(last_elem == "1/2-1/2");
-                }
-                Some((vec_res, vec_gs, score))
+        let game = self.pgn_parser.next()?;
+        let until_ply = if self.is_opening { Some(self.opening_load_untilply) } else { None };
+        let game = game.parse(&mut self.move_list, until_ply);
+        Some((game.moves, game.states, game.result))
+    }
+}
+
+pub struct ParsedGame {
+    pub moves: Vec<GameMove>,
+    //Starts with the standard position, followed by the position after each move
+    pub states: Vec<GameState>,
+    //1 for a white win, -1 for a black win, 0 for a draw and -2 for games that can't be used
+    pub result: isize,
+    //Score of the [%eval] comment after each move in centipawns from white's point of view
+    pub evals: Vec<Option<i16>>,
+}
+
+impl ParsedGame {
+    fn unusable() -> Self {
+        ParsedGame {
+            moves: Vec::new(),
+            states: vec![GameState::standard()],
+            result: -2,
+            evals: Vec::new(),
+        }
+    }
+}
+
+//Parses the movetext of a game. Comments other than [%eval], variations and annotations are skipped,
+//games with a move that isn't legal get the result -2
+pub fn parse_game(game: &str, move_list: &mut movegen::MoveList, until_ply: Option<usize>) -> ParsedGame {
+    let mut res = ParsedGame::unusable();
+    let tokens = tokenize(game);
+    let result = tokens.iter().rev().find(|token| !token.starts_with('{')).map(|token| token.as_str());
+    let result = match result {
+        Some("1-0") => 1,
+        Some("0-1") => -1,
+        Some("1/2-1/2") => 0,
+        _ => return res,
+    };
+    for token in tokens.iter() {
+        if token.starts_with('{') {
+            if let Some(eval) = res.evals.last_mut() {
+                *eval = parse_eval(token);
             }
+            continue;
+        }
+        if token == "1-0" || token == "0-1" || token == "1/2-1/2" || token.starts_with('$') {
+            continue;
         }
+        //Null moves and games that start with a black move, which have to be set up from a fen, are invalid
+        if token.contains("--") || res.moves.is_empty() && token.contains("..") {
+            return res;
+        }
+        let move_str = token.rsplit('.').next().unwrap().trim_end_matches(['!', '?']);
+        if move_str.is_empty() {
+            continue;
+        }
+        let parsed_move = match parse_move(&res.states[res.states.len() - 1], move_str, move_list) {
+            Ok(parsed_move) => parsed_move,
+            Err(_) => return res,
+        };
+        res.states.push(parsed_move.1);
+        res.moves.push(parsed_move.0);
+        res.evals.push(None);
+        if until_ply == Some(res.moves.len()) {
+            break;
+        }
+    }
+    res.result = result;
+    res
+}
+
+//Splits movetext at whitespace. Comments in braces are kept as one token, variations and rest of line comments are dropped
+fn tokenize(game: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut chars = game.chars();
+    let mut variation_depth = 0;
+    while let Some(c) = chars.next() {
+        if c == '{' {
+            let comment = chars.by_ref().take_while(|&c| c != '}').collect::<String>();
+            if variation_depth == 0 {
+                tokens.push(format!("{{{}}}", comment));
+            }
+            continue;
+        } else if c == ';' {
+            chars.by_ref().take_while(|&c| c != '\n').for_each(drop);
+        } else if c == '(' {
+            variation_depth += 1;
+        } else if c == ')' {
+            variation_depth -= 1;
+        } else if variation_depth == 0 && !c.is_whitespace() {
+            token.push(c);
+            continue;
+        }
+        if !token.is_empty() {
+            tokens.push(std::mem::take(&mut token));
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+//Reads [%eval 0.25] or [%eval #-3] from a comment
+pub fn parse_eval(comment: &str) -> Option<i16> {
+    let eval = comment.split("[%eval").nth(1)?.split(']').next()?.trim();
+    if let Some(mate) = eval.strip_prefix('#') {
+        let mate = mate.parse::<i16>().ok()?;
+        Some(if mate < 0 { -MATE_SCORE - mate } else { MATE_SCORE - mate })
+    } else {
+        let pawns = eval.parse::<f64>().ok()?;
+        Some((pawns * 100.).round().max(-f64::from(MATE_SCORE)).min(f64::from(MATE_SCORE)) as i16)
     }
 }
 
@@ -164,110 +237,108 @@ impl MoveSpecification {
     }
 }
 
-pub fn parse_move(g: &GameState, move_str: &str, movelist: &mut movegen::MoveList) -> (GameMove, GameState) {
+//Parses a move in SAN or in long algebraic notation, Err if it isn't a legal move in the position
+pub fn parse_move(g: &GameState, move_str: &str, movelist: &mut movegen::MoveList) -> Result<(GameMove, GameState), String> {
+    find_parsed_move(g, move_str, movelist).ok_or_else(|| format!("Invalid move {} in {}", move_str, g.to_fen()))
+}
+
+fn find_parsed_move(g: &GameState, move_str: &str, movelist: &mut movegen::MoveList) -> Option<(GameMove, GameState)> {
     let mut my_string = move_str.to_string();
     my_string = my_string.replace("#", "").replace("+", "").replace("=", "").replace("x", "");
     movegen::generate_moves(&g, false, movelist);
     if my_string.contains('-') {
         //Castle
-        //Kingside
-        if my_string.len() == 3 {
-            if g.get_color_to_move() == WHITE {
-                assert_eq!(true, g.castle_white_kingside());
-            } else {
-                assert_eq!(true, g.castle_black_kingside());
-            }
-            if let Ok(res) = find_castle(movelist, g, true) {
-                return res;
-            }
-        } else {
-            if g.get_color_to_move() == WHITE {
-                assert_eq!(true, g.castle_white_queenside());
-            } else {
-                assert_eq!(true, g.castle_black_queenside());
-            }
-            if let Ok(res) = find_castle(movelist, g, false) {
-                return res;
-            }
+        let king_side = my_string.len() == 3;
+        let permitted = match (g.get_color_to_move() == WHITE, king_side) {
+            (true, true) => g.castle_white_kingside(),
+            (true, false) => g.castle_white_queenside(),
+            (false, true) => g.castle_black_kingside(),
+            (false, false) => g.castle_black_queenside(),
+        };
+        if !permitted {
+            return None;
         }
+        find_castle(movelist, g, king_side).ok()
     } else {
         let moving_piece_type = get_piece_type(&mut my_string);
         let promotion_piece = is_promotion(&mut my_string);
-        let target_square = 8 * match_rank(my_string.chars().nth(my_string.len() - 1)) + match_file(my_string.chars().nth(my_string.len() - 2));
+        let chars = my_string.chars().collect::<Vec<char>>();
+        if chars.len() < 2 {
+            return None;
+        }
+        let target_square = 8 * match_rank(chars.last().copied())? + match_file(chars.get(chars.len() - 2).copied())?;
         let mut ms = MoveSpecification::new(target_square, moving_piece_type, promotion_piece);
 
-        if my_string.len() == 3 {
-            let first = my_string.chars().nth(0);
+        if chars.len() == 3 {
+            let first = chars.first().copied();
             if is_file(first) {
-                ms.from_file = Some(match_file(first));
+                ms.from_file = Some(match_file(first)?);
             } else {
-                ms.from_rank = Some(match_rank(first));
+                ms.from_rank = Some(match_rank(first)?);
             }
-        } else if my_string.len() == 4 {
-            ms.from_square = Some(8 * match_rank(my_string.chars().nth(1)) + match_file(my_string.chars().nth(0)));
-        }
-        if let Ok(res) = find_move(movelist, g, ms) {
-            return res;
+        } else if chars.len() == 4 {
+            ms.from_square = Some(8 * match_rank(chars.get(1).copied())? + match_file(chars.first().copied())?);
         }
+        find_move(movelist, g, ms).ok()
     }
-    println!("{}", move_str);
-    println!("{}", my_string);
-    println!("{}", g);
-
-    for gmv in movelist.move_list.iter() {
-        println!("{:?}", gmv.0);
-    }
-    panic!("Shouldn't get here");
 }
 
 pub fn is_file(c: Option<char>) -> bool {
-    match c {
-        None => panic!("Invalid!"),
-        Some(character) => match character {
-            'a' => true,
-            'b' => true,
-            'c' => true,
-            'd' => true,
-            'e' => true,
-            'f' => true,
-            'g' => true,
-            'h' => true,
-            _ => false,
-        },
-    }
+    matches!(c, Some('a'..='h'))
 }
 
-pub fn match_file(c: Option<char>) -> usize {
-    char_to_file(c.expect("Invalid"))
+pub fn match_file(c: Option<char>) -> Option<usize> {
+    c.filter(|c| ('a'..='h').contains(c)).map(char_to_file)
 }
 
-pub fn match_rank(c: Option<char>) -> usize {
-    char_to_rank(c.expect("Invalid"))
+pub fn match_rank(c: Option<char>) -> Option<usize> {
+    c.filter(|c| ('1'..='8').contains(c)).map(char_to_rank)
 }
 
 pub struct PGNParser {
     pub reader: BufReader<File>,
 }
 
+pub struct PGNGame {
+    pub movetext: String,
+    //Games with a [FEN] tag don't start from the standard position
+    pub has_fen: bool,
+}
+
+impl PGNGame {
+    //Games that start from a set up position are not replayed and get the result -2
+    pub fn parse(&self, move_list: &mut movegen::MoveList, until_ply: Option<usize>) -> ParsedGame {
+        if self.has_fen {
+            return ParsedGame::unusable();
+        }
+        parse_game(&self.movetext, move_list, until_ply)
+    }
+}
+
 impl Iterator for PGNParser {
-    type Item = String;
+    type Item = PGNGame;
 
-    fn next(&mut self) -> Option<String> {
+    fn next(&mut self) -> Option<PGNGame> {
         let mut res_str = String::new();
+        let mut has_fen = false;
         let mut line = String::new();
         let mut res = self.reader.read_line(&mut line);
         while match res {
             Err(_e) => false,
             Ok(_e) => true,
         } {
-            if line.contains("1.") && !line.contains('[') {
+            has_fen |= line.starts_with("[FEN ");
+            //The movetext of a set up position doesn't have to start with the first move
+            if (line.contains("1.") || has_fen && !line.trim().is_empty()) && !line.starts_with('[') {
                 loop {
                     res_str.push_str(&line);
                     if res_str.contains("1-0") || res_str.contains("0-1") || res_str.contains("1/2-1/2") || res_str.contains('*') {
                         break;
                     }
                     line = String::new();
-                    self.reader.read_line(&mut line).expect("Reader had an error reading moves of game!");
+                    if self.reader.read_line(&mut line).expect("Reader had an error reading moves of game!") == 0 {
+                        break;
+                    }
                 }
                 break;
             }
@@ -285,9 +356,66 @@ impl Iterator for PGNParser {
             }
         }
         if !res_str.is_empty() {
-            Some(res_str)
+            Some(PGNGame { movetext: res_str, has_fen })
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_game_test() {
+        let mut movelist = movegen::MoveList::default();
+        let game = "1. e4 { [%eval 0.17] [%clk 0:03:00] } 1... e5?! { [%eval 0.5] } 2. Nf3 (2. f4 exf4 { gambit }) 2... Nc6 $1 ; rest of line
+3. Bb5 a6 { [%eval #-2] } 1/2-1/2";
+        let parsed = parse_game(game, &mut movelist, None);
+        assert_eq!(parsed.result, 0);
+        assert_eq!(parsed.moves.len(), 6);
+        assert_eq!(parsed.states.len(), 7);
+        assert_eq!(parsed.states[6].to_fen(), "r1bqkbnr/1ppp1ppp/p1n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 0 4");
+        assert_eq!(parsed.evals, vec![Some(17), Some(50), None, None, None, Some(-MATE_SCORE + 2)]);
+
+        let opening = parse_game(game, &mut movelist, Some(2));
+        assert_eq!((opening.moves.len(), opening.result), (2, 0));
+
+        assert_eq!(parse_game("1. e4 e5 2. Nf3 *", &mut movelist, None).result, -2);
+        assert_eq!(parse_game("1... e5 2. Nf3 0-1", &mut movelist, None).result, -2);
+        assert_eq!(parse_game("1. e4 -- 2. Nf3 1-0", &mut movelist, None).result, -2);
+        //Illegal or garbled moves and castling without the right make a game unusable instead of panicking
+        assert_eq!(parse_game("1. e4 e5 2. Ke3 Nc6 1-0", &mut movelist, None).result, -2);
+        assert_eq!(parse_game("1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. O-O-O 1-0", &mut movelist, None).result, -2);
+        assert_eq!(parse_game("1. e4 e5 2. Zz9 1-0", &mut movelist, None).result, -2);
+        assert_eq!(parse_game("1. e4 e5 2. N 1-0", &mut movelist, None).result, -2);
+        let g = GameState::standard();
+        assert!(parse_move(&g, "Nf3", &mut movelist).is_ok());
+        assert!(parse_move(&g, "Nf4", &mut movelist).is_err());
+        assert!(parse_move(&g, "O-O", &mut movelist).is_err());
+        assert!(parse_move(&GameState::from_fen("4k3/8/8/8/8/8/8/4K2R w - - 0 1"), "O-O", &mut movelist).is_err());
+        assert!(parse_move(&g, "i9", &mut movelist).is_err());
+        assert_eq!(parse_eval("{ [%eval -1.234] }"), Some(-123));
+        assert_eq!(parse_eval("{ [%eval #3] }"), Some(MATE_SCORE - 3));
+        assert_eq!(parse_eval("{ no eval }"), None);
+    }
+
+    #[test]
+    fn pgn_parser_test() {
+        let path = std::env::temp_dir().join("fabchess_pgn_parser_test.pgn");
+        let pgn = "[Event \"?\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 40\"]\n\n40. e4 Kd7 1/2-1/2\n\n\
+                   [Event \"?\"]\n[Result \"1-0\"]\n\n1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0\n";
+        std::fs::write(&path, pgn).unwrap();
+        let reader = BufReader::new(File::open(&path).unwrap());
+        let games = PGNParser { reader }.collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut movelist = movegen::MoveList::default();
+        assert_eq!(games.len(), 2);
+        assert!(games[0].has_fen && !games[1].has_fen);
+        assert_eq!(games[0].parse(&mut movelist, None).result, -2);
+        let parsed = games[1].parse(&mut movelist, None);
+        assert_eq!((parsed.result, parsed.moves.len()), (1, 7));
+    }
+}
@@ -0,0 +1,195 @@
+// Offline browsing of search traces recorded by core-sdk with the `search-trace` feature.
+use core_sdk::search::trace::{move_to_uci, read_trace, PruneReason, TraceRecord, NO_PARENT, NO_SCORE};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Result};
+
+pub struct TraceTree {
+    pub fen: String,
+    pub records: Vec<TraceRecord>,
+    index: HashMap<u32, usize>,
+    children: HashMap<u32, Vec<usize>>,
+    pub roots: Vec<usize>,
+}
+
+impl TraceTree {
+    pub fn load(path: &str) -> Result<TraceTree> {
+        let (fen, records) = read_trace(&mut BufReader::new(File::open(path)?))?;
+        Ok(TraceTree::from_records(fen, records))
+    }
+
+    pub fn from_records(fen: String, mut records: Vec<TraceRecord>) -> TraceTree {
+        // Records are written when a node is left, sort them back into the order they were visited
+        records.sort_by_key(|record| record.id);
+        let index: HashMap<u32, usize> = records.iter().enumerate().map(|(i, record)| (record.id, i)).collect();
+        let mut children: HashMap<u32, Vec<usize>> = HashMap::new();
+        let mut roots = Vec::new();
+        for (i, record) in records.iter().enumerate() {
+            // Nodes whose parent is outside of the recorded node range are shown as roots
+            if record.parent == NO_PARENT || !index.contains_key(&record.parent) {
+                roots.push(i);
+            } else {
+                children.entry(record.parent).or_default().push(i);
+            }
+        }
+        TraceTree {
+            fen,
+            records,
+            index,
+            children,
+            roots,
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&TraceRecord> {
+        self.index.get(&id).map(|i| &self.records[*i])
+    }
+
+    pub fn children(&self, id: u32) -> Vec<&TraceRecord> {
+        self.children.get(&id).map(|c| c.iter().map(|i| &self.records[*i]).collect()).unwrap_or_default()
+    }
+
+    // Moves from the first recorded ancestor to the node
+    pub fn path(&self, id: u32) -> Vec<&TraceRecord> {
+        let mut res = Vec::new();
+        let mut current = self.get(id);
+        while let Some(record) = current {
+            res.push(record);
+            current = self.get(record.parent);
+        }
+        res.reverse();
+        res
+    }
+
+    pub fn path_string(&self, id: u32) -> String {
+        self.path(id).iter().skip(1).map(|record| move_to_uci(record.mv)).collect::<Vec<String>>().join(" ")
+    }
+
+    // Follows the given moves (long algebraic notation) from a node, using the last search of each child
+    pub fn find(&self, from: u32, moves: &[&str]) -> Option<&TraceRecord> {
+        let mut current = self.get(from)?;
+        for mv in moves {
+            current = self
+                .children(current.id)
+                .into_iter()
+                .rfind(|child| !child.is_pruned_move() && move_to_uci(child.mv) == *mv)?;
+        }
+        Some(current)
+    }
+
+    pub fn filter(&self, reason: PruneReason) -> Vec<&TraceRecord> {
+        self.records.iter().filter(|record| record.reason == reason).collect()
+    }
+
+    pub fn subtree_to_string(&self, id: u32, max_plies: usize) -> String {
+        let mut res = String::new();
+        if let Some(record) = self.get(id) {
+            self.push_subtree(&mut res, record, 0, max_plies);
+        }
+        res
+    }
+
+    fn push_subtree(&self, res: &mut String, record: &TraceRecord, indent: usize, max_plies: usize) {
+        res.push_str(&format!("{}{}\n", "  ".repeat(indent), record_to_string(record)));
+        if indent < max_plies {
+            for child in self.children(record.id) {
+                self.push_subtree(res, child, indent + 1, max_plies);
+            }
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut res = format!("Root position: {}\nRecords: {}\nRoots: {}\n", self.fen, self.records.len(), self.roots.len());
+        let q_nodes = self.records.iter().filter(|record| record.is_qsearch() && !record.is_pruned_move()).count();
+        let pruned_moves = self.records.iter().filter(|record| record.is_pruned_move()).count();
+        res.push_str(&format!("Quiescence nodes: {}\nPruned moves: {}\n", q_nodes, pruned_moves));
+        let mut counts: Vec<(PruneReason, usize)> = Vec::new();
+        for record in self.records.iter().filter(|record| record.reason != PruneReason::None) {
+            if let Some(entry) = counts.iter_mut().find(|(reason, _)| *reason == record.reason) {
+                entry.1 += 1;
+            } else {
+                counts.push((record.reason, 1));
+            }
+        }
+        for (reason, count) in counts {
+            res.push_str(&format!("  {}: {}\n", reason.name(), count));
+        }
+        res
+    }
+}
+
+pub fn record_to_string(record: &TraceRecord) -> String {
+    let mv = if record.parent == NO_PARENT { "root".to_owned() } else { move_to_uci(record.mv) };
+    if record.is_pruned_move() {
+        return format!("#{} {} pruned {}", record.id, mv, record.reason.name());
+    }
+    let mut res = format!(
+        "#{} {}{} ply {} depth {} window [{}, {}]",
+        record.id,
+        mv,
+        if record.is_qsearch() { " (q)" } else { "" },
+        record.ply,
+        record.depth,
+        record.alpha,
+        record.beta
+    );
+    if record.static_evaluation != NO_SCORE {
+        res.push_str(&format!(" eval {}", record.static_evaluation));
+    }
+    if record.score != NO_SCORE {
+        res.push_str(&format!(" score {}", record.score));
+    }
+    if record.reason != PruneReason::None {
+        res.push_str(&format!(" cut {}", record.reason.name()));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceTree;
+    use core_sdk::search::trace::{PruneReason, TraceRecord, FLAG_PRUNED_MOVE, NO_PARENT, NO_SCORE};
+
+    fn record(id: u32, parent: u32, mv: u16, reason: PruneReason, flags: u8) -> TraceRecord {
+        TraceRecord {
+            id,
+            parent,
+            ply: 0,
+            depth: 1,
+            reason,
+            flags,
+            mv,
+            alpha: -10,
+            beta: 10,
+            static_evaluation: NO_SCORE,
+            score: 0,
+        }
+    }
+
+    #[test]
+    fn trace_tree_test() {
+        // e2e4 is 12 -> 28 and d2d4 is 11 -> 27, both quiet moves
+        let e2e4 = (12 << 10) | (28 << 4) | 1;
+        let d2d4 = (11 << 10) | (27 << 4) | 1;
+        // Written in post-order, like the recorder does
+        let records = vec![
+            record(2, 1, d2d4, PruneReason::TTCutoff, 0),
+            record(3, 0, e2e4, PruneReason::Futility, FLAG_PRUNED_MOVE),
+            record(1, 0, e2e4, PruneReason::None, 0),
+            record(0, NO_PARENT, 0, PruneReason::None, 0),
+            record(7, 5, d2d4, PruneReason::None, 0),
+        ];
+        let tree = TraceTree::from_records("startpos".to_owned(), records);
+        assert_eq!(tree.roots.len(), 2);
+        assert_eq!(tree.children(0).len(), 2);
+        assert_eq!(tree.path_string(2), "e2e4 d2d4");
+        assert_eq!(tree.find(0, &["e2e4", "d2d4"]).unwrap().id, 2);
+        assert!(tree.find(0, &["d2d4"]).is_none());
+        assert_eq!(tree.filter(PruneReason::TTCutoff).len(), 1);
+        let subtree = tree.subtree_to_string(0, 1);
+        assert_eq!(subtree.lines().count(), 3);
+        assert!(subtree.contains("#3 e2e4 pruned futility"));
+        assert_eq!(tree.subtree_to_string(0, 2).lines().count(), 4);
+        assert!(tree.summary().contains("  tt: 1\n"));
+    }
+}
@@ -171,6 +171,10 @@ impl Engine {
                 .next()
                 .unwrap_or_else(|| panic!("Couldn't catch the name of engine {}", res.path));
             res.name = name[..name.len() - 1].to_owned();
+            //Tells apart copies of one binary which only differ in their evaluation parameters, e.g. in a gauntlet of tuned parameter sets
+            if let Some(file) = res.uci_options.get("EvalParamsFile") {
+                res.name = format!("{} ({})", res.name, file);
+            }
         } else {
             panic!("Couldn't catch the name of engine {}", res.path);
         }
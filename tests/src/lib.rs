@@ -1,8 +1,14 @@
 extern crate rand;
 #[cfg(test)]
+mod symmetry;
+#[cfg(test)]
 mod tests {
     use core_sdk::board_representation::game_state::GameState;
+    use core_sdk::board_representation::game_state::{GameMove, GameMoveType, PieceType};
     use core_sdk::board_representation::game_state::{BLACK, WHITE};
+    use core_sdk::evaluation::endgame::{evaluate_endgame, scale_factor, KNOWN_WIN, SCALE_DRAW, SCALE_NORMAL};
+    use core_sdk::evaluation::eval_params::CompiledParams;
+    use core_sdk::evaluation::params::{QUEEN_PIECE_VALUE, ROOK_PIECE_VALUE};
     use core_sdk::evaluation::psqt_evaluation::psqt;
     use core_sdk::move_generation::makemove::make_move;
     use core_sdk::move_generation::movegen;
@@ -144,7 +150,7 @@ mod tests {
 
         for case in cases.iter() {
             println!("{}", case.2);
-            assert_eq!(case.0, perft(&GameState::from_fen(case.2), case.1, &mut movelist,));
+            assert_eq!(case.0, perft(&mut GameState::from_fen(case.2), case.1, &mut movelist,));
         }
     }
 
@@ -156,11 +162,11 @@ mod tests {
         for _i in 0..10000 {
             let mut g = GameState::standard();
             for _j in 0..200 {
-                assert_eq!(g.get_hash(), {
-                    let mut other = g.clone();
-                    other.initialize_zobrist_hash();
-                    other.get_hash()
-                });
+                let mut other = g.clone();
+                other.initialize_zobrist_hash();
+                assert_eq!(g.get_hash(), other.get_hash());
+                other.initialize_material_key();
+                assert_eq!(g.get_material_key(), other.get_material_key());
                 movegen::generate_moves(&g, false, &mut movelist);
                 if movelist.move_list.is_empty() {
                     break;
@@ -170,6 +176,164 @@ mod tests {
         }
     }
 
+    #[test]
+    fn quiet_checks_test() {
+        //Quiet checks have to be exactly the legal non-captures, non-promotions which leave the enemy in check
+        let mut movelist = movegen::MoveList::default();
+        let mut check_movelist = movegen::MoveList::default();
+        let mut rng = rand::thread_rng();
+        for _i in 0..2000 {
+            let mut g = GameState::standard();
+            for _j in 0..200 {
+                movegen::generate_quiet_checks(&g, &mut check_movelist);
+                movegen::generate_moves(&g, false, &mut movelist);
+                let mut expected = 0;
+                for gmv in movelist.move_list.iter() {
+                    let is_quiet = match gmv.0.move_type {
+                        GameMoveType::Quiet | GameMoveType::Castle => true,
+                        _ => false,
+                    };
+                    if is_quiet && make_move(&g, gmv.0).in_check() {
+                        expected += 1;
+                        assert!(check_movelist.find_move(gmv.0, false) < check_movelist.move_list.len());
+                    }
+                }
+                assert_eq!(expected, check_movelist.move_list.len());
+                if movelist.move_list.is_empty() {
+                    break;
+                }
+                g = make_move(&g, movelist.move_list[rng.gen_range(0, movelist.move_list.len())].0)
+            }
+        }
+    }
+
+    #[test]
+    fn staged_movegen_test() {
+        //The staged generators have to generate exactly the legal moves, and the legality check has to agree with them
+        let mut movelist = movegen::MoveList::default();
+        let mut staged_movelist = movegen::MoveList::default();
+        let mut rng = rand::thread_rng();
+        let mut other_moves = Vec::new();
+        //En passant removes both pawns from the rank and uncovers the rook
+        let g = GameState::from_fen("8/8/8/KPp4r/8/8/8/7k w - c6 0 1");
+        let enpassant = GameMove {
+            from: 33,
+            to: 42,
+            move_type: GameMoveType::EnPassant,
+            piece_type: PieceType::Pawn,
+        };
+        assert!(g.is_pseudo_legal(enpassant) && !g.is_legal(enpassant));
+        for _i in 0..2000 {
+            let mut g = GameState::standard();
+            for _j in 0..200 {
+                let agsi = movegen::generate_moves(&g, false, &mut movelist);
+                assert_eq!(agsi.stm_incheck, g.in_check());
+                if agsi.stm_incheck {
+                    movegen::generate_evasions(&g, &mut staged_movelist);
+                } else {
+                    movegen::generate_captures(&g, &mut staged_movelist);
+                    assert!(staged_movelist
+                        .move_list
+                        .iter()
+                        .all(|gmv| gmv.0.is_capture() || matches!(gmv.0.move_type, GameMoveType::Promotion(_, _))));
+                    movegen::generate_quiets(&g, &mut staged_movelist);
+                }
+                assert_eq!(movelist.move_list.len(), staged_movelist.move_list.len());
+                for gmv in movelist.move_list.iter() {
+                    assert!(staged_movelist.find_move(gmv.0, false) < staged_movelist.move_list.len());
+                    assert!(g.is_pseudo_legal(gmv.0) && g.is_legal(gmv.0));
+                }
+                movegen::generate_moves(&g, true, &mut staged_movelist);
+                assert_eq!(movelist.move_list.iter().filter(|gmv| gmv.0.is_capture()).count(), staged_movelist.move_list.len());
+                assert!(staged_movelist.move_list.iter().all(|gmv| gmv.0.is_capture()));
+                //Moves from other positions are only valid if they are generated in this position
+                for mv in other_moves.iter() {
+                    let valid = g.is_pseudo_legal(*mv) && g.is_legal(*mv);
+                    assert_eq!(valid, movelist.find_move(*mv, false) < movelist.move_list.len(), "{:?} in {}", mv, g.to_fen());
+                }
+                if movelist.move_list.is_empty() {
+                    break;
+                }
+                if other_moves.len() > 500 {
+                    other_moves.clear();
+                }
+                other_moves.extend(movelist.move_list.iter().map(|gmv| gmv.0));
+                g = make_move(&g, movelist.move_list[rng.gen_range(0, movelist.move_list.len())].0)
+            }
+        }
+    }
+
+    #[test]
+    fn kpk_test() {
+        let eval = |fen: &str| evaluate_endgame(&GameState::from_fen(fen)).unwrap();
+        //Pawn outruns the king
+        assert!(eval("k7/8/8/8/8/8/7P/7K w - - 0 1") > KNOWN_WIN);
+        assert!(eval("7k/7p/8/8/8/8/8/K7 b - - 0 1") < -KNOWN_WIN);
+        //King on the sixth rank in front of the pawn wins regardless of the side to move
+        assert!(eval("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1") > KNOWN_WIN);
+        assert!(eval("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1") > KNOWN_WIN);
+        assert!(eval("3k4/8/3K4/3P4/8/8/8/8 b - - 0 1") > KNOWN_WIN);
+        //Defending king in front of the rook pawn
+        assert_eq!(eval("7k/8/8/8/8/8/7P/7K w - - 0 1"), 0);
+        assert_eq!(eval("8/8/8/8/8/8/p7/K5k1 w - - 0 1"), 0);
+        //Stalemate
+        assert_eq!(eval("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), 0);
+        //Pawn gets captured
+        assert_eq!(eval("8/8/8/8/8/8/3kP3/7K b - - 0 1"), 0);
+    }
+
+    #[test]
+    fn kbnk_test() {
+        let eval = |fen: &str| evaluate_endgame(&GameState::from_fen(fen)).unwrap();
+        let right_corner = eval("8/8/8/4K3/5B2/8/8/k3N3 w - - 0 1");
+        let wrong_corner = eval("8/8/8/4K3/5B2/8/8/4N2k w - - 0 1");
+        let center = eval("8/8/8/4K3/2k2B2/8/8/4N3 w - - 0 1");
+        assert!(wrong_corner > KNOWN_WIN);
+        assert!(right_corner > center);
+        assert!(right_corner > wrong_corner);
+        assert_eq!(eval("k3n3/8/8/5b2/4K3/8/8/8 b - - 0 1"), -eval("8/8/8/4k3/5B2/8/8/K3N3 w - - 0 1"));
+    }
+
+    #[test]
+    fn krkp_test() {
+        let eval = |fen: &str| evaluate_endgame(&GameState::from_fen(fen)).unwrap();
+        //Strong king in front of the pawn
+        assert!(eval("8/4p3/8/8/8/k7/8/R3K3 w - - 0 1") > ROOK_PIECE_VALUE.1 - 10);
+        assert!(eval("r3k3/8/K7/8/8/8/4P3/8 b - - 0 1") < -ROOK_PIECE_VALUE.1 + 10);
+        //Far advanced pawn supported by its king
+        assert!(eval("R7/8/8/6K1/8/8/1kp5/8 w - - 0 1") < 100);
+    }
+
+    #[test]
+    fn kqkr_test() {
+        let eval = |fen: &str| evaluate_endgame(&GameState::from_fen(fen)).unwrap();
+        let edge = eval("k7/8/8/8/8/2r5/8/3QK3 w - - 0 1");
+        let center = eval("8/8/3k4/8/8/2r5/8/3QK3 w - - 0 1");
+        assert!(center >= QUEEN_PIECE_VALUE.1 - ROOK_PIECE_VALUE.1);
+        assert!(edge > center);
+        assert!(evaluate_endgame(&GameState::from_fen("8/8/3k4/8/8/2q5/8/3RK3 w - - 0 1")).unwrap() < 0);
+        assert!(evaluate_endgame(&GameState::from_fen("8/8/3k4/8/8/2q5/8/2RQK3 w - - 0 1")).is_none());
+    }
+
+    #[test]
+    fn endgame_scaling_test() {
+        //Wrong coloured bishop with rook pawns
+        assert_eq!(scale_factor(&GameState::from_fen("k7/8/8/8/8/8/P7/K1B5 w - - 0 1"), WHITE, 0), SCALE_DRAW);
+        assert_eq!(scale_factor(&GameState::from_fen("k7/8/8/8/P7/8/P7/K1B5 w - - 0 1"), WHITE, 0), SCALE_DRAW);
+        assert_eq!(scale_factor(&GameState::from_fen("5b1k/7p/8/8/8/8/8/6K1 w - - 0 1"), BLACK, 0), SCALE_DRAW);
+        assert_eq!(scale_factor(&GameState::from_fen("k7/8/8/8/8/8/P7/KB6 w - - 0 1"), WHITE, 0), SCALE_NORMAL);
+        assert_eq!(scale_factor(&GameState::from_fen("8/8/8/8/k7/8/P7/K1B5 w - - 0 1"), WHITE, 0), SCALE_NORMAL);
+        assert_eq!(scale_factor(&GameState::from_fen("k7/8/8/8/8/8/PP6/K1B5 w - - 0 1"), WHITE, 0), SCALE_NORMAL);
+        //Opposite coloured bishops
+        assert!(scale_factor(&GameState::from_fen("4k3/8/3b4/8/3P4/2PB4/8/4K3 w - - 0 1"), WHITE, 0) < SCALE_NORMAL);
+        assert!(
+            scale_factor(&GameState::from_fen("4k3/8/3b4/8/3P4/2PB4/8/4K3 w - - 0 1"), WHITE, 1u64 << 27)
+                > scale_factor(&GameState::from_fen("4k3/8/3b4/8/3P4/2PB4/8/4K3 w - - 0 1"), WHITE, 0)
+        );
+        assert!(scale_factor(&GameState::from_fen("4k3/r7/3b4/8/3P4/2PB4/8/R3K3 w - - 0 1"), WHITE, 0) < SCALE_NORMAL);
+        assert_eq!(scale_factor(&GameState::from_fen("4k3/8/4b3/8/3P4/2PB4/8/4K3 w - - 0 1"), WHITE, 0), SCALE_NORMAL);
+    }
+
     #[test]
     fn phase_incremental() {
         let mut rng = rand::thread_rng();
@@ -209,8 +373,8 @@ mod tests {
         let mut movelist = movegen::MoveList::default();
         for _i in 0..100_000 {
             let mut g = GameState::standard();
-            let w_psqt = psqt(&g, WHITE, &mut core_sdk::evaluation::trace::LargeTrace::default());
-            let b_psqt = psqt(&g, BLACK, &mut core_sdk::evaluation::trace::LargeTrace::default());
+            let w_psqt = psqt(&g, WHITE, &CompiledParams, &mut core_sdk::evaluation::trace::LargeTrace::default());
+            let b_psqt = psqt(&g, BLACK, &CompiledParams, &mut core_sdk::evaluation::trace::LargeTrace::default());
             assert_eq!(g.get_psqt(), w_psqt - b_psqt);
             for _j in 0..200 {
                 movegen::generate_moves(&g, false, &mut movelist);
@@ -220,8 +384,8 @@ mod tests {
                 let mv = movelist.move_list[rng.gen_range(0, movelist.move_list.len())].0;
                 let old_g = g.clone();
                 g = make_move(&g, mv);
-                let w_psqt = psqt(&g, WHITE, &mut core_sdk::evaluation::trace::LargeTrace::default());
-                let b_psqt = psqt(&g, BLACK, &mut core_sdk::evaluation::trace::LargeTrace::default());
+                let w_psqt = psqt(&g, WHITE, &CompiledParams, &mut core_sdk::evaluation::trace::LargeTrace::default());
+                let b_psqt = psqt(&g, BLACK, &CompiledParams, &mut core_sdk::evaluation::trace::LargeTrace::default());
                 if g.get_psqt() != w_psqt - b_psqt {
                     println!("G: {}", old_g.to_fen());
                     println!("Mv: {:?}", mv);
@@ -231,6 +395,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn do_undo_move_test() {
+        let mut rng = rand::thread_rng();
+        let mut movelist = movegen::MoveList::default();
+        for _i in 0..10_000 {
+            let mut g = GameState::standard();
+            let mut played = Vec::new();
+            let mut states = Vec::new();
+            for _j in 0..200 {
+                movegen::generate_moves(&g, false, &mut movelist);
+                if movelist.move_list.is_empty() {
+                    break;
+                }
+                let mv = movelist.move_list[rng.gen_range(0, movelist.move_list.len())].0;
+                let copy_made = make_move(&g, mv);
+                states.push(g.clone());
+                g.do_move(mv);
+                assert_eq!(g.to_fen(), copy_made.to_fen());
+                assert_eq!(g.get_hash(), copy_made.get_hash());
+                assert_eq!(g.get_material_key(), copy_made.get_material_key());
+                assert_eq!(g.get_psqt(), copy_made.get_psqt());
+                played.push(mv);
+                if !g.in_check() && rng.gen_range(0, 10) == 0 {
+                    let hash = g.get_hash();
+                    g.do_nullmove();
+                    assert_ne!(g.get_hash(), hash);
+                    g.undo_nullmove();
+                    assert_eq!(g.get_hash(), hash);
+                }
+            }
+            while let Some(mv) = played.pop() {
+                g.undo_move(mv);
+                let before = states.pop().unwrap();
+                assert_eq!(g.to_fen(), before.to_fen());
+                assert_eq!(g.get_hash(), before.get_hash());
+                assert_eq!(g.get_material_key(), before.get_material_key());
+                assert_eq!(g.get_psqt(), before.get_psqt());
+                assert!((g.get_phase().phase - before.get_phase().phase).abs() < std::f32::EPSILON);
+            }
+        }
+    }
+
     #[test]
     #[ignore]
     fn pgn_test() {
@@ -257,31 +463,31 @@ mod tests {
         let g = GameState::from_fen("4k3/6P1/8/1Pp5/6b1/8/2B5/4K2R w K c6 0 2");
         let mut movelist = MoveList::default();
         assert_eq!(
-            make_move(&g, parse_move(&g, "e1g1", &mut movelist).0).get_hash(),
+            make_move(&g, parse_move(&g, "e1g1", &mut movelist).unwrap().0).get_hash(),
             GameState::from_fen("4k3/6P1/8/1Pp5/6b1/8/2B5/5RK1 b - - 1 2").get_hash()
         );
         assert_eq!(
-            make_move(&g, parse_move(&g, "g7g8q", &mut movelist).0).get_hash(),
+            make_move(&g, parse_move(&g, "g7g8q", &mut movelist).unwrap().0).get_hash(),
             GameState::from_fen("4k1Q1/8/8/1Pp5/6b1/8/2B5/4K2R b K - 0 2").get_hash()
         );
         assert_eq!(
-            make_move(&g, parse_move(&g, "g7g8b", &mut movelist).0).get_hash(),
+            make_move(&g, parse_move(&g, "g7g8b", &mut movelist).unwrap().0).get_hash(),
             GameState::from_fen("4k1B1/8/8/1Pp5/6b1/8/2B5/4K2R b K - 0 2").get_hash()
         );
         assert_eq!(
-            make_move(&g, parse_move(&g, "g7g8n", &mut movelist).0).get_hash(),
+            make_move(&g, parse_move(&g, "g7g8n", &mut movelist).unwrap().0).get_hash(),
             GameState::from_fen("4k1N1/8/8/1Pp5/6b1/8/2B5/4K2R b K - 0 2").get_hash()
         );
         assert_eq!(
-            make_move(&g, parse_move(&g, "g7g8r", &mut movelist).0).get_hash(),
+            make_move(&g, parse_move(&g, "g7g8r", &mut movelist).unwrap().0).get_hash(),
             GameState::from_fen("4k1R1/8/8/1Pp5/6b1/8/2B5/4K2R b K - 0 2").get_hash()
         );
         assert_eq!(
-            make_move(&g, parse_move(&g, "b5c6", &mut movelist).0).get_hash(),
+            make_move(&g, parse_move(&g, "b5c6", &mut movelist).unwrap().0).get_hash(),
             GameState::from_fen("4k3/6P1/2P5/8/6b1/8/2B5/4K2R b K - 0 2").get_hash()
         );
         assert_eq!(
-            make_move(&g, parse_move(&g, "c2d3", &mut movelist).0).get_hash(),
+            make_move(&g, parse_move(&g, "c2d3", &mut movelist).unwrap().0).get_hash(),
             GameState::from_fen("4k3/6P1/8/1Pp5/6b1/3B4/8/4K2R b K - 1 2").get_hash()
         );
     }
@@ -0,0 +1,80 @@
+//Checks the evaluation for colour symmetry and the incremental hashes against freshly computed ones
+//on the bench positions and all positions of the test suites.
+use core_sdk::board_representation::game_state::{GameState, WHITE};
+use core_sdk::evaluation::eval_game_state;
+use core_sdk::move_generation::makemove::make_move;
+use core_sdk::move_generation::movegen::{self, MoveList};
+use core_sdk::BENCHMARKING_POSITIONS;
+use std::fs;
+
+const TEST_SUITES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../testsuites");
+
+fn symmetry_positions() -> Vec<GameState> {
+    let mut positions: Vec<GameState> = BENCHMARKING_POSITIONS.iter().map(|fen| GameState::from_fen(fen)).collect();
+    let mut suites = fs::read_dir(TEST_SUITES)
+        .expect("Could not read the test suites")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|ext| ext == "epd").unwrap_or(false))
+        .collect::<Vec<_>>();
+    suites.sort();
+    for suite in suites {
+        for line in fs::read_to_string(&suite).unwrap().lines() {
+            //Epd lines only hold the first four fields of a fen, followed by the operations
+            let fields = line.split_whitespace().take(4).collect::<Vec<&str>>();
+            if fields.len() == 4 {
+                positions.push(GameState::from_fen(&fields.join(" ")));
+            }
+        }
+    }
+    positions
+}
+
+fn legal_moves(g: &GameState, movelist: &mut MoveList) -> usize {
+    movegen::generate_moves(g, false, movelist);
+    movelist.move_list.len()
+}
+
+fn check_hashes(g: &GameState, movelist: &mut MoveList) {
+    movegen::generate_moves(g, false, movelist);
+    let moves = movelist.move_list.iter().map(|mv| mv.0).collect::<Vec<_>>();
+    for mv in moves {
+        let next = make_move(g, mv);
+        let mut fresh = next.clone();
+        fresh.initialize_zobrist_hash();
+        fresh.initialize_material_key();
+        assert_eq!(next.get_hash(), fresh.get_hash(), "{} after {:?}", g.to_fen(), mv);
+        assert_eq!(next.get_material_key(), fresh.get_material_key(), "{} after {:?}", g.to_fen(), mv);
+    }
+}
+
+#[test]
+fn mirror_test() {
+    let g = GameState::from_fen("r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK1R1 w Qkq a6 0 14");
+    assert_eq!(g.mirror().to_fen(), "r2qk1r1/p2n1ppp/1p2p3/1Np1b3/P7/2PP1Q2/2PB1PPP/R3K2R b KQq a3 0 14");
+    assert_eq!(g.flip_horizontal().to_fen(), "r2k3r/ppp1bp2/2q1pp2/7p/3B1Pn1/3P2P1/PPP1N2P/1R1KQ2R w - h6 0 14");
+}
+
+#[test]
+fn symmetry_test() {
+    let mut movelist = MoveList::default();
+    let positions = symmetry_positions();
+    assert!(positions.len() > BENCHMARKING_POSITIONS.len());
+    for g in positions.iter() {
+        let mirrored = g.mirror();
+        assert_eq!(mirrored.mirror().to_fen(), g.to_fen());
+        let flipped = g.flip_horizontal();
+        assert_eq!(flipped.flip_horizontal().get_piece_bb_array(), g.get_piece_bb_array());
+
+        //The side to move is swapped as well, so the tempo bonus changes its sign like every other term
+        let eval = eval_game_state(g).final_eval;
+        assert_eq!(eval, -eval_game_state(&mirrored).final_eval, "{} mirrored to {}", g.to_fen(), mirrored.to_fen());
+
+        assert_eq!(legal_moves(g, &mut movelist), legal_moves(&mirrored, &mut movelist), "{}", g.to_fen());
+        if g.castle_permissions() == 0 {
+            assert_eq!(legal_moves(g, &mut movelist), legal_moves(&flipped, &mut movelist), "{}", g.to_fen());
+        }
+        check_hashes(g, &mut movelist);
+        check_hashes(&mirrored, &mut movelist);
+        assert_eq!(mirrored.get_color_to_move() == WHITE, g.get_color_to_move() != WHITE);
+    }
+}
@@ -0,0 +1,200 @@
+// Checkpoint of a tuning run: magic FCTC, u32 version, the config as length prefixed json, epoch, k, best error, epochs
+// without improvement, optimizer steps, parameter counts and then parameters and both optimizer moments as f64.
+// The shuffle rng is seeded from the config's seed and the epoch, so it needs no state of its own.
+use crate::config::TunerConfig;
+use crate::optimizer::OptimizerState;
+use core_sdk::evaluation::parameters::{normal_parameters::NORMAL_PARAMS, special_parameters::SPECIAL_PARAMS, Parameters};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+
+pub const CHECKPOINT_MAGIC: &[u8; 4] = b"FCTC";
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub config: TunerConfig,
+    //Last completed epoch
+    pub epoch: usize,
+    pub k: f64,
+    pub best_error: f64,
+    pub epochs_without_improvement: usize,
+    pub params: Parameters,
+    pub optimizer: OptimizerState,
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u64(reader: &mut dyn Read) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(reader: &mut dyn Read) -> Result<f64> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+fn read_f64s(reader: &mut dyn Read, values: &mut [f64]) -> Result<()> {
+    let mut bytes = vec![0u8; 8 * values.len()];
+    reader.read_exact(&mut bytes)?;
+    for (value, b) in values.iter_mut().zip(bytes.chunks_exact(8)) {
+        *value = f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]);
+    }
+    Ok(())
+}
+
+fn write_f64s(writer: &mut dyn Write, values: &[f64]) -> Result<()> {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    writer.write_all(&bytes)
+}
+
+fn read_parameters(reader: &mut dyn Read, params: &mut Parameters) -> Result<()> {
+    read_f64s(reader, &mut params.normal[0])?;
+    read_f64s(reader, &mut params.normal[1])?;
+    read_f64s(reader, &mut params.special)
+}
+
+fn write_parameters(writer: &mut dyn Write, params: &Parameters) -> Result<()> {
+    write_f64s(writer, &params.normal[0])?;
+    write_f64s(writer, &params.normal[1])?;
+    write_f64s(writer, &params.special)
+}
+
+impl Checkpoint {
+    pub fn load(path: &str) -> Result<Checkpoint> {
+        Checkpoint::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    //Written to a temporary file first, so an interrupted save keeps the previous checkpoint intact
+    pub fn save(&self, path: &str) -> Result<()> {
+        let temp = format!("{}.tmp", path);
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp, path)
+    }
+
+    pub fn read(reader: &mut dyn Read) -> Result<Checkpoint> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(invalid_data("Not a FabChess tuner checkpoint".to_owned()));
+        }
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != CHECKPOINT_VERSION {
+            return Err(invalid_data(format!("Unsupported checkpoint version {}, expected {}", version, CHECKPOINT_VERSION)));
+        }
+        let mut config = vec![0u8; read_u64(reader)? as usize];
+        reader.read_exact(&mut config)?;
+        let config: TunerConfig = serde_json::from_slice(&config).map_err(|e| invalid_data(format!("Invalid config: {}", e)))?;
+        let epoch = read_u64(reader)? as usize;
+        let k = read_f64(reader)?;
+        let best_error = read_f64(reader)?;
+        let epochs_without_improvement = read_u64(reader)? as usize;
+        let steps = read_u64(reader)? as usize;
+        let (normal_params, special_params) = (read_u64(reader)? as usize, read_u64(reader)? as usize);
+        if normal_params != NORMAL_PARAMS || special_params != SPECIAL_PARAMS {
+            return Err(invalid_data(format!(
+                "Checkpoint has {} normal and {} special parameters, the evaluation has {} and {}",
+                normal_params, special_params, NORMAL_PARAMS, SPECIAL_PARAMS
+            )));
+        }
+        let mut params = Parameters::zero();
+        read_parameters(reader, &mut params)?;
+        let mut optimizer = OptimizerState::new(&config);
+        optimizer.steps = steps;
+        read_parameters(reader, &mut optimizer.squared)?;
+        read_parameters(reader, &mut optimizer.momentum)?;
+        Ok(Checkpoint {
+            config,
+            epoch,
+            k,
+            best_error,
+            epochs_without_improvement,
+            params,
+            optimizer,
+        })
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+        let config = serde_json::to_vec(&self.config).map_err(|e| invalid_data(format!("Invalid config: {}", e)))?;
+        writer.write_all(&(config.len() as u64).to_le_bytes())?;
+        writer.write_all(&config)?;
+        for value in [
+            self.epoch as u64,
+            self.k.to_bits(),
+            self.best_error.to_bits(),
+            self.epochs_without_improvement as u64,
+            self.optimizer.steps as u64,
+        ]
+        .iter()
+        {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&(NORMAL_PARAMS as u64).to_le_bytes())?;
+        writer.write_all(&(SPECIAL_PARAMS as u64).to_le_bytes())?;
+        write_parameters(writer, &self.params)?;
+        write_parameters(writer, &self.optimizer.squared)?;
+        write_parameters(writer, &self.optimizer.momentum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Optimizer;
+
+    #[test]
+    fn checkpoint_test() {
+        //Parameters are too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(32 * 1024 * 1024)
+            .spawn(|| {
+                let config = TunerConfig {
+                    optimizer: Optimizer::Adam,
+                    seed: 7,
+                    groups: vec!["psqt".to_owned()],
+                    ..TunerConfig::default()
+                };
+                let mut optimizer = OptimizerState::new(&config);
+                let mut gradient = Parameters::zero();
+                gradient.normal[1][5] = 0.25;
+                gradient.special[3] = -1.5;
+                optimizer.step(&mut Parameters::zero(), gradient, 1.);
+                let checkpoint = Checkpoint {
+                    config,
+                    epoch: 12,
+                    k: 1.25,
+                    best_error: 0.0625,
+                    epochs_without_improvement: 3,
+                    params: Parameters::default(),
+                    optimizer,
+                };
+                let mut bytes = Vec::new();
+                checkpoint.write(&mut bytes).unwrap();
+                let loaded = Checkpoint::read(&mut bytes.as_slice()).unwrap();
+                assert_eq!(loaded.config.seed, 7);
+                assert_eq!(loaded.config.groups, checkpoint.config.groups);
+                assert_eq!((loaded.epoch, loaded.k, loaded.best_error, loaded.epochs_without_improvement), (12, 1.25, 0.0625, 3));
+                assert_eq!(loaded.optimizer.optimizer, Optimizer::Adam);
+                assert_eq!(loaded.optimizer.steps, 1);
+                assert_eq!(loaded.optimizer.momentum.normal[1][5], checkpoint.optimizer.momentum.normal[1][5]);
+                assert_eq!(loaded.optimizer.squared.special[3], checkpoint.optimizer.squared.special[3]);
+                assert!(loaded.params.normal[0].iter().zip(checkpoint.params.normal[0].iter()).all(|(a, b)| a == b));
+                assert_eq!(format!("{}", loaded.params), format!("{}", checkpoint.params));
+
+                bytes[0] = b'X';
+                assert!(Checkpoint::read(&mut bytes.as_slice()).is_err());
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
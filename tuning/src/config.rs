@@ -0,0 +1,387 @@
+// Json config of the texel tuner, see tuning/EXAMPLE_CONFIG.json. Every field is optional and falls back to its default.
+// Groups are named after the IDX_* blocks of the parameters (PARAMETER_GROUPS), `all` selects every normal parameter.
+use crate::constraints::{Constraint, Constraints};
+use crate::loading::FileFormatSupported;
+use core_sdk::evaluation::parameters::{normal_parameters::*, special_parameters::*};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::str::FromStr;
+
+#[derive(Clone, Copy)]
+pub enum ParameterGroup {
+    Normal(usize, usize),
+    Special(usize, usize),
+}
+
+pub const PARAMETER_GROUPS: &[(&str, ParameterGroup)] = &[
+    ("tempo_bonus", ParameterGroup::Normal(IDX_TEMPO_BONUS, SIZE_TEMPO_BONUS)),
+    ("shielding_pawn_missing", ParameterGroup::Normal(IDX_SHIELDING_PAWN_MISSING, SIZE_SHIELDING_PAWN_MISSING)),
+    (
+        "shielding_pawn_onopen_missing",
+        ParameterGroup::Normal(IDX_SHIELDING_PAWN_ONOPEN_MISSING, SIZE_SHIELDING_PAWN_ONOPEN_MISSING),
+    ),
+    ("pawn_doubled", ParameterGroup::Normal(IDX_PAWN_DOUBLED, SIZE_PAWN_DOUBLED)),
+    ("pawn_isolated", ParameterGroup::Normal(IDX_PAWN_ISOLATED, SIZE_PAWN_ISOLATED)),
+    ("pawn_backward", ParameterGroup::Normal(IDX_PAWN_BACKWARD, SIZE_PAWN_BACKWARD)),
+    ("pawn_supported", ParameterGroup::Normal(IDX_PAWN_SUPPORTED, SIZE_PAWN_SUPPORTED)),
+    ("pawn_attack_center", ParameterGroup::Normal(IDX_PAWN_ATTACK_CENTER, SIZE_PAWN_ATTACK_CENTER)),
+    ("pawn_mobility", ParameterGroup::Normal(IDX_PAWN_MOBILITY, SIZE_PAWN_MOBILITY)),
+    ("pawn_passed", ParameterGroup::Normal(IDX_PAWN_PASSED, SIZE_PAWN_PASSED)),
+    ("pawn_passed_notblocked", ParameterGroup::Normal(IDX_PAWN_PASSED_NOTBLOCKED, SIZE_PAWN_PASSED_NOTBLOCKED)),
+    (
+        "pawn_passed_kingdistance",
+        ParameterGroup::Normal(IDX_PAWN_PASSED_KINGDISTANCE, SIZE_PAWN_PASSED_KINGDISTANCE),
+    ),
+    (
+        "pawn_passed_enemykingdistance",
+        ParameterGroup::Normal(IDX_PAWN_PASSED_ENEMYKINGDISTANCE, SIZE_PAWN_PASSED_ENEMYKINGDISTANCE),
+    ),
+    ("pawn_passed_subdistance", ParameterGroup::Normal(IDX_PAWN_PASSED_SUBDISTANCE, SIZE_PAWN_PASSED_SUBDISTANCE)),
+    (
+        "rook_behind_support_passer",
+        ParameterGroup::Normal(IDX_ROOK_BEHIND_SUPPORT_PASSER, SIZE_ROOK_BEHIND_SUPPORT_PASSER),
+    ),
+    (
+        "rook_behind_enemy_passer",
+        ParameterGroup::Normal(IDX_ROOK_BEHIND_ENEMY_PASSER, SIZE_ROOK_BEHIND_ENEMY_PASSER),
+    ),
+    ("pawn_passed_weak", ParameterGroup::Normal(IDX_PAWN_PASSED_WEAK, SIZE_PAWN_PASSED_WEAK)),
+    ("knight_supported", ParameterGroup::Normal(IDX_KNIGHT_SUPPORTED, SIZE_KNIGHT_SUPPORTED)),
+    ("knight_outpost_table", ParameterGroup::Normal(IDX_KNIGHT_OUTPOST_TABLE, SIZE_KNIGHT_OUTPOST_TABLE)),
+    ("rook_on_open", ParameterGroup::Normal(IDX_ROOK_ON_OPEN, SIZE_ROOK_ON_OPEN)),
+    ("rook_on_semi_open", ParameterGroup::Normal(IDX_ROOK_ON_SEMI_OPEN, SIZE_ROOK_ON_SEMI_OPEN)),
+    ("queen_on_open", ParameterGroup::Normal(IDX_QUEEN_ON_OPEN, SIZE_QUEEN_ON_OPEN)),
+    ("queen_on_semi_open", ParameterGroup::Normal(IDX_QUEEN_ON_SEMI_OPEN, SIZE_QUEEN_ON_SEMI_OPEN)),
+    ("rook_on_seventh", ParameterGroup::Normal(IDX_ROOK_ON_SEVENTH, SIZE_ROOK_ON_SEVENTH)),
+    ("pawn_piece_value", ParameterGroup::Normal(IDX_PAWN_PIECE_VALUE, SIZE_PAWN_PIECE_VALUE)),
+    ("knight_piece_value", ParameterGroup::Normal(IDX_KNIGHT_PIECE_VALUE, SIZE_KNIGHT_PIECE_VALUE)),
+    ("knight_value_with_pawn", ParameterGroup::Normal(IDX_KNIGHT_VALUE_WITH_PAWN, SIZE_KNIGHT_VALUE_WITH_PAWN)),
+    ("bishop_piece_value", ParameterGroup::Normal(IDX_BISHOP_PIECE_VALUE, SIZE_BISHOP_PIECE_VALUE)),
+    ("bishop_pair", ParameterGroup::Normal(IDX_BISHOP_PAIR, SIZE_BISHOP_PAIR)),
+    ("rook_piece_value", ParameterGroup::Normal(IDX_ROOK_PIECE_VALUE, SIZE_ROOK_PIECE_VALUE)),
+    ("queen_piece_value", ParameterGroup::Normal(IDX_QUEEN_PIECE_VALUE, SIZE_QUEEN_PIECE_VALUE)),
+    (
+        "diagonally_adj_sq_wpawns",
+        ParameterGroup::Normal(IDX_DIAGONALLY_ADJ_SQ_WPAWNS, SIZE_DIAGONALLY_ADJ_SQ_WPAWNS),
+    ),
+    ("knight_mobility", ParameterGroup::Normal(IDX_KNIGHT_MOBILITY, SIZE_KNIGHT_MOBILITY)),
+    ("bishop_mobility", ParameterGroup::Normal(IDX_BISHOP_MOBILITY, SIZE_BISHOP_MOBILITY)),
+    ("rook_mobility", ParameterGroup::Normal(IDX_ROOK_MOBILITY, SIZE_ROOK_MOBILITY)),
+    ("queen_mobility", ParameterGroup::Normal(IDX_QUEEN_MOBILITY, SIZE_QUEEN_MOBILITY)),
+    ("king_piece_table", ParameterGroup::Normal(IDX_KING_PIECE_TABLE, SIZE_KING_PIECE_TABLE)),
+    ("psqt", ParameterGroup::Normal(IDX_PSQT, SIZE_PSQT)),
+    ("attack_weight", ParameterGroup::Special(IDX_ATTACK_WEIGHT, SIZE_ATTACK_WEIGHT)),
+    ("safety_table", ParameterGroup::Special(IDX_SAFETY_TABLE, SIZE_SAFETY_TABLE)),
+    ("knight_attack_value", ParameterGroup::Special(IDX_KNIGHT_ATTACK_VALUE, SIZE_KNIGHT_ATTACK_VALUE)),
+    ("bishop_attack_value", ParameterGroup::Special(IDX_BISHOP_ATTACK_VALUE, SIZE_BISHOP_ATTACK_VALUE)),
+    ("rook_attack_value", ParameterGroup::Special(IDX_ROOK_ATTACK_VALUE, SIZE_ROOK_ATTACK_VALUE)),
+    ("queen_attack_value", ParameterGroup::Special(IDX_QUEEN_ATTACK_VALUE, SIZE_QUEEN_ATTACK_VALUE)),
+    ("knight_check_value", ParameterGroup::Special(IDX_KNIGHT_CHECK_VALUE, SIZE_KNIGHT_CHECK_VALUE)),
+    ("bishop_check_value", ParameterGroup::Special(IDX_BISHOP_CHECK_VALUE, SIZE_BISHOP_CHECK_VALUE)),
+    ("rook_check_value", ParameterGroup::Special(IDX_ROOK_CHECK_VALUE, SIZE_ROOK_CHECK_VALUE)),
+    ("queen_check_value", ParameterGroup::Special(IDX_QUEEN_CHECK_VALUE, SIZE_QUEEN_CHECK_VALUE)),
+];
+
+//Own pawn to queen followed by enemy pawn to queen, selectable as king_piece_table_<name>
+pub const KING_PIECE_TABLES: [&str; 10] = [
+    "own_pawn",
+    "own_knight",
+    "own_bishop",
+    "own_rook",
+    "own_queen",
+    "enemy_pawn",
+    "enemy_knight",
+    "enemy_bishop",
+    "enemy_rook",
+    "enemy_queen",
+];
+
+pub fn group_names() -> Vec<String> {
+    let mut names = vec!["all".to_owned()];
+    names.extend(PARAMETER_GROUPS.iter().map(|(name, _)| name.to_string()));
+    names.extend(KING_PIECE_TABLES.iter().map(|table| format!("king_piece_table_{}", table)));
+    names
+}
+
+//Any group of PARAMETER_GROUPS or a single king piece table
+pub fn find_group(name: &str) -> Option<ParameterGroup> {
+    if let Some((_, block)) = PARAMETER_GROUPS.iter().find(|(group, _)| *group == name) {
+        Some(*block)
+    } else {
+        let table = KING_PIECE_TABLES.iter().position(|table| name == format!("king_piece_table_{}", table))?;
+        Some(ParameterGroup::Normal(IDX_KING_PIECE_TABLE + 64 * 64 * table, 64 * 64))
+    }
+}
+
+pub struct ParameterSelection {
+    pub normal: Box<[bool; NORMAL_PARAMS]>,
+    pub special: [bool; SPECIAL_PARAMS],
+}
+
+impl ParameterSelection {
+    pub fn from_groups(groups: &[String]) -> Result<Self, String> {
+        let mut selection = ParameterSelection {
+            normal: Box::new([false; NORMAL_PARAMS]),
+            special: [false; SPECIAL_PARAMS],
+        };
+        for group in groups.iter() {
+            if group == "all" {
+                selection.normal.iter_mut().for_each(|tunable| *tunable = true);
+            } else if let Some(block) = find_group(group) {
+                match block {
+                    ParameterGroup::Normal(idx, size) => selection.normal[idx..idx + size].iter_mut().for_each(|tunable| *tunable = true),
+                    ParameterGroup::Special(idx, size) => selection.special[idx..idx + size].iter_mut().for_each(|tunable| *tunable = true),
+                }
+            } else {
+                return Err(format!("Unknown parameter group {}, valid groups are: {}", group, group_names().join(", ")));
+            }
+        }
+        if !selection.normal.iter().any(|&tunable| tunable) && !selection.special.iter().any(|&tunable| tunable) {
+            return Err("No parameter group selected".to_owned());
+        }
+        Ok(selection)
+    }
+
+    //The king safety gradient is computed for all special parameters at once and masked afterwards
+    pub fn tune_attack(&self) -> bool {
+        self.special.iter().any(|&tunable| tunable)
+    }
+
+    pub fn tune_attack_index(&self) -> bool {
+        self.special[IDX_KNIGHT_ATTACK_VALUE..IDX_QUEEN_CHECK_VALUE + SIZE_QUEEN_CHECK_VALUE]
+            .iter()
+            .any(|&tunable| tunable)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Optimizer {
+    Sgd,
+    AdaGrad,
+    Adam,
+}
+
+impl FromStr for Optimizer {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sgd" => Ok(Optimizer::Sgd),
+            "adagrad" => Ok(Optimizer::AdaGrad),
+            "adam" => Ok(Optimizer::Adam),
+            _ => Err(format!("Unknown optimizer {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LearningRateSchedule {
+    Constant,
+    Step { every: usize, factor: f64 },
+    Exponential { decay: f64 },
+    Cosine { min_learning_rate: f64 },
+}
+
+impl LearningRateSchedule {
+    //Epochs start at 1
+    pub fn learning_rate(&self, start: f64, epoch: usize, epochs: usize) -> f64 {
+        match *self {
+            LearningRateSchedule::Constant => start,
+            LearningRateSchedule::Step { every, factor } => start * factor.powi(((epoch - 1) / every.max(1)) as i32),
+            LearningRateSchedule::Exponential { decay } => start * decay.powi(epoch as i32 - 1),
+            LearningRateSchedule::Cosine { min_learning_rate } => {
+                let progress = (epoch - 1) as f64 / (epochs.max(2) - 1) as f64;
+                min_learning_rate + (start - min_learning_rate) * (1. + (std::f64::consts::PI * progress.min(1.)).cos()) / 2.
+            }
+        }
+    }
+}
+
+//Written as constant, step:<every>:<factor>, exponential:<decay> or cosine:<min learning rate>
+impl FromStr for LearningRateSchedule {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.split(':').collect::<Vec<&str>>();
+        let arg = |i: usize| split.get(i).and_then(|arg| arg.parse::<f64>().ok()).ok_or(format!("Invalid learning rate schedule {}", s));
+        match split[0] {
+            "constant" => Ok(LearningRateSchedule::Constant),
+            "step" => Ok(LearningRateSchedule::Step {
+                every: arg(1)? as usize,
+                factor: arg(2)?,
+            }),
+            "exponential" => Ok(LearningRateSchedule::Exponential { decay: arg(1)? }),
+            "cosine" => Ok(LearningRateSchedule::Cosine { min_learning_rate: arg(1)? }),
+            _ => Err(format!("Invalid learning rate schedule {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TunerConfig {
+    pub position_files: Vec<String>,
+    pub format: FileFormatSupported,
+    pub threads: usize,
+    pub k: f64,
+    pub optimize_k: bool,
+    pub batch_size: usize,
+    pub learning_rate: f64,
+    pub l1_regularization: f64,
+    pub l2_regularization: f64,
+    pub groups: Vec<String>,
+    //Prepended to every written file: tunebest.txt, checkpoint.bin and params.rs
+    pub output_prefix: String,
+    pub optimizer: Optimizer,
+    pub adam_beta1: f64,
+    pub adam_beta2: f64,
+    //0 tunes until stopped
+    pub epochs: usize,
+    pub lr_schedule: LearningRateSchedule,
+    //Part of the positions held out to measure generalisation, the best parameters are chosen by validation error if there are any
+    pub validation_split: f64,
+    //Stops after this many epochs without improvement, 0 disables early stopping
+    pub early_stopping_patience: usize,
+    //Csv with one line of losses per epoch
+    pub log_file: String,
+    //Seeds the validation split and the shuffling of each epoch
+    pub seed: u64,
+    //Epochs between writing <output_prefix>checkpoint.bin and <output_prefix>params.rs, 0 only writes them at the end
+    pub checkpoint_every: usize,
+    //Reads the training positions from disk in every epoch instead of holding them in memory, see crate::streaming
+    pub streaming: bool,
+    //Positions held in the shuffle buffer while streaming
+    pub shuffle_buffer: usize,
+    //Symmetries, ties, anchors and monotonicity the tuned parameters have to keep
+    pub constraints: Vec<Constraint>,
+}
+
+impl Default for TunerConfig {
+    fn default() -> Self {
+        TunerConfig {
+            position_files: Vec::new(),
+            format: FileFormatSupported::EPD,
+            threads: 1,
+            k: 1.1155,
+            optimize_k: false,
+            batch_size: 5_000_000,
+            learning_rate: 2.,
+            l1_regularization: 0.,
+            l2_regularization: 0.,
+            groups: vec!["all".to_owned()],
+            output_prefix: String::new(),
+            optimizer: Optimizer::AdaGrad,
+            adam_beta1: 0.9,
+            adam_beta2: 0.999,
+            epochs: 0,
+            lr_schedule: LearningRateSchedule::Constant,
+            validation_split: 0.,
+            early_stopping_patience: 0,
+            log_file: "tuning_log.csv".to_owned(),
+            seed: 0,
+            checkpoint_every: 10,
+            streaming: false,
+            shuffle_buffer: 1_000_000,
+            constraints: Vec::new(),
+        }
+    }
+}
+
+impl TunerConfig {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.validation_split < 0. || self.validation_split >= 1. {
+            return Err("The validation split has to be in [0, 1)".to_owned());
+        }
+        if self.streaming && self.optimize_k {
+            return Err("Optimizing k needs all positions in memory, it can't be used with streaming".to_owned());
+        }
+        if self.streaming && self.shuffle_buffer == 0 {
+            return Err("Streaming needs a shuffle buffer of at least one position".to_owned());
+        }
+        if let LearningRateSchedule::Cosine { .. } = self.lr_schedule {
+            if self.epochs == 0 {
+                return Err("The cosine schedule needs a number of epochs".to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn selection(&self) -> Result<ParameterSelection, String> {
+        ParameterSelection::from_groups(&self.groups)
+    }
+
+    pub fn constraints(&self, selection: &ParameterSelection) -> Result<Constraints, String> {
+        Constraints::new(&self.constraints, selection)
+    }
+
+    pub fn regularization(&self, term: f64) -> f64 {
+        self.l1_regularization * term.signum() + 2. * self.l2_regularization * term
+    }
+}
+
+impl Display for TunerConfig {
+    fn fmt(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        write!(formatter, "{}", serde_json::to_string_pretty(self).expect("Could not serialize config"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameter_selection_test() {
+        let config: TunerConfig = serde_json::from_str(r#"{"groups": ["pawn_passed", "king_piece_table_enemy_rook", "knight_check_value"], "k": 1.5}"#).unwrap();
+        assert_eq!(config.k, 1.5);
+        assert_eq!(config.batch_size, TunerConfig::default().batch_size);
+        let selection = config.selection().unwrap();
+        assert_eq!(selection.normal.iter().filter(|&&tunable| tunable).count(), SIZE_PAWN_PASSED + 64 * 64);
+        assert!(selection.normal[IDX_PAWN_PASSED] && !selection.normal[IDX_PAWN_PASSED - 1]);
+        assert!(selection.normal[IDX_KING_PIECE_TABLE + 64 * 64 * 8] && !selection.normal[IDX_KING_PIECE_TABLE + 64 * 64 * 9]);
+        assert!(selection.special[IDX_KNIGHT_CHECK_VALUE] && selection.special[IDX_KNIGHT_CHECK_VALUE + 1]);
+        assert!(selection.tune_attack() && selection.tune_attack_index());
+        let group_sizes: usize = PARAMETER_GROUPS
+            .iter()
+            .map(|(_, block)| match *block {
+                ParameterGroup::Normal(_, size) => size,
+                ParameterGroup::Special(..) => 0,
+            })
+            .sum();
+        assert_eq!(group_sizes, NORMAL_PARAMS);
+
+        let printed: TunerConfig = serde_json::from_str(&format!("{}", config)).unwrap();
+        assert_eq!(printed.groups, config.groups);
+        assert!(ParameterSelection::from_groups(&["pawns".to_owned()]).is_err());
+        assert!(ParameterSelection::from_groups(&[]).is_err());
+    }
+
+    #[test]
+    fn learning_rate_schedule_test() {
+        let config: TunerConfig = serde_json::from_str(r#"{"optimizer": "adam", "lr_schedule": {"type": "step", "every": 10, "factor": 0.5}}"#).unwrap();
+        assert_eq!(config.optimizer, Optimizer::Adam);
+        assert_eq!(config.lr_schedule, "step:10:0.5".parse::<LearningRateSchedule>().unwrap());
+        assert_eq!(config.lr_schedule.learning_rate(1., 10, 0), 1.);
+        assert_eq!(config.lr_schedule.learning_rate(1., 11, 0), 0.5);
+        assert_eq!(config.lr_schedule.learning_rate(1., 21, 0), 0.25);
+        let cosine = "cosine:0.1".parse::<LearningRateSchedule>().unwrap();
+        assert!((cosine.learning_rate(1., 1, 11) - 1.).abs() < 1e-9);
+        assert!((cosine.learning_rate(1., 6, 11) - 0.55).abs() < 1e-9);
+        assert!((cosine.learning_rate(1., 11, 11) - 0.1).abs() < 1e-9);
+        assert_eq!("exponential:0.5".parse::<LearningRateSchedule>().unwrap().learning_rate(2., 3, 0), 0.5);
+        assert!("linear".parse::<LearningRateSchedule>().is_err());
+        assert!(TunerConfig {
+            epochs: 0,
+            lr_schedule: cosine,
+            ..TunerConfig::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
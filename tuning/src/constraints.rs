@@ -0,0 +1,323 @@
+// Constraints on the tuned parameters, declared in the `constraints` list of the config. Parameters are named by group,
+// or a single entry of a group as `name[i]`, and a constraint applies to both phases unless it names a `phase` (mg, eg):
+//   {"type": "mirror", "param": "psqt"}                                          square equals the square on the mirrored file
+//   {"type": "monotonic", "param": "knight_mobility", "decreasing": false}       group stays sorted
+//   {"type": "fixed", "param": "pawn_piece_value", "phase": "eg", "value": 100.0} parameter is anchored to a value
+//   {"type": "tie", "params": ["rook_on_open", "queen_on_open"]}                 groups of the same size share their values
+// Tied parameters share the sum of their gradients and fixed ones get none. After each step ties are averaged, monotonic
+// groups are sorted and fixed parameters reset, in that order. Only tuned parameters can be constrained.
+use crate::config::{find_group, ParameterGroup, ParameterSelection};
+use core_sdk::evaluation::parameters::{normal_parameters::NORMAL_PARAMS, Parameters};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ScorePhase {
+    Mg,
+    Eg,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Constraint {
+    Mirror {
+        param: String,
+        phase: Option<ScorePhase>,
+    },
+    Monotonic {
+        param: String,
+        phase: Option<ScorePhase>,
+        #[serde(default)]
+        decreasing: bool,
+    },
+    Fixed {
+        param: String,
+        phase: Option<ScorePhase>,
+        value: f64,
+    },
+    Tie {
+        params: Vec<String>,
+        phase: Option<ScorePhase>,
+    },
+}
+
+//Start and size of the normal parameters a reference points to
+fn resolve(reference: &str) -> Result<(usize, usize), String> {
+    let (name, entry) = match reference.split_once('[') {
+        Some((name, entry)) => {
+            let entry = entry
+                .strip_suffix(']')
+                .and_then(|entry| entry.parse::<usize>().ok())
+                .ok_or(format!("Invalid parameter {}", reference))?;
+            (name, Some(entry))
+        }
+        None => (reference, None),
+    };
+    match find_group(name) {
+        Some(ParameterGroup::Normal(idx, size)) => match entry {
+            Some(entry) if entry >= size => Err(format!("{} is out of range, {} has {} entries", reference, name, size)),
+            Some(entry) => Ok((idx + entry, 1)),
+            None => Ok((idx, size)),
+        },
+        Some(ParameterGroup::Special(..)) => Err(format!("{} is a special parameter, only normal parameters can be constrained", name)),
+        None => Err(format!("Unknown parameter group {}", name)),
+    }
+}
+
+//Parameters are numbered as phase * NORMAL_PARAMS + index
+fn phases(phase: Option<ScorePhase>) -> &'static [usize] {
+    match phase {
+        Some(ScorePhase::Mg) => &[0],
+        Some(ScorePhase::Eg) => &[1],
+        None => &[0, 1],
+    }
+}
+
+fn mirrored(idx: usize, size: usize) -> Result<Vec<(usize, usize)>, String> {
+    if size.is_multiple_of(64 * 64) {
+        Ok((0..size)
+            .map(|i| {
+                let (table, king_square, piece_square) = (i / (64 * 64), i / 64 % 64, i % 64);
+                (idx + i, idx + 64 * 64 * table + 64 * (king_square ^ 7) + (piece_square ^ 7))
+            })
+            .collect())
+    } else if size.is_multiple_of(64) {
+        Ok((0..size).map(|i| (idx + i, idx + i / 64 * 64 + ((i % 64) ^ 7))).collect())
+    } else {
+        Err("Only tables of 64 squares can be mirrored".to_owned())
+    }
+}
+
+fn find(parent: &mut [usize], mut x: usize) -> usize {
+    while parent[x] != x {
+        parent[x] = parent[parent[x]];
+        x = parent[x];
+    }
+    x
+}
+
+//Closest non-decreasing sequence in the least squares sense, by pooling adjacent violators
+fn sorted_projection(values: &[f64]) -> Vec<f64> {
+    //Mean and length of each pool
+    let mut pools: Vec<(f64, usize)> = Vec::with_capacity(values.len());
+    for &value in values {
+        pools.push((value, 1));
+        while pools.len() > 1 && pools[pools.len() - 2].0 > pools[pools.len() - 1].0 {
+            let (mean, len) = pools.pop().unwrap();
+            let last = pools.last_mut().unwrap();
+            last.0 = (last.0 * last.1 as f64 + mean * len as f64) / (last.1 + len) as f64;
+            last.1 += len;
+        }
+    }
+    pools.into_iter().flat_map(|(mean, len)| std::iter::repeat_n(mean, len)).collect()
+}
+
+#[derive(Default)]
+pub struct Constraints {
+    //Parameters sharing one value, only sets of at least two
+    tied: Vec<Vec<usize>>,
+    //Index into tied
+    tied_set: HashMap<usize, usize>,
+    monotonic: Vec<(Vec<usize>, bool)>,
+    fixed: Vec<(usize, f64)>,
+}
+
+impl Constraints {
+    pub fn new(constraints: &[Constraint], selection: &ParameterSelection) -> Result<Self, String> {
+        let mut parent = (0..2 * NORMAL_PARAMS).collect::<Vec<usize>>();
+        let mut tie = |a: usize, b: usize| {
+            let (a, b) = (find(&mut parent, a), find(&mut parent, b));
+            parent[a] = b;
+        };
+        let mut res = Constraints::default();
+        for constraint in constraints.iter() {
+            let references = match constraint {
+                Constraint::Mirror { param, .. } | Constraint::Monotonic { param, .. } | Constraint::Fixed { param, .. } => std::slice::from_ref(param),
+                Constraint::Tie { params, .. } => &params[..],
+            };
+            let mut ranges = Vec::with_capacity(references.len());
+            for reference in references.iter() {
+                let (idx, size) = resolve(reference)?;
+                if !selection.normal[idx..idx + size].iter().all(|&tunable| tunable) {
+                    return Err(format!("The constraint on {} needs all of its parameters to be tuned", reference));
+                }
+                ranges.push((idx, size));
+            }
+            match *constraint {
+                Constraint::Mirror { phase, .. } => {
+                    let pairs = mirrored(ranges[0].0, ranges[0].1).map_err(|e| format!("{}: {}", references[0], e))?;
+                    for &phase in phases(phase) {
+                        for &(a, b) in pairs.iter() {
+                            tie(phase * NORMAL_PARAMS + a, phase * NORMAL_PARAMS + b);
+                        }
+                    }
+                }
+                Constraint::Monotonic { phase, decreasing, .. } => {
+                    let (idx, size) = ranges[0];
+                    for &phase in phases(phase) {
+                        res.monotonic.push(((idx..idx + size).map(|i| phase * NORMAL_PARAMS + i).collect(), decreasing));
+                    }
+                }
+                Constraint::Fixed { phase, value, .. } => {
+                    let (idx, size) = ranges[0];
+                    for &phase in phases(phase) {
+                        res.fixed.extend((idx..idx + size).map(|i| (phase * NORMAL_PARAMS + i, value)));
+                    }
+                }
+                Constraint::Tie { phase, .. } => {
+                    if ranges.len() < 2 || ranges.iter().any(|&(_, size)| size != ranges[0].1) {
+                        return Err(format!("A tie needs at least two parameters of the same size: {}", references.join(", ")));
+                    }
+                    for &phase in phases(phase) {
+                        for &(idx, _) in ranges[1..].iter() {
+                            for i in 0..ranges[0].1 {
+                                tie(phase * NORMAL_PARAMS + ranges[0].0 + i, phase * NORMAL_PARAMS + idx + i);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let mut sets: HashMap<usize, Vec<usize>> = HashMap::new();
+        for parameter in 0..parent.len() {
+            let root = find(&mut parent, parameter);
+            if root != parameter {
+                sets.entry(root).or_default().push(parameter);
+            }
+        }
+        for (root, mut set) in sets.into_iter() {
+            set.push(root);
+            set.sort_unstable();
+            for &parameter in set.iter() {
+                res.tied_set.insert(parameter, res.tied.len());
+            }
+            res.tied.push(set);
+        }
+        Ok(res)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tied.is_empty() && self.monotonic.is_empty() && self.fixed.is_empty()
+    }
+
+    fn get(params: &Parameters, parameter: usize) -> f64 {
+        params.normal[parameter / NORMAL_PARAMS][parameter % NORMAL_PARAMS]
+    }
+
+    //Sets the parameter and everything tied to it
+    fn set(&self, params: &mut Parameters, parameter: usize, value: f64) {
+        match self.tied_set.get(&parameter) {
+            Some(&set) => {
+                for &tied in self.tied[set].iter() {
+                    params.normal[tied / NORMAL_PARAMS][tied % NORMAL_PARAMS] = value;
+                }
+            }
+            None => params.normal[parameter / NORMAL_PARAMS][parameter % NORMAL_PARAMS] = value,
+        }
+    }
+
+    //Tied parameters get the sum of their gradients, fixed ones none
+    pub fn tie_gradient(&self, gradient: &mut Parameters) {
+        for set in self.tied.iter() {
+            let sum = set.iter().map(|&parameter| Constraints::get(gradient, parameter)).sum();
+            self.set(gradient, set[0], sum);
+        }
+        for &(parameter, _) in self.fixed.iter() {
+            self.set(gradient, parameter, 0.);
+        }
+    }
+
+    pub fn project(&self, params: &mut Parameters) {
+        for set in self.tied.iter() {
+            let mean = set.iter().map(|&parameter| Constraints::get(params, parameter)).sum::<f64>() / set.len() as f64;
+            self.set(params, set[0], mean);
+        }
+        for (group, decreasing) in self.monotonic.iter() {
+            let sign = if *decreasing { -1. } else { 1. };
+            let values = group.iter().map(|&parameter| sign * Constraints::get(params, parameter)).collect::<Vec<f64>>();
+            for (&parameter, value) in group.iter().zip(sorted_projection(&values)) {
+                self.set(params, parameter, sign * value);
+            }
+        }
+        for &(parameter, value) in self.fixed.iter() {
+            self.set(params, parameter, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TunerConfig;
+    use core_sdk::evaluation::parameters::normal_parameters::*;
+
+    #[test]
+    fn constraints_test() {
+        //Parameters are too large for the default test thread stack
+        std::thread::Builder::new().stack_size(32 * 1024 * 1024).spawn(constraints).unwrap().join().unwrap();
+    }
+
+    fn constraints() {
+        let config: TunerConfig = serde_json::from_str(
+            r#"{"constraints": [
+                {"type": "mirror", "param": "psqt"},
+                {"type": "mirror", "param": "king_piece_table_enemy_rook", "phase": "eg"},
+                {"type": "monotonic", "param": "knight_mobility"},
+                {"type": "monotonic", "param": "pawn_passed_kingdistance", "decreasing": true},
+                {"type": "fixed", "param": "pawn_piece_value", "phase": "eg", "value": 100.0},
+                {"type": "tie", "params": ["rook_on_open", "queen_on_open"]}
+            ]}"#,
+        )
+        .unwrap();
+        let constraints = config.constraints(&config.selection().unwrap()).unwrap();
+        let mut params = Parameters::default();
+        params.normal[0][IDX_KNIGHT_MOBILITY + 3] = 1000.;
+        constraints.project(&mut params);
+
+        for phase in 0..2 {
+            for i in 0..SIZE_PSQT {
+                assert_eq!(params.normal[phase][IDX_PSQT + i], params.normal[phase][IDX_PSQT + i / 8 * 8 + 7 - i % 8]);
+            }
+            assert!(params.normal[phase][IDX_KNIGHT_MOBILITY..IDX_KNIGHT_MOBILITY + SIZE_KNIGHT_MOBILITY]
+                .windows(2)
+                .all(|w| w[0] <= w[1]));
+            let distance = &params.normal[phase][IDX_PAWN_PASSED_KINGDISTANCE..IDX_PAWN_PASSED_KINGDISTANCE + SIZE_PAWN_PASSED_KINGDISTANCE];
+            assert!(distance.windows(2).all(|w| w[0] >= w[1]));
+            assert_eq!(params.normal[phase][IDX_ROOK_ON_OPEN], params.normal[phase][IDX_QUEEN_ON_OPEN]);
+        }
+        let table = IDX_KING_PIECE_TABLE + 64 * 64 * 8;
+        assert_eq!(params.normal[1][table + 64 * 4 + 9], params.normal[1][table + 64 * 3 + 14]);
+        assert_eq!(params.normal[1][IDX_PAWN_PIECE_VALUE], 100.);
+        assert_eq!(params.normal[0][IDX_PAWN_PIECE_VALUE], Parameters::default().normal[0][IDX_PAWN_PIECE_VALUE]);
+        assert!(format!("{}", params).contains(&format!(
+            "PAWN_PIECE_VALUE: EvaluationScore = EvaluationScore({}, 100);",
+            params.normal[0][IDX_PAWN_PIECE_VALUE].round()
+        )));
+
+        let mut gradient = Parameters::zero();
+        gradient.normal[0][IDX_ROOK_ON_OPEN] = 1.;
+        gradient.normal[0][IDX_QUEEN_ON_OPEN] = 2.;
+        gradient.normal[1][IDX_PAWN_PIECE_VALUE] = 3.;
+        constraints.tie_gradient(&mut gradient);
+        assert_eq!((gradient.normal[0][IDX_ROOK_ON_OPEN], gradient.normal[0][IDX_QUEEN_ON_OPEN]), (3., 3.));
+        assert_eq!(gradient.normal[1][IDX_PAWN_PIECE_VALUE], 0.);
+
+        assert_eq!(sorted_projection(&[1., 3., 2., 1., 5.]), vec![1., 2., 2., 2., 5.]);
+        let invalid = |constraint: &str| {
+            let config: TunerConfig = serde_json::from_str(&format!(r#"{{"constraints": [{}]}}"#, constraint)).unwrap();
+            config.constraints(&config.selection().unwrap()).is_err()
+        };
+        assert!(invalid(r#"{"type": "mirror", "param": "knight_mobility"}"#));
+        assert!(invalid(r#"{"type": "mirror", "param": "pawn_passed"}"#));
+        assert!(invalid(r#"{"type": "fixed", "param": "psqt[384]", "value": 0.0}"#));
+        assert!(invalid(r#"{"type": "tie", "params": ["psqt"]}"#));
+        assert!(invalid(r#"{"type": "monotonic", "param": "safety_table"}"#));
+        assert!(!invalid(r#"{"type": "fixed", "param": "psqt[0]", "value": 0.0}"#));
+        let untuned = TunerConfig {
+            groups: vec!["psqt".to_owned()],
+            ..config
+        };
+        assert!(untuned.constraints(&untuned.selection().unwrap()).is_err());
+    }
+}
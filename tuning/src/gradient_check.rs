@@ -0,0 +1,215 @@
+// Compares calculate_gradient with central finite differences of the evaluation error. A normal parameter only changes
+// the evaluation of positions whose trace holds it, so only those are evaluated again.
+use crate::config::{ParameterGroup, PARAMETER_GROUPS};
+use crate::{calculate_gradient, sigmoid, Tuner, TunerWrapper};
+use core_sdk::evaluation::parameters::{normal_parameters::NORMAL_PARAMS, special_parameters::*, Parameters};
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::thread;
+
+#[derive(Clone, Copy)]
+pub enum ParameterIndex {
+    //Phase and index
+    Normal(usize, usize),
+    Special(usize),
+}
+
+pub struct GradientCheck {
+    pub index: ParameterIndex,
+    //dE/dp of calculate_gradient
+    pub analytic: f64,
+    //(E(p + step) - E(p - step)) / (2 * step)
+    pub numeric: f64,
+}
+
+impl GradientCheck {
+    pub fn relative_error(&self) -> f64 {
+        let scale = self.analytic.abs().max(self.numeric.abs());
+        if scale < 1e-15 {
+            0.
+        } else {
+            (self.analytic - self.numeric).abs() / scale
+        }
+    }
+
+    //The attack constants select an entry of the safety table, a step of one moves the evaluation by a whole table entry.
+    //The error is not linear over such a step, so both sides only agree roughly
+    pub fn tolerance(&self) -> f64 {
+        match self.index {
+            ParameterIndex::Special(i) if (IDX_KNIGHT_ATTACK_VALUE..IDX_QUEEN_CHECK_VALUE + SIZE_QUEEN_CHECK_VALUE).contains(&i) => 0.1,
+            _ => 0.01,
+        }
+    }
+
+    pub fn is_mismatch(&self) -> bool {
+        self.relative_error() > self.tolerance()
+    }
+
+    pub fn name(&self) -> String {
+        let (index, special, phase) = match self.index {
+            ParameterIndex::Normal(phase, i) => (i, false, Some(phase)),
+            ParameterIndex::Special(i) => (i, true, None),
+        };
+        let group = PARAMETER_GROUPS.iter().find_map(|(name, block)| match *block {
+            ParameterGroup::Normal(idx, size) if !special && (idx..idx + size).contains(&index) => Some((name, index - idx)),
+            ParameterGroup::Special(idx, size) if special && (idx..idx + size).contains(&index) => Some((name, index - idx)),
+            _ => None,
+        });
+        let name = group.map(|(name, offset)| format!("{}[{}]", name, offset)).unwrap_or_else(|| format!("special[{}]", index));
+        match phase {
+            Some(0) => format!("{} mg", name),
+            Some(_) => format!("{} eg", name),
+            None => name,
+        }
+    }
+}
+
+fn value(params: &mut Parameters, index: ParameterIndex) -> &mut f64 {
+    match index {
+        ParameterIndex::Normal(phase, i) => &mut params.normal[phase][i],
+        ParameterIndex::Special(i) => &mut params.special[i],
+    }
+}
+
+//Checks every tunable parameter on the first positions of the tuner, sorted by relative error, worst first
+pub fn check_gradient(tuner: Tuner, positions: usize, step: f64) -> Vec<GradientCheck> {
+    let tuner = Arc::new(TunerWrapper(UnsafeCell::new(tuner)));
+    //The tuner is only read from here on
+    unsafe { check_shared(tuner, positions, step) }
+}
+
+unsafe fn check_shared(tuner: Arc<TunerWrapper>, positions: usize, step: f64) -> Vec<GradientCheck> {
+    let n = positions.min(tuner.get().positions.len());
+    let mut analytic = calculate_gradient(tuner.get(), 0, n);
+    let k = tuner.get().k;
+    analytic.scale(-2. / n as f64 * k * 10f64.ln() / 400.);
+
+    let mut indices = Vec::new();
+    for i in 0..NORMAL_PARAMS {
+        if tuner.get().selection.normal[i] {
+            indices.push(ParameterIndex::Normal(0, i));
+            indices.push(ParameterIndex::Normal(1, i));
+        }
+    }
+    for i in 0..SPECIAL_PARAMS {
+        if tuner.get().selection.special[i] {
+            indices.push(ParameterIndex::Special(i));
+        }
+    }
+    let mut containing = vec![Vec::new(); NORMAL_PARAMS];
+    for (pos, state) in tuner.get().positions[..n].iter().enumerate() {
+        for entry in state.trace.normal_tunable_coeffs.iter() {
+            containing[entry.0 as usize].push(pos);
+        }
+    }
+    let containing = Arc::new(containing);
+
+    let threads = tuner.get().config.threads;
+    let per_thread = (indices.len() as f64 / threads as f64).ceil() as usize;
+    let mut indices = indices.into_iter();
+    let mut thread_handles = Vec::with_capacity(threads);
+    for _ in 0..threads {
+        let (tuner, containing) = (Arc::clone(&tuner), Arc::clone(&containing));
+        let chunk = indices.by_ref().take(per_thread).collect::<Vec<_>>();
+        thread_handles.push(
+            thread::Builder::new()
+                .stack_size(12 * 1024 * 1024)
+                .spawn(move || {
+                    let sample = &tuner.get().positions[..n];
+                    let mut params = tuner.get().params.clone();
+                    let all = (0..n).collect::<Vec<_>>();
+                    let error = |params: &Parameters, affected: &[usize]| {
+                        affected
+                            .iter()
+                            .map(|&pos| (sample[pos].label as f64 - sigmoid(k, sample[pos].trace.evaluate(params))).powf(2.))
+                            .sum::<f64>()
+                    };
+                    chunk
+                        .into_iter()
+                        .map(|index| {
+                            let affected: &[usize] = match index {
+                                ParameterIndex::Normal(_, i) => &containing[i],
+                                ParameterIndex::Special(_) => &all,
+                            };
+                            let original = *value(&mut params, index);
+                            *value(&mut params, index) = original + step;
+                            let plus = error(&params, affected);
+                            *value(&mut params, index) = original - step;
+                            let minus = error(&params, affected);
+                            *value(&mut params, index) = original;
+                            (index, (plus - minus) / (2. * step * n as f64))
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .expect("Could not spawn thread!"),
+        );
+    }
+    let mut res = Vec::new();
+    for handle in thread_handles.into_iter() {
+        for (index, numeric) in handle.join().unwrap() {
+            res.push(GradientCheck {
+                index,
+                analytic: *value(&mut analytic, index),
+                numeric,
+            });
+        }
+    }
+    res.sort_by(|a, b| b.relative_error().partial_cmp(&a.relative_error()).unwrap());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TunerConfig;
+    use crate::loading::{texel_state, LabelledGameState};
+    use core_sdk::board_representation::game_state::GameState;
+
+    #[test]
+    fn gradient_check_test() {
+        //Parameters are too large for the default test thread stack
+        thread::Builder::new().stack_size(32 * 1024 * 1024).spawn(gradient_check).unwrap().join().unwrap();
+    }
+
+    fn gradient_check() {
+        let mut groups = vec!["all".to_owned()];
+        groups.extend(
+            PARAMETER_GROUPS
+                .iter()
+                .filter(|(_, block)| matches!(block, ParameterGroup::Special(..)))
+                .map(|(name, _)| name.to_string()),
+        );
+        let config = TunerConfig {
+            threads: 2,
+            groups,
+            ..TunerConfig::default()
+        };
+        let selection = config.selection().unwrap();
+        let params = Parameters::default();
+        let positions = core_sdk::BENCHMARKING_POSITIONS
+            .iter()
+            .enumerate()
+            .map(|(i, fen)| {
+                let pos = LabelledGameState {
+                    game_state: GameState::from_fen(fen),
+                    label: (i % 3) as f32 / 2.,
+                    score: None,
+                };
+                texel_state(&pos, &selection.normal, &params)
+            })
+            .collect::<Vec<_>>();
+        let tuner = Tuner {
+            k: config.k,
+            positions,
+            validation: Vec::new(),
+            params,
+            config,
+            selection,
+            constraints: Default::default(),
+        };
+        let checks = check_gradient(tuner, 20, 1.);
+        for check in checks.iter() {
+            assert!(!check.is_mismatch(), "{}: {} vs {}", check.name(), check.analytic, check.numeric);
+        }
+    }
+}
@@ -2,181 +2,35 @@
 extern crate core;
 extern crate rand;
 
+pub mod checkpoint;
+pub mod config;
+pub mod constraints;
+pub mod gradient_check;
 pub mod loading;
+pub mod nnue;
+pub mod optimizer;
+pub mod packed;
+pub mod streaming;
 
+pub use crate::checkpoint::Checkpoint;
+pub use crate::config::{ParameterSelection, TunerConfig};
+use crate::constraints::Constraints;
+use crate::loading::texel_state;
 pub use crate::loading::{FileFormatSupported, LabelledGameState, Statistics};
-use core_sdk::board_representation::game_state::{BLACK, WHITE};
+use crate::optimizer::OptimizerState;
+use crate::streaming::PositionStream;
+use core_sdk::board_representation::game_state::{GameState, BLACK, WHITE};
 pub use core_sdk::evaluation::parameters::{normal_parameters::*, special_parameters::*, *};
 use core_sdk::evaluation::trace::CollapsedTrace;
-use rand::{seq::SliceRandom, thread_rng};
+use rand::rngs::StdRng;
+use rand::{seq::SliceRandom, SeedableRng};
 use std::cell::UnsafeCell;
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
-pub const PARAM_FILE: &str = "D:/FenCollection/Andrews/20M";
-//Override for all others if true
-pub const TUNE_ALL: bool = false;
-
-pub const TUNE_TEMPO_BONUS: bool = false;
-pub const TUNE_SHIELDING_PAWNS: bool = false;
-pub const TUNE_PAWNS: bool = false;
-//Category passed pawns
-pub const TUNE_PASSED: bool = false;
-pub const TUNE_PASSED_PAWN: bool = false;
-pub const TUNE_PASSED_PAWN_NOT_BLOCKED: bool = false;
-
-pub const TUNE_KNIGHTS: bool = false;
-pub const TUNE_FILES: bool = false;
-
-pub const TUNE_PIECE_VALUES: bool = false;
-pub const TUNE_MOBILITY: bool = false;
-
-pub const TUNE_ATTACK: bool = false;
-pub const TUNE_ATTACK_INDEX: bool = false;
-pub const TUNE_KP_TABLE: bool = true;
-pub const TUNE_KP: [[bool; 5]; 2] = [[false, false, false, false, false], [false, false, false, true, false]]; //Own: P, N, B, R, Q Enemy: P,N,B,R,Q
-pub const TUNE_PSQT: bool = false;
-
-pub const TUNABLE_PARAM: [bool; NORMAL_PARAMS] = init_tunable_param();
-
-pub const OPTIMIZE_K: bool = false;
-pub const BATCH_SIZE: usize = 5_000_000;
-pub const START_LEARNING_RATE: f64 = 2.;
-pub const L1_REGULARIZATION: f64 = 0.;
-pub const L2_REGULARIZATION: f64 = 0.;
-pub const fn init_tunable_param() -> [bool; NORMAL_PARAMS] {
-    let mut res = [false; NORMAL_PARAMS];
-    if TUNE_ALL {
-        res = [true; NORMAL_PARAMS];
-    } else {
-        if TUNE_SHIELDING_PAWNS {
-            let mut i = 0;
-            while i < SIZE_SHIELDING_PAWN_MISSING {
-                res[IDX_SHIELDING_PAWN_MISSING + i] = true;
-                i += 1;
-            }
-            i = 0;
-            while i < SIZE_SHIELDING_PAWN_ONOPEN_MISSING {
-                res[IDX_SHIELDING_PAWN_ONOPEN_MISSING + i] = true;
-                i += 1;
-            }
-        }
-        if TUNE_PAWNS {
-            res[IDX_PAWN_DOUBLED] = true;
-            res[IDX_PAWN_ISOLATED] = true;
-            res[IDX_PAWN_BACKWARD] = true;
-            res[IDX_PAWN_ATTACK_CENTER] = true;
-            res[IDX_PAWN_MOBILITY] = true;
-            let mut i = 0;
-            while i < SIZE_PAWN_SUPPORTED {
-                res[IDX_PAWN_SUPPORTED + i] = true;
-                i += 1;
-            }
-        }
-        if TUNE_PASSED {
-            let mut i = 0;
-            while i < SIZE_PAWN_PASSED {
-                res[IDX_PAWN_PASSED + i] = true;
-                i += 1;
-            }
-            i = 0;
-            while i < SIZE_PAWN_PASSED_NOTBLOCKED {
-                res[IDX_PAWN_PASSED_NOTBLOCKED + i] = true;
-                i += 1;
-            }
-            i = 0;
-            while i < SIZE_PAWN_PASSED_KINGDISTANCE {
-                res[IDX_PAWN_PASSED_KINGDISTANCE + i] = true;
-                i += 1;
-            }
-            i = 0;
-            while i < SIZE_PAWN_PASSED_ENEMYKINGDISTANCE {
-                res[IDX_PAWN_PASSED_ENEMYKINGDISTANCE + i] = true;
-                i += 1;
-            }
-            i = 0;
-            while i < SIZE_PAWN_PASSED_SUBDISTANCE {
-                res[IDX_PAWN_PASSED_SUBDISTANCE + i] = true;
-                i += 1;
-            }
-            res[IDX_ROOK_BEHIND_SUPPORT_PASSER] = true;
-            res[IDX_ROOK_BEHIND_ENEMY_PASSER] = true;
-            res[IDX_PAWN_PASSED_WEAK] = true;
-        }
-        if TUNE_KNIGHTS {
-            res[IDX_KNIGHT_SUPPORTED] = true;
-            let mut i = 0;
-            while i < SIZE_KNIGHT_OUTPOST_TABLE {
-                res[IDX_KNIGHT_OUTPOST_TABLE + i] = true;
-                i += 1;
-            }
-        }
-        if TUNE_FILES {
-            res[IDX_ROOK_ON_OPEN] = true;
-            res[IDX_ROOK_ON_SEMI_OPEN] = true;
-            res[IDX_QUEEN_ON_OPEN] = true;
-            res[IDX_QUEEN_ON_SEMI_OPEN] = true;
-            res[IDX_ROOK_ON_SEVENTH] = true;
-        }
-        if TUNE_PIECE_VALUES {
-            res[IDX_PAWN_PIECE_VALUE] = true;
-            res[IDX_KNIGHT_PIECE_VALUE] = true;
-            res[IDX_BISHOP_PIECE_VALUE] = true;
-            res[IDX_BISHOP_PAIR] = true;
-            res[IDX_ROOK_PIECE_VALUE] = true;
-            res[IDX_QUEEN_PIECE_VALUE] = true;
-            let mut i = 0;
-            while i < SIZE_DIAGONALLY_ADJ_SQ_WPAWNS {
-                res[IDX_DIAGONALLY_ADJ_SQ_WPAWNS + i] = true;
-                i += 1;
-            }
-        }
-        if TUNE_MOBILITY {
-            let mut i = 0;
-            while i < SIZE_KNIGHT_MOBILITY {
-                res[IDX_KNIGHT_MOBILITY + i] = true;
-                i += 1;
-            }
-            i = 0;
-            while i < SIZE_BISHOP_MOBILITY {
-                res[IDX_BISHOP_MOBILITY + i] = true;
-                i += 1;
-            }
-            i = 0;
-            while i < SIZE_ROOK_MOBILITY {
-                res[IDX_ROOK_MOBILITY + i] = true;
-                i += 1;
-            }
-            i = 0;
-            while i < SIZE_QUEEN_MOBILITY {
-                res[IDX_QUEEN_MOBILITY + i] = true;
-                i += 1;
-            }
-        }
-        if TUNE_KP_TABLE {
-            let mut i = 0;
-            while i < 10 {
-                if TUNE_KP[i / 5][i % 5] {
-                    let mut j = 0;
-                    while j < 64 * 64 {
-                        res[IDX_KING_PIECE_TABLE + 64 * 64 * i + j] = true;
-                        j += 1;
-                    }
-                }
-                i += 1;
-            }
-        }
-        if TUNE_PSQT {
-            let mut i = 0;
-            while i < SIZE_PSQT {
-                res[IDX_PSQT + i] = true;
-                i += 1;
-            }
-        }
-    }
-    res[IDX_TEMPO_BONUS] = TUNE_TEMPO_BONUS;
-    res
-}
 pub struct TexelState {
     pub label: f32,
     pub eval: f64,
@@ -186,7 +40,12 @@ pub struct TexelState {
 pub struct Tuner {
     pub k: f64,
     pub positions: Vec<TexelState>,
+    //Held out positions, never used for the gradient
+    pub validation: Vec<TexelState>,
     pub params: Parameters,
+    pub config: TunerConfig,
+    pub selection: ParameterSelection,
+    pub constraints: Constraints,
 }
 pub struct TunerWrapper(UnsafeCell<Tuner>);
 impl TunerWrapper {
@@ -197,26 +56,43 @@ impl TunerWrapper {
 unsafe impl std::marker::Sync for TunerWrapper {}
 
 pub fn update_evaluations(tuner: &mut Tuner) {
-    for pos in tuner.positions.iter_mut() {
+    for pos in tuner.positions.iter_mut().chain(tuner.validation.iter_mut()) {
         pos.eval = pos.trace.evaluate(&tuner.params);
     }
 }
 
-pub fn shuffle_positions(tuner: &mut Tuner) {
-    tuner.positions.shuffle(&mut thread_rng());
+pub fn shuffle_positions(tuner: &mut Tuner, rng: &mut StdRng) {
+    tuner.positions.shuffle(rng);
+}
+
+//The same seed always holds out the same positions, independent of the files they are in and of streaming,
+//which resuming relies on
+pub fn is_validation(g: &GameState, validation_split: f64, seed: u64) -> bool {
+    let mixed = (g.get_hash() ^ seed).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 11;
+    (mixed as f64 / (1u64 << 53) as f64) < validation_split
 }
 
-pub fn add_regularization(gradient: &mut Parameters, parameters: &Parameters, portion: f64) {
+pub fn add_regularization(gradient: &mut Parameters, tuner: &Tuner, portion: f64) {
     for i in 0..gradient.normal[0].len() {
-        gradient.normal[0][i] -= portion * regularization(parameters.normal[0][i]);
-        gradient.normal[1][i] -= portion * regularization(parameters.normal[1][i]);
+        if tuner.selection.normal[i] {
+            gradient.normal[0][i] -= portion * tuner.config.regularization(tuner.params.normal[0][i]);
+            gradient.normal[1][i] -= portion * tuner.config.regularization(tuner.params.normal[1][i]);
+        }
     }
     for i in 0..gradient.special.len() {
-        gradient.special[i] -= portion * regularization(parameters.special[i]);
+        if tuner.selection.special[i] {
+            gradient.special[i] -= portion * tuner.config.regularization(tuner.params.special[i]);
+        }
     }
 }
-pub fn regularization(term: f64) -> f64 {
-    L1_REGULARIZATION * term.signum() + 2. * L2_REGULARIZATION * term
+
+//Steps on the summed gradient of a batch of positions and projects the parameters back onto the constraints
+fn descend(tuner: &mut Tuner, optimizer: &mut OptimizerState, mut gradient: Parameters, positions: usize, lr: f64) {
+    gradient.scale(2. / positions as f64);
+    add_regularization(&mut gradient, tuner, 1.);
+    tuner.constraints.tie_gradient(&mut gradient);
+    optimizer.step(&mut tuner.params, gradient, lr);
+    tuner.constraints.project(&mut tuner.params);
 }
 
 pub fn calculate_gradient(tuner: &mut Tuner, from: usize, to: usize) -> Parameters {
@@ -238,7 +114,7 @@ pub fn calculate_gradient(tuner: &mut Tuner, from: usize, to: usize) -> Paramete
             gradient.normal[1][entry.0 as usize] += start_of_gradient * devaldeg * f64::from(entry.1);
         }
         //Safety
-        if TUNE_ATTACK {
+        if tuner.selection.tune_attack() {
             for i in 0..2 {
                 let devaldg = if i == 0 { devaldmg } else { devaldeg };
                 let attack_knight_white = f64::from(pos.trace.knight_attacked_sq[WHITE]) * tuner.params.special[IDX_KNIGHT_ATTACK_VALUE + i];
@@ -283,10 +159,10 @@ pub fn calculate_gradient(tuner: &mut Tuner, from: usize, to: usize) -> Paramete
                     start_of_gradient * devaldg / 100.0 * tuner.params.special[IDX_ATTACK_WEIGHT + 2 * pos.trace.attackers[WHITE] as usize + i];
                 gradient.special[IDX_ATTACK_WEIGHT + 2 * pos.trace.attackers[BLACK] as usize + i] -=
                     start_of_gradient * devaldg / 100.0 * tuner.params.special[IDX_SAFETY_TABLE + 2 * attacker_value_black as usize + i];
-                gradient.special[IDX_SAFETY_TABLE + 2 * attacker_value_black as usize + i] +=
+                gradient.special[IDX_SAFETY_TABLE + 2 * attacker_value_black as usize + i] -=
                     start_of_gradient * devaldg / 100.0 * tuner.params.special[IDX_ATTACK_WEIGHT + 2 * pos.trace.attackers[BLACK] as usize + i];
                 //Attack constants
-                if TUNE_ATTACK_INDEX {
+                if tuner.selection.tune_attack_index() {
                     //Knight
                     {
                         let c = tuner.params.special[IDX_KNIGHT_ATTACK_VALUE + i];
@@ -403,7 +279,11 @@ pub fn calculate_gradient(tuner: &mut Tuner, from: usize, to: usize) -> Paramete
             }
         }
     }
-    //add_regularization(&mut gradient, &tuner.params, portion);
+    for i in 0..SPECIAL_PARAMS {
+        if !tuner.selection.special[i] {
+            gradient.special[i] = 0.;
+        }
+    }
     gradient
 }
 
@@ -414,73 +294,234 @@ pub fn dsafetytabledconstant(tuner: &Tuner, phase: usize, other: f64, relevant_f
     (safety_table_inc - safety_table_dec) / 2.
 }
 
-pub unsafe fn texel_tuning(tuner: Tuner, threads: usize) {
-    let tuner = Arc::new(TunerWrapper(UnsafeCell::new(tuner)));
-    let mut best_error = average_evaluation_error(tuner.get());
-    println!("Error in epoch 0: {}", best_error);
-    let mut epoch = 0;
-    let lr = START_LEARNING_RATE;
-    let mut adagrad = Parameters::zero();
+//Gradient of positions[from..to], split over the configured threads
+unsafe fn threaded_gradient(tuner: &Arc<TunerWrapper>, from: usize, to: usize) -> Parameters {
+    let threads = tuner.get().config.threads;
+    let mut thread_handles = vec![];
+    for i in 0..threads {
+        let tuner = Arc::clone(tuner);
+        let pos_per_thread = ((to - from) as f64 / threads as f64).ceil() as usize;
+        let (from, to) = ((from + i * pos_per_thread).min(to), (from + (i + 1) * pos_per_thread).min(to));
+        thread_handles.push(
+            thread::Builder::new()
+                .stack_size(12 * 1024 * 1024)
+                .spawn(move || calculate_gradient(tuner.get(), from, to))
+                .expect("Could not spawn thread!"),
+        );
+    }
+    let mut gradient = Parameters::zero();
+    for handle in thread_handles.into_iter() {
+        gradient.add(&handle.join().unwrap(), 1.);
+    }
+    gradient
+}
+
+//Traces a batch of streamed positions, split over the configured threads and kept in order
+unsafe fn trace_batch(tuner: &Arc<TunerWrapper>, batch: Vec<LabelledGameState>) -> Vec<TexelState> {
+    let threads = tuner.get().config.threads;
+    let pos_per_thread = (batch.len() as f64 / threads as f64).ceil() as usize;
+    let mut batch = batch.into_iter();
+    let mut thread_handles = vec![];
+    for _ in 0..threads {
+        let tuner = Arc::clone(tuner);
+        let chunk = batch.by_ref().take(pos_per_thread).collect::<Vec<_>>();
+        thread_handles.push(
+            thread::Builder::new()
+                .stack_size(12 * 1024 * 1024)
+                .spawn(move || {
+                    let parameter = Parameters::default();
+                    let tunable = &tuner.get().selection.normal;
+                    chunk.iter().map(|pos| texel_state(pos, tunable, &parameter)).collect::<Vec<_>>()
+                })
+                .expect("Could not spawn thread!"),
+        );
+    }
+    thread_handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+}
 
+//One pass over the streamed training positions, batch by batch. Steps the optimizer if there is one
+//and returns the average error of the batches before their step, or an error if there were no positions.
+//Like TunerWrapper::get, this must not run while any other reference to the tuner is in use
+unsafe fn streaming_epoch(tuner: &Arc<TunerWrapper>, epoch: usize, mut step: Option<(&mut OptimizerState, f64)>) -> Result<f64, String> {
+    let config = tuner.get().config.clone();
+    let mut stream = PositionStream::new(&config, epoch_rng(config.seed, epoch));
+    let (mut error, mut positions) = (0., 0);
     loop {
-        epoch += 1;
-        println!("Starting epoch {}!", epoch);
-        shuffle_positions(tuner.get());
-        let mut ada_add = Parameters::zero();
-        for batch in 0..=(tuner.get().positions.len() - 1) / BATCH_SIZE {
-            let from = batch * BATCH_SIZE;
-            let to = ((batch + 1) * BATCH_SIZE).min(tuner.get().positions.len());
+        let batch = stream.by_ref().take(config.batch_size).collect::<Vec<_>>();
+        if batch.is_empty() {
+            break;
+        }
+        tuner.get().positions = trace_batch(tuner, batch);
+        let len = tuner.get().positions.len();
+        //Also evaluates the batch with the current parameters
+        let gradient = threaded_gradient(tuner, 0, len);
+        error += evaluation_error(tuner.get().k, &tuner.get().positions) * len as f64;
+        positions += len;
+        if let Some((optimizer, lr)) = step.as_mut() {
+            descend(tuner.get(), optimizer, gradient, len, *lr);
+        }
+    }
+    tuner.get().positions = Vec::new();
+    if positions == 0 {
+        return Err("There are no training positions to stream".to_owned());
+    }
+    Ok(error / positions as f64)
+}
 
-            let mut thread_handles = vec![];
-            for i in 0..threads {
-                let tuner = Arc::clone(&tuner);
-                let pos_per_thread = ((to - from) as f64 / threads as f64).ceil() as usize;
-                let (from, to) = (from + i * pos_per_thread, (from + (i + 1) * pos_per_thread).min(to));
-                thread_handles.push(
-                    thread::Builder::new()
-                        .stack_size(12 * 1024 * 1024)
-                        .spawn(move || calculate_gradient(tuner.get(), from, to))
-                        .expect("Could not spawn thread!"),
-                );
-            }
-            let mut gradient = Parameters::zero();
-            for handle in thread_handles.into_iter() {
-                gradient.add(&handle.join().unwrap(), 1.);
-            }
-            gradient.scale(2. / ((to - from) as f64));
-            ada_add.add(&gradient, 1.);
+fn epoch_rng(seed: u64, epoch: usize) -> StdRng {
+    StdRng::seed_from_u64(seed ^ (epoch as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
 
-            let mut ada_lr = adagrad.clone();
-            ada_lr.add_scalar(1e-6);
-            ada_lr.sqrt();
-            gradient.mul_inverse_other(&ada_lr);
-            tuner.get().params.add(&gradient, lr);
+//Continues from the checkpoint if there is one, the positions have to be in the order they were loaded and split in
+pub unsafe fn texel_tuning(tuner: Tuner, checkpoint: Option<Box<Checkpoint>>) {
+    let tuner = Arc::new(TunerWrapper(UnsafeCell::new(tuner)));
+    let config = tuner.get().config.clone();
+    let mut log = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(checkpoint.is_some())
+        .truncate(checkpoint.is_none())
+        .open(&config.log_file)
+        .expect("Could not open log file");
+    if checkpoint.is_none() {
+        writeln!(log, "epoch,learning_rate,train_error,validation_error,time_ms").expect("Could not write log file");
+    }
+    let start = Instant::now();
+
+    let (mut epoch, mut best_error, mut epochs_without_improvement, mut optimizer) = match checkpoint {
+        Some(checkpoint) => {
+            let checkpoint = *checkpoint;
+            tuner.get().params = checkpoint.params;
+            tuner.get().k = checkpoint.k;
+            update_evaluations(tuner.get());
+            //Replays the shuffles of the finished epochs
+            for epoch in 1..=checkpoint.epoch {
+                shuffle_positions(tuner.get(), &mut epoch_rng(config.seed, epoch));
+            }
+            println!("Resuming after epoch {} with best error {}", checkpoint.epoch, checkpoint.best_error);
+            (checkpoint.epoch, checkpoint.best_error, checkpoint.epochs_without_improvement, checkpoint.optimizer)
         }
-        ada_add.square();
-        adagrad.add(&ada_add, 1.);
+        None => {
+            //The default parameters don't have to satisfy the constraints
+            let t = tuner.get();
+            t.constraints.project(&mut t.params);
+            update_evaluations(t);
+            let train_error = if config.streaming {
+                match streaming_epoch(&tuner, 0, None) {
+                    Ok(error) => error,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                }
+            } else {
+                average_evaluation_error(tuner.get())
+            };
+            let validation_error = average_validation_error(tuner.get());
+            println!("Error in epoch 0: {}{}", train_error, validation_report(validation_error));
+            (0, validation_error.unwrap_or(train_error), 0, OptimizerState::new(&config))
+        }
+    };
+    let save_checkpoint = |tuner: &Tuner, epoch: usize, best_error: f64, epochs_without_improvement: usize, optimizer: &OptimizerState| {
+        let checkpoint = Checkpoint {
+            config: config.clone(),
+            epoch,
+            k: tuner.k,
+            best_error,
+            epochs_without_improvement,
+            params: tuner.params.clone(),
+            optimizer: optimizer.clone(),
+        };
+        let (checkpoint_path, params_path) = (format!("{}checkpoint.bin", config.output_prefix), format!("{}params.rs", config.output_prefix));
+        checkpoint.save(&checkpoint_path).expect("Could not write checkpoint");
+        tuner.params.write_to_file(&params_path);
+        println!("Saved checkpoint of epoch {} in {} and {}", epoch, checkpoint_path, params_path);
+    };
+    let mut stopped = false;
+    while config.epochs == 0 || epoch < config.epochs {
+        epoch += 1;
+        let lr = config.lr_schedule.learning_rate(config.learning_rate, epoch, config.epochs);
+        println!("Starting epoch {}, learning rate {}!", epoch, lr);
+        //Streaming reports the error of each batch before its step, as the positions aren't kept
+        let streamed_error = if config.streaming {
+            match streaming_epoch(&tuner, epoch, Some((&mut optimizer, lr))) {
+                Ok(error) => Some(error),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        } else {
+            shuffle_positions(tuner.get(), &mut epoch_rng(config.seed, epoch));
+            for batch in 0..=(tuner.get().positions.len() - 1) / config.batch_size {
+                let from = batch * config.batch_size;
+                let to = ((batch + 1) * config.batch_size).min(tuner.get().positions.len());
+                let gradient = threaded_gradient(&tuner, from, to);
+                descend(tuner.get(), &mut optimizer, gradient, to - from, lr);
+            }
+            None
+        };
 
         update_evaluations(tuner.get());
-        let error = average_evaluation_error(tuner.get());
-        println!("Error in epoch {}: {}", epoch, error);
+        let train_error = streamed_error.unwrap_or_else(|| average_evaluation_error(tuner.get()));
+        let validation_error = average_validation_error(tuner.get());
+        println!("Error in epoch {}: {}{}", epoch, train_error, validation_report(validation_error));
+        writeln!(
+            log,
+            "{},{},{},{},{}",
+            epoch,
+            lr,
+            train_error,
+            validation_error.map(|error| error.to_string()).unwrap_or_default(),
+            start.elapsed().as_millis()
+        )
+        .expect("Could not write log file");
+        let error = validation_error.unwrap_or(train_error);
         if error < best_error {
             best_error = error;
-            tuner.get().params.write_to_file(&format!("{}tunebest.txt", PARAM_FILE));
+            epochs_without_improvement = 0;
+            tuner.get().params.write_to_file(&format!("{}tunebest.txt", config.output_prefix));
             println!("Saved new best params in tunebest.txt");
+        } else {
+            epochs_without_improvement += 1;
+            stopped = config.early_stopping_patience > 0 && epochs_without_improvement >= config.early_stopping_patience;
         }
-        //Save progress
-        if (epoch + 1) % 10 == 0 {
-            tuner.get().params.write_to_file(&format!("{}tune{}.txt", PARAM_FILE, epoch + 1));
-            println!("Saved general progress params in tune.txt");
+        if stopped || (config.checkpoint_every > 0 && epoch % config.checkpoint_every == 0) {
+            save_checkpoint(tuner.get(), epoch, best_error, epochs_without_improvement, &optimizer);
+        }
+        if stopped {
+            println!("No improvement for {} epochs, stopping", epochs_without_improvement);
+            break;
         }
     }
+    if !stopped && (config.checkpoint_every == 0 || epoch % config.checkpoint_every != 0) {
+        save_checkpoint(tuner.get(), epoch, best_error, epochs_without_improvement, &optimizer);
+    }
+    println!("Best error: {}", best_error);
 }
 
-pub fn average_evaluation_error(tuner: &Tuner) -> f64 {
+fn validation_report(validation_error: Option<f64>) -> String {
+    validation_error.map(|error| format!(", validation error: {}", error)).unwrap_or_default()
+}
+
+pub fn evaluation_error(k: f64, positions: &[TexelState]) -> f64 {
     let mut res = 0.;
-    for pos in &tuner.positions {
-        res += (pos.label as f64 - sigmoid(tuner.k, pos.eval)).powf(2.0);
+    for pos in positions {
+        res += (pos.label as f64 - sigmoid(k, pos.eval)).powf(2.0);
+    }
+    res / positions.len() as f64
+}
+
+pub fn average_evaluation_error(tuner: &Tuner) -> f64 {
+    evaluation_error(tuner.k, &tuner.positions)
+}
+
+pub fn average_validation_error(tuner: &Tuner) -> Option<f64> {
+    if tuner.validation.is_empty() {
+        None
+    } else {
+        Some(evaluation_error(tuner.k, &tuner.validation))
     }
-    res / tuner.positions.len() as f64
 }
 
 pub fn minimize_evaluation_error_fork(tuner: &mut Tuner) -> f64 {
@@ -489,19 +530,22 @@ pub fn minimize_evaluation_error_fork(tuner: &mut Tuner) -> f64 {
     println!("Error in epoch 0: {}", best_error);
     let mut epoch = 0;
     let mut lr = 0.3;
+    //Shuffles an order instead of the positions, so that tuning starts from the same order with or without optimizing k
+    let mut order = (0..tuner.positions.len()).collect::<Vec<usize>>();
+    let mut rng = StdRng::seed_from_u64(tuner.config.seed);
     loop {
         epoch += 1;
-        //Shuffle positions
-        shuffle_positions(tuner);
+        order.shuffle(&mut rng);
         //Calculate dE/dk
-        for batch in 0..=(tuner.positions.len() - 1) / BATCH_SIZE {
-            let from = batch * BATCH_SIZE;
-            let mut to = (batch + 1) * BATCH_SIZE;
+        for batch in 0..=(tuner.positions.len() - 1) / tuner.config.batch_size {
+            let from = batch * tuner.config.batch_size;
+            let mut to = (batch + 1) * tuner.config.batch_size;
             if to > tuner.positions.len() {
                 to = tuner.positions.len();
             }
             let mut dedk = 0.;
-            for pos in &tuner.positions[from..to] {
+            for &i in &order[from..to] {
+                let pos = &tuner.positions[i];
                 let eval = pos.eval;
                 dedk += (pos.label as f64 - sigmoid(tuner.k, eval)) * dsigmoiddk(tuner.k, eval);
             }
@@ -1,34 +1,72 @@
 use super::TexelState;
-use crate::TUNABLE_PARAM;
-use core_sdk::evaluation::parameters::Parameters;
+use crate::packed;
+use core_sdk::evaluation::parameters::{normal_parameters::NORMAL_PARAMS, Parameters};
+use core_sdk::move_generation::movegen::MoveList;
 use core_sdk::{board_representation::game_state::GameState, evaluation::eval_game_state};
+use extended_sdk::pgn::pgn_reader::{GameParser, PGNParser, ParsedGame};
+use rand::{seq::index, Rng};
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter, Result};
-use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
 pub enum FileFormatSupported {
+    #[serde(rename = "own")]
     OwnEncoding,
     EPD,
     PGN,
+    //See crate::packed
+    #[serde(rename = "bin")]
+    Packed,
 }
 
 pub struct LabelledGameState {
     pub game_state: GameState,
     pub label: f32,
+    //Search score in centipawns from white's point of view, if the dataset has one
+    pub score: Option<i16>,
 }
 
+//Label distribution of a set of positions. Games are only counted for pgn input
 pub struct Statistics {
     pub games: usize,
+    //Unfinished games, games with an illegal move and games from a set up position
+    pub skipped_games: usize,
+    pub positions: usize,
     pub white_wins: usize,
     pub black_wins: usize,
     pub draws: usize,
 }
 
+impl Statistics {
+    pub fn from_positions(positions: &[LabelledGameState]) -> Self {
+        let mut stats = Statistics::default();
+        for pos in positions {
+            stats.add(pos.label);
+        }
+        stats
+    }
+
+    pub fn add(&mut self, label: f32) {
+        self.positions += 1;
+        if (label - 1.0).abs() < std::f32::EPSILON {
+            self.white_wins += 1;
+        } else if label == 0.0 {
+            self.black_wins += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+}
+
 impl Default for Statistics {
     fn default() -> Self {
         Statistics {
             games: 0,
+            skipped_games: 0,
+            positions: 0,
             white_wins: 0,
             black_wins: 0,
             draws: 0,
@@ -38,20 +76,27 @@ impl Default for Statistics {
 
 impl Display for Statistics {
     fn fmt(&self, formatter: &mut Formatter) -> Result {
+        let percentage = |count: usize| 100. * count as f64 / self.positions.max(1) as f64;
         let mut res_str: String = String::new();
-        res_str.push_str(&format!("Games: {}\n", self.games));
-        res_str.push_str(&format!("White-Wins: {}\n", self.white_wins));
-        res_str.push_str(&format!("Black-Wins: {}\n", self.black_wins));
-        res_str.push_str(&format!("Draws: {}\n", self.draws));
+        if self.games > 0 {
+            res_str.push_str(&format!("Games: {}\n", self.games));
+        }
+        if self.skipped_games > 0 {
+            res_str.push_str(&format!("Skipped games: {}\n", self.skipped_games));
+        }
+        res_str.push_str(&format!("Positions: {}\n", self.positions));
+        res_str.push_str(&format!("White-Wins: {} ({:.2}%)\n", self.white_wins, percentage(self.white_wins)));
+        res_str.push_str(&format!("Black-Wins: {} ({:.2}%)\n", self.black_wins, percentage(self.black_wins)));
+        res_str.push_str(&format!("Draws: {} ({:.2}%)\n", self.draws, percentage(self.draws)));
         write!(formatter, "{}", res_str)
     }
 }
 
-pub fn save_positions(to_file: &str, positions: &[LabelledGameState]) {
-    let mut res_str = String::new();
+pub fn write_positions<W: Write>(writer: &mut W, positions: &[LabelledGameState]) -> io::Result<()> {
     for pos in positions {
-        res_str.push_str(&format!(
-            "{} |{}\n",
+        writeln!(
+            writer,
+            "{} |{}{}",
             pos.game_state.to_fen(),
             if (pos.label - 1.0).abs() < std::f32::EPSILON {
                 "White"
@@ -59,77 +104,202 @@ pub fn save_positions(to_file: &str, positions: &[LabelledGameState]) {
                 "Black"
             } else {
                 "Draw"
-            }
-        ));
+            },
+            pos.score.map(|score| format!("|{}", score)).unwrap_or_default()
+        )?;
     }
-    fs::write(to_file, res_str).expect("Unable to write positions");
+    Ok(())
+}
+
+pub fn save_positions(to_file: &str, positions: &[LabelledGameState]) {
+    let mut writer = BufWriter::new(File::create(to_file).expect("Unable to write positions"));
+    write_positions(&mut writer, positions).and_then(|_| writer.flush()).expect("Unable to write positions");
+}
+
+//Traces the evaluation and collapses all terms that aren't tuned
+pub fn texel_state(state: &LabelledGameState, tunable_params: &[bool; NORMAL_PARAMS], parameter: &Parameters) -> TexelState {
+    let eval = eval_game_state(&state.game_state);
+    let trace = eval.trace.collapse(tunable_params, parameter);
+    let eval = trace.evaluate(parameter);
+    TexelState { label: state.label, eval, trace }
+}
+
+//How positions are sampled from a pgn game, see sample_game
+#[derive(Clone, Copy)]
+pub struct GameSampling {
+    pub per_game: usize,
+    //Opening plies that are never sampled
+    pub skip_plies: usize,
+    //Positions after the move with the first [%eval] of at least this many centipawns are left out
+    pub adjudication: Option<i16>,
+    //Score positions with the [%eval] comment after the move leading to them
+    pub evals: bool,
+}
+
+impl Default for GameSampling {
+    fn default() -> Self {
+        GameSampling {
+            per_game: 10,
+            skip_plies: 16,
+            adjudication: None,
+            evals: false,
+        }
+    }
+}
+
+//Samples up to per_game positions of a finished game in the order they were played, labelled with the game result
+pub fn sample_game<R: Rng + ?Sized>(game: &ParsedGame, sampling: &GameSampling, rng: &mut R) -> Vec<LabelledGameState> {
+    //The game was as good as over after the move with the first decisive eval
+    let end = sampling
+        .adjudication
+        .and_then(|bound| game.evals.iter().position(|eval| eval.map(|eval| eval.abs() >= bound).unwrap_or(false)))
+        .map(|index| index + 1)
+        .unwrap_or_else(|| game.states.len());
+    let label = (game.result as f32 + 1.) / 2.;
+    let plies = end.saturating_sub(sampling.skip_plies);
+    let mut indices = index::sample(rng, plies, sampling.per_game.min(plies)).into_vec();
+    indices.sort_unstable();
+    indices
+        .into_iter()
+        .map(|index| index + sampling.skip_plies)
+        .map(|ply| LabelledGameState {
+            game_state: game.states[ply].clone(),
+            label,
+            //The eval after move n belongs to the position at ply n + 1
+            score: if sampling.evals && ply > 0 { game.evals[ply - 1] } else { None },
+        })
+        .collect()
+}
+
+enum PositionSource {
+    Lines(BufReader<File>),
+    //Every position of a game is labelled with its result
+    Games(GameParser, Vec<LabelledGameState>),
+    Packed(BufReader<File>),
 }
 
 pub struct PositionLoader {
-    reader: BufReader<File>,
+    source: PositionSource,
     file_format: FileFormatSupported,
     parameter: Parameters,
+    pub games: usize,
+    pub skipped_games: usize,
+    //Positions that were skipped because of an invalid score column or a corrupted packed record
+    pub invalid_positions: usize,
 }
 impl PositionLoader {
     pub fn new(from_file: &str, file_format: FileFormatSupported) -> Self {
+        let mut reader = BufReader::new(File::open(from_file).expect("Could not open file"));
+        let source = match file_format {
+            FileFormatSupported::PGN => {
+                let parser = GameParser {
+                    pgn_parser: PGNParser { reader },
+                    is_opening: false,
+                    opening_load_untilply: 0,
+                    move_list: MoveList::default(),
+                };
+                PositionSource::Games(parser, Vec::new())
+            }
+            FileFormatSupported::Packed => {
+                packed::read_magic(&mut reader).unwrap_or_else(|e| panic!("Could not read {}: {}", from_file, e));
+                PositionSource::Packed(reader)
+            }
+            _ => PositionSource::Lines(reader),
+        };
         PositionLoader {
-            reader: BufReader::new(File::open(from_file).expect("Could not open file")),
+            source,
             file_format,
             parameter: Parameters::default(),
+            games: 0,
+            skipped_games: 0,
+            invalid_positions: 0,
         }
     }
     pub fn next_position(&mut self) -> Option<LabelledGameState> {
-        let mut line = String::new();
-        self.reader.read_line(&mut line).unwrap();
-        if let FileFormatSupported::OwnEncoding = self.file_format {
-            if !line.contains('|') {
-                return None;
+        let reader = match &mut self.source {
+            PositionSource::Lines(reader) => reader,
+            PositionSource::Games(parser, pending) => {
+                while pending.is_empty() {
+                    let (_, states, result) = parser.next()?;
+                    //Games with an invalid movetext, without a result or from a set up position are skipped
+                    if result == -2 {
+                        self.skipped_games += 1;
+                        continue;
+                    }
+                    self.games += 1;
+                    let label = (result as f32 + 1.) / 2.;
+                    pending.extend(states.into_iter().rev().map(|game_state| LabelledGameState { game_state, label, score: None }));
+                }
+                return pending.pop();
             }
-            let fen_split = line.split('|').collect::<Vec<&str>>();
-            let game_result = if fen_split[1].contains("Black") {
-                0.0
-            } else if fen_split[1].contains("White") {
-                1.0
-            } else if fen_split[1].contains("Draw") {
-                0.5
-            } else {
-                panic!(format!("Invalid split {}", fen_split[1]));
-            };
-            let state = GameState::from_fen(fen_split[0]);
-
-            return Some(LabelledGameState {
-                game_state: state,
-                label: game_result,
-            });
-        } else if let FileFormatSupported::EPD = self.file_format {
-            if line.is_empty() {
-                return None;
+            PositionSource::Packed(reader) => loop {
+                match packed::read_packed_position(reader) {
+                    Ok(pos) => return pos,
+                    Err(ref e) if e.kind() == io::ErrorKind::InvalidData => self.invalid_positions += 1,
+                    Err(e) => panic!("Could not read packed position: {}", e),
+                }
+            },
+        };
+        //Lines with an invalid score are skipped
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let FileFormatSupported::OwnEncoding = self.file_format {
+                if !line.contains('|') {
+                    return None;
+                }
+                let fen_split = line.split('|').collect::<Vec<&str>>();
+                let game_result = if fen_split[1].contains("Black") {
+                    0.0
+                } else if fen_split[1].contains("White") {
+                    1.0
+                } else if fen_split[1].contains("Draw") {
+                    0.5
+                } else {
+                    panic!(format!("Invalid split {}", fen_split[1]));
+                };
+                let state = GameState::from_fen(fen_split[0]);
+                //An optional third column holds the search score
+                let score = match fen_split.get(2).map(|score| score.trim().parse::<i16>()).transpose() {
+                    Ok(score) => score,
+                    Err(_) => {
+                        self.invalid_positions += 1;
+                        continue;
+                    }
+                };
+
+                return Some(LabelledGameState {
+                    game_state: state,
+                    label: game_result,
+                    score,
+                });
+            } else if let FileFormatSupported::EPD = self.file_format {
+                if line.is_empty() {
+                    return None;
+                }
+                let (fen, result) = line.rsplit_once(' ').unwrap();
+                let game_result = if result.contains("1.0") || result.contains("1-0") {
+                    1.0
+                } else if result.contains("0.5") || result.contains("1/2-1/2") {
+                    0.5
+                } else {
+                    0.0
+                };
+                let state = GameState::from_fen(fen);
+                return Some(LabelledGameState {
+                    game_state: state,
+                    label: game_result,
+                    score: None,
+                });
             }
-            let (fen, result) = line.rsplit_once(' ').unwrap();
-            let game_result = if result.contains("1.0") || result.contains("1-0") {
-                1.0
-            } else if result.contains("0.5") || result.contains("1/2-1/2") {
-                0.5
-            } else {
-                0.0
-            };
-            let state = GameState::from_fen(fen);
-            return Some(LabelledGameState {
-                game_state: state,
-                label: game_result,
-            });
+            return None;
         }
-        None
     }
 
-    pub fn next_texel_position(&mut self) -> Option<TexelState> {
+    pub fn next_texel_position(&mut self, tunable_params: &[bool; NORMAL_PARAMS]) -> Option<TexelState> {
         let state = self.next_position();
         if state.is_some() {
-            let state = state.unwrap();
-            let eval = eval_game_state(&state.game_state);
-            let trace = eval.trace.collapse(&TUNABLE_PARAM, &self.parameter);
-            let eval = trace.evaluate(&self.parameter);
-            return Some(TexelState { label: state.label, eval, trace });
+            return Some(texel_state(&state.unwrap(), tunable_params, &self.parameter));
         }
         None
     }
@@ -140,9 +310,116 @@ impl PositionLoader {
         }
     }
 
-    pub fn load_texel_positions(&mut self, buf: &mut Vec<TexelState>) {
-        while let Some(pos) = self.next_texel_position() {
+    pub fn load_texel_positions(&mut self, buf: &mut Vec<TexelState>, tunable_params: &[bool; NORMAL_PARAMS]) {
+        while let Some(pos) = self.next_texel_position(tunable_params) {
             buf.push(pos);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use extended_sdk::pgn::pgn_reader::parse_game;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::fs;
+
+    #[test]
+    fn pgn_loading_test() {
+        let path = std::env::temp_dir().join("fabchess_pgn_loading_test.pgn");
+        let pgn = "[Event \"?\"]\n[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1\n\n[Event \"?\"]\n[Result \"1-0\"]\n\n1. e4 e5 2. Ke3 1-0\n\n\
+                   [Event \"?\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\n1. e4 Kd7 1-0\n\n[Event \"?\"]\n[Result \"1/2-1/2\"]\n\n1. e4 e5 1/2-1/2\n";
+        fs::write(&path, pgn).unwrap();
+        let mut positions = Vec::new();
+        let mut loader = PositionLoader::new(path.to_str().unwrap(), FileFormatSupported::PGN);
+        loader.load_positions(&mut positions);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((loader.games, loader.skipped_games), (2, 2));
+        assert_eq!(positions.len(), 5 + 3);
+        assert_eq!(positions[0].game_state.to_fen(), GameState::standard().to_fen());
+        assert!(positions[4].game_state.in_check());
+        assert_eq!(positions[7].game_state.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+
+        let mut stats = Statistics::from_positions(&positions);
+        stats.games = loader.games;
+        stats.skipped_games = loader.skipped_games;
+        assert_eq!((stats.positions, stats.white_wins, stats.black_wins, stats.draws), (8, 0, 5, 3));
+        assert!(format!("{}", stats).contains("Skipped games: 2\n"));
+        assert!(format!("{}", stats).contains("Draws: 3 (37.50%)"));
+    }
+
+    #[test]
+    fn invalid_score_test() {
+        let path = std::env::temp_dir().join("fabchess_invalid_score_test.txt");
+        let lines =
+            "8/5k2/8/8/8/8/1K6/8 w - - 0 1 |White|35\n8/5k2/8/8/8/8/1K6/8 b - - 0 1 |Black|0.35\n8/5k2/8/8/8/8/1K6/8 w - - 0 1 |Draw\n8/5k2/8/8/8/8/1K6/8 b - - 0 1 |Draw|\n";
+        fs::write(&path, lines).unwrap();
+        let mut positions = Vec::new();
+        let mut loader = PositionLoader::new(path.to_str().unwrap(), FileFormatSupported::OwnEncoding);
+        loader.load_positions(&mut positions);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loader.invalid_positions, 2);
+        assert_eq!(
+            positions.iter().map(|position| (position.label, position.score)).collect::<Vec<_>>(),
+            vec![(1., Some(35)), (0.5, None)]
+        );
+    }
+
+    #[test]
+    fn sample_game_test() {
+        let mut move_list = MoveList::default();
+        let game = parse_game(
+            "1. e4 {[%eval 0.3]} e5 {[%eval 0.2]} 2. Nf3 {[%eval 0.4]} Nc6 {[%eval 0.3]} 3. Bb5 {[%eval 0.2]} a6 {[%eval 2.5]} 4. Ba4 {[%eval 0.3]} Nf6 1-0",
+            &mut move_list,
+            None,
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let all = GameSampling {
+            per_game: 100,
+            skip_plies: 0,
+            ..GameSampling::default()
+        };
+        let sampled = sample_game(&game, &all, &mut rng);
+        assert_eq!(sampled.len(), 9);
+        assert!(sampled.iter().all(|position| position.label == 1. && position.score.is_none()));
+        for (position, state) in sampled.iter().zip(game.states.iter()) {
+            assert_eq!(position.game_state.to_fen(), state.to_fen());
+        }
+
+        //Sampled positions are distinct and keep the order of the game
+        let some = GameSampling {
+            per_game: 3,
+            skip_plies: 2,
+            ..GameSampling::default()
+        };
+        let sampled = sample_game(&game, &some, &mut rng);
+        assert_eq!(sampled.len(), 3);
+        let plies = sampled
+            .iter()
+            .map(|position| game.states.iter().position(|state| state.to_fen() == position.game_state.to_fen()).unwrap())
+            .collect::<Vec<_>>();
+        assert!(plies[0] >= 2 && plies[0] < plies[1] && plies[1] < plies[2]);
+
+        //The first eval of at least 2.00 comes after 3... a6, the position after it is left out
+        let adjudicated = GameSampling {
+            adjudication: Some(200),
+            evals: true,
+            ..all
+        };
+        let sampled = sample_game(&game, &adjudicated, &mut rng);
+        assert_eq!(sampled.len(), 6);
+        assert_eq!(sampled[5].game_state.to_fen(), game.states[5].to_fen());
+        assert_eq!(
+            sampled.iter().map(|position| position.score).collect::<Vec<_>>(),
+            vec![None, Some(30), Some(20), Some(40), Some(30), Some(20)]
+        );
+        assert_eq!(sample_game(&game, &GameSampling { skip_plies: 6, ..adjudicated }, &mut rng).len(), 0);
+
+        let draw = parse_game("1. e4 e5 2. Nf3 Nc6 1/2-1/2", &mut move_list, None);
+        let sampled = sample_game(&draw, &all, &mut rng);
+        assert_eq!(sampled.len(), 5);
+        assert!(sampled.iter().all(|position| position.label == 0.5));
+    }
+}
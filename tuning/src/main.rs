@@ -1,67 +1,241 @@
+use std::sync::Arc;
 use std::thread;
 use std::time::Instant;
+use tuning::config::group_names;
+use tuning::constraints::Constraints;
+use tuning::loading::texel_state;
 use tuning::*;
 
+const USAGE: &str = "Usage: tuning [options] [<position file>...]
+Texel tunes the evaluation parameters. Options override the values of the config file.
+Options:
+  --config <file>        Json config, see tuning/src/config.rs
+  --resume <checkpoint>  Continue a run from its checkpoint.bin, with the config stored in it
+  --format own|epd|bin   Position file format, default epd
+  --threads <n>          Default 1
+  --k <f>                Sigmoid scaling, default 1.1155
+  --optimize-k           Optimize k before tuning
+  --batch-size <n>       Default 5000000
+  --lr <f>               Learning rate, default 2.0
+  --l1 <f>               L1 regularization, default 0
+  --l2 <f>               L2 regularization, default 0
+  --groups <a,b,...>     Parameter groups to tune, default all
+  --out <prefix>         Prefix of the written parameter and checkpoint files
+  --optimizer <name>     sgd, adagrad or adam, default adagrad
+  --epochs <n>           Default 0, tunes until stopped
+  --schedule <schedule>  constant, step:<every>:<factor>, exponential:<decay> or cosine:<min lr>, default constant
+  --validation <f>       Part of the positions held out for validation, default 0
+  --patience <n>         Stop after this many epochs without improvement, default 0 (off)
+  --log <file>           Csv log of the errors per epoch, default tuning_log.csv
+  --seed <n>             Seed of the validation split and shuffling, default 0
+  --checkpoint-every <n> Epochs between checkpoints, default 10
+  --streaming            Read the positions from disk in every epoch instead of holding them in memory
+  --shuffle-buffer <n>   Positions shuffled at once while streaming, default 1000000
+  --check-gradient <n>   Compare the gradient with finite differences on n positions instead of tuning
+  --list-groups          Print all parameter groups";
+
+fn parse<T: std::str::FromStr>(option: &str, value: Option<String>) -> T {
+    value.and_then(|v| v.parse::<T>().ok()).unwrap_or_else(|| panic!("Invalid value for {}\n{}", option, USAGE))
+}
+
+fn take_option(args: &mut Vec<String>, option: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == option)?;
+    let value = args.get(index + 1).cloned().unwrap_or_else(|| panic!("Invalid value for {}\n{}", option, USAGE));
+    args.drain(index..index + 2);
+    Some(value)
+}
+
 pub fn main() {
-    let mut args = std::env::args();
-    let threads = if args.nth(1) == Some("t".to_owned()) {
-        args.nth(0).unwrap().parse::<usize>().unwrap()
-    } else {
-        1
-    };
-    let t = thread::Builder::new()
-        .stack_size(12 * 1024 * 1024)
-        .spawn(move || {
-            actual_main(threads);
-        })
-        .expect("Couldn't start thread");
+    //Parameters and checkpoints are too large for the main thread's stack
+    let t = thread::Builder::new().stack_size(32 * 1024 * 1024).spawn(run).expect("Couldn't start thread");
     t.join().expect("Could not join thread");
 }
 
-pub fn actual_main(threads: usize) {
+fn run() {
+    let mut args = std::env::args().skip(1).collect::<Vec<String>>();
+    //The config is read first, so that all other options override it
+    let config_file = take_option(&mut args, "--config");
+    let checkpoint = take_option(&mut args, "--resume").map(|path| Box::new(Checkpoint::load(&path).unwrap_or_else(|e| panic!("Could not read checkpoint {}: {}", path, e))));
+    let mut config = match (&checkpoint, config_file) {
+        (Some(_), Some(_)) => {
+            println!("A resumed run uses the config of its checkpoint, --config can't be used with --resume");
+            return;
+        }
+        (Some(checkpoint), None) => checkpoint.config.clone(),
+        (None, Some(path)) => TunerConfig::load(&path).unwrap_or_else(|e| panic!("Could not read config {}: {}", path, e)),
+        (None, None) => TunerConfig::default(),
+    };
+    let mut position_files = Vec::new();
+    let mut gradient_check = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                config.format = match args.next().as_deref() {
+                    Some("own") => FileFormatSupported::OwnEncoding,
+                    Some("epd") => FileFormatSupported::EPD,
+                    Some("bin") => FileFormatSupported::Packed,
+                    _ => panic!("Invalid value for --format\n{}", USAGE),
+                }
+            }
+            "--threads" => config.threads = parse(&arg, args.next()),
+            "--k" => config.k = parse(&arg, args.next()),
+            "--optimize-k" => config.optimize_k = true,
+            "--batch-size" => config.batch_size = parse(&arg, args.next()),
+            "--lr" => config.learning_rate = parse(&arg, args.next()),
+            "--l1" => config.l1_regularization = parse(&arg, args.next()),
+            "--l2" => config.l2_regularization = parse(&arg, args.next()),
+            "--groups" => config.groups = parse::<String>(&arg, args.next()).split(',').map(|group| group.trim().to_owned()).collect(),
+            "--out" => config.output_prefix = parse(&arg, args.next()),
+            "--optimizer" => config.optimizer = parse(&arg, args.next()),
+            "--epochs" => config.epochs = parse(&arg, args.next()),
+            "--schedule" => config.lr_schedule = parse(&arg, args.next()),
+            "--validation" => config.validation_split = parse(&arg, args.next()),
+            "--patience" => config.early_stopping_patience = parse(&arg, args.next()),
+            "--log" => config.log_file = parse(&arg, args.next()),
+            "--seed" => config.seed = parse(&arg, args.next()),
+            "--checkpoint-every" => config.checkpoint_every = parse(&arg, args.next()),
+            "--streaming" => config.streaming = true,
+            "--shuffle-buffer" => config.shuffle_buffer = parse(&arg, args.next()),
+            "--check-gradient" => gradient_check = Some(parse::<usize>(&arg, args.next())),
+            "--list-groups" => {
+                println!("{}", group_names().join("\n"));
+                return;
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => position_files.push(arg),
+        }
+    }
+    if !position_files.is_empty() {
+        config.position_files = position_files;
+    }
+    if config.position_files.is_empty() || config.threads == 0 || config.batch_size == 0 {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(e) = config.validate() {
+        println!("{}", e);
+        return;
+    }
+    let selection = match config.selection() {
+        Ok(selection) => selection,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let constraints = match config.constraints(&selection) {
+        Ok(constraints) => constraints,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    println!("Config:\n{}", config);
+    actual_main(config, selection, constraints, checkpoint, gradient_check);
+}
+
+pub fn actual_main(config: TunerConfig, selection: ParameterSelection, constraints: Constraints, checkpoint: Option<Box<Checkpoint>>, gradient_check: Option<usize>) {
     //Step 1. Load all positions from a file. Those positions should already be the q-searched positions.
-    let position_files = [
-        "D:/FenCollection/Andrews/E12.33-1M-D12-Resolved.epd",
-        "D:/FenCollection/Andrews/E12.41-1M-D12-Resolved.epd",
-        "D:/FenCollection/Andrews/E12.46FRC-1250k-D12-1s-Resolved.epd",
-        "D:/FenCollection/Andrews/E12.52-1M-D12-Resolved.epd",
-    ];
-    let position_files = ["D:/FenCollection/Andrews/a.epd"];
+    let threads = config.threads;
     let now = Instant::now();
     let mut positions = Vec::with_capacity(1);
+    let selection = Arc::new(selection);
     let mut thread_handles = Vec::with_capacity(threads);
     for i in 0..threads {
-        let pos_per_thread = (position_files.len() as f64 / threads as f64).ceil() as usize;
-        let my_pos = ((i * pos_per_thread).min(position_files.len()), ((i + 1) * pos_per_thread).min(position_files.len()));
-        let my_pos = position_files[my_pos.0..my_pos.1].to_vec();
-        println!("i: {}, {:?}", i, my_pos);
+        let pos_per_thread = (config.position_files.len() as f64 / threads as f64).ceil() as usize;
+        let my_pos = (
+            (i * pos_per_thread).min(config.position_files.len()),
+            ((i + 1) * pos_per_thread).min(config.position_files.len()),
+        );
+        let my_pos = config.position_files[my_pos.0..my_pos.1].to_vec();
+        let (format, selection) = (config.format, Arc::clone(&selection));
+        let (streaming, validation_split, seed) = (config.streaming, config.validation_split, config.seed);
         thread_handles.push(
             thread::Builder::new()
                 .stack_size(12 * 1024 * 1024)
                 .spawn(move || {
                     let mut positions: Vec<TexelState> = Vec::with_capacity(1);
-                    for &file in my_pos.iter() {
-                        tuning::loading::PositionLoader::new(file, FileFormatSupported::EPD).load_texel_positions(&mut positions);
+                    let mut validation: Vec<TexelState> = Vec::new();
+                    let parameter = Parameters::default();
+                    for file in my_pos.iter() {
+                        if streaming && validation_split == 0. {
+                            break;
+                        }
+                        let mut loader = tuning::loading::PositionLoader::new(file, format);
+                        //With streaming only the validation positions are held in memory
+                        while let Some(pos) = loader.next_position() {
+                            if is_validation(&pos.game_state, validation_split, seed) {
+                                validation.push(texel_state(&pos, &selection.normal, &parameter));
+                            } else if !streaming {
+                                positions.push(texel_state(&pos, &selection.normal, &parameter));
+                            }
+                        }
                     }
-                    positions
+                    (positions, validation)
                 })
                 .expect("Couldn't start thread"),
         );
     }
+    let mut validation = Vec::new();
     for handle in thread_handles.into_iter() {
-        positions.extend(handle.join().unwrap());
+        let (thread_positions, thread_validation) = handle.join().unwrap();
+        positions.extend(thread_positions);
+        validation.extend(thread_validation);
     }
-    println!("Loaded {} positions!", positions.len());
+    println!("Loaded {} positions!", positions.len() + validation.len());
     println!("Took {}ms", Instant::now().duration_since(now).as_millis());
+    if config.streaming {
+        println!("Streaming the training positions");
+    } else if positions.is_empty() {
+        println!("There are no training positions");
+        return;
+    }
+    if !validation.is_empty() {
+        println!("Holding out {} positions for validation", validation.len());
+    }
     let mut tuner = Tuner {
-        k: 1.1155,
+        k: config.k,
         positions,
+        validation,
         params: Parameters::default(),
+        selection: Arc::try_unwrap(selection).ok().expect("Loading threads still hold the selection"),
+        constraints,
+        config,
     };
-    println!("Start tuning for k");
-    if OPTIMIZE_K {
+    if let Some(positions) = gradient_check {
+        report_gradient_check(tuner, positions);
+        return;
+    }
+    if tuner.config.optimize_k && checkpoint.is_none() {
+        println!("Start tuning for k");
         minimize_evaluation_error_fork(&mut tuner);
     }
     println!("Optimal K: {}", tuner.k);
-    unsafe { texel_tuning(tuner, threads) };
+    unsafe { texel_tuning(tuner, checkpoint) };
+}
+
+fn report_gradient_check(tuner: Tuner, positions: usize) {
+    let positions = positions.min(tuner.positions.len());
+    if positions == 0 {
+        println!("No positions to check the gradient on");
+        return;
+    }
+    let now = Instant::now();
+    let checks = gradient_check::check_gradient(tuner, positions, 1.);
+    let mismatches = checks.iter().filter(|check| check.is_mismatch()).count();
+    println!(
+        "Checked {} parameters on {} positions in {}ms, {} differ by more than their tolerance",
+        checks.len(),
+        positions,
+        now.elapsed().as_millis(),
+        mismatches
+    );
+    println!("{:<40} {:>16} {:>16} {:>10}", "parameter", "analytic", "numeric", "relative");
+    for check in checks.iter().take(20) {
+        println!("{:<40} {:>16.6e} {:>16.6e} {:>10.4}", check.name(), check.analytic, check.numeric, check.relative_error());
+    }
 }
@@ -0,0 +1,542 @@
+// CPU trainer for the networks of core_sdk::evaluation::nnue. Training happens in floating point with the same
+// architecture as the engine's network, the result is quantized and exported in the engine's network file format.
+// Every position is trained towards a blend of the game result and, if the dataset has one, the search score:
+//   target = score_weight * sigmoid(k, score) + (1 - score_weight) * result
+// The loss is the squared error between sigmoid(k, network output) and the target, everything from the side to move's point of view.
+use crate::loading::{FileFormatSupported, LabelledGameState, PositionLoader};
+use core_sdk::board_representation::game_state::WHITE;
+use core_sdk::evaluation::nnue::{FeatureSet, Network};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::thread;
+use std::time::Instant;
+
+//Quantization of the exported network, see the network file format
+pub const NNUE_QA: i32 = 255;
+pub const NNUE_QB: i32 = 64;
+//Network output 1.0 corresponds to NNUE_SCALE centipawns
+pub const NNUE_SCALE: i32 = 400;
+//Bounds on the weights, so that they still fit into i16 after quantization. Feature weights are kept well below that,
+//as the accumulators sum up to 32 of them
+const MAX_FEATURE_WEIGHT: f32 = 1.98;
+const MAX_OUTPUT_WEIGHT: f32 = i16::MAX as f32 / NNUE_QB as f32;
+
+const ADAM_BETA1: f32 = 0.9;
+const ADAM_BETA2: f32 = 0.999;
+const ADAM_EPSILON: f32 = 1e-8;
+
+pub struct TrainerConfig {
+    pub feature_set: FeatureSet,
+    pub hidden: usize,
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub learning_rate: f32,
+    //Weight of the search score in the target, the rest is the game result
+    pub score_weight: f32,
+    //Scaling of the sigmoid, same as the k of the texel tuner
+    pub k: f32,
+    pub threads: usize,
+    //Part of the positions that is held back to measure the validation loss
+    pub validation_split: f32,
+    pub seed: u64,
+    pub output: String,
+}
+
+impl Default for TrainerConfig {
+    fn default() -> Self {
+        TrainerConfig {
+            feature_set: FeatureSet::HalfKP,
+            hidden: 128,
+            epochs: 30,
+            batch_size: 16384,
+            learning_rate: 0.001,
+            score_weight: 0.5,
+            k: 1.0,
+            threads: 1,
+            validation_split: 0.05,
+            seed: 0,
+            output: "fabchess.nnue".to_owned(),
+        }
+    }
+}
+
+pub struct TrainingPosition {
+    //Active features of the side to move first, then of the other side
+    pub features: [Vec<u16>; 2],
+    //Game result and search score from the side to move's point of view
+    pub result: f32,
+    pub score: Option<f32>,
+}
+
+impl TrainingPosition {
+    pub fn from_labelled(position: &LabelledGameState, feature_set: FeatureSet) -> Self {
+        let stm = position.game_state.get_color_to_move();
+        let to_u16 = |features: Vec<usize>| features.into_iter().map(|f| f as u16).collect();
+        let white_pov = if stm == WHITE { 1. } else { -1. };
+        TrainingPosition {
+            features: [
+                to_u16(feature_set.features(&position.game_state, stm)),
+                to_u16(feature_set.features(&position.game_state, 1 - stm)),
+            ],
+            result: if stm == WHITE { position.label } else { 1. - position.label },
+            score: position.score.map(|score| f32::from(score) * white_pov),
+        }
+    }
+
+    pub fn target(&self, config: &TrainerConfig) -> f32 {
+        match self.score {
+            Some(score) => config.score_weight * sigmoid(config.k, score) + (1. - config.score_weight) * self.result,
+            None => self.result,
+        }
+    }
+}
+
+//Returns the number of invalid positions that were skipped
+pub fn load_training_positions(file: &str, file_format: FileFormatSupported, feature_set: FeatureSet, buf: &mut Vec<TrainingPosition>) -> usize {
+    let mut loader = PositionLoader::new(file, file_format);
+    while let Some(position) = loader.next_position() {
+        buf.push(TrainingPosition::from_labelled(&position, feature_set));
+    }
+    loader.invalid_positions
+}
+
+pub fn sigmoid(k: f32, cp: f32) -> f32 {
+    1. / (1. + 10f32.powf(-k * cp / 400.))
+}
+
+#[derive(Clone)]
+pub struct FloatNetwork {
+    pub feature_set: FeatureSet,
+    pub hidden: usize,
+    pub feature_weights: Vec<f32>,
+    pub feature_biases: Vec<f32>,
+    pub output_weights: Vec<f32>,
+    pub output_bias: f32,
+}
+
+impl FloatNetwork {
+    pub fn random(feature_set: FeatureSet, hidden: usize, rng: &mut StdRng) -> Self {
+        //About 30 features are active at once
+        let feature_range = 1. / 30f32.sqrt();
+        let output_range = 1. / (2. * hidden as f32).sqrt();
+        FloatNetwork {
+            feature_set,
+            hidden,
+            feature_weights: (0..feature_set.inputs() * hidden).map(|_| rng.gen_range(-feature_range, feature_range)).collect(),
+            feature_biases: vec![0.; hidden],
+            output_weights: (0..2 * hidden).map(|_| rng.gen_range(-output_range, output_range)).collect(),
+            output_bias: 0.,
+        }
+    }
+
+    fn accumulate(&self, features: &[u16], acc: &mut [f32]) {
+        acc.copy_from_slice(&self.feature_biases);
+        for &feature in features {
+            let weights = &self.feature_weights[feature as usize * self.hidden..(feature as usize + 1) * self.hidden];
+            for (a, w) in acc.iter_mut().zip(weights.iter()) {
+                *a += w;
+            }
+        }
+    }
+
+    //Output of the network in units of NNUE_SCALE centipawns. Leaves the accumulators of both sides in acc
+    fn forward(&self, position: &TrainingPosition, acc: &mut [Vec<f32>; 2]) -> f32 {
+        let mut output = self.output_bias;
+        for (side, acc) in acc.iter_mut().enumerate() {
+            self.accumulate(&position.features[side], acc);
+            let weights = &self.output_weights[side * self.hidden..(side + 1) * self.hidden];
+            output += acc.iter().zip(weights.iter()).map(|(a, w)| a.clamp(0., 1.) * w).sum::<f32>();
+        }
+        output
+    }
+
+    //Evaluation in centipawns from the side to move's point of view
+    pub fn evaluate(&self, position: &TrainingPosition) -> f32 {
+        let mut acc = [vec![0.; self.hidden], vec![0.; self.hidden]];
+        self.forward(position, &mut acc) * NNUE_SCALE as f32
+    }
+
+    pub fn quantize(&self) -> Network {
+        let mut network = Network::zeroed(self.feature_set, self.hidden, NNUE_QA, NNUE_QB, NNUE_SCALE);
+        let quantize = |values: &[f32], factor: i32, target: &mut Vec<i16>| {
+            for (t, v) in target.iter_mut().zip(values.iter()) {
+                *t = (v * factor as f32).round().max(f32::from(i16::MIN)).min(f32::from(i16::MAX)) as i16;
+            }
+        };
+        quantize(&self.feature_weights, NNUE_QA, &mut network.feature_weights);
+        quantize(&self.feature_biases, NNUE_QA, &mut network.feature_biases);
+        quantize(&self.output_weights, NNUE_QB, &mut network.output_weights);
+        network.output_bias = (self.output_bias * (NNUE_QA * NNUE_QB) as f32).round() as i32;
+        network
+    }
+}
+
+//Gradient of the loss summed over some positions. The feature weight gradient is dense, but only the rows of features
+//that were active in one of the positions are non zero
+struct Gradient {
+    feature_weights: Vec<f32>,
+    touched: Vec<bool>,
+    touched_features: Vec<usize>,
+    feature_biases: Vec<f32>,
+    output_weights: Vec<f32>,
+    output_bias: f32,
+    loss: f64,
+}
+
+impl Gradient {
+    fn new(feature_set: FeatureSet, hidden: usize) -> Self {
+        Gradient {
+            feature_weights: vec![0.; feature_set.inputs() * hidden],
+            touched: vec![false; feature_set.inputs()],
+            touched_features: Vec::new(),
+            feature_biases: vec![0.; hidden],
+            output_weights: vec![0.; 2 * hidden],
+            output_bias: 0.,
+            loss: 0.,
+        }
+    }
+
+    fn clear(&mut self, hidden: usize) {
+        for &feature in self.touched_features.iter() {
+            self.feature_weights[feature * hidden..(feature + 1) * hidden].iter_mut().for_each(|g| *g = 0.);
+            self.touched[feature] = false;
+        }
+        self.touched_features.clear();
+        self.feature_biases.iter_mut().for_each(|g| *g = 0.);
+        self.output_weights.iter_mut().for_each(|g| *g = 0.);
+        self.output_bias = 0.;
+        self.loss = 0.;
+    }
+
+    fn touch(&mut self, feature: usize) {
+        if !self.touched[feature] {
+            self.touched[feature] = true;
+            self.touched_features.push(feature);
+        }
+    }
+
+    fn add(&mut self, other: &Gradient, hidden: usize) {
+        for &feature in other.touched_features.iter() {
+            self.touch(feature);
+            let range = feature * hidden..(feature + 1) * hidden;
+            for (g, o) in self.feature_weights[range.clone()].iter_mut().zip(other.feature_weights[range].iter()) {
+                *g += o;
+            }
+        }
+        for (g, o) in self.feature_biases.iter_mut().zip(other.feature_biases.iter()) {
+            *g += o;
+        }
+        for (g, o) in self.output_weights.iter_mut().zip(other.output_weights.iter()) {
+            *g += o;
+        }
+        self.output_bias += other.output_bias;
+        self.loss += other.loss;
+    }
+
+    fn accumulate(&mut self, network: &FloatNetwork, config: &TrainerConfig, positions: &[TrainingPosition]) {
+        let hidden = network.hidden;
+        let mut acc = [vec![0.; hidden], vec![0.; hidden]];
+        let mut acc_gradient = vec![0.; hidden];
+        //Derivative of the sigmoid's input with respect to the network output
+        let dzdy = config.k * 10f32.ln() * NNUE_SCALE as f32 / 400.;
+        for position in positions {
+            let output = network.forward(position, &mut acc);
+            let prediction = sigmoid(config.k, output * NNUE_SCALE as f32);
+            let error = prediction - position.target(config);
+            self.loss += f64::from(error * error);
+            let g = 2. * error * prediction * (1. - prediction) * dzdy;
+            self.output_bias += g;
+            for (side, acc) in acc.iter().enumerate() {
+                let output_weights = &network.output_weights[side * hidden..(side + 1) * hidden];
+                let output_gradient = &mut self.output_weights[side * hidden..(side + 1) * hidden];
+                for i in 0..hidden {
+                    let a = acc[i];
+                    output_gradient[i] += g * a.clamp(0., 1.);
+                    //Clipped relu passes the gradient only in its linear part
+                    acc_gradient[i] = if a > 0. && a < 1. { g * output_weights[i] } else { 0. };
+                    self.feature_biases[i] += acc_gradient[i];
+                }
+                for &feature in position.features[side].iter() {
+                    let feature = feature as usize;
+                    self.touch(feature);
+                    for (w, a) in self.feature_weights[feature * hidden..(feature + 1) * hidden].iter_mut().zip(acc_gradient.iter()) {
+                        *w += a;
+                    }
+                }
+            }
+        }
+    }
+}
+
+//First and second moment estimates of one group of parameters
+struct Adam {
+    m: Vec<f32>,
+    v: Vec<f32>,
+}
+
+impl Adam {
+    fn new(size: usize) -> Self {
+        Adam {
+            m: vec![0.; size],
+            v: vec![0.; size],
+        }
+    }
+
+    //Updates params[index] and clips it to [-max, max]. lr already contains the bias correction of the first moment
+    #[inline(always)]
+    fn step(&mut self, index: usize, params: &mut [f32], gradient: f32, lr: f32, v_correction: f32, max: f32) {
+        self.m[index] = ADAM_BETA1 * self.m[index] + (1. - ADAM_BETA1) * gradient;
+        self.v[index] = ADAM_BETA2 * self.v[index] + (1. - ADAM_BETA2) * gradient * gradient;
+        params[index] = (params[index] - lr * self.m[index] / ((self.v[index] / v_correction).sqrt() + ADAM_EPSILON))
+            .max(-max)
+            .min(max);
+    }
+}
+
+pub struct NetworkTrainer {
+    pub config: TrainerConfig,
+    pub network: FloatNetwork,
+    rng: StdRng,
+    //One gradient buffer per thread, reused between batches
+    gradients: Vec<Gradient>,
+    feature_weights_adam: Adam,
+    feature_biases_adam: Adam,
+    output_weights_adam: Adam,
+    output_bias_adam: Adam,
+    steps: i32,
+}
+
+impl NetworkTrainer {
+    pub fn new(config: TrainerConfig) -> Self {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let network = FloatNetwork::random(config.feature_set, config.hidden, &mut rng);
+        let gradients = (0..config.threads.max(1)).map(|_| Gradient::new(config.feature_set, config.hidden)).collect();
+        NetworkTrainer {
+            feature_weights_adam: Adam::new(network.feature_weights.len()),
+            feature_biases_adam: Adam::new(network.feature_biases.len()),
+            output_weights_adam: Adam::new(network.output_weights.len()),
+            config,
+            network,
+            rng,
+            gradients,
+            output_bias_adam: Adam::new(1),
+            steps: 0,
+        }
+    }
+
+    fn compute_gradient(&mut self, positions: &[TrainingPosition]) {
+        let NetworkTrainer { network, config, gradients, .. } = self;
+        let chunk_size = positions.len().div_ceil(gradients.len());
+        thread::scope(|s| {
+            for (gradient, chunk) in gradients.iter_mut().zip(positions.chunks(chunk_size.max(1))) {
+                let (network, config) = (&*network, &*config);
+                s.spawn(move || gradient.accumulate(network, config, chunk));
+            }
+        });
+        let (first, others) = gradients.split_at_mut(1);
+        for other in others.iter_mut() {
+            first[0].add(other, network.hidden);
+            other.clear(network.hidden);
+        }
+    }
+
+    //Adam step with the gradient averaged over the batch. Feature weights of features that did not occur in the batch
+    //are left alone, including their moment estimates
+    fn apply_gradient(&mut self, batch_size: usize) {
+        self.steps += 1;
+        let hidden = self.network.hidden;
+        let scale = 1. / batch_size as f32;
+        let lr = self.config.learning_rate / (1. - ADAM_BETA1.powi(self.steps));
+        let v_correction = 1. - ADAM_BETA2.powi(self.steps);
+        let gradient = &self.gradients[0];
+        let network = &mut self.network;
+        for &feature in gradient.touched_features.iter() {
+            for index in feature * hidden..(feature + 1) * hidden {
+                self.feature_weights_adam.step(
+                    index,
+                    &mut network.feature_weights,
+                    gradient.feature_weights[index] * scale,
+                    lr,
+                    v_correction,
+                    MAX_FEATURE_WEIGHT,
+                );
+            }
+        }
+        for index in 0..hidden {
+            self.feature_biases_adam.step(
+                index,
+                &mut network.feature_biases,
+                gradient.feature_biases[index] * scale,
+                lr,
+                v_correction,
+                MAX_FEATURE_WEIGHT,
+            );
+        }
+        for index in 0..2 * hidden {
+            self.output_weights_adam.step(
+                index,
+                &mut network.output_weights,
+                gradient.output_weights[index] * scale,
+                lr,
+                v_correction,
+                MAX_OUTPUT_WEIGHT,
+            );
+        }
+        let mut output_bias = [network.output_bias];
+        self.output_bias_adam.step(0, &mut output_bias, gradient.output_bias * scale, lr, v_correction, f32::MAX);
+        network.output_bias = output_bias[0];
+        self.gradients[0].clear(hidden);
+    }
+
+    //Trains one epoch over the shuffled positions and returns the average training loss
+    pub fn train_epoch(&mut self, positions: &mut [TrainingPosition]) -> f64 {
+        positions.shuffle(&mut self.rng);
+        let mut loss = 0.;
+        for batch in positions.chunks(self.config.batch_size) {
+            self.compute_gradient(batch);
+            loss += self.gradients[0].loss;
+            self.apply_gradient(batch.len());
+        }
+        loss / positions.len().max(1) as f64
+    }
+
+    //Average loss over the positions
+    pub fn loss(&self, positions: &[TrainingPosition]) -> f64 {
+        if positions.is_empty() {
+            return 0.;
+        }
+        let threads = self.gradients.len();
+        let chunk_size = positions.len().div_ceil(threads);
+        let total: f64 = thread::scope(|s| {
+            let handles: Vec<_> = positions
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|position| {
+                                let error = sigmoid(self.config.k, self.network.evaluate(position)) - position.target(&self.config);
+                                f64::from(error * error)
+                            })
+                            .sum::<f64>()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).sum()
+        });
+        total / positions.len() as f64
+    }
+
+    //Trains for the configured number of epochs. After every epoch with a new best validation loss, the quantized network is
+    //written to the output file
+    pub fn train(&mut self, mut positions: Vec<TrainingPosition>) {
+        positions.shuffle(&mut self.rng);
+        let validation_size = (positions.len() as f32 * self.config.validation_split) as usize;
+        let validation = positions.split_off(positions.len() - validation_size);
+        println!("Training on {} positions, validating on {} positions", positions.len(), validation.len());
+        let mut best_loss = f64::MAX;
+        for epoch in 1..=self.config.epochs {
+            let now = Instant::now();
+            let train_loss = self.train_epoch(&mut positions);
+            let validation_loss = if validation.is_empty() { train_loss } else { self.loss(&validation) };
+            println!(
+                "Epoch {}: train loss {:.6}, validation loss {:.6}, took {}ms",
+                epoch,
+                train_loss,
+                validation_loss,
+                now.elapsed().as_millis()
+            );
+            if validation_loss < best_loss {
+                best_loss = validation_loss;
+                self.network.quantize().save(&self.config.output).expect("Could not write network");
+                println!("Saved network with best validation loss to {}", self.config.output);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FloatNetwork, NetworkTrainer, TrainerConfig, TrainingPosition, NNUE_QA, NNUE_QB};
+    use crate::loading::LabelledGameState;
+    use core_sdk::board_representation::game_state::{GameState, WHITE};
+    use core_sdk::evaluation::nnue::FeatureSet;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn positions(feature_set: FeatureSet) -> Vec<TrainingPosition> {
+        let labelled = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 0.5, Some(20)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNB1KBNR w KQkq - 0 1", 0.0, Some(-850)),
+            ("rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1", 1.0, None),
+            ("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1", 1.0, Some(150)),
+            ("4k3/4p3/8/8/8/8/8/4K3 w - - 0 1", 0.0, Some(-150)),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 0.5, Some(0)),
+        ];
+        labelled
+            .iter()
+            .map(|(fen, label, score)| {
+                let position = LabelledGameState {
+                    game_state: GameState::from_fen(fen),
+                    label: *label,
+                    score: *score,
+                };
+                TrainingPosition::from_labelled(&position, feature_set)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quantization_test() {
+        let mut network = FloatNetwork::random(FeatureSet::HalfKA, 32, &mut StdRng::seed_from_u64(1));
+        //Weights that lie on the quantization grid, so that the only difference is the rounding of the final division
+        let to_grid = |values: &mut Vec<f32>, factor: f32| values.iter_mut().for_each(|v| *v = (*v * factor).round() / factor);
+        to_grid(&mut network.feature_weights, NNUE_QA as f32);
+        to_grid(&mut network.output_weights, NNUE_QB as f32);
+        network.feature_biases.iter_mut().for_each(|b| *b = 0.1);
+        to_grid(&mut network.feature_biases, NNUE_QA as f32);
+        network.output_bias = 0.05;
+        let quantized = network.quantize();
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+        ]
+        .iter()
+        {
+            let game_state = GameState::from_fen(fen);
+            let labelled = LabelledGameState {
+                game_state: game_state.clone(),
+                label: 0.5,
+                score: None,
+            };
+            let float_eval = network.evaluate(&TrainingPosition::from_labelled(&labelled, FeatureSet::HalfKA));
+            let stm_eval = if game_state.get_color_to_move() == WHITE {
+                quantized.evaluate_full(&game_state)
+            } else {
+                -quantized.evaluate_full(&game_state)
+            };
+            assert!((float_eval - f32::from(stm_eval)).abs() <= 1., "{} vs {}", float_eval, stm_eval);
+        }
+    }
+
+    #[test]
+    fn training_test() {
+        for feature_set in [FeatureSet::HalfKP, FeatureSet::HalfKA].iter() {
+            let mut positions = positions(*feature_set);
+            let mut trainer = NetworkTrainer::new(TrainerConfig {
+                feature_set: *feature_set,
+                hidden: 16,
+                batch_size: 4,
+                learning_rate: 0.01,
+                threads: 2,
+                ..TrainerConfig::default()
+            });
+            let initial_loss = trainer.loss(&positions);
+            for _ in 0..50 {
+                trainer.train_epoch(&mut positions);
+            }
+            assert!(trainer.loss(&positions) < initial_loss / 2.);
+        }
+    }
+}
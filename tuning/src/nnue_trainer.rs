@@ -0,0 +1,83 @@
+use core_sdk::evaluation::nnue::FeatureSet;
+use std::time::Instant;
+use tuning::loading::FileFormatSupported;
+use tuning::nnue::{load_training_positions, NetworkTrainer, TrainerConfig};
+
+const USAGE: &str = "Usage: nnue-trainer [options] <dataset>...
+Datasets are read like the texel tuner's position files.
+Options:
+  --format own|epd|bin      Dataset format, default own
+  --features halfkp|halfka  Input features, default halfkp
+  --hidden <n>              Accumulator size, a multiple of 16, default 128
+  --epochs <n>              Default 30
+  --batch-size <n>          Default 16384
+  --lr <f>                  Adam learning rate, default 0.001
+  --score-weight <f>        Weight of the search score against the game result, default 0.5
+  --k <f>                   Sigmoid scaling, default 1.0
+  --threads <n>             Default 1
+  --validation <f>          Part of the positions used for validation, default 0.05
+  --seed <n>                Seed for initialization and shuffling, default 0
+  --out <file>              Network file, default fabchess.nnue";
+
+fn parse<T: std::str::FromStr>(option: &str, value: Option<String>) -> T {
+    value.and_then(|v| v.parse::<T>().ok()).unwrap_or_else(|| panic!("Invalid value for {}\n{}", option, USAGE))
+}
+
+fn main() {
+    let mut config = TrainerConfig::default();
+    let mut format = FileFormatSupported::OwnEncoding;
+    let mut datasets = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("own") => FileFormatSupported::OwnEncoding,
+                    Some("epd") => FileFormatSupported::EPD,
+                    Some("bin") => FileFormatSupported::Packed,
+                    _ => panic!("Invalid value for --format\n{}", USAGE),
+                }
+            }
+            "--features" => {
+                config.feature_set = match args.next().as_deref() {
+                    Some("halfkp") => FeatureSet::HalfKP,
+                    Some("halfka") => FeatureSet::HalfKA,
+                    _ => panic!("Invalid value for --features\n{}", USAGE),
+                }
+            }
+            "--hidden" => config.hidden = parse(&arg, args.next()),
+            "--epochs" => config.epochs = parse(&arg, args.next()),
+            "--batch-size" => config.batch_size = parse(&arg, args.next()),
+            "--lr" => config.learning_rate = parse(&arg, args.next()),
+            "--score-weight" => config.score_weight = parse(&arg, args.next()),
+            "--k" => config.k = parse(&arg, args.next()),
+            "--threads" => config.threads = parse(&arg, args.next()),
+            "--validation" => config.validation_split = parse(&arg, args.next()),
+            "--seed" => config.seed = parse(&arg, args.next()),
+            "--out" => config.output = parse(&arg, args.next()),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => datasets.push(arg),
+        }
+    }
+    if datasets.is_empty() || config.hidden == 0 || config.hidden % 16 != 0 {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let now = Instant::now();
+    let mut positions = Vec::new();
+    let mut invalid_positions = 0;
+    for dataset in datasets.iter() {
+        invalid_positions += load_training_positions(dataset, format, config.feature_set, &mut positions);
+    }
+    println!(
+        "Loaded {} positions in {}ms, skipped {} invalid positions",
+        positions.len(),
+        now.elapsed().as_millis(),
+        invalid_positions
+    );
+    NetworkTrainer::new(config).train(positions);
+}
@@ -0,0 +1,100 @@
+use crate::config::{Optimizer, TunerConfig};
+use core_sdk::evaluation::parameters::Parameters;
+
+const EPSILON: f64 = 1e-8;
+
+//Gradients passed to step point in the direction of a lower error
+#[derive(Clone)]
+pub struct OptimizerState {
+    pub optimizer: Optimizer,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub steps: usize,
+    //AdaGrad: sum of the squared gradients, Adam: second moment
+    pub squared: Parameters,
+    //Adam: first moment
+    pub momentum: Parameters,
+}
+
+impl OptimizerState {
+    pub fn new(config: &TunerConfig) -> Self {
+        OptimizerState {
+            optimizer: config.optimizer,
+            beta1: config.adam_beta1,
+            beta2: config.adam_beta2,
+            steps: 0,
+            squared: Parameters::zero(),
+            momentum: Parameters::zero(),
+        }
+    }
+
+    pub fn step(&mut self, params: &mut Parameters, mut gradient: Parameters, lr: f64) {
+        self.steps += 1;
+        match self.optimizer {
+            Optimizer::Sgd => params.add(&gradient, lr),
+            Optimizer::AdaGrad => {
+                let mut squared = gradient.clone();
+                squared.square();
+                self.squared.add(&squared, 1.);
+                let mut ada_lr = self.squared.clone();
+                ada_lr.sqrt();
+                ada_lr.add_scalar(EPSILON);
+                gradient.mul_inverse_other(&ada_lr);
+                params.add(&gradient, lr);
+            }
+            Optimizer::Adam => {
+                self.momentum.scale(self.beta1);
+                self.momentum.add(&gradient, 1. - self.beta1);
+                gradient.square();
+                self.squared.scale(self.beta2);
+                self.squared.add(&gradient, 1. - self.beta2);
+
+                let mut first_moment = self.momentum.clone();
+                first_moment.scale(1. / (1. - self.beta1.powi(self.steps as i32)));
+                let mut second_moment = self.squared.clone();
+                second_moment.scale(1. / (1. - self.beta2.powi(self.steps as i32)));
+                second_moment.sqrt();
+                second_moment.add_scalar(EPSILON);
+                first_moment.mul_inverse_other(&second_moment);
+                params.add(&first_moment, lr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Minimizes (p - 50)^2 on a single parameter
+    fn minimize(optimizer: Optimizer, lr: f64, steps: usize) -> f64 {
+        let config = TunerConfig {
+            optimizer,
+            ..TunerConfig::default()
+        };
+        let mut state = OptimizerState::new(&config);
+        let mut params = Parameters::zero();
+        for _ in 0..steps {
+            let mut gradient = Parameters::zero();
+            gradient.normal[0][0] = 2. * (50. - params.normal[0][0]);
+            state.step(&mut params, gradient, lr);
+        }
+        assert_eq!(params.normal[1][0], 0.);
+        params.normal[0][0]
+    }
+
+    #[test]
+    fn optimizer_test() {
+        //Parameters are too large for the default test thread stack
+        std::thread::Builder::new()
+            .stack_size(12 * 1024 * 1024)
+            .spawn(|| {
+                assert!((minimize(Optimizer::Sgd, 0.1, 100) - 50.).abs() < 0.01);
+                assert!((minimize(Optimizer::AdaGrad, 10., 300) - 50.).abs() < 0.5);
+                assert!((minimize(Optimizer::Adam, 1., 300) - 50.).abs() < 0.5);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
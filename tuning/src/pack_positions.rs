@@ -0,0 +1,53 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+use tuning::loading::{FileFormatSupported, PositionLoader};
+use tuning::packed::{pack, MAGIC};
+
+const USAGE: &str = "Usage: pack-positions [options] <position file>...
+Converts position files into one packed binary file, see tuning/src/packed.rs.
+Options:
+  --format own|epd|pgn   Format of the inputs, default epd
+  --out <file>           Default positions.bin";
+
+fn main() {
+    let mut format = FileFormatSupported::EPD;
+    let mut out = String::from("positions.bin");
+    let mut inputs = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("own") => FileFormatSupported::OwnEncoding,
+                    Some("epd") => FileFormatSupported::EPD,
+                    Some("pgn") => FileFormatSupported::PGN,
+                    _ => panic!("Invalid value for --format\n{}", USAGE),
+                }
+            }
+            "--out" => out = args.next().unwrap_or_else(|| panic!("Invalid value for --out\n{}", USAGE)),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() {
+        println!("{}", USAGE);
+        return;
+    }
+    let now = Instant::now();
+    let mut writer = BufWriter::new(File::create(&out).expect("Unable to write positions"));
+    writer.write_all(MAGIC).expect("Unable to write positions");
+    let mut positions = 0;
+    for input in inputs.iter() {
+        let mut loader = PositionLoader::new(input, format);
+        while let Some(pos) = loader.next_position() {
+            writer.write_all(&pack(&pos)).expect("Unable to write positions");
+            positions += 1;
+        }
+    }
+    writer.flush().expect("Unable to write positions");
+    println!("Packed {} positions into {} in {}ms", positions, out, now.elapsed().as_millis());
+}
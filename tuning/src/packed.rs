@@ -0,0 +1,204 @@
+// Packed position files: the magic FABPOS01 followed by records of 32 bytes, all numbers little endian:
+//   0..8    occupancy bitboard
+//   8..24   one nibble per occupied square from a1 to h8, low nibble first: color << 3 | piece type
+//   24      bit 0 black to move, bits 1..5 castle permissions, bit 5 score present
+//   25      en passant square, 255 if none
+//   26      half moves, saturated at 255
+//   27..29  full moves
+//   29      result: 0 black win, 1 draw, 2 white win
+//   30..32  search score in centipawns from white's point of view
+use crate::loading::LabelledGameState;
+use core_sdk::board_representation::game_state::{GameState, Irreversible, PieceType, BLACK, WHITE};
+use core_sdk::evaluation::phase::Phase;
+use core_sdk::evaluation::EvaluationScore;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+
+pub const MAGIC: &[u8; 8] = b"FABPOS01";
+pub const RECORD_SIZE: usize = 32;
+
+const SCORE_FLAG: u8 = 1 << 5;
+const NO_EN_PASSANT: u8 = 255;
+
+pub fn pack(pos: &LabelledGameState) -> [u8; RECORD_SIZE] {
+    let g = &pos.game_state;
+    let mut record = [0u8; RECORD_SIZE];
+    let occupancy = g.get_all_pieces();
+    record[0..8].copy_from_slice(&occupancy.to_le_bytes());
+    let piece_bb = g.get_piece_bb_array();
+    let mut squares = occupancy;
+    let mut index = 0;
+    while squares != 0 {
+        let square = squares.trailing_zeros() as usize;
+        let piece = piece_bb.iter().position(|bb| bb & (1 << square) != 0).unwrap() as u8;
+        let color = if g.get_pieces_from_side(WHITE) & (1 << square) != 0 { 0 } else { 1 };
+        record[8 + index / 2] |= (color << 3 | piece) << (4 * (index % 2));
+        index += 1;
+        squares &= squares - 1;
+    }
+    record[24] = g.get_color_to_move() as u8 | g.castle_permissions() << 1 | if pos.score.is_some() { SCORE_FLAG } else { 0 };
+    record[25] = if g.get_en_passant() == 0 {
+        NO_EN_PASSANT
+    } else {
+        g.get_en_passant().trailing_zeros() as u8
+    };
+    record[26] = g.get_half_moves().min(255) as u8;
+    record[27..29].copy_from_slice(&(g.get_full_moves().min(u16::MAX as usize) as u16).to_le_bytes());
+    record[29] = (pos.label * 2.).round() as u8;
+    record[30..32].copy_from_slice(&pos.score.unwrap_or(0).to_le_bytes());
+    record
+}
+
+fn invalid_record() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid packed position")
+}
+
+pub fn unpack(record: &[u8; RECORD_SIZE]) -> io::Result<LabelledGameState> {
+    let mut occupancy = [0u8; 8];
+    occupancy.copy_from_slice(&record[0..8]);
+    let mut squares = u64::from_le_bytes(occupancy);
+    if squares.count_ones() > 32 || record[29] > 2 {
+        return Err(invalid_record());
+    }
+    let mut piece_bb = [0u64; 6];
+    let mut color_bb = [0u64; 2];
+    let mut index = 0;
+    while squares != 0 {
+        let square = squares.trailing_zeros() as usize;
+        let nibble = record[8 + index / 2] >> (4 * (index % 2)) & 0xF;
+        if nibble & 7 >= 6 {
+            return Err(invalid_record());
+        }
+        piece_bb[(nibble & 7) as usize] |= 1 << square;
+        color_bb[if nibble >> 3 == 0 { WHITE } else { BLACK }] |= 1 << square;
+        index += 1;
+        squares &= squares - 1;
+    }
+    let kings = piece_bb[PieceType::King as usize];
+    if (kings & color_bb[WHITE]).count_ones() != 1 || (kings & color_bb[BLACK]).count_ones() != 1 {
+        return Err(invalid_record());
+    }
+    let en_passant = if record[25] == NO_EN_PASSANT { 0 } else { 1u64 << (record[25] & 63) };
+    let mut game_state = GameState::new(
+        (record[24] & 1) as usize,
+        piece_bb,
+        color_bb,
+        Irreversible::new(0, 0, en_passant, u16::from(record[26]), record[24] >> 1 & 0xF, Phase::default(), EvaluationScore(0, 0)),
+        usize::from(u16::from_le_bytes([record[27], record[28]])),
+    );
+    game_state.initialize();
+    Ok(LabelledGameState {
+        game_state,
+        label: f32::from(record[29]) / 2.,
+        score: if record[24] & SCORE_FLAG != 0 {
+            Some(i16::from_le_bytes([record[30], record[31]]))
+        } else {
+            None
+        },
+    })
+}
+
+pub fn write_packed_positions<W: Write>(writer: &mut W, positions: &[LabelledGameState]) -> io::Result<()> {
+    for pos in positions {
+        writer.write_all(&pack(pos))?;
+    }
+    Ok(())
+}
+
+pub fn save_packed_positions(to_file: &str, positions: &[LabelledGameState]) {
+    let mut writer = BufWriter::new(File::create(to_file).expect("Unable to write positions"));
+    writer
+        .write_all(MAGIC)
+        .and_then(|_| write_packed_positions(&mut writer, positions))
+        .and_then(|_| writer.flush())
+        .expect("Unable to write positions");
+}
+
+//Returns None at the end of the file and an InvalidData error for records that can't be decoded
+pub fn read_packed_position<R: Read>(reader: &mut R) -> io::Result<Option<LabelledGameState>> {
+    let mut record = [0u8; RECORD_SIZE];
+    match reader.read_exact(&mut record) {
+        Ok(()) => unpack(&record).map(Some),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn read_magic<R: Read>(reader: &mut R) -> io::Result<()> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a packed position file"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loading::{FileFormatSupported, PositionLoader};
+
+    #[test]
+    fn packed_positions_test() {
+        let positions = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 0.5, None),
+            ("r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK1R1 w Qkq a6 0 14", 1., Some(-35)),
+            ("8/8/4k3/8/2pP4/8/8/4K3 b - d3 0 57", 0., Some(i16::MIN)),
+            ("8/5k2/8/8/8/8/1K6/8 w - - 99 300", 0.5, Some(0)),
+        ]
+        .iter()
+        .map(|(fen, label, score)| LabelledGameState {
+            game_state: GameState::from_fen(fen),
+            label: *label,
+            score: *score,
+        })
+        .collect::<Vec<_>>();
+        for pos in positions.iter() {
+            let unpacked = unpack(&pack(pos)).unwrap();
+            assert_eq!(unpacked.game_state.to_fen(), pos.game_state.to_fen());
+            assert_eq!(unpacked.game_state.get_hash(), pos.game_state.get_hash());
+            assert_eq!(unpacked.game_state.get_psqt(), pos.game_state.get_psqt());
+            assert_eq!((unpacked.label, unpacked.score), (pos.label, pos.score));
+        }
+
+        let path = std::env::temp_dir().join("fabchess_packed_positions_test.bin");
+        save_packed_positions(path.to_str().unwrap(), &positions);
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, MAGIC.len() + positions.len() * RECORD_SIZE);
+        let mut loaded = Vec::new();
+        PositionLoader::new(path.to_str().unwrap(), FileFormatSupported::Packed).load_positions(&mut loaded);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), positions.len());
+        assert_eq!(loaded[1].game_state.to_fen(), positions[1].game_state.to_fen());
+        assert!(read_magic(&mut &b"rnbqkbnr"[..]).is_err());
+
+        //Corrupted records are errors instead of panics
+        let record = pack(&positions[0]);
+        let mut too_many_pieces = record;
+        too_many_pieces[0..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut invalid_piece = record;
+        invalid_piece[8] = 6;
+        let mut invalid_result = record;
+        invalid_result[29] = 3;
+        let mut no_king = record;
+        //The king on e1 is the fifth piece, which becomes a queen
+        no_king[10] = no_king[10] & 0xF0 | PieceType::Queen as u8;
+        for record in [too_many_pieces, invalid_piece, invalid_result, no_king].iter() {
+            assert_eq!(unpack(record).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        }
+        let mut file = Vec::new();
+        file.extend_from_slice(&invalid_result);
+        file.extend_from_slice(&record);
+        let mut reader = &file[..];
+        assert!(read_packed_position(&mut reader).is_err());
+        assert!(read_packed_position(&mut reader).unwrap().is_some());
+        assert!(read_packed_position(&mut reader).unwrap().is_none());
+
+        //The loader skips and counts them
+        std::fs::write(&path, [&MAGIC[..], &file[..]].concat()).unwrap();
+        let mut loaded = Vec::new();
+        let mut loader = PositionLoader::new(path.to_str().unwrap(), FileFormatSupported::Packed);
+        loader.load_positions(&mut loaded);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((loaded.len(), loader.invalid_positions), (1, 1));
+    }
+}
@@ -0,0 +1,86 @@
+use core_sdk::move_generation::movegen::MoveList;
+use extended_sdk::pgn::pgn_reader::PGNParser;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use tuning::loading::{sample_game, write_positions, GameSampling, Statistics};
+
+const USAGE: &str = "Usage: pgn-extractor [options] <pgn file or directory>...
+Samples positions from pgn games, labels them with the game result and writes them in the own encoding.
+Options:
+  --out <file>           Default pgn_positions.txt
+  --per-game <n>         Positions sampled per game, default 10
+  --skip-plies <n>       Opening plies that are never sampled, default 16
+  --adjudication <cp>    Skip the rest of a game from the first [%eval] of at least this many centipawns, default off
+  --evals                Write the score of the [%eval] comment after the move leading to a position
+  --seed <n>             Seed of the sampling, default 0";
+
+fn parse<T: std::str::FromStr>(option: &str, value: Option<String>) -> T {
+    value.and_then(|v| v.parse::<T>().ok()).unwrap_or_else(|| panic!("Invalid value for {}\n{}", option, USAGE))
+}
+
+fn main() {
+    let mut out = String::from("pgn_positions.txt");
+    let mut sampling = GameSampling::default();
+    let mut seed = 0;
+    let mut inputs = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out = parse(&arg, args.next()),
+            "--per-game" => sampling.per_game = parse(&arg, args.next()),
+            "--skip-plies" => sampling.skip_plies = parse(&arg, args.next()),
+            "--adjudication" => sampling.adjudication = Some(parse(&arg, args.next())),
+            "--evals" => sampling.evals = true,
+            "--seed" => seed = parse(&arg, args.next()),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() || sampling.per_game == 0 {
+        println!("{}", USAGE);
+        return;
+    }
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(&input);
+        if path.is_dir() {
+            let mut dir = fs::read_dir(path).expect("Could not read directory").map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+            dir.sort();
+            files.extend(dir.into_iter().filter(|file| file.extension().map(|ext| ext == "pgn").unwrap_or(false)));
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    let now = Instant::now();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut move_list = MoveList::default();
+    let mut stats = Statistics::default();
+    let mut writer = BufWriter::new(File::create(&out).expect("Unable to write positions"));
+    for file in files.iter() {
+        let reader = BufReader::new(File::open(file).expect("Could not open file"));
+        for game in (PGNParser { reader }) {
+            let game = game.parse(&mut move_list, None);
+            if game.result == -2 {
+                stats.skipped_games += 1;
+                continue;
+            }
+            let sampled = sample_game(&game, &sampling, &mut rng);
+            for position in sampled.iter() {
+                stats.add(position.label);
+            }
+            write_positions(&mut writer, &sampled).expect("Unable to write positions");
+            stats.games += 1;
+        }
+    }
+    writer.flush().expect("Unable to write positions");
+    println!("Read {} files in {}ms", files.len(), now.elapsed().as_millis());
+    println!("{}", stats);
+}
@@ -1,4 +1,4 @@
-use core_sdk::board_representation::game_state::{swap_side, GameMove, GameMoveType, GameState, WHITE};
+use core_sdk::board_representation::game_state::{GameMove, GameMoveType, GameState, BLACK, WHITE};
 use core_sdk::evaluation::eval_game_state;
 use core_sdk::move_generation::makemove::make_move;
 use core_sdk::move_generation::movegen::{self, AdditionalGameStateInformation, MoveList};
@@ -8,65 +8,222 @@ use core_sdk::search::quiescence::{best_move_value, passes_delta_pruning, see, D
 use core_sdk::search::reserved_memory::ReservedMoveList;
 use core_sdk::search::SearchInstruction;
 use core_sdk::search::{MAX_SEARCH_DEPTH, STANDARD_SCORE};
+use rand::rngs::StdRng;
+use rand::{seq::SliceRandom, SeedableRng};
+use std::collections::HashSet;
 use std::fs;
-use tuning::loading::{save_positions, FileFormatSupported, LabelledGameState};
+use std::path::Path;
+use std::thread;
+use std::time::Instant;
+use tuning::loading::{save_positions, FileFormatSupported, LabelledGameState, PositionLoader, Statistics};
+use tuning::packed::save_packed_positions;
 
-//const FEN_DIR: &str = "D:/FenCollection/Real";
-const FEN_DIR: &str = "D:/FenCollection/Lichess";
+const USAGE: &str = "Usage: preparation [options] <position file or directory>...
+Replaces all positions by their quiet positions after a q-search, filters and deduplicates them
+and writes a training and a validation set in the own encoding, or packed for outputs ending in .bin.
+Options:
+  --format <format>      own, epd, pgn or bin for all inputs, by default detected from the extension (.pgn, .epd, .bin, else own)
+  --threads <n>          Default the number of available cores
+  --out <file>           Training positions, default quiet_positions.txt
+  --validation-out <f>   Validation positions, default validation_positions.txt
+  --validation <f>       Part of the positions written to the validation set, default 0
+  --seed <n>             Seed of the shuffle before the split, default 0
+  --min-eval <cp>        Lowest q-search score from white's point of view, default -999
+  --max-eval <cp>        Highest q-search score from white's point of view, default 999
+  --min-ply <n>          Lowest ply of the position before the q-search, default 0
+  --max-ply <n>          Highest ply of the position before the q-search, default unlimited
+  --min-pieces <n>       Fewest pieces on the board including kings, default 0
+  --max-pieces <n>       Most pieces on the board including kings, default 32
+  --keep-checks          Keep positions that are in check before or after the q-search";
+
+#[derive(Clone, Copy)]
+struct Filters {
+    min_eval: i16,
+    max_eval: i16,
+    min_ply: usize,
+    max_ply: usize,
+    min_pieces: u32,
+    max_pieces: u32,
+    keep_checks: bool,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Filters {
+            min_eval: -999,
+            max_eval: 999,
+            min_ply: 0,
+            max_ply: usize::MAX,
+            min_pieces: 0,
+            max_pieces: 32,
+            keep_checks: false,
+        }
+    }
+}
+
+impl Filters {
+    fn before_q_search(&self, position: &LabelledGameState) -> bool {
+        let g = &position.game_state;
+        let ply = 2 * (g.get_full_moves().max(1) - 1) + if g.get_color_to_move() == BLACK { 1 } else { 0 };
+        ply >= self.min_ply && ply <= self.max_ply && (self.keep_checks || !g.in_check())
+    }
+
+    fn after_q_search(&self, quiet: &GameState, score: i16) -> bool {
+        let pieces = quiet.get_all_pieces().count_ones();
+        score >= self.min_eval && score <= self.max_eval && pieces >= self.min_pieces && pieces <= self.max_pieces && (self.keep_checks || !quiet.in_check())
+    }
+}
+
+fn parse<T: std::str::FromStr>(option: &str, value: Option<String>) -> T {
+    value.and_then(|v| v.parse::<T>().ok()).unwrap_or_else(|| panic!("Invalid value for {}\n{}", option, USAGE))
+}
+
+fn file_format(path: &Path) -> FileFormatSupported {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("pgn") => FileFormatSupported::PGN,
+        Some("epd") => FileFormatSupported::EPD,
+        Some("bin") => FileFormatSupported::Packed,
+        _ => FileFormatSupported::OwnEncoding,
+    }
+}
+
+fn save(path: &str, positions: &[LabelledGameState]) {
+    if path.ends_with(".bin") {
+        save_packed_positions(path, positions);
+    } else {
+        save_positions(path, positions);
+    }
+}
 
 fn main() {
-    //2. Transform all FEN-Positions in Quiet positions
-    //3. Save all positions just like loaded, all positions after q-search, all positions after q-search without stripped(no positions with >10 or <-10 eval)
-    let mut positions: Vec<LabelledGameState> = Vec::with_capacity(8_000_000);
-    let paths = fs::read_dir(FEN_DIR).unwrap();
-    for path in paths {
-        tuning::loading::PositionLoader::new(&format!("{}", path.unwrap().path().display()), FileFormatSupported::EPD).load_positions(&mut positions);
-    }
-    println!("Positions: {}", positions.len());
-    /*save_positions(
-        &format!("{}/all_positions_noqsearch.txt", FEN_DIR),
-        &positions,
-    );*/
-
-    let mut quiet_nonstripped: Vec<LabelledGameState> = Vec::with_capacity(positions.len());
-    let mut quiet_stripped: Vec<LabelledGameState> = Vec::with_capacity(positions.len());
+    let mut format = None;
+    let mut threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut out = String::from("quiet_positions.txt");
+    let mut validation_out = String::from("validation_positions.txt");
+    let mut validation = 0.;
+    let mut seed = 0;
+    let mut filters = Filters::default();
+    let mut inputs = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("own") => Some(FileFormatSupported::OwnEncoding),
+                    Some("epd") => Some(FileFormatSupported::EPD),
+                    Some("pgn") => Some(FileFormatSupported::PGN),
+                    Some("bin") => Some(FileFormatSupported::Packed),
+                    _ => panic!("Invalid value for --format\n{}", USAGE),
+                }
+            }
+            "--threads" => threads = parse(&arg, args.next()),
+            "--out" => out = parse(&arg, args.next()),
+            "--validation-out" => validation_out = parse(&arg, args.next()),
+            "--validation" => validation = parse(&arg, args.next()),
+            "--seed" => seed = parse(&arg, args.next()),
+            "--min-eval" => filters.min_eval = parse(&arg, args.next()),
+            "--max-eval" => filters.max_eval = parse(&arg, args.next()),
+            "--min-ply" => filters.min_ply = parse(&arg, args.next()),
+            "--max-ply" => filters.max_ply = parse(&arg, args.next()),
+            "--min-pieces" => filters.min_pieces = parse(&arg, args.next()),
+            "--max-pieces" => filters.max_pieces = parse(&arg, args.next()),
+            "--keep-checks" => filters.keep_checks = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() || threads == 0 || !(0. ..1.).contains(&validation) {
+        println!("{}", USAGE);
+        return;
+    }
 
+    //1. Load all positions, directories are expanded to the files they contain
+    let now = Instant::now();
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(&input);
+        if path.is_dir() {
+            let mut dir = fs::read_dir(path).expect("Could not read directory").map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+            dir.sort();
+            files.extend(dir);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    let mut positions: Vec<LabelledGameState> = Vec::new();
+    let (mut games, mut skipped_games) = (0, 0);
+    for file in files.iter() {
+        let mut loader = PositionLoader::new(&format!("{}", file.display()), format.unwrap_or_else(|| file_format(file)));
+        loader.load_positions(&mut positions);
+        games += loader.games;
+        skipped_games += loader.skipped_games;
+    }
+    let mut stats = Statistics::from_positions(&positions);
+    stats.games = games;
+    stats.skipped_games = skipped_games;
+    println!("Loaded {} files in {}ms\n{}", files.len(), now.elapsed().as_millis(), stats);
+
+    //2. Transform all positions into quiet positions in parallel, then drop duplicates by their zobrist hash
+    let now = Instant::now();
+    let positions = positions.into_iter().filter(|position| filters.before_q_search(position)).collect::<Vec<_>>();
+    let per_thread = (positions.len() as f64 / threads as f64).ceil().max(1.) as usize;
+    let mut positions = positions.into_iter();
+    let mut thread_handles = Vec::with_capacity(threads);
+    for _ in 0..threads {
+        let chunk = positions.by_ref().take(per_thread).collect::<Vec<_>>();
+        thread_handles.push(
+            thread::Builder::new()
+                .stack_size(12 * 1024 * 1024)
+                .spawn(move || quiet_positions(chunk, filters))
+                .expect("Couldn't start thread"),
+        );
+    }
+    let quiet = deduplicate(thread_handles.into_iter().flat_map(|handle| handle.join().expect("Could not join thread")));
+    println!("Quiet, filtered and deduplicated positions: {} in {}ms", quiet.len(), now.elapsed().as_millis());
+
+    //3. Shuffle and split into the training and validation set
+    let (quiet, validation_positions) = split(quiet, validation, seed);
+    println!("Training positions:\n{}", Statistics::from_positions(&quiet));
+    save(&out, &quiet);
+    if !validation_positions.is_empty() {
+        println!("Validation positions:\n{}", Statistics::from_positions(&validation_positions));
+        save(&validation_out, &validation_positions);
+    }
+}
+
+//Keeps the first position of every zobrist hash in the order of the input
+fn deduplicate(positions: impl IntoIterator<Item = LabelledGameState>) -> Vec<LabelledGameState> {
+    let mut seen = HashSet::new();
+    positions.into_iter().filter(|position| seen.insert(position.game_state.get_hash())).collect()
+}
+
+//Returns the training and the validation set, the same seed always gives the same split
+fn split(mut positions: Vec<LabelledGameState>, validation: f64, seed: u64) -> (Vec<LabelledGameState>, Vec<LabelledGameState>) {
+    positions.shuffle(&mut StdRng::seed_from_u64(seed));
+    let validation_positions = positions.split_off(positions.len() - (positions.len() as f64 * validation) as usize);
+    (positions, validation_positions)
+}
+
+fn quiet_positions(positions: Vec<LabelledGameState>, filters: Filters) -> Vec<LabelledGameState> {
     let mut history = History::default();
     let mut move_list = ReservedMoveList::default();
     let mut see_buffer = vec![0i16; MAX_SEARCH_DEPTH];
-
+    let mut res = Vec::with_capacity(positions.len());
     for position in positions {
-        let mut other = position.game_state.clone();
-        other.set_color_to_move(swap_side(other.get_color_to_move()));
-        let (score, state) = stripped_q_search(
-            -16000,
-            16000,
-            position.game_state.clone(),
-            if position.game_state.get_color_to_move() == WHITE { 1 } else { -1 },
-            0,
-            0,
-            &mut history,
-            &mut move_list,
-            &mut see_buffer,
-        );
-        quiet_nonstripped.push(LabelledGameState {
-            game_state: state.clone(),
-            label: position.label,
-        });
-        if score.abs() < 1000 {
-            quiet_stripped.push(LabelledGameState {
+        let color = if position.game_state.get_color_to_move() == WHITE { 1 } else { -1 };
+        let (score, state) = stripped_q_search(-16000, 16000, position.game_state, color, 0, 0, &mut history, &mut move_list, &mut see_buffer);
+        if filters.after_q_search(&state, score * color) {
+            res.push(LabelledGameState {
                 game_state: state,
                 label: position.label,
+                score: position.score,
             });
         }
     }
-    println!("Quiet positions: {}", quiet_nonstripped.len());
-    println!("Quiet and stripped positions: {}", quiet_stripped.len());
-    save_positions(&format!("{}/all_positions_qsearch.txt", FEN_DIR), &quiet_nonstripped);
-    /*save_positions(
-        &format!("{}/all_positions_qsearchstripped.txt", FEN_DIR),
-        &quiet_stripped,
-    );*/
+    res
 }
 
 pub fn stripped_q_search(
@@ -120,8 +277,9 @@ pub fn stripped_q_search(
             break;
         }
         let (i, capture_move) = capture_move.unwrap();
+        //Pruned moves stay in the list, so all remaining moves are pruned as well
         if capture_move.1.unwrap() < 0. {
-            continue;
+            break;
         }
         let capture_move = capture_move.0;
         move_list.move_lists[current_depth].move_list.remove(i);
@@ -176,3 +334,109 @@ pub fn make_moves(
     }
     agsi
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(fens: &[&str]) -> Vec<LabelledGameState> {
+        fens.iter()
+            .enumerate()
+            .map(|(i, fen)| LabelledGameState {
+                game_state: GameState::from_fen(fen),
+                label: i as f32,
+                score: None,
+            })
+            .collect()
+    }
+
+    fn kept(positions: Vec<LabelledGameState>, filters: Filters) -> usize {
+        let positions = positions.into_iter().filter(|position| filters.before_q_search(position)).collect::<Vec<_>>();
+        quiet_positions(positions, filters).len()
+    }
+
+    #[test]
+    fn filters_test() {
+        let fens = [
+            //Ply 0, 32 pieces, equal
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            //Ply 40, white is in check, 2 pieces and equal after Kxe2
+            "4k3/8/8/8/8/8/4r3/4K3 w - - 0 21",
+            //Ply 41, 3 pieces, white is up a queen
+            "4k3/8/8/8/8/8/8/3QK3 b - - 0 21",
+            //Ply 41, 4 pieces, equal
+            "4k3/4p3/8/8/8/8/4P3/4K3 b - - 0 21",
+        ];
+        assert_eq!(kept(positions(&fens), Filters::default()), 2);
+        let keep_checks = Filters {
+            keep_checks: true,
+            ..Filters::default()
+        };
+        assert_eq!(kept(positions(&fens), keep_checks), 3);
+        let min_ply = Filters { min_ply: 1, ..Filters::default() };
+        assert_eq!(kept(positions(&fens), min_ply), 1);
+        let max_ply = Filters {
+            max_ply: 40,
+            ..Filters::default()
+        };
+        assert_eq!(kept(positions(&fens), max_ply), 1);
+        let min_pieces = Filters {
+            min_pieces: 5,
+            ..Filters::default()
+        };
+        assert_eq!(kept(positions(&fens), min_pieces), 1);
+        let max_pieces = Filters {
+            max_pieces: 4,
+            ..Filters::default()
+        };
+        assert_eq!(kept(positions(&fens), max_pieces), 1);
+        let eval = Filters {
+            min_eval: -100,
+            max_eval: 100,
+            ..Filters::default()
+        };
+        assert_eq!(kept(positions(&fens), eval), 2);
+        let min_eval = Filters {
+            min_eval: 500,
+            ..Filters::default()
+        };
+        assert_eq!(kept(positions(&fens), min_eval), 0);
+        let max_eval = Filters {
+            max_eval: 5000,
+            ..Filters::default()
+        };
+        assert_eq!(kept(positions(&fens), max_eval), 3);
+    }
+
+    #[test]
+    fn deduplicate_test() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "4k3/8/8/8/8/8/8/3QK3 w - - 0 21",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "4k3/4p3/8/8/8/8/4P3/4K3 b - - 0 21",
+            "4k3/8/8/8/8/8/8/3QK3 w - - 5 30",
+        ];
+        //The first occurence is kept, the move counters are not part of the hash
+        let labels = deduplicate(positions(&fens)).iter().map(|position| position.label).collect::<Vec<_>>();
+        assert_eq!(labels, vec![0., 1., 3.]);
+    }
+
+    #[test]
+    fn split_test() {
+        let fens = ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"; 100];
+        let labels = |positions: &[LabelledGameState]| positions.iter().map(|position| position.label).collect::<Vec<_>>();
+        let (training, validation) = split(positions(&fens), 0.25, 7);
+        assert_eq!((training.len(), validation.len()), (75, 25));
+        let (same_training, same_validation) = split(positions(&fens), 0.25, 7);
+        assert_eq!(labels(&training), labels(&same_training));
+        assert_eq!(labels(&validation), labels(&same_validation));
+        let (other_training, _) = split(positions(&fens), 0.25, 8);
+        assert_ne!(labels(&training), labels(&other_training));
+        let mut all = labels(&training);
+        all.extend(labels(&validation));
+        all.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(all, (0..100).map(|i| i as f32).collect::<Vec<_>>());
+        assert!(split(positions(&fens), 0., 7).1.is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+// Streaming of training positions for datasets that don't fit into memory. Every epoch reads the files again in a
+// shuffled order and passes the positions through a shuffle buffer, which only mixes nearby positions, so the files
+// should be shuffled beforehand. Validation positions stay in memory.
+use crate::config::TunerConfig;
+use crate::is_validation;
+use crate::loading::{FileFormatSupported, LabelledGameState, PositionLoader};
+use rand::rngs::StdRng;
+use rand::{seq::SliceRandom, Rng};
+
+pub struct ShuffleBuffer<T> {
+    items: Vec<T>,
+    capacity: usize,
+}
+
+impl<T> ShuffleBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        ShuffleBuffer {
+            items: Vec::with_capacity(capacity.min(1 << 20)),
+            capacity,
+        }
+    }
+
+    //Once the buffer is full, every item pushed replaces a random item, which is returned
+    pub fn push(&mut self, item: T, rng: &mut StdRng) -> Option<T> {
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+            return None;
+        }
+        let index = rng.gen_range(0, self.items.len());
+        Some(std::mem::replace(&mut self.items[index], item))
+    }
+
+    //Empties the buffer in random order
+    pub fn pop(&mut self, rng: &mut StdRng) -> Option<T> {
+        if self.items.is_empty() {
+            return None;
+        }
+        let index = rng.gen_range(0, self.items.len());
+        Some(self.items.swap_remove(index))
+    }
+}
+
+//The training positions of all position files in shuffled order
+pub struct PositionStream {
+    //Popped from the back
+    files: Vec<String>,
+    format: FileFormatSupported,
+    validation_split: f64,
+    seed: u64,
+    loader: Option<PositionLoader>,
+    buffer: ShuffleBuffer<LabelledGameState>,
+    rng: StdRng,
+}
+
+impl PositionStream {
+    pub fn new(config: &TunerConfig, mut rng: StdRng) -> Self {
+        let mut files = config.position_files.clone();
+        files.shuffle(&mut rng);
+        PositionStream {
+            files,
+            format: config.format,
+            validation_split: config.validation_split,
+            seed: config.seed,
+            loader: None,
+            buffer: ShuffleBuffer::new(config.shuffle_buffer),
+            rng,
+        }
+    }
+
+    fn next_from_files(&mut self) -> Option<LabelledGameState> {
+        loop {
+            if let Some(loader) = self.loader.as_mut() {
+                while let Some(pos) = loader.next_position() {
+                    if !is_validation(&pos.game_state, self.validation_split, self.seed) {
+                        return Some(pos);
+                    }
+                }
+            }
+            self.loader = Some(PositionLoader::new(&self.files.pop()?, self.format));
+        }
+    }
+}
+
+impl Iterator for PositionStream {
+    type Item = LabelledGameState;
+
+    fn next(&mut self) -> Option<LabelledGameState> {
+        while let Some(pos) = self.next_from_files() {
+            if let Some(pos) = self.buffer.push(pos, &mut self.rng) {
+                return Some(pos);
+            }
+        }
+        self.buffer.pop(&mut self.rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loading::save_positions;
+    use crate::{streaming_epoch, Tuner, TunerWrapper};
+    use core_sdk::board_representation::game_state::GameState;
+    use core_sdk::evaluation::parameters::Parameters;
+    use rand::SeedableRng;
+    use std::cell::UnsafeCell;
+    use std::sync::Arc;
+
+    #[test]
+    fn shuffle_buffer_test() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut buffer = ShuffleBuffer::new(10);
+        let mut out = (0..100).filter_map(|i| buffer.push(i, &mut rng)).collect::<Vec<_>>();
+        assert_eq!(out.len(), 90);
+        while let Some(i) = buffer.pop(&mut rng) {
+            out.push(i);
+        }
+        assert_ne!(out, (0..100).collect::<Vec<_>>());
+        out.sort();
+        assert_eq!(out, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn position_stream_test() {
+        //Position loaders hold parameters, which are too large for the default test thread stack
+        std::thread::Builder::new().stack_size(32 * 1024 * 1024).spawn(position_stream).unwrap().join().unwrap();
+        std::thread::Builder::new().stack_size(32 * 1024 * 1024).spawn(empty_stream).unwrap().join().unwrap();
+    }
+
+    //An epoch without training positions has no average error
+    fn empty_stream() {
+        let path = std::env::temp_dir().join("fabchess_empty_stream_test.txt");
+        save_positions(path.to_str().unwrap(), &[]);
+        let config = TunerConfig {
+            format: FileFormatSupported::OwnEncoding,
+            position_files: vec![path.to_str().unwrap().to_owned()],
+            streaming: true,
+            ..TunerConfig::default()
+        };
+        let tuner = Tuner {
+            k: config.k,
+            positions: Vec::new(),
+            validation: Vec::new(),
+            params: Parameters::default(),
+            selection: config.selection().unwrap(),
+            constraints: Default::default(),
+            config,
+        };
+        let error = unsafe { streaming_epoch(&Arc::new(TunerWrapper(UnsafeCell::new(tuner))), 0, None) };
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.is_err());
+    }
+
+    fn position_stream() {
+        let fens = core_sdk::BENCHMARKING_POSITIONS;
+        let dir = std::env::temp_dir();
+        let mut config = TunerConfig {
+            format: FileFormatSupported::OwnEncoding,
+            validation_split: 0.25,
+            shuffle_buffer: 8,
+            ..TunerConfig::default()
+        };
+        for (i, chunk) in fens.chunks(fens.len() / 2 + 1).enumerate() {
+            let path = dir.join(format!("fabchess_position_stream_test_{}.txt", i));
+            let positions = chunk
+                .iter()
+                .map(|fen| LabelledGameState {
+                    game_state: GameState::from_fen(fen),
+                    label: 0.5,
+                    score: None,
+                })
+                .collect::<Vec<_>>();
+            save_positions(path.to_str().unwrap(), &positions);
+            config.position_files.push(path.to_str().unwrap().to_owned());
+        }
+        let epoch = |seed| {
+            PositionStream::new(&config, StdRng::seed_from_u64(seed))
+                .map(|pos| pos.game_state.to_fen())
+                .collect::<Vec<_>>()
+        };
+        let (first, second) = (epoch(1), epoch(2));
+        for file in config.position_files.iter() {
+            std::fs::remove_file(file).unwrap();
+        }
+
+        let validation = fens.iter().filter(|fen| is_validation(&GameState::from_fen(fen), 0.25, 0)).count();
+        assert!(validation > 0 && validation < fens.len());
+        assert_eq!(first.len(), fens.len() - validation);
+        assert_ne!(first, second);
+        let (mut first, mut second) = (first, second);
+        first.sort();
+        second.sort();
+        assert_eq!(first, second);
+    }
+}
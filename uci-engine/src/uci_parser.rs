@@ -1,12 +1,16 @@
 use super::uci_engine::UCIEngine;
 use core_sdk::board_representation::game_state::{GameMove, GameMoveType, GameState, PieceType};
+use core_sdk::evaluation::eval_params::RuntimeParams;
+use core_sdk::evaluation::nnue::Network;
 use core_sdk::move_generation::makemove::make_move;
 use core_sdk::move_generation::movegen;
 use core_sdk::search::alphabeta::{MAX_FUTILITY_MARGIN, MIN_FUTILITY_MARGIN};
-use core_sdk::search::cache::{Cache, MAX_HASH_SIZE, MIN_HASH_SIZE};
+use core_sdk::search::cache::{MAX_HASH_SIZE, MIN_HASH_SIZE};
+use core_sdk::search::eval_cache::{MAX_EVAL_CACHE_SIZE, MIN_EVAL_CACHE_SIZE};
 use core_sdk::search::searcher::{search_move, InterThreadCommunicationSystem, MAX_SKIP_RATIO, MAX_THREADS, MIN_SKIP_RATIO, MIN_THREADS};
 use core_sdk::search::timecontrol::{TimeControl, MAX_MOVE_OVERHEAD, MIN_MOVE_OVERHEAD};
 use core_sdk::search::MAX_SEARCH_DEPTH;
+use core_sdk::solver::{self, SolverLimits};
 use core_sdk::UCIOptions;
 use std::io;
 use std::sync::{atomic::Ordering, Arc};
@@ -20,7 +24,7 @@ pub fn parse_loop() {
     let mut us = UCIEngine::standard();
 
     let itcs = Arc::new(InterThreadCommunicationSystem::default());
-    *itcs.cache() = Cache::with_size_threaded(itcs.get_current_uci_options().hash_size, itcs.get_current_uci_options().threads);
+    itcs.resize_cache(itcs.get_current_uci_options().hash_size, itcs.get_current_uci_options().threads);
     let mut movelist = movegen::MoveList::default();
 
     let stdin = io::stdin();
@@ -48,6 +52,7 @@ pub fn parse_loop() {
             "ucinewgame" | "newgame" => {
                 newgame(&mut us);
                 itcs.cache().clear_threaded(itcs.get_current_uci_options().threads);
+                itcs.clear_eval_caches();
                 itcs.saved_time.store(0, Ordering::Relaxed);
             }
             "isready" => isready(&itcs, true),
@@ -77,13 +82,23 @@ pub fn parse_loop() {
             "quit" => {
                 break;
             }
+            "debug" => {
+                if arg.len() > 1 {
+                    itcs.uci_options.write().unwrap().search_statistics = arg[1] == "on";
+                }
+            }
             "d" => {
                 print_internal_state(&us);
             }
             "perft" => perft(&us.internal_state, &arg[1..]),
-            "static"|"eval" => {
-                println!("{}", core_sdk::evaluation::eval_game_state(&us.internal_state).final_eval);
-            }
+            "solve" => solve(&us.internal_state, &history, &arg[1..]),
+            "static"|"eval" => match &*itcs.network.read().unwrap() {
+                Some(network) => println!("{}", network.evaluate_full(&us.internal_state)),
+                None => match &*itcs.eval_params.read().unwrap() {
+                    Some(params) => println!("{}", core_sdk::evaluation::eval_game_state_with_params(&us.internal_state, &**params).final_eval),
+                    None => println!("{}", core_sdk::evaluation::eval_game_state(&us.internal_state).final_eval),
+                },
+            },
             _ => {
                 println!("Unknown command {}", line);
             }
@@ -96,6 +111,41 @@ pub fn perft(game_state: &GameState, cmd: &[&str]) {
     core_sdk::perft_div(&game_state, depth);
 }
 
+pub fn solve(game_state: &GameState, history: &[GameState], cmd: &[&str]) {
+    let mut limits = SolverLimits::default();
+    let mut pgn_file = None;
+    let mut index = 0;
+    while index + 1 < cmd.len() {
+        let (name, value) = (cmd[index].to_lowercase(), cmd[index + 1]);
+        let valid = match name.as_str() {
+            "nodes" => value.parse::<u64>().map(|nodes| limits.nodes = nodes).is_ok(),
+            "depth" => value.parse::<usize>().map(|depth| limits.max_depth = depth).is_ok(),
+            "movetime" => value.parse::<u64>().map(|time| limits.time = Some(time)).is_ok(),
+            "pgn" => {
+                pgn_file = Some(value);
+                true
+            }
+            _ => true,
+        };
+        if !valid {
+            println!("info string Invalid value {} for {}", value, name);
+            return;
+        }
+        index += 2;
+    }
+    let solution = solver::solve(game_state, history, limits);
+    let pv: Vec<String> = solution.main_line().iter().map(|mv| format!("{:?}", mv)).collect();
+    println!("info string solution {} nodes {} time {} pv {}", solution.verdict, solution.nodes, solution.time, pv.join(" "));
+    match pgn_file {
+        Some(file) => {
+            if let Err(e) = std::fs::write(file, solution.to_pgn()) {
+                println!("info string Couldn't write proof tree to {}: {}", file, e);
+            }
+        }
+        None => print!("{}", solution.to_pgn()),
+    }
+}
+
 pub fn print_internal_state(engine: &UCIEngine) {
     println!("{}", engine.internal_state);
 }
@@ -132,6 +182,10 @@ pub fn go(engine: &UCIEngine, cmd: &[&str]) -> (TimeControl, usize) {
                 let mvtime = cmd[index + 1].parse::<u64>().unwrap_or(0);
                 return (TimeControl::MoveTime(mvtime), depth);
             }
+            "nodes" => {
+                let nodes = cmd[index + 1].parse::<u64>().unwrap_or(1);
+                return (TimeControl::Nodes(nodes), depth);
+            }
             "movestogo" => movestogo = Some(cmd[index + 1].parse::<usize>().unwrap_or(1)),
             _ => println!("Some parts of the go command weren't recognized well."),
         };
@@ -231,12 +285,17 @@ pub fn uci(engine: &UCIEngine) {
     let options = UCIOptions::default();
     println!("option name Hash type spin default {} min {} max {}", options.hash_size, MIN_HASH_SIZE, MAX_HASH_SIZE);
     println!("option name ClearHash type button");
+    println!(
+        "option name EvalCache type spin default {} min {} max {}",
+        options.eval_cache_size, MIN_EVAL_CACHE_SIZE, MAX_EVAL_CACHE_SIZE
+    );
     println!("option name Threads type spin default {} min {} max {}", options.threads, MIN_THREADS, MAX_THREADS);
     println!(
         "option name MoveOverhead type spin default {} min {} max {}",
         options.move_overhead, MIN_MOVE_OVERHEAD, MAX_MOVE_OVERHEAD
     );
     println!("option name DebugSMPPrint type check default {}", options.debug_print);
+    println!("option name SearchStatistics type check default {}", options.search_statistics);
     println!(
         "option name SMPSkipRatio type spin default {} min {} max {}",
         options.skip_ratio, MIN_SKIP_RATIO, MAX_SKIP_RATIO
@@ -245,6 +304,14 @@ pub fn uci(engine: &UCIEngine) {
         "option name FutilityMargin type spin default {} min {} max {}",
         options.futility_margin, MIN_FUTILITY_MARGIN, MAX_FUTILITY_MARGIN
     );
+    println!("option name EvalFile type string default <empty>");
+    println!("option name EvalParamsFile type string default <empty>");
+    #[cfg(feature = "search-trace")]
+    {
+        println!("option name TraceFile type string default <empty>");
+        println!("option name TraceNodes type string default 0-");
+        println!("option name TracePrefix type string default <empty>");
+    }
     println!("uciok");
 }
 
@@ -257,7 +324,7 @@ pub fn setoption(cmd: &[&str], itcs: &Arc<InterThreadCommunicationSystem>) {
                 let num = cmd[index + 2].parse::<usize>().expect("Invalid Hash value!");
                 itcs.uci_options.write().unwrap().hash_size = num;
                 let num_threads = itcs.get_current_uci_options().threads;
-                *itcs.cache() = Cache::with_size_threaded(num, num_threads);
+                itcs.resize_cache(num, num_threads);
                 println!("info String Succesfully set Hash to {}", num);
                 return;
             }
@@ -266,6 +333,12 @@ pub fn setoption(cmd: &[&str], itcs: &Arc<InterThreadCommunicationSystem>) {
                 println!("info String Succesfully cleared hash!");
                 return;
             }
+            "evalcache" => {
+                let num = cmd[index + 2].parse::<usize>().expect("Invalid EvalCache value!").max(MIN_EVAL_CACHE_SIZE).min(MAX_EVAL_CACHE_SIZE);
+                itcs.uci_options.write().unwrap().eval_cache_size = num;
+                println!("info String Succesfully set EvalCache to {}", num);
+                return;
+            }
             "threads" => {
                 let num = cmd[index + 2].parse::<usize>().expect("Invalid Threads value!");
                 InterThreadCommunicationSystem::update_thread_count(&itcs, num);
@@ -284,6 +357,12 @@ pub fn setoption(cmd: &[&str], itcs: &Arc<InterThreadCommunicationSystem>) {
                 println!("info String Succesfully set DebugSMPPrint to {}", val);
                 return;
             }
+            "searchstatistics" => {
+                let val = cmd[index + 2].parse::<bool>().expect("Invalid SearchStatistics value!");
+                itcs.uci_options.write().unwrap().search_statistics = val;
+                println!("info String Succesfully set SearchStatistics to {}", val);
+                return;
+            }
             "smpskipratio" => {
                 let num = cmd[index + 2].parse::<usize>().expect("Invalid SMPSkipRatio value!");
                 itcs.uci_options.write().unwrap().skip_ratio = num;
@@ -296,6 +375,71 @@ pub fn setoption(cmd: &[&str], itcs: &Arc<InterThreadCommunicationSystem>) {
                 println!("info String Succesfully set FutilityMargin to {}", num);
                 return;
             }
+            "evalfile" => {
+                let file = cmd[index + 2..].join(" ");
+                if file.is_empty() || file == "<empty>" {
+                    *itcs.network.write().unwrap() = None;
+                    println!("info String Succesfully unloaded EvalFile, using the handcrafted evaluation");
+                } else {
+                    match Network::load(&file) {
+                        Ok(network) => {
+                            *itcs.network.write().unwrap() = Some(Arc::new(network));
+                            println!("info String Succesfully set EvalFile to {}", file);
+                        }
+                        Err(e) => {
+                            println!("info String Could not load EvalFile {}: {}", file, e);
+                            return;
+                        }
+                    }
+                }
+                //Scores in the table were computed by the previous evaluation
+                itcs.cache().clear_threaded(itcs.get_current_uci_options().threads);
+                return;
+            }
+            "evalparamsfile" => {
+                let file = cmd[index + 2..].join(" ");
+                if file.is_empty() || file == "<empty>" {
+                    *itcs.eval_params.write().unwrap() = None;
+                    println!("info String Succesfully unloaded EvalParamsFile, using the compiled parameters");
+                } else {
+                    match RuntimeParams::load(&file) {
+                        Ok(params) => {
+                            *itcs.eval_params.write().unwrap() = Some(Arc::new(params));
+                            println!("info String Succesfully set EvalParamsFile to {}", file);
+                        }
+                        Err(e) => {
+                            println!("info String Could not load EvalParamsFile {}: {}", file, e);
+                            return;
+                        }
+                    }
+                }
+                itcs.cache().clear_threaded(itcs.get_current_uci_options().threads);
+                return;
+            }
+            #[cfg(feature = "search-trace")]
+            "tracefile" => {
+                let file = cmd[index + 2..].join(" ");
+                let file = if file.is_empty() || file == "<empty>" { None } else { Some(file) };
+                println!("info String Succesfully set TraceFile to {:?}", file);
+                itcs.trace_config.write().unwrap().file = file;
+                return;
+            }
+            #[cfg(feature = "search-trace")]
+            "tracenodes" => {
+                let range = cmd.get(index + 2).copied().unwrap_or("0-");
+                if !itcs.trace_config.write().unwrap().set_node_range(range) {
+                    panic!("Invalid TraceNodes value!");
+                }
+                println!("info String Succesfully set TraceNodes to {}", range);
+                return;
+            }
+            #[cfg(feature = "search-trace")]
+            "traceprefix" => {
+                let prefix: Vec<String> = cmd[index + 2..].iter().filter(|mv| **mv != "<empty>").map(|mv| mv.to_string()).collect();
+                println!("info String Succesfully set TracePrefix to {}", prefix.join(" "));
+                itcs.trace_config.write().unwrap().pv_prefix = prefix;
+                return;
+            }
             _ => {
                 index += 1;
             }